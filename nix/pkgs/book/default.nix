@@ -13,7 +13,7 @@ stdenv.mkDerivation {
     root = inputs.self;
     include = [
       "book.toml"
-      "conduwuit-example.toml"
+      "pqchat-example.toml"
       "CODE_OF_CONDUCT.md"
       "CONTRIBUTING.md"
       "README.md"
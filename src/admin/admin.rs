@@ -4,8 +4,8 @@
 use crate::{
 	appservice, appservice::AppserviceCommand, check, check::CheckCommand, command::Command,
 	debug, debug::DebugCommand, federation, federation::FederationCommand, media,
-	media::MediaCommand, query, query::QueryCommand, room, room::RoomCommand, server,
-	server::ServerCommand, user, user::UserCommand,
+	media::MediaCommand, query, query::QueryCommand, reports, reports::ReportsCommand, room,
+	room::RoomCommand, server, server::ServerCommand, user, user::UserCommand,
 };
 
 #[derive(Debug, Parser)]
@@ -35,6 +35,10 @@ pub(super) enum AdminCommand {
 	/// - Commands for managing media
 	Media(MediaCommand),
 
+	#[command(subcommand)]
+	/// - Commands for triaging abuse reports
+	Reports(ReportsCommand),
+
 	#[command(subcommand)]
 	/// - Commands for checking integrity
 	Check(CheckCommand),
@@ -55,6 +59,7 @@ pub(super) async fn process(command: AdminCommand, context: &Command<'_>) -> Res
 	match command {
 		| Appservices(command) => appservice::process(command, context).await?,
 		| Media(command) => media::process(command, context).await?,
+		| Reports(command) => reports::process(command, context).await?,
 		| Users(command) => user::process(command, context).await?,
 		| Rooms(command) => room::process(command, context).await?,
 		| Federation(command) => federation::process(command, context).await?,
@@ -1,12 +1,18 @@
-use std::time::Duration;
+use std::{fmt::Write, time::Duration};
 
 use conduwuit::{
-	debug, debug_info, debug_warn, error, info, trace, utils::time::parse_timepoint_ago, Result,
+	debug, debug_info, debug_warn, error, info, trace,
+	utils::{bytes::pretty, time::parse_timepoint_ago},
+	Err, Result,
 };
-use conduwuit_service::media::Dim;
+use conduwuit_service::{
+	media::{DedupStats, Dim, RetentionReport},
+	Services,
+};
+use futures::StreamExt;
 use ruma::{
 	events::room::message::RoomMessageEventContent, EventId, Mxc, MxcUri, OwnedMxcUri,
-	OwnedServerName, ServerName,
+	OwnedRoomOrAliasId, OwnedServerName, ServerName,
 };
 
 use crate::{admin_command, utils::parse_local_user_id};
@@ -338,6 +344,220 @@ pub(super) async fn delete_all_from_server(
 	)))
 }
 
+#[admin_command]
+pub(super) async fn quarantine(
+	&self,
+	mxc: Option<OwnedMxcUri>,
+	room: Option<OwnedRoomOrAliasId>,
+	user: Option<String>,
+) -> Result<RoomMessageEventContent> {
+	let count = set_quarantined(self.services, mxc, room, user, true).await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!("Quarantined {count} MXC URLs.")))
+}
+
+#[admin_command]
+pub(super) async fn release(
+	&self,
+	mxc: Option<OwnedMxcUri>,
+	room: Option<OwnedRoomOrAliasId>,
+	user: Option<String>,
+) -> Result<RoomMessageEventContent> {
+	let count = set_quarantined(self.services, mxc, room, user, false).await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Released {count} MXC URLs from quarantine."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn list_quarantined(&self) -> Result<RoomMessageEventContent> {
+	let mxcs: Vec<_> = self
+		.services
+		.media
+		.quarantined()
+		.map(|mxc| mxc.to_string())
+		.collect()
+		.await;
+
+	if mxcs.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No media is quarantined."));
+	}
+
+	let output_plain =
+		format!("Quarantined media ({}):\n```\n{}\n```", mxcs.len(), mxcs.join("\n"));
+
+	Ok(RoomMessageEventContent::notice_markdown(output_plain))
+}
+
+#[admin_command]
+pub(super) async fn set_quota(
+	&self,
+	user_id: String,
+	quota: Option<u64>,
+) -> Result<RoomMessageEventContent> {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+	self.services.media.set_media_quota(&user_id, quota);
+
+	let message = match quota {
+		| None => format!("User {user_id} now has the configured media quota."),
+		| Some(0) => format!("User {user_id} can now upload media without limit."),
+		| Some(quota) => format!("Set the media quota of {user_id} to {quota} bytes."),
+	};
+
+	Ok(RoomMessageEventContent::text_plain(message))
+}
+
+#[admin_command]
+pub(super) async fn usage(
+	&self,
+	user_id: Option<String>,
+	limit: usize,
+) -> Result<RoomMessageEventContent> {
+	let media = &self.services.media;
+	if let Some(user_id) = user_id {
+		let user_id = parse_local_user_id(self.services, &user_id)?;
+		let usage = media.media_usage(&user_id).await;
+		let quota = media
+			.media_quota(&user_id)
+			.await
+			.map_or_else(|| "none".to_owned(), |quota| format!("{quota} bytes"));
+
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"User {user_id} uses {usage} bytes of media. Quota: {quota}"
+		)));
+	}
+
+	let uploaders = media.top_uploaders(limit).await;
+	if uploaders.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No media has been uploaded."));
+	}
+
+	let mut output_plain = String::from("| User | Bytes | Quota |\n| --- | --- | --- |\n");
+	for (user_id, usage) in uploaders {
+		let quota = media
+			.media_quota(&user_id)
+			.await
+			.map_or_else(|| "none".to_owned(), |quota| quota.to_string());
+
+		writeln!(output_plain, "| {user_id} | {usage} | {quota} |")?;
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(output_plain))
+}
+
+#[admin_command]
+pub(super) async fn migrate_storage(
+	&self,
+	from: String,
+	to: String,
+) -> Result<RoomMessageEventContent> {
+	if from == to {
+		return Ok(RoomMessageEventContent::text_plain(
+			"The source and target storages must differ.",
+		));
+	}
+
+	let (copied, missing) = self.services.media.copy_storage(&from, &to).await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Copied {copied} media files from {from} to {to}. {missing} files were missing from \
+		 {from}."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn regenerate_thumbnails(
+	&self,
+	mxc: Option<OwnedMxcUri>,
+) -> Result<RoomMessageEventContent> {
+	if let Some(mxc) = mxc {
+		let mxc: Mxc<'_> = mxc.as_str().try_into()?;
+		self.services.media.regenerate_thumbnails(&mxc).await?;
+
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"Regenerating the thumbnails of {mxc} in the background."
+		)));
+	}
+
+	let mxcs = self.services.media.get_all_mxcs().await?;
+	let local = mxcs.into_iter().filter(|mxc| {
+		mxc.server_name()
+			.is_ok_and(|server| self.services.globals.server_is_ours(server))
+	});
+
+	let mut queued: usize = 0;
+	for mxc in local {
+		let Ok(parsed) = mxc.as_str().try_into() else {
+			continue;
+		};
+
+		match self.services.media.regenerate_thumbnails(&parsed).await {
+			| Ok(()) => queued = queued.saturating_add(1),
+			| Err(e) => debug_warn!(%mxc, "Failed to regenerate thumbnails: {e}"),
+		}
+	}
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Regenerating the thumbnails of {queued} media files in the background."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn dedup_stats(&self) -> Result<RoomMessageEventContent> {
+	let DedupStats { files, blobs, stored, saved } = self.services.media.dedup_stats().await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"{files} media files are stored as {blobs} distinct files taking {}. Deduplication \
+		 saves {}.",
+		pretty(stored.try_into()?),
+		pretty(saved.try_into()?),
+	)))
+}
+
+#[admin_command]
+pub(super) async fn dedupe_media(&self) -> Result<RoomMessageEventContent> {
+	let (hashed, duplicates) = self.services.media.dedupe_media_files().await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Hashed {hashed} media files, of which {duplicates} were duplicates and deleted."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn retention(&self, dry_run: bool) -> Result<RoomMessageEventContent> {
+	let RetentionReport { remote, local, bytes, deleted } =
+		self.services.media.apply_retention(dry_run).await?;
+
+	let expired = remote.len().saturating_add(local.len());
+	if !dry_run {
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"Deleted {deleted} of {expired} expired media files ({} remote, {} local), freeing \
+			 up to {}.",
+			remote.len(),
+			local.len(),
+			pretty(bytes.try_into()?),
+		)));
+	}
+
+	let list = |mxcs: &[OwnedMxcUri]| {
+		mxcs.iter()
+			.map(ToString::to_string)
+			.collect::<Vec<_>>()
+			.join("\n")
+	};
+
+	Ok(RoomMessageEventContent::notice_markdown(format!(
+		"{expired} media files taking {} would be deleted.\n\nRemote \
+		 ({}):\n```\n{}\n```\n\nLocal ({}):\n```\n{}\n```",
+		pretty(bytes.try_into()?),
+		remote.len(),
+		list(&remote),
+		local.len(),
+		list(&local),
+	)))
+}
+
 #[admin_command]
 pub(super) async fn get_file_info(&self, mxc: OwnedMxcUri) -> Result<RoomMessageEventContent> {
 	let mxc: Mxc<'_> = mxc.as_str().try_into()?;
@@ -394,3 +614,30 @@ pub(super) async fn get_remote_thumbnail(
 	let out = format!("```\n{result:#?}\nreceived {len} bytes for file content.\n```");
 	Ok(RoomMessageEventContent::notice_markdown(out))
 }
+
+async fn set_quarantined(
+	services: &Services,
+	mxc: Option<OwnedMxcUri>,
+	room: Option<OwnedRoomOrAliasId>,
+	user: Option<String>,
+	quarantined: bool,
+) -> Result<usize> {
+	let media = &services.media;
+	match (mxc, room, user) {
+		| (Some(mxc), None, None) => {
+			media
+				.set_quarantined(&mxc.as_str().try_into()?, quarantined)
+				.await;
+			Ok(1)
+		},
+		| (None, Some(room), None) => {
+			let room_id = services.rooms.alias.resolve(&room).await?;
+			Ok(media.set_quarantined_from_room(&room_id, quarantined).await)
+		},
+		| (None, None, Some(user)) => {
+			let user_id = parse_local_user_id(services, &user)?;
+			Ok(media.set_quarantined_from_user(&user_id, quarantined).await)
+		},
+		| _ => Err!("Please specify one of --mxc, --room or --user."),
+	}
+}
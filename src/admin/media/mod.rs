@@ -2,7 +2,7 @@
 
 use clap::Subcommand;
 use conduwuit::Result;
-use ruma::{EventId, MxcUri, OwnedMxcUri, OwnedServerName, ServerName};
+use ruma::{EventId, MxcUri, OwnedMxcUri, OwnedRoomOrAliasId, OwnedServerName, ServerName};
 
 use crate::admin_command_dispatch;
 
@@ -63,6 +63,92 @@ pub(super) enum MediaCommand {
 		yes_i_want_to_delete_local_media: bool,
 	},
 
+	/// - Quarantines media so that it is neither served to anyone nor fetched
+	///   again from remote servers, without deleting it
+	///
+	/// Select the media by MXC URL, by room (all media referenced in the
+	/// room's timeline) or by local user (all media they uploaded).
+	Quarantine {
+		#[arg(long, conflicts_with_all = ["room", "user"])]
+		mxc: Option<OwnedMxcUri>,
+
+		#[arg(long, conflicts_with = "user")]
+		room: Option<OwnedRoomOrAliasId>,
+
+		#[arg(long)]
+		user: Option<String>,
+	},
+
+	/// - Releases media from quarantine, selected like for `quarantine`
+	Release {
+		#[arg(long, conflicts_with_all = ["room", "user"])]
+		mxc: Option<OwnedMxcUri>,
+
+		#[arg(long, conflicts_with = "user")]
+		room: Option<OwnedRoomOrAliasId>,
+
+		#[arg(long)]
+		user: Option<String>,
+	},
+
+	/// - Lists the quarantined MXC URLs
+	ListQuarantined,
+
+	/// - Sets the media storage quota of a local user in bytes
+	///
+	/// A quota of 0 lets the user upload without limit. Without a quota the
+	/// user returns to the configured `media_quota`.
+	SetQuota {
+		user_id: String,
+		quota: Option<u64>,
+	},
+
+	/// - Shows the local users who uploaded the most media, or the usage and
+	///   quota of a single user
+	Usage {
+		user_id: Option<String>,
+
+		#[arg(short, long, default_value("20"))]
+		limit: usize,
+	},
+
+	/// - Copies all media files from one media storage to another
+	///
+	/// Files already in the target storage are skipped, so the copy can be
+	/// resumed. Storages are "filesystem" and "s3", configured as for
+	/// `media_storage`. Set `media_storage_fallback` to the old storage while
+	/// copying so media not copied yet can still be read.
+	MigrateStorage {
+		from: String,
+		to: String,
+	},
+
+	/// - Deletes the thumbnails generated of media and generates them again in
+	///   the background, in the sizes of `media_thumbnail_sizes`
+	///
+	/// Without an MXC URL, this is done for all local media.
+	RegenerateThumbnails {
+		mxc: Option<OwnedMxcUri>,
+	},
+
+	/// - Shows how much space deduplicating media files by their content saves
+	DedupStats,
+
+	/// - Deduplicates the media files stored before media was deduplicated by
+	///   content
+	///
+	/// Every such file is read to hash its content, and deleted when the same
+	/// content is stored already. This may take a while.
+	DedupeMedia,
+
+	/// - Applies the media retention policies now rather than at the next
+	///   `media_retention_interval`
+	Retention {
+		/// Only list the media which would be deleted
+		#[arg(long)]
+		dry_run: bool,
+	},
+
 	GetFileInfo {
 		/// The MXC URL to lookup info for.
 		mxc: OwnedMxcUri,
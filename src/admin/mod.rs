@@ -15,6 +15,7 @@
 pub(crate) mod federation;
 pub(crate) mod media;
 pub(crate) mod query;
+pub(crate) mod reports;
 pub(crate) mod room;
 pub(crate) mod server;
 pub(crate) mod user;
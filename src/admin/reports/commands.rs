@@ -0,0 +1,123 @@
+use std::fmt::Write;
+
+use conduwuit::{
+	utils::{time, ReadyExt},
+	Result,
+};
+use futures::StreamExt;
+use ruma::{events::room::message::RoomMessageEventContent, MilliSecondsSinceUnixEpoch};
+use service::reports::{Report, Status};
+
+use crate::admin_command;
+
+#[admin_command]
+pub(super) async fn list(
+	&self,
+	status: Option<Status>,
+	limit: usize,
+) -> Result<RoomMessageEventContent> {
+	let reports: Vec<_> = self
+		.services
+		.reports
+		.reports()
+		.ready_filter(|(_, report)| status.is_none_or(|status| report.status == status))
+		.take(limit)
+		.collect()
+		.await;
+
+	let open = self.services.reports.count_open().await;
+	if reports.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"No reports found. {open} reports are open."
+		)));
+	}
+
+	let mut output = format!("Reports ({}, {open} open in total):\n```\n", reports.len());
+	for (id, report) in &reports {
+		let target = report
+			.event_id
+			.as_ref()
+			.map_or_else(|| report.room_id.to_string(), ToString::to_string);
+
+		writeln!(
+			output,
+			"{id}\t{}\t{}\t{}\t{target}\t{}",
+			report.status,
+			timestamp(report.received_ts),
+			report.reporter,
+			report.reason.as_deref().unwrap_or(""),
+		)?;
+	}
+	output.push_str("```");
+
+	Ok(RoomMessageEventContent::notice_markdown(output))
+}
+
+#[admin_command]
+pub(super) async fn show(&self, report_id: u64) -> Result<RoomMessageEventContent> {
+	let report = self.services.reports.get(report_id).await?;
+	let mut output = describe(report_id, &report)?;
+
+	if let Some(event_id) = &report.event_id {
+		match self.services.rooms.timeline.get_pdu_json(event_id).await {
+			| Ok(json) => {
+				let json = serde_json::to_string_pretty(&json)?;
+				write!(output, "\n\nReported event:\n```json\n{json}\n```")?;
+			},
+			| Err(e) => write!(output, "\n\nReported event could not be fetched: {e}")?,
+		}
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(output))
+}
+
+#[admin_command]
+pub(super) async fn resolve(
+	&self,
+	report_id: u64,
+	dismiss: bool,
+	note: Vec<String>,
+) -> Result<RoomMessageEventContent> {
+	let status = if dismiss { Status::Dismissed } else { Status::Resolved };
+	let note = Some(note.join(" ")).filter(|note| !note.is_empty());
+
+	self.services
+		.reports
+		.set_status(report_id, status, note)
+		.await?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Report {report_id} marked as {status}."
+	)))
+}
+
+fn describe(id: u64, report: &Report) -> Result<String> {
+	let mut output = format!("Report {id} ({})\n\n", report.status);
+	writeln!(output, "Received: {}", timestamp(report.received_ts))?;
+	writeln!(output, "Reporter: {}", report.reporter)?;
+	writeln!(output, "Room ID: {}", report.room_id)?;
+	if let Some(event_id) = &report.event_id {
+		writeln!(output, "Event ID: {event_id}")?;
+	}
+	if let Some(sender) = &report.event_sender {
+		writeln!(output, "Sent by: {sender}")?;
+	}
+	if let Some(score) = report.score {
+		writeln!(output, "Score: {score}")?;
+	}
+	writeln!(output, "Reason: {}", report.reason.as_deref().unwrap_or(""))?;
+	if let Some(handled_ts) = report.handled_ts {
+		writeln!(output, "Handled: {}", timestamp(handled_ts))?;
+	}
+	if let Some(note) = &report.note {
+		writeln!(output, "Note: {note}")?;
+	}
+
+	Ok(output)
+}
+
+fn timestamp(ts: MilliSecondsSinceUnixEpoch) -> String {
+	ts.to_system_time()
+		.map(|ts| time::format(ts, "%Y-%m-%d %H:%M:%S UTC"))
+		.unwrap_or_default()
+}
@@ -0,0 +1,40 @@
+mod commands;
+
+use clap::Subcommand;
+use conduwuit::Result;
+use service::reports::Status;
+
+use crate::admin_command_dispatch;
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(super) enum ReportsCommand {
+	/// - List abuse reports submitted by users, newest first
+	List {
+		/// Only list reports with this status: open, resolved or dismissed
+		#[arg(long)]
+		status: Option<Status>,
+
+		/// Maximum number of reports to list
+		#[arg(short, long, default_value("50"))]
+		limit: usize,
+	},
+
+	/// - Show the details of a report, including the reported event
+	Show {
+		report_id: u64,
+	},
+
+	/// - Mark a report as resolved, or as dismissed with `--dismiss`
+	///
+	/// Any remaining arguments are recorded as a note on the report.
+	Resolve {
+		report_id: u64,
+
+		/// Dismiss the report as unfounded instead of resolving it
+		#[arg(long)]
+		dismiss: bool,
+
+		note: Vec<String>,
+	},
+}
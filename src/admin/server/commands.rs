@@ -1,9 +1,10 @@
 use std::{fmt::Write, path::PathBuf, sync::Arc};
 
 use conduwuit::{info, utils::time, warn, Err, Result};
-use ruma::events::room::message::RoomMessageEventContent;
+use futures::StreamExt;
+use ruma::{events::room::message::RoomMessageEventContent, OwnedUserId};
 
-use crate::admin_command;
+use crate::{admin_command, utils::parse_local_user_id};
 
 #[admin_command]
 pub(super) async fn uptime(&self) -> Result<RoomMessageEventContent> {
@@ -129,6 +130,64 @@ pub(super) async fn admin_notice(&self, message: Vec<String>) -> Result<RoomMess
 	Ok(RoomMessageEventContent::notice_plain("Notice was sent to #admins"))
 }
 
+#[admin_command]
+pub(super) async fn notice(
+	&self,
+	users: Vec<String>,
+	all: bool,
+	message: Vec<String>,
+) -> Result<RoomMessageEventContent> {
+	if !self.services.server_notices.is_enabled() {
+		return Err!("Server notices are not enabled; set server_notices_localpart first.");
+	}
+
+	let message = message.join(" ");
+	if message.is_empty() {
+		return Err!("The notice has no message.");
+	}
+
+	let user_ids: Vec<OwnedUserId> = if all {
+		self.services
+			.users
+			.list_local_users()
+			.map(ToOwned::to_owned)
+			.collect()
+			.await
+	} else if users.is_empty() {
+		return Err!("Give the users to notify with --user, or --all for all local users.");
+	} else {
+		users
+			.iter()
+			.map(|user_id| parse_local_user_id(self.services, user_id))
+			.collect::<Result<_>>()?
+	};
+
+	let mut sent: usize = 0;
+	let mut failed = String::new();
+	for user_id in &user_ids {
+		if *user_id == self.services.globals.server_user {
+			continue;
+		}
+
+		match self
+			.services
+			.server_notices
+			.send_text(user_id, &message)
+			.await
+		{
+			| Ok(_) => sent = sent.saturating_add(1),
+			| Err(e) => writeln!(failed, "- {user_id}: {e}")?,
+		}
+	}
+
+	let mut response = format!("Sent the notice to {sent} user(s).");
+	if !failed.is_empty() {
+		write!(response, "\n\nFailed to send it to:\n{failed}")?;
+	}
+
+	Ok(RoomMessageEventContent::notice_markdown(response))
+}
+
 #[admin_command]
 pub(super) async fn reload_mods(&self) -> Result<RoomMessageEventContent> {
 	self.services.server.reload()?;
@@ -51,6 +51,22 @@ pub(super) enum ServerCommand {
 		message: Vec<String>,
 	},
 
+	/// - Send a server notice to local users
+	///
+	/// The notice is sent to each user given with --user, or to all local
+	/// users with --all. Users get it in their server notices room. --all
+	/// only reaches users with a password: accounts without one, such as
+	/// appservice users, are treated as deactivated and get no notices.
+	Notice {
+		#[arg(short, long = "user")]
+		users: Vec<String>,
+
+		#[arg(long, conflicts_with = "users")]
+		all: bool,
+
+		message: Vec<String>,
+	},
+
 	/// - Hot-reload the server
 	#[clap(alias = "reload")]
 	ReloadMods,
@@ -3,6 +3,7 @@
 use api::client::{full_user_deactivate, join_room_by_id_helper, leave_room};
 use conduwuit::{
 	debug_warn, error, info, is_equal_to,
+	result::LogErr,
 	utils::{self, ReadyExt},
 	warn, PduBuilder, Result,
 };
@@ -18,7 +19,7 @@
 		tag::{TagEvent, TagEventContent, TagInfo},
 		RoomAccountDataEventType, StateEventType,
 	},
-	EventId, OwnedRoomId, OwnedRoomOrAliasId, OwnedUserId, RoomId, UserId,
+	EventId, OwnedDeviceId, OwnedRoomId, OwnedRoomOrAliasId, OwnedUserId, RoomId, UserId,
 };
 
 use crate::{
@@ -258,6 +259,18 @@ pub(super) async fn reset_password(
 		));
 	}
 
+	if let Some(password) = password.as_deref() {
+		if let Err(e) = self
+			.services
+			.users
+			.check_password_policy(&user_id, password)
+		{
+			return Ok(RoomMessageEventContent::text_plain(format!(
+				"Password does not meet the password policy: {e}"
+			)));
+		}
+	}
+
 	let new_password = password.unwrap_or_else(|| utils::random_string(AUTO_GEN_PASSWORD_LENGTH));
 
 	match self
@@ -265,15 +278,130 @@ pub(super) async fn reset_password(
 		.users
 		.set_password(&user_id, Some(new_password.as_str()))
 	{
-		| Ok(()) => Ok(RoomMessageEventContent::text_plain(format!(
-			"Successfully reset the password for user {user_id}: `{new_password}`"
-		))),
+		| Ok(()) => {
+			if self.services.server_notices.is_enabled() {
+				self.services
+					.server_notices
+					.send_text(
+						&user_id,
+						"Your password was reset by a server administrator. Contact them for \
+						 your new password if you did not ask for this.",
+					)
+					.await
+					.log_err()
+					.ok();
+			}
+
+			Ok(RoomMessageEventContent::text_plain(format!(
+				"Successfully reset the password for user {user_id}: `{new_password}`"
+			)))
+		},
 		| Err(e) => Ok(RoomMessageEventContent::text_plain(format!(
 			"Couldn't reset the password for user {user_id}: {e}"
 		))),
 	}
 }
 
+#[admin_command]
+pub(super) async fn unlock(&self, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+
+	if !self.services.lockout.unlock(&user_id) {
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"User {user_id} has no failed password attempts recorded."
+		)));
+	}
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"User {user_id} has been unlocked and their failed password attempts were reset."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn list_locked(&self) -> Result<RoomMessageEventContent> {
+	let mut locked = self.services.lockout.locked_accounts();
+	if locked.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No accounts are currently locked."));
+	}
+
+	locked.sort_by_key(|(_, remaining)| *remaining);
+	locked.reverse();
+
+	let output_plain = format!(
+		"Locked accounts ({}):\n```\n{}\n```",
+		locked.len(),
+		locked
+			.iter()
+			.map(|(user_id, remaining)| {
+				format!("{user_id}\tUnlocks in: {}", utils::time::pretty(*remaining))
+			})
+			.collect::<Vec<_>>()
+			.join("\n")
+	);
+
+	Ok(RoomMessageEventContent::notice_markdown(output_plain))
+}
+
+#[admin_command]
+pub(super) async fn shadow_ban(&self, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+
+	if user_id == self.services.globals.server_user {
+		return Ok(RoomMessageEventContent::text_plain(
+			"Not allowed to shadow-ban the server service account.",
+		));
+	}
+
+	if self.services.users.is_shadow_banned(&user_id).await {
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"User {user_id} is already shadow-banned."
+		)));
+	}
+
+	self.services.users.shadow_ban(&user_id, true);
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"User {user_id} has been shadow-banned."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn unshadow_ban(&self, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+
+	if !self.services.users.is_shadow_banned(&user_id).await {
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"User {user_id} is not shadow-banned."
+		)));
+	}
+
+	self.services.users.shadow_ban(&user_id, false);
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"The shadow-ban of user {user_id} has been lifted."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn list_shadow_banned(&self) -> Result<RoomMessageEventContent> {
+	let users: Vec<_> = self
+		.services
+		.users
+		.list_shadow_banned()
+		.map(ToString::to_string)
+		.collect()
+		.await;
+
+	if users.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("No users are shadow-banned."));
+	}
+
+	let output_plain =
+		format!("Shadow-banned users ({}):\n```\n{}\n```", users.len(), users.join("\n"));
+
+	Ok(RoomMessageEventContent::notice_markdown(output_plain))
+}
+
 #[admin_command]
 pub(super) async fn deactivate_all(
 	&self,
@@ -389,6 +517,101 @@ pub(super) async fn deactivate_all(
 	}
 }
 
+#[admin_command]
+pub(super) async fn list_devices(&self, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+
+	let devices: Vec<_> = self
+		.services
+		.users
+		.all_devices_metadata(&user_id)
+		.collect()
+		.await;
+
+	if devices.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("User has no devices."));
+	}
+
+	let mut lines = Vec::with_capacity(devices.len());
+	for device in devices {
+		let user_agent = self
+			.services
+			.users
+			.get_device_user_agent(&user_id, &device.device_id)
+			.await
+			.unwrap_or_else(|_| "unknown".to_owned());
+
+		let last_seen = device
+			.last_seen_ts
+			.and_then(|ts| ts.to_system_time())
+			.map_or_else(|| "never".to_owned(), |ts| utils::time::format(ts, "%+"));
+
+		lines.push(format!(
+			"{}\tName: {}\tLast seen: {last_seen}\tIP: {}\tUser agent: {user_agent}",
+			device.device_id,
+			device.display_name.as_deref().unwrap_or(""),
+			device.last_seen_ip.as_deref().unwrap_or("unknown"),
+		));
+	}
+
+	let output_plain =
+		format!("Devices of {user_id} ({}):\n```\n{}\n```", lines.len(), lines.join("\n"));
+
+	Ok(RoomMessageEventContent::notice_markdown(output_plain))
+}
+
+#[admin_command]
+pub(super) async fn logout_device(
+	&self,
+	user_id: String,
+	device_id: OwnedDeviceId,
+) -> Result<RoomMessageEventContent> {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+
+	if self
+		.services
+		.users
+		.get_device_metadata(&user_id, &device_id)
+		.await
+		.is_err()
+	{
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"User {user_id} has no device {device_id}."
+		)));
+	}
+
+	self.services
+		.users
+		.remove_device(&user_id, &device_id)
+		.await;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Logged out device {device_id} of user {user_id}."
+	)))
+}
+
+#[admin_command]
+pub(super) async fn logout_all(&self, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+
+	let device_ids: Vec<OwnedDeviceId> = self
+		.services
+		.users
+		.all_device_ids(&user_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for device_id in &device_ids {
+		self.services.users.remove_device(&user_id, device_id).await;
+	}
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Logged out {} device(s) of user {user_id}.",
+		device_ids.len()
+	)))
+}
+
 #[admin_command]
 pub(super) async fn list_joined_rooms(&self, user_id: String) -> Result<RoomMessageEventContent> {
 	// Validate user id
@@ -2,7 +2,7 @@
 
 use clap::Subcommand;
 use conduwuit::Result;
-use ruma::{EventId, OwnedRoomOrAliasId, RoomId};
+use ruma::{EventId, OwnedDeviceId, OwnedRoomOrAliasId, RoomId};
 
 use crate::admin_command_dispatch;
 
@@ -26,6 +26,31 @@ pub(super) enum UserCommand {
 		password: Option<String>,
 	},
 
+	/// - Unlock an account locked after too many failed password attempts
+	Unlock {
+		user_id: String,
+	},
+
+	/// - List accounts currently locked after too many failed password attempts
+	ListLocked,
+
+	/// - Shadow-ban a local user
+	///
+	/// The user's messages, joins, invites and typing notifications look
+	/// successful to them, but nobody else sees them. They can still leave
+	/// the rooms they are in.
+	ShadowBan {
+		user_id: String,
+	},
+
+	/// - Lift the shadow-ban of a local user
+	UnshadowBan {
+		user_id: String,
+	},
+
+	/// - List shadow-banned users
+	ListShadowBanned,
+
 	/// - Deactivate a user
 	///
 	/// User will be removed from all rooms by default.
@@ -63,6 +88,29 @@ pub(super) enum UserCommand {
 	#[clap(alias = "list")]
 	ListUsers,
 
+	/// - Lists the devices of a local user with their last seen IP address,
+	///   user agent and timestamp
+	ListDevices {
+		user_id: String,
+	},
+
+	/// - Logs out a single device of a local user
+	///
+	/// This invalidates the device's access token, deletes its pending
+	/// to-device messages and removes the device.
+	LogoutDevice {
+		user_id: String,
+		device_id: OwnedDeviceId,
+	},
+
+	/// - Logs out all devices of a local user
+	///
+	/// This invalidates every access token of the user, deletes their pending
+	/// to-device messages and removes all of their devices.
+	LogoutAll {
+		user_id: String,
+	},
+
 	/// - Lists all the rooms (local and remote) that the specified user is
 	///   joined in
 	ListJoinedRooms {
@@ -0,0 +1,58 @@
+//! Server administration API, compatible with the subset of Synapse's admin
+//! API which moderation tooling relies on.
+
+mod reports;
+
+use axum::{async_trait, extract::FromRequestParts, RequestPartsExt};
+use axum_extra::{
+	headers::{authorization::Bearer, Authorization},
+	TypedHeader,
+};
+use conduwuit::{err, Err, Error, Result};
+use http::request::Parts;
+use ruma::{api::client::error::ErrorKind, OwnedUserId};
+use serde::Deserialize;
+
+pub(super) use self::reports::*;
+use crate::State;
+
+/// The local admin user making an admin API request, authenticated by their
+/// access token.
+pub(crate) struct Admin(pub(crate) OwnedUserId);
+
+#[derive(Deserialize)]
+struct TokenQuery {
+	access_token: Option<String>,
+}
+
+#[async_trait]
+impl FromRequestParts<State> for Admin {
+	type Rejection = Error;
+
+	async fn from_request_parts(parts: &mut Parts, services: &State) -> Result<Self> {
+		let bearer: Option<TypedHeader<Authorization<Bearer>>> = parts.extract().await?;
+		let query: TokenQuery = serde_html_form::from_str(parts.uri.query().unwrap_or_default())
+			.map_err(|e| err!(Request(Unknown("Failed to read query parameters: {e}"))))?;
+
+		let token = match &bearer {
+			| Some(TypedHeader(Authorization(bearer))) => bearer.token(),
+			| None => query
+				.access_token
+				.as_deref()
+				.ok_or_else(|| err!(Request(MissingToken("Missing access token."))))?,
+		};
+
+		let Ok((user_id, _)) = services.users.find_from_token(token).await else {
+			return Err(Error::BadRequest(
+				ErrorKind::UnknownToken { soft_logout: false },
+				"Unknown access token.",
+			));
+		};
+
+		if !services.users.is_admin(&user_id).await {
+			return Err!(Request(Forbidden("You are not a server admin.")));
+		}
+
+		Ok(Self(user_id))
+	}
+}
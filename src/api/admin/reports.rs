@@ -0,0 +1,133 @@
+use axum::{
+	extract::{Path, RawQuery, State},
+	response::IntoResponse,
+	Json,
+};
+use conduwuit::{err, utils::ReadyExt, Err, Result};
+use futures::StreamExt;
+use ruma::{OwnedRoomId, OwnedUserId};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use service::{
+	reports::{Report, Status},
+	Services,
+};
+
+use super::Admin;
+
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+struct ListQuery {
+	#[serde(default)]
+	from: usize,
+	limit: Option<usize>,
+	dir: Option<String>,
+	user_id: Option<OwnedUserId>,
+	room_id: Option<OwnedRoomId>,
+	status: Option<Status>,
+}
+
+/// # `GET /_synapse/admin/v1/event_reports`
+///
+/// Lists event reports newest first (or oldest first with `dir=f`). Reports
+/// can be filtered by reporter with `user_id`, by `room_id`, and by `status`,
+/// the latter being an extension to Synapse's API.
+pub(crate) async fn get_event_reports_route(
+	State(services): State<crate::State>,
+	Admin(_): Admin,
+	RawQuery(query): RawQuery,
+) -> Result<impl IntoResponse> {
+	let query: ListQuery = serde_html_form::from_str(&query.unwrap_or_default())
+		.map_err(|e| err!(Request(InvalidParam("Failed to read query parameters: {e}"))))?;
+
+	let mut reports: Vec<_> = services
+		.reports
+		.reports()
+		.ready_filter(|(_, report)| {
+			report.event_id.is_some()
+				&& query
+					.user_id
+					.as_ref()
+					.is_none_or(|user_id| report.reporter == *user_id)
+				&& query
+					.room_id
+					.as_ref()
+					.is_none_or(|room_id| report.room_id == *room_id)
+				&& query.status.is_none_or(|status| report.status == status)
+		})
+		.collect()
+		.await;
+
+	match query.dir.as_deref() {
+		| None | Some("b") => {},
+		| Some("f") => reports.reverse(),
+		| Some(_) => return Err!(Request(InvalidParam("dir must be either 'f' or 'b'."))),
+	}
+
+	let total = reports.len();
+	let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+	let page: Vec<_> = reports.iter().skip(query.from).take(limit).collect();
+
+	let mut event_reports = Vec::with_capacity(page.len());
+	for (id, report) in &page {
+		event_reports.push(report_json(&services, *id, report).await);
+	}
+
+	let next = query.from.saturating_add(page.len());
+	let mut response = json!({
+		"event_reports": event_reports,
+		"total": total,
+	});
+
+	if next < total {
+		response["next_token"] = next.into();
+	}
+
+	Ok(Json(response))
+}
+
+/// # `GET /_synapse/admin/v1/event_reports/{reportId}`
+///
+/// Returns a single report including the reported event.
+pub(crate) async fn get_event_report_route(
+	State(services): State<crate::State>,
+	Admin(_): Admin,
+	Path(report_id): Path<u64>,
+) -> Result<impl IntoResponse> {
+	let report = services.reports.get(report_id).await?;
+	let mut response = report_json(&services, report_id, &report).await;
+
+	if let Some(event_id) = &report.event_id {
+		response["event_json"] = services
+			.rooms
+			.timeline
+			.get_pdu_json(event_id)
+			.await
+			.ok()
+			.and_then(|json| serde_json::to_value(json).ok())
+			.unwrap_or_default();
+	}
+
+	Ok(Json(response))
+}
+
+async fn report_json(services: &Services, id: u64, report: &Report) -> Value {
+	let state_accessor = &services.rooms.state_accessor;
+
+	json!({
+		"id": id,
+		"received_ts": report.received_ts,
+		"room_id": report.room_id,
+		"name": state_accessor.get_name(&report.room_id).await.ok(),
+		"canonical_alias": state_accessor.get_canonical_alias(&report.room_id).await.ok(),
+		"event_id": report.event_id,
+		"user_id": report.reporter,
+		"sender": report.event_sender,
+		"reason": report.reason,
+		"score": report.score,
+		"status": report.status,
+		"handled_ts": report.handled_ts,
+		"note": report.note,
+	})
+}
@@ -10,14 +10,13 @@
 use ruma::{
 	api::client::{
 		account::{
-			change_password, check_registration_token_validity, deactivate, get_3pids,
+			change_password, check_registration_token_validity, deactivate,
 			get_username_availability,
 			register::{self, LoginType},
-			request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn,
 			whoami, ThirdPartyIdRemovalStatus,
 		},
 		error::ErrorKind,
-		uiaa::{AuthFlow, AuthType, UiaaInfo},
+		uiaa::{AuthData, AuthFlow, AuthType, EmailIdentity, UiaaInfo},
 	},
 	events::{
 		room::{
@@ -28,7 +27,7 @@
 	},
 	push, OwnedRoomId, UserId,
 };
-use service::Services;
+use service::{threepid::Purpose, Services};
 
 use super::{join_room_by_id_helper, DEVICE_ID_LENGTH, SESSION_ID_LENGTH, TOKEN_LENGTH};
 use crate::Ruma;
@@ -229,6 +228,11 @@ pub(crate) async fn register_route(
 		return Err(Error::BadRequest(ErrorKind::Exclusive, "User ID reserved by appservice."));
 	}
 
+	// Reject weak passwords before the client goes through the UIAA flow
+	if let Some(password) = body.password.as_deref().filter(|_| !is_guest) {
+		services.users.check_password_policy(&user_id, password)?;
+	}
+
 	// UIAA
 	let mut uiaainfo;
 	let skip_auth = if services.globals.registration_token.is_some() {
@@ -288,40 +292,7 @@ pub(crate) async fn register_route(
 
 	let password = if is_guest { None } else { body.password.as_deref() };
 
-	// Create user
-	services.users.create(&user_id, password)?;
-
-	// Default to pretty displayname
-	let mut displayname = user_id.localpart().to_owned();
-
-	// If `new_user_displayname_suffix` is set, registration will push whatever
-	// content is set to the user's display name with a space before it
-	if !services.globals.new_user_displayname_suffix().is_empty()
-		&& body.appservice_info.is_none()
-	{
-		write!(displayname, " {}", services.server.config.new_user_displayname_suffix)
-			.expect("should be able to write to string buffer");
-	}
-
-	services
-		.users
-		.set_displayname(&user_id, Some(displayname.clone()));
-
-	// Initial account data
-	services
-		.account_data
-		.update(
-			None,
-			&user_id,
-			GlobalAccountDataEventType::PushRules.to_string().into(),
-			&serde_json::to_value(ruma::events::push_rules::PushRulesEvent {
-				content: ruma::events::push_rules::PushRulesEventContent {
-					global: push::Ruleset::server_default(&user_id),
-				},
-			})
-			.expect("to json always works"),
-		)
-		.await?;
+	create_account(services, &user_id, password, body.appservice_info.is_none()).await?;
 
 	// Inhibit login does not work for guests
 	if !is_guest && body.inhibit_login {
@@ -347,7 +318,7 @@ pub(crate) async fn register_route(
 		.create_device(
 			&user_id,
 			&device_id,
-			&token,
+			Some(&token),
 			body.initial_device_display_name.clone(),
 			Some(client.to_string()),
 		)
@@ -516,12 +487,11 @@ pub(crate) async fn change_password_route(
 	InsecureClientIp(client): InsecureClientIp,
 	body: Ruma<change_password::v3::Request>,
 ) -> Result<change_password::v3::Response> {
-	// Authentication for this endpoint was made optional, but we need
-	// authentication currently
-	let sender_user = body
-		.sender_user
-		.as_ref()
-		.ok_or_else(|| Error::BadRequest(ErrorKind::MissingToken, "Missing access token."))?;
+	// Without an access token the user has to prove ownership of an email address
+	// associated with their account instead
+	let Some(sender_user) = body.sender_user.as_ref() else {
+		return reset_password_via_email(&services, &body).await;
+	};
 	let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
 	let mut uiaainfo = UiaaInfo {
@@ -554,6 +524,10 @@ pub(crate) async fn change_password_route(
 		return Err(Error::BadRequest(ErrorKind::NotJson, "Not json."));
 	}
 
+	services
+		.users
+		.check_password_policy(sender_user, &body.new_password)?;
+
 	services
 		.users
 		.set_password(sender_user, Some(&body.new_password))?;
@@ -583,6 +557,70 @@ pub(crate) async fn change_password_route(
 	Ok(change_password::v3::Response {})
 }
 
+/// Resets the password of the account an email address validated through
+/// `/account/password/email/requestToken` belongs to.
+async fn reset_password_via_email(
+	services: &Services,
+	body: &Ruma<change_password::v3::Request>,
+) -> Result<change_password::v3::Response> {
+	let Some(AuthData::EmailIdentity(EmailIdentity { thirdparty_id_creds, .. })) = &body.auth
+	else {
+		return Err(Error::Uiaa(UiaaInfo {
+			flows: vec![AuthFlow { stages: vec![AuthType::EmailIdentity] }],
+			completed: Vec::new(),
+			params: Box::default(),
+			session: None,
+			auth_error: None,
+		}));
+	};
+
+	let (medium, address) = services
+		.threepid
+		.validated(
+			&thirdparty_id_creds.sid,
+			&thirdparty_id_creds.client_secret,
+			Purpose::PasswordReset,
+		)
+		.await?;
+
+	let user_id = services.threepid.find_user(&medium, &address).await?;
+
+	services
+		.users
+		.check_password_policy(&user_id, &body.new_password)?;
+
+	services
+		.users
+		.set_password(&user_id, Some(&body.new_password))?;
+
+	services
+		.threepid
+		.consume_session(&thirdparty_id_creds.sid)
+		.await;
+
+	if body.logout_devices {
+		services
+			.users
+			.all_device_ids(&user_id)
+			.for_each(|id| services.users.remove_device(&user_id, id))
+			.await;
+	}
+
+	info!("User {user_id} reset their password by email.");
+
+	if services.server.config.admin_room_notices {
+		services
+			.admin
+			.send_message(RoomMessageEventContent::notice_plain(format!(
+				"User {user_id} reset their password by email."
+			)))
+			.await
+			.ok();
+	}
+
+	Ok(change_password::v3::Response {})
+}
+
 /// # `GET _matrix/client/r0/account/whoami`
 ///
 /// Get `user_id` of the sender user.
@@ -688,51 +726,6 @@ pub(crate) async fn deactivate_route(
 	})
 }
 
-/// # `GET _matrix/client/v3/account/3pid`
-///
-/// Get a list of third party identifiers associated with this account.
-///
-/// - Currently always returns empty list
-pub(crate) async fn third_party_route(
-	body: Ruma<get_3pids::v3::Request>,
-) -> Result<get_3pids::v3::Response> {
-	let _sender_user = body.sender_user.as_ref().expect("user is authenticated");
-
-	Ok(get_3pids::v3::Response::new(Vec::new()))
-}
-
-/// # `POST /_matrix/client/v3/account/3pid/email/requestToken`
-///
-/// "This API should be used to request validation tokens when adding an email
-/// address to an account"
-///
-/// - 403 signals that The homeserver does not allow the third party identifier
-///   as a contact option.
-pub(crate) async fn request_3pid_management_token_via_email_route(
-	_body: Ruma<request_3pid_management_token_via_email::v3::Request>,
-) -> Result<request_3pid_management_token_via_email::v3::Response> {
-	Err(Error::BadRequest(
-		ErrorKind::ThreepidDenied,
-		"Third party identifier is not allowed",
-	))
-}
-
-/// # `POST /_matrix/client/v3/account/3pid/msisdn/requestToken`
-///
-/// "This API should be used to request validation tokens when adding an phone
-/// number to an account"
-///
-/// - 403 signals that The homeserver does not allow the third party identifier
-///   as a contact option.
-pub(crate) async fn request_3pid_management_token_via_msisdn_route(
-	_body: Ruma<request_3pid_management_token_via_msisdn::v3::Request>,
-) -> Result<request_3pid_management_token_via_msisdn::v3::Response> {
-	Err(Error::BadRequest(
-		ErrorKind::ThreepidDenied,
-		"Third party identifier is not allowed",
-	))
-}
-
 /// # `GET /_matrix/client/v1/register/m.login.registration_token/validity`
 ///
 /// Checks if the provided registration token is valid at the time of checking
@@ -753,6 +746,47 @@ pub(crate) async fn check_registration_token_validity(
 	Ok(check_registration_token_validity::v1::Response { valid: reg_token == body.token })
 }
 
+/// Creates the account of a new local user with their default display name and
+/// push rules. The configured display name suffix is only appended when
+/// `displayname_suffix` is set, as appservices name their users themselves.
+pub(crate) async fn create_account(
+	services: &Services,
+	user_id: &UserId,
+	password: Option<&str>,
+	displayname_suffix: bool,
+) -> Result {
+	// Create user
+	services.users.create(user_id, password)?;
+
+	// Default to pretty displayname
+	let mut displayname = user_id.localpart().to_owned();
+
+	// If `new_user_displayname_suffix` is set, registration will push whatever
+	// content is set to the user's display name with a space before it
+	if !services.globals.new_user_displayname_suffix().is_empty() && displayname_suffix {
+		write!(displayname, " {}", services.server.config.new_user_displayname_suffix)
+			.expect("should be able to write to string buffer");
+	}
+
+	services.users.set_displayname(user_id, Some(displayname));
+
+	// Initial account data
+	services
+		.account_data
+		.update(
+			None,
+			user_id,
+			GlobalAccountDataEventType::PushRules.to_string().into(),
+			&serde_json::to_value(ruma::events::push_rules::PushRulesEvent {
+				content: ruma::events::push_rules::PushRulesEventContent {
+					global: push::Ruleset::server_default(user_id),
+				},
+			})
+			.expect("to json always works"),
+		)
+		.await
+}
+
 /// Runs through all the deactivation steps:
 ///
 /// - Mark as deactivated
@@ -766,6 +800,7 @@ pub async fn full_user_deactivate(
 	all_joined_rooms: &[OwnedRoomId],
 ) -> Result<()> {
 	services.users.deactivate_account(user_id).await.ok();
+	services.threepid.delete_all(user_id).await;
 	super::update_displayname(services, user_id, None, all_joined_rooms).await;
 	super::update_avatar_url(services, user_id, None, None, all_joined_rooms).await;
 
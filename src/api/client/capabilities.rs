@@ -11,6 +11,7 @@
 };
 use serde_json::json;
 
+use super::password_policy_rules;
 use crate::Ruma;
 
 /// # `GET /_matrix/client/v3/capabilities`
@@ -30,8 +31,9 @@ pub(crate) async fn get_capabilities_route(
 		available,
 	};
 
-	// we do not implement 3PID stuff
-	capabilities.thirdparty_id_changes = ThirdPartyIdChangesCapability { enabled: false };
+	// 3PIDs can only be changed when email is enabled
+	capabilities.thirdparty_id_changes =
+		ThirdPartyIdChangesCapability { enabled: services.mailer.is_enabled() };
 
 	capabilities.get_login_token = GetLoginTokenCapability {
 		enabled: services.server.config.login_via_existing_session,
@@ -42,5 +44,15 @@ pub(crate) async fn get_capabilities_route(
 		.set("uk.tcpip.msc4133.profile_fields", json!({"enabled": true}))
 		.expect("this is valid JSON we created");
 
+	// MSC2000 password policy
+	if services.server.config.password_policy.enable {
+		capabilities
+			.set(
+				"m.password_policy",
+				password_policy_rules(&services.server.config.password_policy),
+			)
+			.expect("this is valid JSON we created");
+	}
+
 	Ok(get_capabilities::v3::Response { capabilities })
 }
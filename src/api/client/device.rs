@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use axum::extract::State;
 use axum_client_ip::InsecureClientIp;
 use conduwuit::{err, Err};
@@ -8,8 +10,9 @@
 		error::ErrorKind,
 		uiaa::{AuthFlow, AuthType, UiaaInfo},
 	},
-	MilliSecondsSinceUnixEpoch,
+	DeviceId, MilliSecondsSinceUnixEpoch, OwnedDeviceId,
 };
+use service::appservice::RegistrationInfo;
 
 use super::SESSION_ID_LENGTH;
 use crate::{utils, Error, Result, Ruma};
@@ -53,6 +56,9 @@ pub(crate) async fn get_device_route(
 /// # `PUT /_matrix/client/r0/devices/{deviceId}`
 ///
 /// Updates the metadata on a given device of the sender user.
+///
+/// - Appservices managing devices (MSC4190) create the device if it does not
+///   exist
 #[tracing::instrument(skip_all, fields(%client), name = "update_device")]
 pub(crate) async fn update_device_route(
 	State(services): State<crate::State>,
@@ -61,11 +67,22 @@ pub(crate) async fn update_device_route(
 ) -> Result<update_device::v3::Response> {
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
-	let mut device = services
+	let Ok(mut device) = services
 		.users
 		.get_device_metadata(sender_user, &body.device_id)
 		.await
-		.map_err(|_| err!(Request(NotFound("Device not found."))))?;
+	else {
+		if !manages_devices(&body.appservice_info) {
+			return Err!(Request(NotFound("Device not found.")));
+		}
+
+		services
+			.users
+			.create_device(sender_user, &body.device_id, None, body.display_name.clone(), None)
+			.await?;
+
+		return Ok(update_device::v3::Response {});
+	};
 
 	device.display_name.clone_from(&body.display_name);
 	device.last_seen_ip.clone_from(&Some(client.to_string()));
@@ -85,7 +102,9 @@ pub(crate) async fn update_device_route(
 ///
 /// Deletes the given device.
 ///
-/// - Requires UIAA to verify user password
+/// - Requires UIAA to verify user password, unless sent by an appservice
+///   managing devices (MSC4190)
+/// - Fails with `M_NOT_FOUND` if the device does not exist
 /// - Invalidates access token
 /// - Deletes device metadata (device id, device display name, last seen ip,
 ///   last seen ts)
@@ -96,6 +115,25 @@ pub(crate) async fn delete_device_route(
 	body: Ruma<delete_device::v3::Request>,
 ) -> Result<delete_device::v3::Response> {
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+	if services
+		.users
+		.get_device_metadata(sender_user, &body.device_id)
+		.await
+		.is_err()
+	{
+		return Err!(Request(NotFound("Device not found.")));
+	}
+
+	if manages_devices(&body.appservice_info) {
+		services
+			.users
+			.remove_device(sender_user, &body.device_id)
+			.await;
+
+		return Ok(delete_device::v3::Response {});
+	}
+
 	let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
 	// UIAA
@@ -140,7 +178,9 @@ pub(crate) async fn delete_device_route(
 ///
 /// Deletes the given device.
 ///
-/// - Requires UIAA to verify user password
+/// - Requires UIAA to verify user password, unless sent by an appservice
+///   managing devices (MSC4190)
+/// - Fails with `M_NOT_FOUND` if any of the devices does not exist
 ///
 /// For each device:
 /// - Invalidates access token
@@ -153,6 +193,26 @@ pub(crate) async fn delete_devices_route(
 	body: Ruma<delete_devices::v3::Request>,
 ) -> Result<delete_devices::v3::Response> {
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+	let existing: HashSet<OwnedDeviceId> = services
+		.users
+		.all_device_ids(sender_user)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	if let Some(device_id) = missing_device(&body.devices, &existing) {
+		return Err!(Request(NotFound("Device {device_id} not found.")));
+	}
+
+	if manages_devices(&body.appservice_info) {
+		for device_id in &body.devices {
+			services.users.remove_device(sender_user, device_id).await;
+		}
+
+		return Ok(delete_devices::v3::Response {});
+	}
+
 	let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
 	// UIAA
@@ -191,3 +251,44 @@ pub(crate) async fn delete_devices_route(
 
 	Ok(delete_devices::v3::Response {})
 }
+
+/// Returns the first of the requested devices which does not exist.
+fn missing_device<'a>(
+	requested: &'a [OwnedDeviceId],
+	existing: &HashSet<OwnedDeviceId>,
+) -> Option<&'a DeviceId> {
+	requested
+		.iter()
+		.find(|device_id| !existing.contains(*device_id))
+		.map(AsRef::as_ref)
+}
+
+fn manages_devices(appservice_info: &Option<RegistrationInfo>) -> bool {
+	appservice_info
+		.as_ref()
+		.is_some_and(RegistrationInfo::manages_devices)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+
+	use ruma::{owned_device_id, OwnedDeviceId};
+
+	#[test]
+	fn missing_devices() {
+		let existing: HashSet<OwnedDeviceId> =
+			[owned_device_id!("PHONE"), owned_device_id!("LAPTOP")].into();
+
+		let requested = [owned_device_id!("PHONE"), owned_device_id!("LAPTOP")];
+		assert_eq!(super::missing_device(&requested, &existing), None);
+		assert_eq!(super::missing_device(&[], &existing), None);
+
+		let requested = [owned_device_id!("PHONE"), owned_device_id!("TABLET")];
+		assert_eq!(
+			super::missing_device(&requested, &existing).map(AsRef::as_ref),
+			Some("TABLET"),
+			"unknown device was not reported"
+		);
+	}
+}
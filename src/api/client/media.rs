@@ -1,16 +1,35 @@
-use std::time::Duration;
+use std::{
+	io,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
 
-use axum::extract::State;
+use axum::{body::Body, extract::State, response::Response};
 use axum_client_ip::InsecureClientIp;
 use conduwuit::{
 	err,
-	utils::{self, content_disposition::make_content_disposition, math::ruma_from_usize},
+	utils::{
+		self,
+		content_disposition::make_content_disposition,
+		math::{ruma_from_usize, usize_from_u64_truncated},
+	},
 	Err, Result,
 };
 use conduwuit_service::{
-	media::{Dim, FileMeta, CACHE_CONTROL_IMMUTABLE, CORP_CROSS_ORIGIN, MXC_LENGTH},
+	media::{
+		ByteRange, Dim, FileMeta, FileStream, MediaStream, CACHE_CONTROL_IMMUTABLE,
+		CORP_CROSS_ORIGIN, MXC_LENGTH,
+	},
 	Services,
 };
+use futures::{stream, StreamExt, TryStreamExt};
+use http::{
+	header::{
+		HeaderName, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH,
+		CONTENT_RANGE, CONTENT_TYPE, RANGE,
+	},
+	HeaderMap, StatusCode,
+};
 use reqwest::Url;
 use ruma::{
 	api::client::{
@@ -23,7 +42,7 @@
 	Mxc, UserId,
 };
 
-use crate::Ruma;
+use crate::{Ruma, RumaResponse, Upload};
 
 /// # `GET /_matrix/client/v1/media/config`
 pub(crate) async fn get_media_config_route(
@@ -40,7 +59,7 @@ pub(crate) async fn get_media_config_route(
 /// Permanently save media in the server.
 ///
 /// - Some metadata will be saved in the database
-/// - Media will be saved in the media/ directory
+/// - Media will be streamed into the media storage as it is received
 #[tracing::instrument(
 	name = "media_upload",
 	level = "debug",
@@ -50,9 +69,9 @@ pub(crate) async fn get_media_config_route(
 pub(crate) async fn create_content_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
-	body: Ruma<create_content::v3::Request>,
-) -> Result<create_content::v3::Response> {
-	let user = body.sender_user.as_ref().expect("user is authenticated");
+	body: Upload<create_content::v3::Request>,
+) -> Result<RumaResponse<create_content::v3::Response>> {
+	let Upload { body, sender_user, len, file } = body;
 
 	let filename = body.filename.as_deref();
 	let content_type = body.content_type.as_deref();
@@ -62,23 +81,41 @@ pub(crate) async fn create_content_route(
 		media_id: &utils::random_string(MXC_LENGTH),
 	};
 
+	// The blurhash needs the whole image, which is kept as it is uploaded unless
+	// it is too large to be blurhashed anyway.
+	let limit = services.server.config.blurhashing.blurhash_max_raw_size;
+	let kept = Arc::new(Mutex::new(None));
+	let file = if body.generate_blurhash && limit > 0 {
+		keep_stream(file, usize_from_u64_truncated(limit), kept.clone())
+	} else {
+		file
+	};
+
 	services
 		.media
-		.create(mxc, Some(user), Some(&content_disposition), content_type, &body.file)
+		.create_stream(
+			mxc,
+			Some(&sender_user),
+			Some(&content_disposition),
+			content_type,
+			len,
+			file,
+		)
 		.await?;
 
-	let blurhash = body.generate_blurhash.then(|| {
+	let kept = kept.lock().expect("locked").take();
+	let blurhash = kept.and_then(|file| {
 		services
 			.media
-			.create_blurhash(&body.file, content_type, filename)
+			.create_blurhash(&file, content_type, filename)
 			.ok()
 			.flatten()
 	});
 
-	Ok(create_content::v3::Response {
+	Ok(RumaResponse(create_content::v3::Response {
 		content_uri: mxc.to_string().into(),
-		blurhash: blurhash.flatten(),
-	})
+		blurhash,
+	}))
 }
 
 /// # `GET /_matrix/client/v1/media/thumbnail/{serverName}/{mediaId}`
@@ -97,7 +134,7 @@ pub(crate) async fn get_content_thumbnail_route(
 ) -> Result<get_content_thumbnail::v1::Response> {
 	let user = body.sender_user.as_ref().expect("user is authenticated");
 
-	let dim = Dim::from_ruma(body.width, body.height, body.method.clone())?;
+	let dim = Dim::from_ruma(body.width, body.height, body.method.clone(), body.animated)?;
 	let mxc = Mxc {
 		server_name: &body.server_name,
 		media_id: &body.media_id,
@@ -121,6 +158,11 @@ pub(crate) async fn get_content_thumbnail_route(
 /// # `GET /_matrix/client/v1/media/download/{serverName}/{mediaId}`
 ///
 /// Load media from our server or over federation.
+///
+/// - The file is streamed rather than loaded into memory
+/// - A single byte range may be requested with the `Range` header
+/// - Local media may be requested with the signature of a signed URL rather
+///   than an access token, see `media_redirect`
 #[tracing::instrument(
 	name = "media_get",
 	level = "debug",
@@ -130,33 +172,31 @@ pub(crate) async fn get_content_thumbnail_route(
 pub(crate) async fn get_content_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	headers: HeaderMap,
 	body: Ruma<get_content::v1::Request>,
-) -> Result<get_content::v1::Response> {
-	let user = body.sender_user.as_ref().expect("user is authenticated");
+) -> Result<Response> {
+	// None for signed URLs
+	let user = body.sender_user.as_deref();
 
 	let mxc = Mxc {
 		server_name: &body.server_name,
 		media_id: &body.media_id,
 	};
 
-	let FileMeta {
-		content,
-		content_type,
-		content_disposition,
-	} = fetch_file(&services, &mxc, user, body.timeout_ms, None).await?;
+	let range = headers.get(RANGE).and_then(|range| range.to_str().ok());
+	let file = fetch_file(&services, &mxc, user, body.timeout_ms, range, None).await?;
 
-	Ok(get_content::v1::Response {
-		file: content.expect("entire file contents"),
-		content_type: content_type.map(Into::into),
-		cross_origin_resource_policy: Some(CORP_CROSS_ORIGIN.into()),
-		cache_control: Some(CACHE_CONTROL_IMMUTABLE.into()),
-		content_disposition,
-	})
+	file_response(file)
 }
 
 /// # `GET /_matrix/client/v1/media/download/{serverName}/{mediaId}/{fileName}`
 ///
 /// Load media from our server or over federation as fileName.
+///
+/// - The file is streamed rather than loaded into memory
+/// - A single byte range may be requested with the `Range` header
+/// - Local media may be requested with the signature of a signed URL rather
+///   than an access token, see `media_redirect`
 #[tracing::instrument(
 	name = "media_get_af",
 	level = "debug",
@@ -166,28 +206,22 @@ pub(crate) async fn get_content_route(
 pub(crate) async fn get_content_as_filename_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	headers: HeaderMap,
 	body: Ruma<get_content_as_filename::v1::Request>,
-) -> Result<get_content_as_filename::v1::Response> {
-	let user = body.sender_user.as_ref().expect("user is authenticated");
+) -> Result<Response> {
+	// None for signed URLs
+	let user = body.sender_user.as_deref();
 
 	let mxc = Mxc {
 		server_name: &body.server_name,
 		media_id: &body.media_id,
 	};
 
-	let FileMeta {
-		content,
-		content_type,
-		content_disposition,
-	} = fetch_file(&services, &mxc, user, body.timeout_ms, Some(&body.filename)).await?;
+	let range = headers.get(RANGE).and_then(|range| range.to_str().ok());
+	let file =
+		fetch_file(&services, &mxc, user, body.timeout_ms, range, Some(&body.filename)).await?;
 
-	Ok(get_content_as_filename::v1::Response {
-		file: content.expect("entire file contents"),
-		content_type: content_type.map(Into::into),
-		cross_origin_resource_policy: Some(CORP_CROSS_ORIGIN.into()),
-		cache_control: Some(CACHE_CONTROL_IMMUTABLE.into()),
-		content_disposition,
-	})
+	file_response(file)
 }
 
 /// # `GET /_matrix/client/v1/media/preview_url`
@@ -267,27 +301,20 @@ async fn fetch_thumbnail(
 async fn fetch_file(
 	services: &Services,
 	mxc: &Mxc<'_>,
-	user: &UserId,
+	user: Option<&UserId>,
 	timeout_ms: Duration,
+	range: Option<&str>,
 	filename: Option<&str>,
-) -> Result<FileMeta> {
-	let FileMeta {
-		content,
-		content_type,
-		content_disposition,
-	} = fetch_file_meta(services, mxc, user, timeout_ms).await?;
+) -> Result<FileStream> {
+	let mut file = fetch_file_stream(services, mxc, user, timeout_ms, range).await?;
 
-	let content_disposition = Some(make_content_disposition(
-		content_disposition.as_ref(),
-		content_type.as_deref(),
+	file.content_disposition = Some(make_content_disposition(
+		file.content_disposition.as_ref(),
+		file.content_type.as_deref(),
 		filename,
 	));
 
-	Ok(FileMeta {
-		content,
-		content_type,
-		content_disposition,
-	})
+	Ok(file)
 }
 
 async fn fetch_thumbnail_meta(
@@ -311,22 +338,98 @@ async fn fetch_thumbnail_meta(
 		.await
 }
 
-async fn fetch_file_meta(
+async fn fetch_file_stream(
 	services: &Services,
 	mxc: &Mxc<'_>,
-	user: &UserId,
+	user: Option<&UserId>,
 	timeout_ms: Duration,
-) -> Result<FileMeta> {
-	if let Some(filemeta) = services.media.get(mxc).await? {
-		return Ok(filemeta);
+	range: Option<&str>,
+) -> Result<FileStream> {
+	if let Some(file) = services.media.get_stream(mxc, range).await? {
+		return Ok(file);
 	}
 
 	if services.globals.server_is_ours(mxc.server_name) {
 		return Err!(Request(NotFound("Local media not found.")));
 	}
 
-	services
+	let FileMeta {
+		content,
+		content_type,
+		content_disposition,
+	} = services
 		.media
-		.fetch_remote_content(mxc, Some(user), None, timeout_ms)
-		.await
+		.fetch_remote_content(mxc, user, None, timeout_ms)
+		.await?;
+
+	// Remote media is cached as it is fetched, so ranges are served from there.
+	if let Some(file) = services.media.get_stream(mxc, range).await? {
+		return Ok(file);
+	}
+
+	let content = content.unwrap_or_default();
+	Ok(FileStream {
+		len: utils::math::try_into(content.len())?,
+		content: stream::once(async move { Ok(content.into()) }).boxed(),
+		content_type,
+		content_disposition,
+		range: None,
+	})
+}
+
+/// Copies an upload into `kept` as it passes, giving up on the copy once it
+/// grows past `limit` bytes.
+fn keep_stream(
+	file: MediaStream,
+	limit: usize,
+	kept: Arc<Mutex<Option<Vec<u8>>>>,
+) -> MediaStream {
+	*kept.lock().expect("locked") = Some(Vec::new());
+	file.inspect_ok(move |chunk| {
+		let mut kept = kept.lock().expect("locked");
+		match kept.as_mut() {
+			| Some(file) if file.len().saturating_add(chunk.len()) <= limit =>
+				file.extend_from_slice(chunk),
+			| _ => *kept = None,
+		}
+	})
+	.boxed()
+}
+
+/// Responds with a file as it is read from the media storage, or with the
+/// part of it which was requested.
+pub(super) fn file_response(file: FileStream) -> Result<Response> {
+	let FileStream {
+		content,
+		content_type,
+		content_disposition,
+		len,
+		range,
+	} = file;
+
+	let mut response = Response::builder()
+		.header(ACCEPT_RANGES, "bytes")
+		.header(CACHE_CONTROL, CACHE_CONTROL_IMMUTABLE)
+		.header(HeaderName::from_static("cross-origin-resource-policy"), CORP_CROSS_ORIGIN);
+
+	if let Some(content_type) = content_type {
+		response = response.header(CONTENT_TYPE, content_type);
+	}
+
+	if let Some(content_disposition) = content_disposition {
+		response = response.header(CONTENT_DISPOSITION, content_disposition.to_string());
+	}
+
+	response = match range {
+		| Some(ByteRange { start, end }) => response
+			.status(StatusCode::PARTIAL_CONTENT)
+			.header(CONTENT_RANGE, format!("bytes {start}-{}/{len}", end.saturating_sub(1)))
+			.header(CONTENT_LENGTH, end.saturating_sub(start)),
+		| None => response.status(StatusCode::OK).header(CONTENT_LENGTH, len),
+	};
+
+	let body = content.map_err(|e| io::Error::other(e.to_string()));
+	response
+		.body(Body::from_stream(body))
+		.map_err(|e| err!(error!("Failed to build media response: {e}")))
 }
@@ -1,13 +1,20 @@
 #![allow(deprecated)]
 
-use axum::extract::State;
+use std::time::Duration;
+
+use axum::{extract::State, response::Response};
 use axum_client_ip::InsecureClientIp;
 use conduwuit::{
 	err,
-	utils::{content_disposition::make_content_disposition, math::ruma_from_usize},
+	utils::{self, content_disposition::make_content_disposition, math::ruma_from_usize},
 	Err, Result,
 };
-use conduwuit_service::media::{Dim, FileMeta, CACHE_CONTROL_IMMUTABLE, CORP_CROSS_ORIGIN};
+use conduwuit_service::{
+	media::{Dim, FileMeta, FileStream, CACHE_CONTROL_IMMUTABLE, CORP_CROSS_ORIGIN},
+	Services,
+};
+use futures::{stream, StreamExt};
+use http::{header::RANGE, HeaderMap};
 use reqwest::Url;
 use ruma::{
 	api::client::media::{
@@ -17,7 +24,8 @@
 	Mxc,
 };
 
-use crate::{client::create_content_route, Ruma, RumaResponse};
+use super::media::file_response;
+use crate::{client::create_content_route, Ruma, RumaResponse, Upload};
 
 /// # `GET /_matrix/media/v3/config`
 ///
@@ -116,11 +124,9 @@ pub(crate) async fn get_media_preview_legacy_legacy_route(
 pub(crate) async fn create_content_legacy_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
-	body: Ruma<create_content::v3::Request>,
+	body: Upload<create_content::v3::Request>,
 ) -> Result<RumaResponse<create_content::v3::Response>> {
-	create_content_route(State(services), InsecureClientIp(client), body)
-		.await
-		.map(RumaResponse)
+	create_content_route(State(services), InsecureClientIp(client), body).await
 }
 
 /// # `GET /_matrix/media/v3/download/{serverName}/{mediaId}`
@@ -135,54 +141,27 @@ pub(crate) async fn create_content_legacy_route(
 pub(crate) async fn get_content_legacy_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	headers: HeaderMap,
 	body: Ruma<get_content::v3::Request>,
-) -> Result<get_content::v3::Response> {
+) -> Result<Response> {
 	let mxc = Mxc {
 		server_name: &body.server_name,
 		media_id: &body.media_id,
 	};
 
-	if let Some(FileMeta {
-		content,
-		content_type,
-		content_disposition,
-	}) = services.media.get(&mxc).await?
-	{
-		let content_disposition =
-			make_content_disposition(content_disposition.as_ref(), content_type.as_deref(), None);
+	let range = headers.get(RANGE).and_then(|range| range.to_str().ok());
+	let file = fetch_file_legacy(
+		&services,
+		&mxc,
+		range,
+		None,
+		body.allow_remote,
+		body.allow_redirect,
+		body.timeout_ms,
+	)
+	.await?;
 
-		Ok(get_content::v3::Response {
-			file: content.expect("entire file contents"),
-			content_type: content_type.map(Into::into),
-			content_disposition: Some(content_disposition),
-			cross_origin_resource_policy: Some(CORP_CROSS_ORIGIN.into()),
-			cache_control: Some(CACHE_CONTROL_IMMUTABLE.into()),
-		})
-	} else if !services.globals.server_is_ours(&body.server_name) && body.allow_remote {
-		let response = services
-			.media
-			.fetch_remote_content_legacy(&mxc, body.allow_redirect, body.timeout_ms)
-			.await
-			.map_err(|e| {
-				err!(Request(NotFound(debug_warn!(%mxc, "Fetching media failed: {e:?}"))))
-			})?;
-
-		let content_disposition = make_content_disposition(
-			response.content_disposition.as_ref(),
-			response.content_type.as_deref(),
-			None,
-		);
-
-		Ok(get_content::v3::Response {
-			file: response.file,
-			content_type: response.content_type,
-			content_disposition: Some(content_disposition),
-			cross_origin_resource_policy: Some(CORP_CROSS_ORIGIN.into()),
-			cache_control: Some(CACHE_CONTROL_IMMUTABLE.into()),
-		})
-	} else {
-		Err!(Request(NotFound("Media not found.")))
-	}
+	file_response(file)
 }
 
 /// # `GET /_matrix/media/v1/download/{serverName}/{mediaId}`
@@ -201,11 +180,10 @@ pub(crate) async fn get_content_legacy_route(
 pub(crate) async fn get_content_legacy_legacy_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	headers: HeaderMap,
 	body: Ruma<get_content::v3::Request>,
-) -> Result<RumaResponse<get_content::v3::Response>> {
-	get_content_legacy_route(State(services), InsecureClientIp(client), body)
-		.await
-		.map(RumaResponse)
+) -> Result<Response> {
+	get_content_legacy_route(State(services), InsecureClientIp(client), headers, body).await
 }
 
 /// # `GET /_matrix/media/v3/download/{serverName}/{mediaId}/{fileName}`
@@ -220,57 +198,27 @@ pub(crate) async fn get_content_legacy_legacy_route(
 pub(crate) async fn get_content_as_filename_legacy_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	headers: HeaderMap,
 	body: Ruma<get_content_as_filename::v3::Request>,
-) -> Result<get_content_as_filename::v3::Response> {
+) -> Result<Response> {
 	let mxc = Mxc {
 		server_name: &body.server_name,
 		media_id: &body.media_id,
 	};
 
-	if let Some(FileMeta {
-		content,
-		content_type,
-		content_disposition,
-	}) = services.media.get(&mxc).await?
-	{
-		let content_disposition = make_content_disposition(
-			content_disposition.as_ref(),
-			content_type.as_deref(),
-			Some(&body.filename),
-		);
-
-		Ok(get_content_as_filename::v3::Response {
-			file: content.expect("entire file contents"),
-			content_type: content_type.map(Into::into),
-			content_disposition: Some(content_disposition),
-			cross_origin_resource_policy: Some(CORP_CROSS_ORIGIN.into()),
-			cache_control: Some(CACHE_CONTROL_IMMUTABLE.into()),
-		})
-	} else if !services.globals.server_is_ours(&body.server_name) && body.allow_remote {
-		let response = services
-			.media
-			.fetch_remote_content_legacy(&mxc, body.allow_redirect, body.timeout_ms)
-			.await
-			.map_err(|e| {
-				err!(Request(NotFound(debug_warn!(%mxc, "Fetching media failed: {e:?}"))))
-			})?;
-
-		let content_disposition = make_content_disposition(
-			response.content_disposition.as_ref(),
-			response.content_type.as_deref(),
-			None,
-		);
+	let range = headers.get(RANGE).and_then(|range| range.to_str().ok());
+	let file = fetch_file_legacy(
+		&services,
+		&mxc,
+		range,
+		Some(&body.filename),
+		body.allow_remote,
+		body.allow_redirect,
+		body.timeout_ms,
+	)
+	.await?;
 
-		Ok(get_content_as_filename::v3::Response {
-			content_disposition: Some(content_disposition),
-			content_type: response.content_type,
-			file: response.file,
-			cross_origin_resource_policy: Some(CORP_CROSS_ORIGIN.into()),
-			cache_control: Some(CACHE_CONTROL_IMMUTABLE.into()),
-		})
-	} else {
-		Err!(Request(NotFound("Media not found.")))
-	}
+	file_response(file)
 }
 
 /// # `GET /_matrix/media/v1/download/{serverName}/{mediaId}/{fileName}`
@@ -288,11 +236,11 @@ pub(crate) async fn get_content_as_filename_legacy_route(
 pub(crate) async fn get_content_as_filename_legacy_legacy_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	headers: HeaderMap,
 	body: Ruma<get_content_as_filename::v3::Request>,
-) -> Result<RumaResponse<get_content_as_filename::v3::Response>> {
-	get_content_as_filename_legacy_route(State(services), InsecureClientIp(client), body)
+) -> Result<Response> {
+	get_content_as_filename_legacy_route(State(services), InsecureClientIp(client), headers, body)
 		.await
-		.map(RumaResponse)
 }
 
 /// # `GET /_matrix/media/v3/thumbnail/{serverName}/{mediaId}`
@@ -314,7 +262,7 @@ pub(crate) async fn get_content_thumbnail_legacy_route(
 		media_id: &body.media_id,
 	};
 
-	let dim = Dim::from_ruma(body.width, body.height, body.method.clone())?;
+	let dim = Dim::from_ruma(body.width, body.height, body.method.clone(), body.animated)?;
 	if let Some(FileMeta {
 		content,
 		content_type,
@@ -379,3 +327,49 @@ pub(crate) async fn get_content_thumbnail_legacy_legacy_route(
 		.await
 		.map(RumaResponse)
 }
+
+/// Loads media from our server, or over federation when `allow_remote` is
+/// set, as it is read from the media storage.
+async fn fetch_file_legacy(
+	services: &Services,
+	mxc: &Mxc<'_>,
+	range: Option<&str>,
+	filename: Option<&str>,
+	allow_remote: bool,
+	allow_redirect: bool,
+	timeout_ms: Duration,
+) -> Result<FileStream> {
+	let mut file = match services.media.get_stream(mxc, range).await? {
+		| Some(file) => file,
+		| None if !services.globals.server_is_ours(mxc.server_name) && allow_remote => {
+			let response = services
+				.media
+				.fetch_remote_content_legacy(mxc, allow_redirect, timeout_ms)
+				.await
+				.map_err(|e| {
+					err!(Request(NotFound(debug_warn!(%mxc, "Fetching media failed: {e:?}"))))
+				})?;
+
+			// Remote media is cached as it is fetched, so ranges are served from there.
+			match services.media.get_stream(mxc, range).await? {
+				| Some(file) => file,
+				| None => FileStream {
+					len: utils::math::try_into(response.file.len())?,
+					content: stream::once(async move { Ok(response.file.into()) }).boxed(),
+					content_type: response.content_type,
+					content_disposition: response.content_disposition,
+					range: None,
+				},
+			}
+		},
+		| None => return Err!(Request(NotFound("Media not found."))),
+	};
+
+	file.content_disposition = Some(make_content_disposition(
+		file.content_disposition.as_ref(),
+		file.content_type.as_deref(),
+		filename,
+	));
+
+	Ok(file)
+}
@@ -870,6 +870,12 @@ pub async fn join_room_by_id_helper(
 		|| servers.is_empty()
 		|| (servers.len() == 1 && services.globals.server_is_ours(&servers[0]));
 
+	// Local joins of shadow-banned users are dropped by the timeline.
+	if !local_join && services.users.is_shadow_banned(sender_user).await {
+		debug_warn!("Dropping remote join of shadow-banned user {sender_user} to {room_id}");
+		return Ok(join_room_by_id::v3::Response { room_id: room_id.into() });
+	}
+
 	if local_join {
 		join_room_by_id_helper_local(
 			services,
@@ -1552,6 +1558,13 @@ pub(crate) async fn invite_helper(
 		return Err!(Request(Forbidden("Invites are not allowed on this server.")));
 	}
 
+	if services.users.is_shadow_banned(sender_user).await {
+		debug_warn!(
+			"Dropping invite of {user_id} to {room_id} by shadow-banned user {sender_user}"
+		);
+		return Ok(());
+	}
+
 	if !services.globals.user_is_local(user_id) {
 		let (pdu, pdu_json, invite_room_state) = {
 			let state_lock = services.rooms.state.mutex.lock(room_id).await;
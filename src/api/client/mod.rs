@@ -14,6 +14,7 @@
 pub(super) mod membership;
 pub(super) mod message;
 pub(super) mod openid;
+pub(super) mod password_policy;
 pub(super) mod presence;
 pub(super) mod profile;
 pub(super) mod push;
@@ -31,6 +32,7 @@
 pub(super) mod tag;
 pub(super) mod thirdparty;
 pub(super) mod threads;
+pub(super) mod threepid;
 pub(super) mod to_device;
 pub(super) mod typing;
 pub(super) mod unstable;
@@ -57,6 +59,7 @@
 pub use membership::{join_room_by_id_helper, leave_all_rooms, leave_room};
 pub(super) use message::*;
 pub(super) use openid::*;
+pub(super) use password_policy::*;
 pub(super) use presence::*;
 pub(super) use profile::*;
 pub use profile::{update_all_rooms, update_avatar_url, update_displayname};
@@ -75,6 +78,7 @@
 pub(super) use tag::*;
 pub(super) use thirdparty::*;
 pub(super) use threads::*;
+pub(super) use threepid::*;
 pub(super) use to_device::*;
 pub(super) use typing::*;
 pub(super) use unstable::*;
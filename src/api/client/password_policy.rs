@@ -0,0 +1,28 @@
+use axum::{extract::State, response::IntoResponse, Json};
+use conduwuit::{config::PasswordPolicyConfig, Result};
+use serde_json::{json, Value};
+
+/// # `GET /_matrix/client/v3/password_policy`
+///
+/// Returns the password policy new passwords must satisfy, as described in
+/// MSC2000. Rules which are not enforced are omitted.
+pub(crate) async fn get_password_policy_route(
+	State(services): State<crate::State>,
+) -> Result<impl IntoResponse> {
+	Ok(Json(password_policy_rules(&services.server.config.password_policy)))
+}
+
+/// Describes the configured password policy using the MSC2000 rule names.
+pub(crate) fn password_policy_rules(policy: &PasswordPolicyConfig) -> Value {
+	if !policy.enable {
+		return json!({});
+	}
+
+	json!({
+		"m.minimum_length": policy.minimum_length,
+		"m.require_digit": policy.require_digit,
+		"m.require_symbol": policy.require_symbol,
+		"m.require_lowercase": policy.require_lowercase,
+		"m.require_uppercase": policy.require_uppercase,
+	})
+}
@@ -1,10 +1,18 @@
-use axum::extract::State;
-use conduwuit::{err, Err};
+use axum::{
+	extract::{RawQuery, State},
+	response::IntoResponse,
+};
+use conduwuit::{
+	err,
+	utils::{result::FlatOk, ReadyExt},
+	Err,
+};
+use futures::{FutureExt, StreamExt};
 use ruma::{
 	api::client::{
 		error::ErrorKind,
 		push::{
-			delete_pushrule, get_pushers, get_pushrule, get_pushrule_actions,
+			delete_pushrule, get_notifications, get_pushers, get_pushrule, get_pushrule_actions,
 			get_pushrule_enabled, get_pushrules_all, get_pushrules_global_scope, set_pusher,
 			set_pushrule, set_pushrule_actions, set_pushrule_enabled,
 		},
@@ -17,8 +25,9 @@
 		InsertPushRuleError, PredefinedContentRuleId, PredefinedOverrideRuleId,
 		RemovePushRuleError, Ruleset,
 	},
-	CanonicalJsonObject, CanonicalJsonValue,
+	CanonicalJsonObject, CanonicalJsonValue, MilliSecondsSinceUnixEpoch, OwnedUserId,
 };
+use serde::Deserialize;
 use service::Services;
 
 use crate::{Error, Result, Ruma};
@@ -509,6 +518,110 @@ pub(crate) async fn set_pushers_route(
 	Ok(set_pusher::v3::Response::new())
 }
 
+/// # `GET /_matrix/client/v3/notifications`
+///
+/// Lists the events that notified the sender user, newest first.
+///
+/// - `only=highlight` lists highlights only
+/// - Notifications older than the configured retention are gone
+pub(crate) async fn get_notifications_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_notifications::v3::Request>,
+) -> Result<get_notifications::v3::Response> {
+	let sender_user = body.sender_user();
+
+	let from: Option<u64> = body
+		.from
+		.as_deref()
+		.map(str::parse)
+		.transpose()
+		.map_err(|_| err!(Request(InvalidParam("Invalid from token."))))?;
+
+	// Use limit or else 30, with maximum 100
+	let limit: usize = body
+		.limit
+		.map(TryInto::try_into)
+		.flat_ok()
+		.unwrap_or(30)
+		.min(100);
+
+	let only_highlight = body.only.as_deref() == Some("highlight");
+
+	let notifications: Vec<_> = services
+		.pusher
+		.notifications(sender_user, from)
+		.ready_filter(|(_, record)| !only_highlight || record.is_highlight())
+		.filter_map(|(count, record)| async move {
+			let pdu = services
+				.rooms
+				.timeline
+				.get_pdu(&record.event_id)
+				.await
+				.ok()?;
+
+			let read = services
+				.rooms
+				.user
+				.last_notification_read(sender_user, &record.room_id)
+				.await > count;
+
+			let notification = get_notifications::v3::Notification {
+				actions: record.actions,
+				event: pdu.to_sync_room_event(),
+				profile_tag: None,
+				read,
+				room_id: record.room_id,
+				ts: MilliSecondsSinceUnixEpoch(record.ts.try_into().unwrap_or_default()),
+			};
+
+			Some((count, notification))
+		})
+		.take(limit)
+		.collect()
+		.boxed()
+		.await;
+
+	let next_token = (notifications.len() == limit)
+		.then(|| notifications.last())
+		.flatten()
+		.map(|(count, _)| count.to_string());
+
+	Ok(get_notifications::v3::Response {
+		next_token,
+		notifications: notifications
+			.into_iter()
+			.map(|(_, notification)| notification)
+			.collect(),
+	})
+}
+
+#[derive(Deserialize)]
+struct Unsubscribe {
+	user_id: OwnedUserId,
+	pushkey: String,
+	token: String,
+}
+
+/// # `GET /_conduwuit/push/email/unsubscribe`
+///
+/// Target of the unsubscribe link in notification digest emails. Removes the
+/// email pusher.
+pub(crate) async fn unsubscribe_email_route(
+	State(services): State<crate::State>,
+	RawQuery(query): RawQuery,
+) -> Result<impl IntoResponse> {
+	let query = query.unwrap_or_default();
+	let body: Unsubscribe = serde_html_form::from_str(&query)
+		.map_err(|e| err!(Request(InvalidParam("Invalid unsubscribe link: {e}"))))?;
+
+	services
+		.pusher
+		.unsubscribe_email(&body.user_id, &body.pushkey, &body.token)
+		.await?;
+
+	Ok("You will no longer receive notification emails at this address.")
+}
+
 /// user somehow has bad push rules, these must always exist per spec.
 /// so recreate it and return server default silently
 async fn recreate_push_rules_and_return(
@@ -46,7 +46,8 @@ pub(crate) async fn set_read_marker_route(
 		services
 			.rooms
 			.user
-			.reset_notification_counts(sender_user, &body.room_id);
+			.reset_notification_counts(sender_user, &body.room_id)
+			.await;
 	}
 
 	// ping presence
@@ -119,10 +120,18 @@ pub(crate) async fn create_receipt_route(
 		&body.receipt_type,
 		create_receipt::v3::ReceiptType::Read | create_receipt::v3::ReceiptType::ReadPrivate
 	) {
-		services
-			.rooms
-			.user
-			.reset_notification_counts(sender_user, &body.room_id);
+		let user = &services.rooms.user;
+		match &body.thread {
+			| ReceiptThread::Main =>
+				user.reset_main_notification_counts(sender_user, &body.room_id)
+					.await,
+			| ReceiptThread::Thread(thread_root) =>
+				user.reset_thread_notification_counts(sender_user, &body.room_id, thread_root)
+					.await,
+			| _ =>
+				user.reset_notification_counts(sender_user, &body.room_id)
+					.await,
+		}
 	}
 
 	// ping presence
@@ -159,7 +168,7 @@ pub(crate) async fn create_receipt_route(
 						sender_user.to_owned(),
 						ruma::events::receipt::Receipt {
 							ts: Some(MilliSecondsSinceUnixEpoch::now()),
-							thread: ReceiptThread::Unthreaded,
+							thread: body.thread.clone(),
 						},
 					)]),
 				)]),
@@ -58,12 +58,18 @@ pub(crate) async fn report_room_route(
 		)));
 	}
 
+	let report_id =
+		services
+			.reports
+			.report_room(sender_user, &body.room_id, body.reason.clone())?;
+
 	// send admin room message that we received the report with an @room ping for
 	// urgency
 	services
 		.admin
 		.send_message(message::RoomMessageEventContent::text_markdown(format!(
-			"@room Room report received from {} -\n\nRoom ID: {}\n\nReport Reason: {}",
+			"@room Room report {report_id} received from {} -\n\nRoom ID: {}\n\nReport Reason: \
+			 {}",
 			sender_user.to_owned(),
 			body.room_id,
 			body.reason.as_deref().unwrap_or("")
@@ -112,13 +118,18 @@ pub(crate) async fn report_event_route(
 	)
 	.await?;
 
+	let report_id =
+		services
+			.reports
+			.report_event(sender_user, &pdu, body.score, body.reason.clone())?;
+
 	// send admin room message that we received the report with an @room ping for
 	// urgency
 	services
 		.admin
 		.send_message(message::RoomMessageEventContent::text_markdown(format!(
-			"@room Event report received from {} -\n\nEvent ID: {}\nRoom ID: {}\nSent By: \
-			 {}\n\nReport Score: {}\nReport Reason: {}",
+			"@room Event report {report_id} received from {} -\n\nEvent ID: {}\nRoom ID: \
+			 {}\nSent By: {}\n\nReport Score: {}\nReport Reason: {}",
 			sender_user.to_owned(),
 			pdu.event_id,
 			pdu.room_id,
@@ -0,0 +1,240 @@
+use conduwuit::{config::JwtConfig, err, info, utils, Err, Result};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use ruma::{CanonicalJsonValue, OwnedUserId, UserId};
+use serde::Deserialize;
+use service::Services;
+
+use crate::client::create_account;
+
+/// Login type identifier, matching Synapse's JWT login.
+pub(super) const LOGIN_TYPE: &str = "org.matrix.login.jwt";
+
+/// Length of the unusable random password given to auto-registered users.
+const RANDOM_PASSWORD_LENGTH: usize = 32;
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+	/// Localpart or full user ID of the user logging in.
+	sub: String,
+}
+
+/// Returns the token from the request body when it is a JWT login.
+pub(super) fn token(json_body: Option<&CanonicalJsonValue>) -> Option<&str> {
+	let CanonicalJsonValue::Object(body) = json_body? else {
+		return None;
+	};
+
+	let Some(CanonicalJsonValue::String(login_type)) = body.get("type") else {
+		return None;
+	};
+
+	if login_type != LOGIN_TYPE {
+		return None;
+	}
+
+	match body.get("token") {
+		| Some(CanonicalJsonValue::String(token)) => Some(token),
+		| _ => None,
+	}
+}
+
+/// Validates the token against the configured key and claims, returning the
+/// local user it was issued for. Unknown users are registered if enabled.
+pub(super) async fn handle_login(services: &Services, token: &str) -> Result<OwnedUserId> {
+	let config = &services.server.config.jwt;
+	if !config.enable {
+		return Err!(Request(Unknown("JWT login is not enabled.")));
+	}
+
+	let claims = decode(config, token)?;
+
+	let user_id =
+		UserId::parse_with_server_name(claims.sub.to_lowercase(), services.globals.server_name())
+			.ok()
+			.filter(|user_id| services.globals.user_is_local(user_id))
+			.ok_or_else(|| {
+				err!(Request(InvalidUsername("JWT subject is not a valid local user.")))
+			})?;
+
+	if !services.users.exists(&user_id).await {
+		if !config.register_user {
+			return Err!(Request(Forbidden("User {user_id} is not registered on this server.")));
+		}
+
+		register(services, &user_id).await?;
+	}
+
+	if services.users.is_deactivated(&user_id).await? {
+		return Err!(Request(UserDeactivated("The user has been deactivated")));
+	}
+
+	Ok(user_id)
+}
+
+/// Checks the signature and the claims of the token.
+fn decode(config: &JwtConfig, token: &str) -> Result<Claims> {
+	let key = decoding_key(config)?;
+	let validation = validation(config)?;
+	jsonwebtoken::decode::<Claims>(token, &key, &validation)
+		.map(|data| data.claims)
+		.map_err(|e| err!(Request(Forbidden(debug_warn!("Invalid JWT: {e}")))))
+}
+
+fn validation(config: &JwtConfig) -> Result<Validation> {
+	let algorithm: Algorithm = config
+		.algorithm
+		.parse()
+		.map_err(|e| err!(Config("jwt.algorithm", "Unsupported JWT algorithm: {e}")))?;
+
+	let mut validation = Validation::new(algorithm);
+	validation.leeway = config.leeway;
+	validation.validate_exp = config.validate_exp;
+	validation.validate_nbf = config.validate_nbf;
+	validation.validate_aud = !config.audience.is_empty();
+
+	let mut required = vec!["sub"];
+	if config.validate_exp {
+		required.push("exp");
+	}
+
+	if !config.issuer.is_empty() {
+		validation.set_issuer(&config.issuer);
+		required.push("iss");
+	}
+
+	if !config.audience.is_empty() {
+		validation.set_audience(&config.audience);
+		required.push("aud");
+	}
+
+	validation.set_required_spec_claims(&required);
+
+	Ok(validation)
+}
+
+fn decoding_key(config: &JwtConfig) -> Result<DecodingKey> {
+	let key = config.key.as_bytes();
+	let algorithm = config.algorithm.as_str();
+	let decoding_key = match algorithm {
+		| "HS256" | "HS384" | "HS512" => return Ok(DecodingKey::from_secret(key)),
+		| "RS256" | "RS384" | "RS512" | "PS256" | "PS384" | "PS512" =>
+			DecodingKey::from_rsa_pem(key),
+		| "ES256" | "ES384" => DecodingKey::from_ec_pem(key),
+		| "EdDSA" => DecodingKey::from_ed_pem(key),
+		| _ => return Err!(Config("jwt.algorithm", "Unsupported JWT algorithm: {algorithm}")),
+	};
+
+	decoding_key.map_err(|e| err!(Config("jwt.key", "Invalid JWT validation key: {e}")))
+}
+
+async fn register(services: &Services, user_id: &UserId) -> Result {
+	if services
+		.globals
+		.forbidden_usernames()
+		.is_match(user_id.localpart())
+	{
+		return Err!(Request(Forbidden("Username is forbidden.")));
+	}
+
+	if services.appservice.is_exclusive_user_id(user_id).await {
+		return Err!(Request(Exclusive("User ID reserved by appservice.")));
+	}
+
+	// The issuer vouches for the user, so they are given an unusable password and
+	// can only log in through the token flow.
+	let password = utils::random_string(RANDOM_PASSWORD_LENGTH);
+	create_account(services, user_id, Some(&password), true).await?;
+
+	info!("New user \"{user_id}\" registered on this server via JWT login.");
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use conduwuit::config::JwtConfig;
+	use jsonwebtoken::{EncodingKey, Header};
+	use serde_json::{json, Value};
+
+	const KEY: &str = "shared secret";
+
+	fn config() -> JwtConfig {
+		JwtConfig {
+			enable: true,
+			key: KEY.to_owned(),
+			algorithm: "HS256".to_owned(),
+			issuer: vec!["https://issuer.example".to_owned()],
+			audience: vec!["pqchat".to_owned()],
+			validate_exp: true,
+			validate_nbf: true,
+			..Default::default()
+		}
+	}
+
+	fn sign(claims: &Value, key: &str) -> String {
+		jsonwebtoken::encode(
+			&Header::default(),
+			claims,
+			&EncodingKey::from_secret(key.as_bytes()),
+		)
+		.expect("claims are encodable")
+	}
+
+	fn claims() -> Value {
+		json!({
+			"sub": "alice",
+			"iss": "https://issuer.example",
+			"aud": "pqchat",
+			"exp": jsonwebtoken::get_current_timestamp().saturating_add(300),
+		})
+	}
+
+	#[test]
+	fn valid_token() {
+		let claims = super::decode(&config(), &sign(&claims(), KEY)).expect("valid token");
+		assert_eq!(claims.sub, "alice", "subject is taken from the token");
+	}
+
+	#[test]
+	fn bad_signature() {
+		let token = sign(&claims(), "other secret");
+		assert!(super::decode(&config(), &token).is_err(), "token signed with another key");
+	}
+
+	#[test]
+	fn expired() {
+		let mut claims = claims();
+		claims["exp"] = json!(jsonwebtoken::get_current_timestamp().saturating_sub(3600));
+		assert!(super::decode(&config(), &sign(&claims, KEY)).is_err(), "expired token");
+
+		claims.as_object_mut().expect("object").remove("exp");
+		assert!(super::decode(&config(), &sign(&claims, KEY)).is_err(), "token without exp");
+	}
+
+	#[test]
+	fn wrong_issuer() {
+		let mut claims = claims();
+		claims["iss"] = json!("https://elsewhere.example");
+		assert!(super::decode(&config(), &sign(&claims, KEY)).is_err(), "foreign issuer");
+
+		claims.as_object_mut().expect("object").remove("iss");
+		assert!(super::decode(&config(), &sign(&claims, KEY)).is_err(), "token without iss");
+	}
+
+	#[test]
+	fn wrong_audience() {
+		let mut claims = claims();
+		claims["aud"] = json!("someone-else");
+		assert!(super::decode(&config(), &sign(&claims, KEY)).is_err(), "foreign audience");
+
+		claims.as_object_mut().expect("object").remove("aud");
+		assert!(super::decode(&config(), &sign(&claims, KEY)).is_err(), "token without aud");
+	}
+
+	#[test]
+	fn missing_subject() {
+		let mut claims = claims();
+		claims.as_object_mut().expect("object").remove("sub");
+		assert!(super::decode(&config(), &sign(&claims, KEY)).is_err(), "token without sub");
+	}
+}
@@ -1,8 +1,10 @@
+mod jwt;
+
 use std::time::Duration;
 
 use axum::extract::State;
 use axum_client_ip::InsecureClientIp;
-use conduwuit::{debug, err, info, utils::ReadyExt, warn, Err};
+use conduwuit::{debug, info, utils::ReadyExt, warn, Err};
 use futures::StreamExt;
 use ruma::{
 	api::client::{
@@ -21,6 +23,7 @@
 		},
 		uiaa,
 	},
+	serde::JsonObject,
 	OwnedUserId, UserId,
 };
 use service::uiaa::SESSION_ID_LENGTH;
@@ -38,13 +41,19 @@ pub(crate) async fn get_login_types_route(
 	InsecureClientIp(client): InsecureClientIp,
 	_body: Ruma<get_login_types::v3::Request>,
 ) -> Result<get_login_types::v3::Response> {
-	Ok(get_login_types::v3::Response::new(vec![
+	let mut flows = vec![
 		get_login_types::v3::LoginType::Password(PasswordLoginType::default()),
 		get_login_types::v3::LoginType::ApplicationService(ApplicationServiceLoginType::default()),
 		get_login_types::v3::LoginType::Token(TokenLoginType {
 			get_login_token: services.server.config.login_via_existing_session,
 		}),
-	]))
+	];
+
+	if services.server.config.jwt.enable {
+		flows.push(get_login_types::v3::LoginType::new(jwt::LOGIN_TYPE, JsonObject::new())?);
+	}
+
+	Ok(get_login_types::v3::Response::new(flows))
 }
 
 /// # `POST /_matrix/client/v3/login`
@@ -53,7 +62,7 @@ pub(crate) async fn get_login_types_route(
 /// requests.
 ///
 /// - The user needs to authenticate using their password (or if enabled using a
-///   json web token)
+///   json web token, see `org.matrix.login.jwt`)
 /// - If `device_id` is known: invalidates old access token of that device
 /// - If `device_id` is unknown: creates a new device
 /// - Returns access token that is associated with the user and device
@@ -93,20 +102,24 @@ pub(crate) async fn login_route(
 			}
 			.map_err(|_| Error::BadRequest(ErrorKind::InvalidUsername, "Username is invalid."))?;
 
-			let hash = services
-				.users
-				.password_hash(&user_id)
-				.await
-				.map_err(|_| err!(Request(Forbidden("Wrong username or password."))))?;
+			services.lockout.check(&user_id, Some(client))?;
+
+			let Ok(hash) = services.users.password_hash(&user_id).await else {
+				services.lockout.failed_address(client);
+				return Err!(Request(Forbidden("Wrong username or password.")));
+			};
 
 			if hash.is_empty() {
 				return Err!(Request(UserDeactivated("The user has been deactivated")));
 			}
 
 			if hash::verify_password(password, &hash).is_err() {
+				services.lockout.failed(&user_id, Some(client)).await;
 				return Err!(Request(Forbidden("Wrong username or password.")));
 			}
 
+			services.lockout.succeeded(&user_id);
+
 			user_id
 		},
 		| login::v3::LoginInfo::Token(login::v3::Token { token }) => {
@@ -156,12 +169,17 @@ pub(crate) async fn login_route(
 			user_id
 		},
 		| _ => {
-			warn!("Unsupported or unknown login type: {:?}", &body.login_info);
-			debug!("JSON body: {:?}", &body.json_body);
-			return Err(Error::BadRequest(
-				ErrorKind::Unknown,
-				"Unsupported or unknown login type.",
-			));
+			let Some(token) = jwt::token(body.json_body.as_ref()) else {
+				warn!("Unsupported or unknown login type: {:?}", &body.login_info);
+				debug!("JSON body: {:?}", &body.json_body);
+				return Err(Error::BadRequest(
+					ErrorKind::Unknown,
+					"Unsupported or unknown login type.",
+				));
+			};
+
+			debug!("Got JWT login type");
+			jwt::handle_login(&services, token).await?
 		},
 	};
 
@@ -196,7 +214,7 @@ pub(crate) async fn login_route(
 			.create_device(
 				&user_id,
 				&device_id,
-				&token,
+				Some(&token),
 				body.initial_device_display_name.clone(),
 				Some(client.to_string()),
 			)
@@ -779,6 +779,16 @@ async fn load_joined_room(
 		})
 		.into();
 
+	let thread_counts: OptionFuture<_> = (send_notification_counts
+		&& filter.room.timeline.unread_thread_notifications)
+		.then(|| {
+			services
+				.rooms
+				.user
+				.thread_notification_counts(sender_user, room_id)
+		})
+		.into();
+
 	let typing_events = services
 		.rooms
 		.typing
@@ -798,7 +808,7 @@ async fn load_joined_room(
 		})
 		.unwrap_or(Vec::new());
 
-	let unread_notifications = join(notification_count, highlight_count);
+	let unread_notifications = join3(notification_count, highlight_count, thread_counts);
 	let events = join3(room_events, account_data_events, typing_events);
 	let (unread_notifications, events, device_updates) =
 		join3(unread_notifications, events, device_updates)
@@ -806,7 +816,21 @@ async fn load_joined_room(
 			.await;
 
 	let (room_events, account_data_events, typing_events) = events;
-	let (notification_count, highlight_count) = unread_notifications;
+	let (mut notification_count, mut highlight_count, thread_counts) = unread_notifications;
+
+	// Clients which asked for thread counts get the main timeline's in the room
+	// counts (MSC3773).
+	let mut unread_thread_notifications = BTreeMap::new();
+	for (thread_root, counts) in thread_counts.unwrap_or_default() {
+		let thread_notifications = ruma_from_u64(counts.notification_count);
+		let thread_highlights = ruma_from_u64(counts.highlight_count);
+		notification_count = notification_count.map(|n| n.saturating_sub(thread_notifications));
+		highlight_count = highlight_count.map(|n| n.saturating_sub(thread_highlights));
+		unread_thread_notifications.insert(thread_root, UnreadNotificationsCount {
+			notification_count: Some(thread_notifications),
+			highlight_count: Some(thread_highlights),
+		});
+	}
 
 	device_list_updates.extend(device_updates);
 
@@ -866,7 +890,7 @@ async fn load_joined_room(
 				.collect(),
 		},
 		ephemeral: Ephemeral { events: edus },
-		unread_thread_notifications: BTreeMap::new(),
+		unread_thread_notifications,
 	};
 
 	Ok((joined_room, device_list_updates, left_encrypted_users))
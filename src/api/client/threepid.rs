@@ -0,0 +1,279 @@
+use axum::{
+	extract::{RawQuery, State},
+	response::IntoResponse,
+	Json,
+};
+use axum_client_ip::InsecureClientIp;
+use conduwuit::{err, utils, Err, Error, Result};
+use futures::StreamExt;
+use ruma::{
+	api::client::{
+		account::{
+			add_3pid, bind_3pid, delete_3pid, get_3pids, request_3pid_management_token_via_email,
+			request_3pid_management_token_via_msisdn, request_password_change_token_via_email,
+			ThirdPartyIdRemovalStatus,
+		},
+		error::ErrorKind,
+		uiaa::{AuthFlow, AuthType, UiaaInfo},
+	},
+	thirdparty::Medium,
+	OwnedClientSecret, OwnedSessionId,
+};
+use serde::Deserialize;
+use service::threepid::Purpose;
+
+use super::SESSION_ID_LENGTH;
+use crate::Ruma;
+
+/// # `GET _matrix/client/v3/account/3pid`
+///
+/// Get a list of third party identifiers associated with this account.
+pub(crate) async fn third_party_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_3pids::v3::Request>,
+) -> Result<get_3pids::v3::Response> {
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+	let threepids = services.threepid.threepids(sender_user).collect().await;
+
+	Ok(get_3pids::v3::Response::new(threepids))
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/email/requestToken`
+///
+/// "This API should be used to request validation tokens when adding an email
+/// address to an account"
+///
+/// - 403 signals that The homeserver does not allow the third party identifier
+///   as a contact option.
+/// - 400 with `M_THREEPID_IN_USE` if the address belongs to another account.
+pub(crate) async fn request_3pid_management_token_via_email_route(
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	body: Ruma<request_3pid_management_token_via_email::v3::Request>,
+) -> Result<request_3pid_management_token_via_email::v3::Response> {
+	if services
+		.threepid
+		.find_user(&Medium::Email, &body.email)
+		.await
+		.is_ok()
+	{
+		return Err!(Request(ThreepidInUse("This email address is already in use.")));
+	}
+
+	let sid = services
+		.threepid
+		.request_email_validation(
+			&body.client_secret,
+			&body.email,
+			body.send_attempt,
+			Purpose::AddThreepid,
+			client,
+		)
+		.await?;
+
+	Ok(request_3pid_management_token_via_email::v3::Response {
+		sid,
+		submit_url: services.threepid.submit_url(),
+	})
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/msisdn/requestToken`
+///
+/// "This API should be used to request validation tokens when adding an phone
+/// number to an account"
+///
+/// - 403 signals that The homeserver does not allow the third party identifier
+///   as a contact option.
+pub(crate) async fn request_3pid_management_token_via_msisdn_route(
+	_body: Ruma<request_3pid_management_token_via_msisdn::v3::Request>,
+) -> Result<request_3pid_management_token_via_msisdn::v3::Response> {
+	Err(Error::BadRequest(
+		ErrorKind::ThreepidDenied,
+		"Third party identifier is not allowed",
+	))
+}
+
+/// # `POST /_matrix/client/v3/account/password/email/requestToken`
+///
+/// Sends a validation email which allows resetting the password of the
+/// account the address belongs to.
+///
+/// - 400 with `M_THREEPID_NOT_FOUND` if no account uses the address.
+pub(crate) async fn request_password_change_token_via_email_route(
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	body: Ruma<request_password_change_token_via_email::v3::Request>,
+) -> Result<request_password_change_token_via_email::v3::Response> {
+	services
+		.threepid
+		.find_user(&Medium::Email, &body.email)
+		.await?;
+
+	let sid = services
+		.threepid
+		.request_email_validation(
+			&body.client_secret,
+			&body.email,
+			body.send_attempt,
+			Purpose::PasswordReset,
+			client,
+		)
+		.await?;
+
+	Ok(request_password_change_token_via_email::v3::Response {
+		sid,
+		submit_url: services.threepid.submit_url(),
+	})
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/add`
+///
+/// Adds a third party identifier validated through this server to the
+/// account.
+///
+/// - Requires UIAA to verify user password
+pub(crate) async fn add_3pid_route(
+	State(services): State<crate::State>,
+	body: Ruma<add_3pid::v3::Request>,
+) -> Result<add_3pid::v3::Response> {
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+	let sender_device = body.sender_device.as_ref().expect("user is authenticated");
+
+	let mut uiaainfo = UiaaInfo {
+		flows: vec![AuthFlow { stages: vec![AuthType::Password] }],
+		completed: Vec::new(),
+		params: Box::default(),
+		session: None,
+		auth_error: None,
+	};
+
+	if let Some(auth) = &body.auth {
+		let (worked, uiaainfo) = services
+			.uiaa
+			.try_auth(sender_user, sender_device, auth, &uiaainfo)
+			.await?;
+
+		if !worked {
+			return Err(Error::Uiaa(uiaainfo));
+		}
+
+		// Success!
+	} else if let Some(json) = body.json_body {
+		uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
+		services
+			.uiaa
+			.create(sender_user, sender_device, &uiaainfo, &json);
+
+		return Err(Error::Uiaa(uiaainfo));
+	} else {
+		return Err(Error::BadRequest(ErrorKind::NotJson, "Not json."));
+	}
+
+	let (medium, address) = services
+		.threepid
+		.validated(&body.sid, &body.client_secret, Purpose::AddThreepid)
+		.await?;
+
+	services
+		.threepid
+		.add(sender_user, &medium, &address)
+		.await?;
+
+	services.threepid.consume_session(&body.sid).await;
+
+	Ok(add_3pid::v3::Response::new())
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/bind`
+///
+/// Asks an identity server to bind a third party identifier it validated to
+/// the account.
+pub(crate) async fn bind_3pid_route(
+	State(services): State<crate::State>,
+	body: Ruma<bind_3pid::v3::Request>,
+) -> Result<bind_3pid::v3::Response> {
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+	services
+		.threepid
+		.bind(
+			sender_user,
+			&body.id_server,
+			&body.id_access_token,
+			&body.sid,
+			&body.client_secret,
+		)
+		.await?;
+
+	Ok(bind_3pid::v3::Response::new())
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/delete`
+///
+/// Removes a third party identifier from the account.
+///
+/// - Unbinding from identity servers is not supported; if the identifier was
+///   bound through this server, `no-support` is returned.
+pub(crate) async fn delete_3pid_route(
+	State(services): State<crate::State>,
+	body: Ruma<delete_3pid::v3::Request>,
+) -> Result<delete_3pid::v3::Response> {
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+	let id_server = services
+		.threepid
+		.delete(sender_user, &body.medium, &body.address)
+		.await?;
+
+	let id_server_unbind_result = match id_server {
+		| Some(_) => ThirdPartyIdRemovalStatus::NoSupport,
+		| None => ThirdPartyIdRemovalStatus::Success,
+	};
+
+	Ok(delete_3pid::v3::Response { id_server_unbind_result })
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SubmitToken {
+	sid: OwnedSessionId,
+	client_secret: OwnedClientSecret,
+	token: String,
+}
+
+/// # `GET /_conduwuit/threepid/email/submit_token`
+///
+/// Target of the link in validation emails.
+pub(crate) async fn submit_email_token_link(
+	State(services): State<crate::State>,
+	RawQuery(query): RawQuery,
+) -> Result<impl IntoResponse> {
+	let query = query.unwrap_or_default();
+	let body: SubmitToken = serde_html_form::from_str(&query)
+		.map_err(|e| err!(Request(InvalidParam("Invalid validation link: {e}"))))?;
+
+	services
+		.threepid
+		.submit_token(&body.sid, &body.client_secret, &body.token)
+		.await?;
+
+	Ok("Your email address has been validated. You can now return to your client.")
+}
+
+/// # `POST /_conduwuit/threepid/email/submit_token`
+///
+/// Submits a validation token entered into a client, as advertised by the
+/// `submit_url` of the token request.
+pub(crate) async fn submit_email_token(
+	State(services): State<crate::State>,
+	Json(body): Json<SubmitToken>,
+) -> Result<impl IntoResponse> {
+	services
+		.threepid
+		.submit_token(&body.sid, &body.client_secret, &body.token)
+		.await?;
+
+	Ok(Json(serde_json::json!({
+		"success": true
+	})))
+}
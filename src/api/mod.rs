@@ -1,5 +1,6 @@
 #![allow(clippy::toplevel_ref_arg)]
 
+pub mod admin;
 pub mod client;
 pub mod router;
 pub mod server;
@@ -9,7 +10,7 @@
 
 pub(crate) use conduwuit::{debug_info, pdu::PduEvent, utils, Error, Result};
 
-pub(crate) use self::router::{Ruma, RumaResponse, State};
+pub(crate) use self::router::{Ruma, RumaResponse, State, Upload};
 
 conduwuit::mod_ctor! {}
 conduwuit::mod_dtor! {}
@@ -4,6 +4,7 @@
 mod request;
 mod response;
 pub mod state;
+mod upload;
 
 use std::str::FromStr;
 
@@ -14,10 +15,14 @@
 };
 use conduwuit::{err, Server};
 use http::{uri, Uri};
+use ruma::api::{
+	client::{authenticated_media, media::create_content},
+	federation,
+};
 
 use self::handler::RouterExt;
-pub(super) use self::{args::Args as Ruma, response::RumaResponse, state::State};
-use crate::{client, server};
+pub(super) use self::{args::Args as Ruma, response::RumaResponse, state::State, upload::Upload};
+use crate::{admin, client, server};
 
 pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 	let config = &server.config;
@@ -43,6 +48,12 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::third_party_route)
 		.ruma_route(&client::request_3pid_management_token_via_email_route)
 		.ruma_route(&client::request_3pid_management_token_via_msisdn_route)
+		.ruma_route(&client::request_password_change_token_via_email_route)
+		.ruma_route(&client::add_3pid_route)
+		.ruma_route(&client::bind_3pid_route)
+		.ruma_route(&client::delete_3pid_route)
+		.route("/_conduwuit/threepid/email/submit_token",
+			get(client::submit_email_token_link).post(client::submit_email_token))
 		.ruma_route(&client::check_registration_token_validity)
 		.ruma_route(&client::get_capabilities_route)
 		.ruma_route(&client::get_pushrules_all_route)
@@ -152,10 +163,14 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::search_events_route)
 		.ruma_route(&client::turn_server_route)
 		.ruma_route(&client::send_event_to_device_route)
-		.ruma_route(&client::create_content_route)
+		.ruma_raw_route::<create_content::v3::Request, _, _>(client::create_content_route)
 		.ruma_route(&client::get_content_thumbnail_route)
-		.ruma_route(&client::get_content_route)
-		.ruma_route(&client::get_content_as_filename_route)
+		.ruma_raw_route::<authenticated_media::get_content::v1::Request, _, _>(
+			client::get_content_route,
+		)
+		.ruma_raw_route::<authenticated_media::get_content_as_filename::v1::Request, _, _>(
+			client::get_content_as_filename_route,
+		)
 		.ruma_route(&client::get_media_preview_route)
 		.ruma_route(&client::get_media_config_route)
 		.ruma_route(&client::get_devices_route)
@@ -171,6 +186,8 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::get_key_changes_route)
 		.ruma_route(&client::get_pushers_route)
 		.ruma_route(&client::set_pushers_route)
+		.ruma_route(&client::get_notifications_route)
+		.route("/_conduwuit/push/email/unsubscribe", get(client::unsubscribe_email_route))
 		.ruma_route(&client::upgrade_room_route)
 		.ruma_route(&client::get_threads_route)
 		.ruma_route(&client::get_relating_events_with_rel_type_and_event_type_route)
@@ -186,8 +203,15 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::well_known_support)
 		.ruma_route(&client::well_known_client)
 		.route("/_conduwuit/server_version", get(client::conduwuit_server_version))
+		.route("/_matrix/client/v3/password_policy", get(client::get_password_policy_route))
+		.route("/_matrix/client/r0/password_policy", get(client::get_password_policy_route))
 		.ruma_route(&client::room_initial_sync_route)
-		.route("/client/server.json", get(client::syncv3_client_server_json));
+		.route("/client/server.json", get(client::syncv3_client_server_json))
+		.route("/_synapse/admin/v1/event_reports", get(admin::get_event_reports_route))
+		.route(
+			"/_synapse/admin/v1/event_reports/:report_id",
+			get(admin::get_event_report_route),
+		);
 
 	if config.allow_federation {
 		router = router
@@ -223,7 +247,9 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 			.ruma_route(&server::get_openid_userinfo_route)
 			.ruma_route(&server::get_hierarchy_route)
 			.ruma_route(&server::well_known_server)
-			.ruma_route(&server::get_content_route)
+			.ruma_raw_route::<federation::authenticated_media::get_content::v1::Request, _, _>(
+				server::get_content_route,
+			)
 			.ruma_route(&server::get_content_thumbnail_route)
 			.route("/_conduwuit/local_user_count", get(client::conduwuit_local_user_count));
 	} else {
@@ -235,11 +261,9 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 	}
 
 	if config.allow_legacy_media {
-		router = router
+		router = legacy_media_downloads(router)
 			.ruma_route(&client::get_media_config_legacy_route)
 			.ruma_route(&client::get_media_preview_legacy_route)
-			.ruma_route(&client::get_content_legacy_route)
-			.ruma_route(&client::get_content_as_filename_legacy_route)
 			.ruma_route(&client::get_content_thumbnail_legacy_route)
 			.route("/_matrix/media/v1/config", get(client::get_media_config_legacy_legacy_route))
 			.route("/_matrix/media/v1/upload", post(client::create_content_legacy_route))
@@ -275,6 +299,18 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 	router
 }
 
+/// Routes the legacy downloads, which stream their responses.
+#[allow(deprecated)]
+fn legacy_media_downloads(router: Router<State>) -> Router<State> {
+	use ruma::api::client::media::{get_content, get_content_as_filename};
+
+	router
+		.ruma_raw_route::<get_content::v3::Request, _, _>(client::get_content_legacy_route)
+		.ruma_raw_route::<get_content_as_filename::v3::Request, _, _>(
+			client::get_content_as_filename_legacy_route,
+		)
+}
+
 async fn redirect_legacy_preview(uri: Uri) -> impl IntoResponse {
 	let path = "/_matrix/client/v1/media/preview_url";
 	let query = uri.query().unwrap_or_default();
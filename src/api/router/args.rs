@@ -1,6 +1,7 @@
 use std::{mem, ops::Deref};
 
-use axum::{async_trait, body::Body, extract::FromRequest};
+use axum::{async_trait, body::Body, extract::FromRequest, RequestPartsExt};
+use axum_client_ip::InsecureClientIp;
 use bytes::{BufMut, Bytes, BytesMut};
 use conduwuit::{debug, debug_warn, err, trace, utils::string::EMPTY, Error, Result};
 use ruma::{
@@ -108,6 +109,10 @@ async fn from_request(
 			json_body = Some(CanonicalJsonValue::Object(CanonicalJsonObject::new()));
 		}
 		let auth = auth::auth(services, &mut request, json_body.as_ref(), &T::METADATA).await?;
+		if let (Some(user_id), Some(device_id)) = (&auth.sender_user, &auth.sender_device) {
+			update_last_seen(services, &mut request, user_id, device_id).await;
+		}
+
 		Ok(Self {
 			body: make_body::<T>(services, &mut request, json_body.as_mut(), &auth)?,
 			origin: auth.origin,
@@ -119,6 +124,31 @@ async fn from_request(
 	}
 }
 
+pub(super) async fn update_last_seen(
+	services: &Services,
+	request: &mut Request,
+	user_id: &UserId,
+	device_id: &DeviceId,
+) {
+	let client_ip = request
+		.parts
+		.extract::<InsecureClientIp>()
+		.await
+		.ok()
+		.map(|InsecureClientIp(ip)| ip.to_string());
+
+	let user_agent = request
+		.parts
+		.headers
+		.get(http::header::USER_AGENT)
+		.and_then(|user_agent| user_agent.to_str().ok());
+
+	services
+		.users
+		.update_device_last_seen(user_id, device_id, client_ip, user_agent)
+		.await;
+}
+
 fn make_body<T>(
 	services: &Services,
 	request: &mut Request,
@@ -134,7 +164,7 @@ fn make_body<T>(
 		.map_err(|e| err!(Request(BadJson(debug_warn!("{e}")))))
 }
 
-fn into_http_request(request: &Request, body: Bytes) -> hyper::Request<Bytes> {
+pub(super) fn into_http_request(request: &Request, body: Bytes) -> hyper::Request<Bytes> {
 	let mut http_request = hyper::Request::builder()
 		.uri(request.parts.uri.clone())
 		.method(request.parts.method.clone());
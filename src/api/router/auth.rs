@@ -10,6 +10,7 @@
 use ruma::{
 	api::{
 		client::{
+			authenticated_media::{get_content, get_content_as_filename},
 			directory::get_public_rooms,
 			error::ErrorKind,
 			profile::{
@@ -21,7 +22,8 @@
 		AuthScheme, IncomingRequest, Metadata,
 	},
 	server_util::authorization::XMatrix,
-	CanonicalJsonObject, CanonicalJsonValue, OwnedDeviceId, OwnedServerName, OwnedUserId, UserId,
+	CanonicalJsonObject, CanonicalJsonValue, Mxc, OwnedDeviceId, OwnedServerName, OwnedUserId,
+	ServerName, UserId,
 };
 use service::{
 	server_keys::{PubKeyMap, PubKeys},
@@ -140,6 +142,10 @@ pub(super) async fn auth(
 					Err(Error::BadRequest(ErrorKind::MissingToken, "Missing access token."))
 				}
 			},
+			| &get_content::v1::Request::METADATA
+			| &get_content_as_filename::v1::Request::METADATA
+				if request.query.signature.is_some() =>
+				auth_signed_media(services, request),
 			| _ => Err(Error::BadRequest(ErrorKind::MissingToken, "Missing access token.")),
 		},
 		| (
@@ -223,14 +229,57 @@ async fn auth_appservice(
 		return Err!(Request(Exclusive("User is not in namespace.")));
 	}
 
+	// Device masquerading (MSC3202)
+	let sender_device = request.query.device_id.as_deref().map(OwnedDeviceId::from);
+	if let Some(device_id) = &sender_device {
+		if services
+			.users
+			.get_device_metadata(&user_id, device_id)
+			.await
+			.is_err()
+		{
+			return Err!(Request(Forbidden("Device {device_id} does not exist for {user_id}.")));
+		}
+	}
+
 	Ok(Auth {
 		origin: None,
 		sender_user: Some(user_id),
-		sender_device: None,
+		sender_device,
 		appservice_info: Some(*info),
 	})
 }
 
+/// Authorizes a signed URL to download local media in place of an access
+/// token, see `media_redirect`.
+fn auth_signed_media(services: &Services, request: &Request) -> Result<Auth> {
+	let (Some(expires), Some(signature)) = (&request.query.expires, &request.query.signature)
+	else {
+		return Err!(Request(Forbidden("Signed media URL is incomplete.")));
+	};
+
+	let Ok(expires) = expires.parse() else {
+		return Err!(Request(Forbidden("Signed media URL has an invalid expiry.")));
+	};
+
+	let [server_name, media_id, ..] = request.path.as_slice() else {
+		return Err!(Request(Forbidden("Signed media URL has no MXC.")));
+	};
+
+	let server_name = ServerName::parse(server_name)?;
+	let mxc = Mxc { server_name: &server_name, media_id };
+	services
+		.media
+		.verify_media_signature(&mxc, expires, signature)?;
+
+	Ok(Auth {
+		origin: None,
+		sender_user: None,
+		sender_device: None,
+		appservice_info: None,
+	})
+}
+
 async fn auth_server(
 	services: &Services,
 	request: &mut Request,
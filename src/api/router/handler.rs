@@ -1,5 +1,6 @@
 use axum::{
 	extract::FromRequestParts,
+	handler::Handler,
 	response::IntoResponse,
 	routing::{on, MethodFilter},
 	Router,
@@ -20,6 +21,14 @@ pub(in super::super) trait RouterExt {
 	fn ruma_route<H, T>(self, handler: &'static H) -> Self
 	where
 		H: RumaHandler<T>;
+
+	/// Routes the paths of a Ruma request to a handler which builds its own
+	/// response, for bodies which are streamed rather than buffered.
+	fn ruma_raw_route<Req, H, T>(self, handler: H) -> Self
+	where
+		Req: IncomingRequest,
+		H: Handler<T, State>,
+		T: 'static;
 }
 
 impl RouterExt for Router<State> {
@@ -29,6 +38,19 @@ fn ruma_route<H, T>(self, handler: &'static H) -> Self
 	{
 		handler.add_routes(self)
 	}
+
+	fn ruma_raw_route<Req, H, T>(self, handler: H) -> Self
+	where
+		Req: IncomingRequest,
+		H: Handler<T, State>,
+		T: 'static,
+	{
+		let method = method_to_filter(&Req::METADATA.method);
+		Req::METADATA
+			.history
+			.all_paths()
+			.fold(self, |router, path| router.route(path, on(method, handler.clone())))
+	}
 }
 
 macro_rules! ruma_handler {
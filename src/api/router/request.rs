@@ -11,6 +11,12 @@
 pub(super) struct QueryParams {
 	pub(super) access_token: Option<String>,
 	pub(super) user_id: Option<String>,
+	#[serde(alias = "org.matrix.msc3202.device_id")]
+	pub(super) device_id: Option<String>,
+	/// Expiry of a signed media URL, see `media_redirect`.
+	pub(super) expires: Option<String>,
+	/// Signature of a signed media URL.
+	pub(super) signature: Option<String>,
 }
 
 pub(super) struct Request {
@@ -25,12 +31,7 @@ pub(super) async fn from(
 	request: hyper::Request<axum::body::Body>,
 ) -> Result<Request> {
 	let limited = request.with_limited_body();
-	let (mut parts, body) = limited.into_parts();
-
-	let path: Path<Vec<String>> = parts.extract().await?;
-	let query = parts.uri.query().unwrap_or_default();
-	let query = serde_html_form::from_str(query)
-		.map_err(|e| err!(Request(Unknown("Failed to read query parameters: {e}"))))?;
+	let (parts, body) = limited.into_parts();
 
 	let max_body_size = services.server.config.max_request_size;
 
@@ -38,5 +39,15 @@ pub(super) async fn from(
 		.await
 		.map_err(|e| err!(Request(TooLarge("Request body too large: {e}"))))?;
 
+	from_parts(parts, body).await
+}
+
+/// Reads the path and query of a request whose body is handled separately.
+pub(super) async fn from_parts(mut parts: Parts, body: Bytes) -> Result<Request> {
+	let path: Path<Vec<String>> = parts.extract().await?;
+	let query = parts.uri.query().unwrap_or_default();
+	let query = serde_html_form::from_str(query)
+		.map_err(|e| err!(Request(Unknown("Failed to read query parameters: {e}"))))?;
+
 	Ok(Request { path, query, body, parts })
 }
@@ -0,0 +1,106 @@
+use std::ops::Deref;
+
+use axum::{async_trait, body::Body, extract::FromRequest};
+use bytes::Bytes;
+use conduwuit::{debug_warn, err, utils, Err, Error, Result};
+use futures::StreamExt;
+use http::header::CONTENT_LENGTH;
+use ruma::{api::IncomingRequest, OwnedUserId};
+use service::media::MediaStream;
+
+use super::{
+	args::{into_http_request, update_last_seen},
+	auth, request,
+};
+use crate::State;
+
+/// Extractor for media uploads. Unlike [`Args`](super::Ruma) it does not
+/// buffer the request body; the file is streamed to the media storage as it
+/// arrives, and the Ruma request struct is parsed with an empty file.
+pub(crate) struct Upload<T> {
+	/// Request struct without the file
+	pub(crate) body: T,
+
+	/// Local user authentication: user_id.
+	pub(crate) sender_user: OwnedUserId,
+
+	/// Length of the file announced by the Content-Length header.
+	pub(crate) len: Option<u64>,
+
+	/// The file, refused once longer than `max_request_size`.
+	pub(crate) file: MediaStream,
+}
+
+impl<T> Deref for Upload<T>
+where
+	T: IncomingRequest + Send + Sync + 'static,
+{
+	type Target = T;
+
+	fn deref(&self) -> &Self::Target { &self.body }
+}
+
+#[async_trait]
+impl<T> FromRequest<State, Body> for Upload<T>
+where
+	T: IncomingRequest + Send + Sync + 'static,
+{
+	type Rejection = Error;
+
+	async fn from_request(
+		request: hyper::Request<Body>,
+		services: &State,
+	) -> Result<Self, Self::Rejection> {
+		let (parts, file) = request.into_parts();
+		let mut request = request::from_parts(parts, Bytes::new()).await?;
+		let auth = auth::auth(services, &mut request, None, &T::METADATA).await?;
+		let Some(sender_user) = auth.sender_user else {
+			return Err!(Request(MissingToken("Missing access token.")));
+		};
+
+		if let Some(device_id) = &auth.sender_device {
+			update_last_seen(services, &mut request, &sender_user, device_id).await;
+		}
+
+		let max_len: u64 = utils::math::try_into(services.server.config.max_request_size)?;
+		let len = request
+			.parts
+			.headers
+			.get(CONTENT_LENGTH)
+			.and_then(|len| len.to_str().ok()?.parse().ok());
+
+		if len.is_some_and(|len| len > max_len) {
+			return Err!(Request(TooLarge("Upload is larger than the maximum request size.")));
+		}
+
+		let http_request = into_http_request(&request, Bytes::new());
+		let body = T::try_from_http_request(http_request, &request.path)
+			.map_err(|e| err!(Request(BadJson(debug_warn!("{e}")))))?;
+
+		Ok(Self {
+			body,
+			sender_user,
+			len,
+			file: limit_body(file, max_len),
+		})
+	}
+}
+
+/// Streams a request body, refusing it once it is longer than `max_len`.
+fn limit_body(body: Body, max_len: u64) -> MediaStream {
+	let mut received: u64 = 0;
+	body.into_data_stream()
+		.map(move |chunk| {
+			let chunk =
+				chunk.map_err(|e| err!(Request(Unknown("Failed to receive upload: {e}"))))?;
+			received = received.saturating_add(utils::math::try_into(chunk.len())?);
+			if received > max_len {
+				return Err!(Request(TooLarge(
+					"Upload is larger than the maximum request size."
+				)));
+			}
+
+			Ok(chunk)
+		})
+		.boxed()
+}
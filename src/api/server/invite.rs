@@ -1,9 +1,14 @@
+use std::collections::BTreeMap;
+
 use axum::extract::State;
 use axum_client_ip::InsecureClientIp;
 use base64::{engine::general_purpose, Engine as _};
 use conduwuit::{err, utils, utils::hash::sha256, warn, Err, Error, PduEvent, Result};
 use ruma::{
-	api::{client::error::ErrorKind, federation::membership::create_invite},
+	api::{
+		appservice::event::push_events::v1::DeviceLists, client::error::ErrorKind,
+		federation::membership::create_invite,
+	},
 	events::room::member::{MembershipState, RoomMemberEventContent},
 	serde::JsonObject,
 	CanonicalJsonValue, OwnedUserId, UserId,
@@ -157,6 +162,9 @@ pub(crate) async fn create_invite_route(
 								.into(),
 							ephemeral: Vec::new(),
 							to_device: Vec::new(),
+							device_lists: DeviceLists::default(),
+							device_one_time_keys_count: BTreeMap::new(),
+							device_unused_fallback_key_types: BTreeMap::new(),
 						},
 					)
 					.await?;
@@ -1,7 +1,23 @@
-use axum::extract::State;
+use std::io;
+
+use axum::{
+	body::Body,
+	extract::State,
+	response::{IntoResponse, Response},
+};
 use axum_client_ip::InsecureClientIp;
-use conduwuit::{utils::content_disposition::make_content_disposition, Err, Result};
-use conduwuit_service::media::{Dim, FileMeta};
+use bytes::Bytes;
+use conduwuit::{
+	err,
+	utils::{self, content_disposition::make_content_disposition},
+	Err, Result,
+};
+use conduwuit_service::media::{Dim, FileMeta, FileStream};
+use futures::{future::ready, stream, StreamExt, TryStreamExt};
+use http::{
+	header::{CONTENT_LENGTH, CONTENT_TYPE},
+	StatusCode,
+};
 use ruma::{
 	api::federation::authenticated_media::{
 		get_content, get_content_thumbnail, Content, ContentMetadata, FileOrLocation,
@@ -9,11 +25,15 @@
 	Mxc,
 };
 
-use crate::Ruma;
+use crate::{Ruma, RumaResponse};
 
 /// # `GET /_matrix/federation/v1/media/download/{mediaId}`
 ///
 /// Load media from our server.
+///
+/// - The file is streamed rather than loaded into memory
+/// - With `media_redirect`, responds with the location of a short-lived signed
+///   URL rather than the file
 #[tracing::instrument(
 	name = "media_get",
 	level = "debug",
@@ -24,33 +44,26 @@ pub(crate) async fn get_content_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
 	body: Ruma<get_content::v1::Request>,
-) -> Result<get_content::v1::Response> {
+) -> Result<Response> {
 	let mxc = Mxc {
 		server_name: services.globals.server_name(),
 		media_id: &body.media_id,
 	};
 
-	let Some(FileMeta {
-		content,
-		content_type,
-		content_disposition,
-	}) = services.media.get(&mxc).await?
-	else {
-		return Err!(Request(NotFound("Media not found.")));
-	};
+	if let Some(location) = services.media.media_redirect(&mxc).await? {
+		let response = get_content::v1::Response {
+			content: FileOrLocation::Location(location.into()),
+			metadata: ContentMetadata::new(),
+		};
 
-	let content_disposition =
-		make_content_disposition(content_disposition.as_ref(), content_type.as_deref(), None);
-	let content = Content {
-		file: content.expect("entire file contents"),
-		content_type: content_type.map(Into::into),
-		content_disposition: Some(content_disposition),
+		return Ok(RumaResponse(response).into_response());
+	}
+
+	let Some(file) = services.media.get_stream(&mxc, None).await? else {
+		return Err!(Request(NotFound("Media not found.")));
 	};
 
-	Ok(get_content::v1::Response {
-		content: FileOrLocation::File(content),
-		metadata: ContentMetadata::new(),
-	})
+	multipart_response(file)
 }
 
 /// # `GET /_matrix/federation/v1/media/thumbnail/{mediaId}`
@@ -67,7 +80,7 @@ pub(crate) async fn get_content_thumbnail_route(
 	InsecureClientIp(client): InsecureClientIp,
 	body: Ruma<get_content_thumbnail::v1::Request>,
 ) -> Result<get_content_thumbnail::v1::Response> {
-	let dim = Dim::from_ruma(body.width, body.height, body.method.clone())?;
+	let dim = Dim::from_ruma(body.width, body.height, body.method.clone(), body.animated)?;
 	let mxc = Mxc {
 		server_name: services.globals.server_name(),
 		media_id: &body.media_id,
@@ -95,3 +108,44 @@ pub(crate) async fn get_content_thumbnail_route(
 		metadata: ContentMetadata::new(),
 	})
 }
+
+/// Responds with the metadata and the file of authenticated media as a
+/// `multipart/mixed` body, the file as it is read from the media storage.
+fn multipart_response(file: FileStream) -> Result<Response> {
+	let FileStream {
+		content,
+		content_type,
+		content_disposition,
+		len,
+		..
+	} = file;
+
+	let boundary = utils::random_string(32);
+	let content_disposition =
+		make_content_disposition(content_disposition.as_ref(), content_type.as_deref(), None);
+	let content_type = content_type
+		.map(|content_type| format!("Content-Type: {content_type}\r\n"))
+		.unwrap_or_default();
+
+	// The metadata part holds an empty JSON object.
+	let metadata = format!("--{boundary}\r\nContent-Type: application/json\r\n\r\n{{}}\r\n");
+	let head = format!(
+		"{metadata}--{boundary}\r\n{content_type}Content-Disposition: \
+		 {content_disposition}\r\n\r\n"
+	);
+	let tail = format!("\r\n--{boundary}--\r\n");
+
+	let head_len: u64 = utils::math::try_into(head.len())?;
+	let tail_len: u64 = utils::math::try_into(tail.len())?;
+	let body = stream::once(ready(Ok(Bytes::from(head))))
+		.chain(content)
+		.chain(stream::once(ready(Ok(Bytes::from(tail)))))
+		.map_err(|e| io::Error::other(e.to_string()));
+
+	Response::builder()
+		.status(StatusCode::OK)
+		.header(CONTENT_TYPE, format!("multipart/mixed; boundary={boundary}"))
+		.header(CONTENT_LENGTH, head_len.saturating_add(len).saturating_add(tail_len))
+		.body(Body::from_stream(body))
+		.map_err(|e| err!(error!("Failed to build media response: {e}")))
+}
@@ -150,6 +150,14 @@ pub fn check(config: &Config) -> Result {
 		));
 	}
 
+	if config.media_thumbnail_sizes.is_empty() {
+		return Err!(Config(
+			"media_thumbnail_sizes",
+			"At least one thumbnail size must be specified, or thumbnails would never be \
+			 generated."
+		));
+	}
+
 	if config.max_request_size < 10_000_000 {
 		return Err!(Config(
 			"max_request_size",
@@ -158,6 +166,45 @@ pub fn check(config: &Config) -> Result {
 		));
 	}
 
+	if config
+		.media_thumbnail_sizes
+		.iter()
+		.any(|size| size.width == 0 || size.height == 0)
+	{
+		return Err!(Config(
+			"media_thumbnail_sizes",
+			"Thumbnail sizes must be at least 1 pixel wide and high."
+		));
+	}
+
+	if config.media_scan_clamd.is_some() && !config.media_scan_command.is_empty() {
+		return Err!(Config(
+			"media_scan_command",
+			"Media can be scanned either by clamd or by a command, not both."
+		));
+	}
+
+	if config.media_retention_interval == 0 {
+		return Err!(Config(
+			"media_retention_interval",
+			"Media retention policies cannot be applied continuously."
+		));
+	}
+
+	if config.media_retention_rate == 0 {
+		return Err!(Config(
+			"media_retention_rate",
+			"At least one media file has to be deleted per second."
+		));
+	}
+
+	if config.media_redirect && config.media_redirect_ttl == 0 {
+		return Err!(Config(
+			"media_redirect_ttl",
+			"Signed media URLs have to stay valid for a while."
+		));
+	}
+
 	// check if user specified valid IP CIDR ranges on startup
 	for cidr in &config.ip_range_denylist {
 		if let Err(e) = ipaddress::IPAddress::parse(cidr) {
@@ -249,6 +296,27 @@ pub fn check(config: &Config) -> Result {
 		}
 	}
 
+	if config.jwt.enable && config.jwt.key.is_empty() {
+		return Err!(Config("jwt.key", "JWT login is enabled but no validation key was set."));
+	}
+
+	if config.email.enable {
+		if config.email.from.is_empty() {
+			return Err!(Config("email.from", "Email is enabled but no sender address was set."));
+		}
+
+		if !matches!(config.email.transport.as_str(), "smtp" | "sendmail") {
+			return Err!(Config("email.transport", "Must be either \"smtp\" or \"sendmail\"."));
+		}
+
+		if !matches!(config.email.smtp_security.as_str(), "tls" | "starttls" | "none") {
+			return Err!(Config(
+				"email.smtp_security",
+				"Must be one of \"tls\", \"starttls\" or \"none\"."
+			));
+		}
+	}
+
 	if !Server::available_room_versions()
 		.any(|(version, _)| version == config.default_room_version)
 	{
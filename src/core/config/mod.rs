@@ -17,8 +17,8 @@
 pub use figment::{value::Value as FigmentValue, Figment};
 use regex::RegexSet;
 use ruma::{
-	api::client::discovery::discover_support::ContactRole, OwnedRoomOrAliasId, OwnedServerName,
-	OwnedUserId, RoomVersionId,
+	api::client::discovery::discover_support::ContactRole, media::Method, OwnedRoomOrAliasId,
+	OwnedServerName, OwnedUserId, RoomVersionId,
 };
 use serde::{de::IgnoredAny, Deserialize};
 use url::Url;
@@ -32,7 +32,7 @@
 #[allow(rustdoc::broken_intra_doc_links, rustdoc::bare_urls)]
 #[derive(Clone, Debug, Deserialize)]
 #[config_example_generator(
-	filename = "conduwuit-example.toml",
+	filename = "pqchat-example.toml",
 	section = "global",
 	undocumented = "# This item is undocumented. Please contribute documentation for it.",
 	header = r#"### conduwuit Configuration
@@ -52,7 +52,7 @@
 ### For more information, see:
 ### https://conduwuit.puppyirl.gay/configuration.html
 "#,
-	ignore = "catchall well_known tls blurhashing"
+	ignore = "catchall well_known tls blurhashing jwt password_policy email"
 )]
 pub struct Config {
 	/// The server_name is the pretty name of this server. It is used as a
@@ -268,6 +268,13 @@ pub struct Config {
 	#[serde(default = "default_roomid_spacehierarchy_cache_capacity")]
 	pub roomid_spacehierarchy_cache_capacity: u32,
 
+	/// Number of users whose push rules are kept parsed in memory. Push rules
+	/// are evaluated for every local member on every event in their rooms.
+	///
+	/// default: varies by system
+	#[serde(default = "default_pushrule_cache_capacity")]
+	pub pushrule_cache_capacity: u32,
+
 	/// Maximum entries stored in DNS memory-cache. The size of an entry may
 	/// vary so please take care if raising this value excessively. Only
 	/// decrease this when using an external DNS cache. Please note that
@@ -366,6 +373,17 @@ pub struct Config {
 	#[serde(default = "default_max_request_size")]
 	pub max_request_size: usize,
 
+	/// Total size in bytes of the media each local user may upload. Uploads
+	/// beyond it fail with M_RESOURCE_LIMIT_EXCEEDED, and the user gets a
+	/// server notice if server notices are enabled. Admins can set quotas for
+	/// single users with `!admin media set-quota`.
+	///
+	/// 0 means no quota.
+	///
+	/// default: 0
+	#[serde(default)]
+	pub media_quota: u64,
+
 	/// default: 192
 	#[serde(default = "default_max_fetch_prev_events")]
 	pub max_fetch_prev_events: u16,
@@ -821,6 +839,52 @@ pub struct Config {
 	#[serde(default = "default_login_token_ttl")]
 	pub login_token_ttl: u64,
 
+	/// Number of consecutive failed password attempts against an account
+	/// before it is temporarily locked. Locked accounts are rejected with
+	/// `M_USER_LOCKED` on password login and password UIA stages until the
+	/// lock expires or an admin unlocks them.
+	///
+	/// Failures are counted per account across all client addresses, and
+	/// password UIA stages count towards the same lock. Each further failure
+	/// after the lock expires doubles the lock duration, up to
+	/// `login_lockout_max_duration`. A successful login or
+	/// `login_lockout_decay` seconds without failures resets the count.
+	///
+	/// Set to 0 to disable account lockout.
+	///
+	/// default: 5
+	#[serde(default = "default_login_lockout_threshold")]
+	pub login_lockout_threshold: u32,
+
+	/// Number of failed password attempts from a single IP address, across
+	/// any accounts, before further password attempts from that address are
+	/// rate limited.
+	///
+	/// Set to 0 to disable per-address limiting.
+	///
+	/// default: 20
+	#[serde(default = "default_login_lockout_ip_threshold")]
+	pub login_lockout_ip_threshold: u32,
+
+	/// Duration in seconds of the first lockout once a threshold is reached.
+	///
+	/// default: 60
+	#[serde(default = "default_login_lockout_base_duration")]
+	pub login_lockout_base_duration: u64,
+
+	/// Maximum duration in seconds a lockout can grow to.
+	///
+	/// default: 86400
+	#[serde(default = "default_login_lockout_max_duration")]
+	pub login_lockout_max_duration: u64,
+
+	/// Seconds without failed password attempts, counted from the end of any
+	/// lock, after which an account or address starts over with no failures.
+	///
+	/// default: 3600
+	#[serde(default = "default_login_lockout_decay")]
+	pub login_lockout_decay: u64,
+
 	/// Static TURN username to provide the client if not using a shared secret
 	/// ("turn_secret"), It is recommended to use a shared secret over static
 	/// credentials.
@@ -1169,6 +1233,24 @@ pub struct Config {
 	#[serde(default = "default_notification_push_path")]
 	pub notification_push_path: String,
 
+	/// Secret used to sign the requests of webhook pushers (pusher kind
+	/// "io.conduwuit.webhook"). Each request carries an
+	/// `X-Conduwuit-Timestamp` header and an `X-Conduwuit-Signature` header
+	/// holding the base64 encoded HMAC-SHA256 of the timestamp, a period and
+	/// the request body.
+	///
+	/// Webhook pushers cannot be added while this is unset.
+	///
+	/// display: sensitive
+	pub webhook_pusher_secret: Option<String>,
+
+	/// How long in seconds events which notified a user are kept for the
+	/// notifications endpoint. Set to 0 to not keep them at all.
+	///
+	/// default: 2592000
+	#[serde(default = "default_notification_retention")]
+	pub notification_retention: u64,
+
 	/// Allow local (your server only) presence updates/requests.
 	///
 	/// Note that presence on conduwuit is very fast unlike Synapse's. If using
@@ -1352,6 +1434,204 @@ pub struct Config {
 	#[serde(default)]
 	pub prune_missing_media: bool,
 
+	/// Where media is stored: "filesystem" for the `media` directory in the
+	/// database path, or "s3" for an S3-compatible object storage configured
+	/// with the `media_s3_*` options.
+	///
+	/// The media startup check only runs with "filesystem" and no
+	/// `media_storage_fallback`.
+	///
+	/// default: "filesystem"
+	#[serde(default = "default_media_storage")]
+	pub media_storage: String,
+
+	/// A second media storage that media missing from `media_storage` is read
+	/// from. Set it to the previous storage when switching storages, and copy
+	/// the media over with `!admin media migrate-storage`. Remove it once the
+	/// copy is done.
+	pub media_storage_fallback: Option<String>,
+
+	/// URL of the S3-compatible endpoint for the "s3" media storage, e.g.
+	/// "https://s3.eu-central-1.amazonaws.com" or "http://localhost:9000".
+	pub media_s3_endpoint: Option<Url>,
+
+	/// Bucket of the "s3" media storage. It must exist already.
+	pub media_s3_bucket: Option<String>,
+
+	/// default: "us-east-1"
+	#[serde(default = "default_media_s3_region")]
+	pub media_s3_region: String,
+
+	pub media_s3_access_key: Option<String>,
+
+	pub media_s3_secret_key: Option<String>,
+
+	/// Address the bucket in the path of the endpoint instead of as a
+	/// subdomain. MinIO and most self-hosted object storages need this.
+	#[serde(default = "true_fn")]
+	pub media_s3_path_style: bool,
+
+	/// Prefix of the object names in the bucket, so the bucket can be shared.
+	///
+	/// default: ""
+	#[serde(default)]
+	pub media_s3_prefix: String,
+
+	/// The ffmpeg program which extracts the first frame of videos for their
+	/// thumbnails. Only used when built with the `media_thumbnail_video`
+	/// feature; without ffmpeg, videos get no thumbnails.
+	///
+	/// default: "ffmpeg"
+	#[serde(default = "default_media_ffmpeg_path")]
+	pub media_ffmpeg_path: PathBuf,
+
+	/// Thumbnail sizes of media. Thumbnail requests are answered with the
+	/// smallest of these of the requested method covering the requested size,
+	/// else with the smallest of the other method covering it, or with the
+	/// original file when none does. "crop" thumbnails are cut to the exact
+	/// size, "scale" ones keep the aspect ratio of the image. There must be at
+	/// least one size.
+	///
+	/// After changing the sizes, run `!admin media regenerate-thumbnails` to
+	/// replace the thumbnails of existing media.
+	///
+	/// example: [{ width = 96, height = 96, method = "crop" }]
+	///
+	/// default: 32x32 and 96x96 "crop", 320x240, 640x480 and 800x600 "scale"
+	#[serde(default = "default_media_thumbnail_sizes")]
+	pub media_thumbnail_sizes: Vec<ThumbnailSize>,
+
+	/// Generate the thumbnails of uploaded images and videos in the
+	/// background, in all `media_thumbnail_sizes`. Otherwise each thumbnail is
+	/// generated when it is first requested.
+	#[serde(default = "true_fn")]
+	pub media_thumbnail_pregenerate: bool,
+
+	/// Strip EXIF, XMP and other metadata, such as the location a photo was
+	/// taken at, from JPEG, PNG and WebP images uploaded by local users and
+	/// from images fetched for URL previews. The orientation of a photo is
+	/// kept by rotating the image itself, which needs the 'media_thumbnail'
+	/// feature. Images which cannot be read, or which are larger than 64 MiB,
+	/// are refused rather than stored with their metadata. When disabled,
+	/// uploads are stored byte for byte.
+	#[serde(default)]
+	pub media_strip_metadata: bool,
+
+	/// Address of a clamd daemon to scan uploads and remote media for
+	/// malware before they are served: the path of its unix socket, or a host
+	/// and port. Media is streamed to it with the INSTREAM command.
+	///
+	/// example: "/run/clamav/clamd.ctl"
+	pub media_scan_clamd: Option<String>,
+
+	/// Command to scan uploads and remote media for malware with, instead of
+	/// clamd. The file is written to its standard input; like clamscan, it
+	/// exits with 0 for clean media and 1 for infected media, printing the
+	/// name of the signature last.
+	///
+	/// example: ["clamscan", "--no-summary", "--stdout", "-"]
+	///
+	/// default: []
+	#[serde(default)]
+	pub media_scan_command: Vec<String>,
+
+	/// What happens to media in which the scanner finds malware: "block"
+	/// deletes it, "quarantine" keeps it in quarantine for the admins to
+	/// review. Either way the request fails with the "MCS_MEDIA_NOT_CLEAN"
+	/// error code.
+	///
+	/// default: "block"
+	#[serde(default)]
+	pub media_scan_action: MediaScanAction,
+
+	/// Refuse media when the scanner fails or cannot be reached. When
+	/// disabled such media is stored without being scanned.
+	#[serde(default = "true_fn")]
+	pub media_scan_fail_closed: bool,
+
+	/// Seconds a scan of media may take before the scanner is considered to
+	/// have failed.
+	///
+	/// default: 60
+	#[serde(default = "default_media_scan_timeout")]
+	pub media_scan_timeout: u64,
+
+	/// Days after which remote media nobody requested is deleted from the
+	/// cache. It is fetched again when requested later. Avatars of users and
+	/// rooms are kept. 0 keeps remote media forever.
+	///
+	/// default: 0
+	#[serde(default)]
+	pub media_retention_remote_days: u64,
+
+	/// Days after which media uploaded by local users is deleted when no
+	/// event in any room references it, such as uploads which were never
+	/// sent. Avatars of users and rooms are kept, and media of users who have
+	/// been in an encrypted room is kept for `media_retention_encrypted_days`.
+	/// While this is set, the local MXCs events reference are indexed as they
+	/// arrive, and once for the events from before. 0 keeps local media
+	/// forever.
+	///
+	/// default: 0
+	#[serde(default)]
+	pub media_retention_local_days: u64,
+
+	/// Days after which unreferenced media of local users who have been in an
+	/// encrypted room is deleted, when `media_retention_local_days` is set.
+	/// The events of encrypted rooms cannot be read for the media they
+	/// reference, so this media is kept longer in case it was sent there, but
+	/// never shorter than `media_retention_local_days`. 0 keeps it no longer
+	/// than other local media.
+	///
+	/// default: 365
+	#[serde(default = "default_media_retention_encrypted_days")]
+	pub media_retention_encrypted_days: u64,
+
+	/// Seconds between passes of the media retention policies.
+	///
+	/// default: 86400
+	#[serde(default = "default_media_retention_interval")]
+	pub media_retention_interval: u64,
+
+	/// Most media deleted per second by the retention policies, so a large
+	/// purge does not load the server or the storage.
+	///
+	/// default: 10
+	#[serde(default = "default_media_retention_rate")]
+	pub media_retention_rate: u32,
+
+	/// Only log the media the retention policies would delete, without
+	/// deleting anything. Useful to try out new policies.
+	#[serde(default)]
+	pub media_retention_dry_run: bool,
+
+	/// Answer federation downloads of local media with a redirect to a
+	/// short-lived signed URL rather than with the file, as authenticated
+	/// media allows. With the "s3" storage the URL is presigned by the object
+	/// storage, which then serves the file itself. Otherwise it points at the
+	/// client download route under `media_redirect_base_url`, which accepts
+	/// the signature in place of an access token.
+	#[serde(default)]
+	pub media_redirect: bool,
+
+	/// Seconds a signed media URL stays valid.
+	///
+	/// default: 300
+	#[serde(default = "default_media_redirect_ttl")]
+	pub media_redirect_ttl: u64,
+
+	/// Base URL of signed media URLs, such as a CDN which caches media from
+	/// this server. Defaults to `well_known.client`, or
+	/// `https://<server_name>` if that is unset.
+	///
+	/// example: "https://media.example.com"
+	pub media_redirect_base_url: Option<Url>,
+
+	/// Secret key signed media URLs are signed with, which all servers
+	/// serving them must share. When unset a random key is generated at
+	/// startup, so signed URLs do not survive a restart.
+	pub media_redirect_secret: Option<String>,
+
 	/// Vector list of servers that conduwuit will refuse to download remote
 	/// media from.
 	///
@@ -1489,6 +1769,27 @@ pub struct Config {
 	#[serde(default)]
 	pub url_preview_check_root_domain: bool,
 
+	/// Discover the oEmbed endpoints of previewed pages through their
+	/// `<link type="application/json+oembed">` tags, and fill the previews of
+	/// videos, posts and code with the title, provider and thumbnail the site
+	/// offers for embedding. The endpoint and the thumbnail are only requested
+	/// when the URL preview allowlists and denylists allow their domains, and
+	/// never on a range of `ip_range_denylist`.
+	#[serde(default)]
+	pub url_preview_oembed: bool,
+
+	/// oEmbed providers to ask about URLs matching their schemes, for sites
+	/// whose pages do not link to their endpoint. A "*" in a scheme matches
+	/// anything; "{format}" in an endpoint is replaced by "json". These are
+	/// used even when `url_preview_oembed` is disabled.
+	///
+	/// example: [{ endpoint = "https://www.youtube.com/oembed", schemes =
+	/// ["https://www.youtube.com/watch*", "https://youtu.be/*"] }]
+	///
+	/// default: []
+	#[serde(default)]
+	pub url_preview_oembed_providers: Vec<OembedProvider>,
+
 	/// List of forbidden room aliases and room IDs as strings of regex
 	/// patterns.
 	///
@@ -1613,6 +1914,28 @@ pub struct Config {
 	#[serde(default = "default_admin_room_tag")]
 	pub admin_room_tag: String,
 
+	/// Localpart of the system user which sends server notices. Each user
+	/// gets the notices in a room of their own, tagged "m.server_notice".
+	/// Admins can send notices with `!admin server notice`, and the server
+	/// sends some by itself, e.g. when an admin resets a user's password.
+	///
+	/// Server notices are disabled while this is unset.
+	///
+	/// example: "notices"
+	pub server_notices_localpart: Option<String>,
+
+	/// Display name of the server notices user.
+	///
+	/// default: "Server Notices"
+	#[serde(default = "default_server_notices_name")]
+	pub server_notices_displayname: String,
+
+	/// Name of the server notices rooms.
+	///
+	/// default: "Server Notices"
+	#[serde(default = "default_server_notices_name")]
+	pub server_notices_room_name: String,
+
 	/// Sentry.io crash/panic reporting, performance monitoring/metrics, etc.
 	/// This is NOT enabled by default. conduwuit's default Sentry reporting
 	/// endpoint domain is `o4506996327251968.ingest.us.sentry.io`.
@@ -1792,6 +2115,18 @@ pub struct Config {
 	// external structure; separate section
 	#[serde(default)]
 	pub blurhashing: BlurhashConfig,
+
+	// external structure; separate section
+	#[serde(default)]
+	pub jwt: JwtConfig,
+
+	// external structure; separate section
+	#[serde(default)]
+	pub password_policy: PasswordPolicyConfig,
+
+	// external structure; separate section
+	#[serde(default)]
+	pub email: EmailConfig,
 	#[serde(flatten)]
 	#[allow(clippy::zero_sized_map_values)]
 	// this is a catchall, the map shouldn't be zero at runtime
@@ -1812,6 +2147,245 @@ struct ListeningAddr {
 	addrs: Either<IpAddr, Vec<IpAddr>>,
 }
 
+/// A size of the thumbnails generated of media, see `media_thumbnail_sizes`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ThumbnailSize {
+	pub width: u32,
+	pub height: u32,
+	pub method: Method,
+}
+
+/// An oEmbed provider, see `url_preview_oembed_providers`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OembedProvider {
+	pub endpoint: Url,
+	pub schemes: Vec<String>,
+}
+
+/// What happens to infected media, see `media_scan_action`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaScanAction {
+	#[default]
+	Block,
+	Quarantine,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[config_example_generator(filename = "pqchat-example.toml", section = "global.jwt")]
+pub struct JwtConfig {
+	/// Enables the `org.matrix.login.jwt` login type, allowing trusted
+	/// services to log users in by presenting a signed JSON Web Token.
+	///
+	/// The token's `sub` claim is taken as the user's localpart (or full
+	/// user ID on this server).
+	#[serde(default)]
+	pub enable: bool,
+
+	/// The key used to validate the token signature. For HMAC algorithms
+	/// (HS256, HS384, HS512) this is the shared secret. For RSA, ECDSA and
+	/// EdDSA algorithms this is the PEM-encoded public key.
+	///
+	/// example: "my-shared-secret"
+	#[serde(default)]
+	pub key: String,
+
+	/// The signing algorithm tokens must use. Tokens signed with any other
+	/// algorithm are rejected.
+	///
+	/// Supported values include "HS256", "HS384", "HS512", "RS256", "RS384",
+	/// "RS512", "ES256", "ES384" and "EdDSA".
+	///
+	/// default: "HS256"
+	#[serde(default = "default_jwt_algorithm")]
+	pub algorithm: String,
+
+	/// If set, the token's `iss` claim must match one of these values.
+	///
+	/// default: []
+	#[serde(default)]
+	pub issuer: Vec<String>,
+
+	/// If set, the token's `aud` claim must contain one of these values.
+	///
+	/// default: []
+	#[serde(default)]
+	pub audience: Vec<String>,
+
+	/// Require and validate the `exp` claim. Disabling this allows tokens
+	/// without an expiry to be used indefinitely.
+	///
+	/// default: true
+	#[serde(default = "true_fn")]
+	pub validate_exp: bool,
+
+	/// Validate the `nbf` claim if present.
+	///
+	/// default: true
+	#[serde(default = "true_fn")]
+	pub validate_nbf: bool,
+
+	/// Allowed clock skew in seconds when validating `exp` and `nbf`.
+	///
+	/// default: 60
+	#[serde(default = "default_jwt_leeway")]
+	pub leeway: u64,
+
+	/// Automatically register a new account when a valid token is presented
+	/// for a user which does not exist yet. This bypasses `allow_registration`
+	/// and registration tokens, as the issuer is trusted.
+	///
+	/// default: false
+	#[serde(default)]
+	pub register_user: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[config_example_generator(filename = "pqchat-example.toml", section = "global.password_policy")]
+pub struct PasswordPolicyConfig {
+	/// Enforce the rules below whenever a password is set, whether through
+	/// registration, a password change, or an admin password reset. The
+	/// policy is advertised to clients through the `m.password_policy`
+	/// capability.
+	#[serde(default)]
+	pub enable: bool,
+
+	/// Minimum number of characters a password must have.
+	///
+	/// default: 8
+	#[serde(default = "default_password_policy_minimum_length")]
+	pub minimum_length: usize,
+
+	/// Require at least one ASCII digit.
+	#[serde(default)]
+	pub require_digit: bool,
+
+	/// Require at least one symbol (any character which is not a letter or
+	/// digit).
+	#[serde(default)]
+	pub require_symbol: bool,
+
+	/// Require at least one lowercase letter.
+	#[serde(default)]
+	pub require_lowercase: bool,
+
+	/// Require at least one uppercase letter.
+	#[serde(default)]
+	pub require_uppercase: bool,
+
+	/// Reject passwords containing the localpart of the user's ID, ignoring
+	/// case.
+	#[serde(default)]
+	pub reject_localpart: bool,
+
+	/// Path to a file of known-breached or otherwise forbidden passwords,
+	/// one per line. Passwords matching an entry are rejected. The file is
+	/// read at startup when the policy is enabled.
+	///
+	/// example: "/etc/conduwuit/breached-passwords.txt"
+	pub breached_passwords_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[config_example_generator(filename = "pqchat-example.toml", section = "global.email")]
+pub struct EmailConfig {
+	/// Enables sending email, which allows users to add email addresses to
+	/// their account and to reset their password by email.
+	#[serde(default)]
+	pub enable: bool,
+
+	/// The sender of emails sent by this server. May include a display name.
+	///
+	/// example: "conduwuit <noreply@example.com>"
+	#[serde(default)]
+	pub from: String,
+
+	/// How emails are delivered: "smtp" to relay them through an SMTP server,
+	/// or "sendmail" to pipe them to a sendmail-compatible command.
+	///
+	/// default: "smtp"
+	#[serde(default = "default_email_transport")]
+	pub transport: String,
+
+	/// Hostname of the SMTP server.
+	///
+	/// default: "localhost"
+	#[serde(default = "default_email_smtp_host")]
+	pub smtp_host: String,
+
+	/// Port of the SMTP server. Defaults to 465 for "tls", 587 for
+	/// "starttls" and 25 for "none".
+	pub smtp_port: Option<u16>,
+
+	/// Connection security to use with the SMTP server: "tls" for implicit
+	/// TLS, "starttls" to upgrade a plaintext connection, or "none" to send
+	/// in plaintext (only suitable for a local relay).
+	///
+	/// default: "starttls"
+	#[serde(default = "default_email_smtp_security")]
+	pub smtp_security: String,
+
+	/// Username to authenticate to the SMTP server with, if any.
+	pub smtp_username: Option<String>,
+
+	/// Password to authenticate to the SMTP server with, if any.
+	pub smtp_password: Option<String>,
+
+	/// The sendmail-compatible command used when `transport` is "sendmail".
+	///
+	/// default: "sendmail"
+	#[serde(default = "default_email_sendmail_command")]
+	pub sendmail_command: String,
+
+	/// Base URL used for links in emails, such as the validation link. This
+	/// should be the URL clients use to reach this server. Defaults to
+	/// `well_known.client`, or `https://<server_name>` if that is unset.
+	///
+	/// example: "https://matrix.example.com"
+	pub client_base_url: Option<Url>,
+
+	/// How long in seconds an email validation token remains valid.
+	///
+	/// default: 3600
+	#[serde(default = "default_email_validation_token_lifetime")]
+	pub validation_token_lifetime: u64,
+
+	/// Maximum number of validation emails sent to a single email address
+	/// within `validation_rate_window` seconds. The token request routes do
+	/// not require authentication, so this keeps them from being used to
+	/// flood an inbox.
+	///
+	/// Set to 0 to disable per-address limiting.
+	///
+	/// default: 3
+	#[serde(default = "default_email_validation_rate_limit_address")]
+	pub validation_rate_limit_address: u32,
+
+	/// Maximum number of validation emails a single client IP address can
+	/// request within `validation_rate_window` seconds, across any email
+	/// addresses.
+	///
+	/// Set to 0 to disable per-client limiting.
+	///
+	/// default: 10
+	#[serde(default = "default_email_validation_rate_limit_client")]
+	pub validation_rate_limit_client: u32,
+
+	/// Window in seconds the validation email limits apply to.
+	///
+	/// default: 3600
+	#[serde(default = "default_email_validation_rate_window")]
+	pub validation_rate_window: u64,
+
+	/// How long in seconds highlights are collected for email pushers before
+	/// they are sent as a single digest. Highlights in rooms the user has
+	/// read in the meantime are left out.
+	///
+	/// default: 600
+	#[serde(default = "default_email_notification_digest_window")]
+	pub notification_digest_window: u64,
+}
+
 const DEPRECATED_KEYS: &[&str; 9] = &[
 	"cache_capacity",
 	"conduit_cache_capacity_modifier",
@@ -1947,6 +2521,8 @@ fn default_stateinfo_cache_capacity() -> u32 { parallelism_scaled_u32(100) }
 
 fn default_roomid_spacehierarchy_cache_capacity() -> u32 { parallelism_scaled_u32(1000) }
 
+fn default_pushrule_cache_capacity() -> u32 { parallelism_scaled_u32(1000) }
+
 fn default_dns_cache_entries() -> u32 { 32768 }
 
 fn default_dns_min_ttl() -> u64 { 60 * 180 }
@@ -1959,6 +2535,35 @@ fn default_dns_timeout() -> u64 { 10 }
 
 fn default_ip_lookup_strategy() -> u8 { 5 }
 
+fn default_media_storage() -> String { "filesystem".to_owned() }
+
+fn default_media_s3_region() -> String { "us-east-1".to_owned() }
+
+fn default_media_ffmpeg_path() -> PathBuf { "ffmpeg".into() }
+
+fn default_media_scan_timeout() -> u64 { 60 }
+
+fn default_media_retention_interval() -> u64 { 86400 }
+
+fn default_media_retention_encrypted_days() -> u64 { 365 }
+
+fn default_media_retention_rate() -> u32 { 10 }
+
+fn default_media_redirect_ttl() -> u64 { 300 }
+
+fn default_media_thumbnail_sizes() -> Vec<ThumbnailSize> {
+	[
+		(32, 32, Method::Crop),
+		(96, 96, Method::Crop),
+		(320, 240, Method::Scale),
+		(640, 480, Method::Scale),
+		(800, 600, Method::Scale),
+	]
+	.into_iter()
+	.map(|(width, height, method)| ThumbnailSize { width, height, method })
+	.collect()
+}
+
 fn default_max_request_size() -> usize {
 	20 * 1024 * 1024 // Default to 20 MB
 }
@@ -2031,10 +2636,46 @@ pub fn default_log_span_events() -> String { "none".into() }
 
 fn default_notification_push_path() -> String { "/_matrix/push/v1/notify".to_owned() }
 
+fn default_notification_retention() -> u64 { 60 * 60 * 24 * 30 }
+
 fn default_openid_token_ttl() -> u64 { 60 * 60 }
 
 fn default_login_token_ttl() -> u64 { 2 * 60 * 1000 }
 
+fn default_login_lockout_threshold() -> u32 { 5 }
+
+fn default_login_lockout_ip_threshold() -> u32 { 20 }
+
+fn default_login_lockout_base_duration() -> u64 { 60 }
+
+fn default_login_lockout_max_duration() -> u64 { 60 * 60 * 24 }
+
+fn default_login_lockout_decay() -> u64 { 60 * 60 }
+
+fn default_jwt_algorithm() -> String { "HS256".to_owned() }
+
+fn default_jwt_leeway() -> u64 { 60 }
+
+fn default_password_policy_minimum_length() -> usize { 8 }
+
+fn default_email_transport() -> String { "smtp".to_owned() }
+
+fn default_email_smtp_host() -> String { "localhost".to_owned() }
+
+fn default_email_smtp_security() -> String { "starttls".to_owned() }
+
+fn default_email_sendmail_command() -> String { "sendmail".to_owned() }
+
+fn default_email_validation_token_lifetime() -> u64 { 60 * 60 }
+
+fn default_email_validation_rate_limit_address() -> u32 { 3 }
+
+fn default_email_validation_rate_limit_client() -> u32 { 10 }
+
+fn default_email_validation_rate_window() -> u64 { 60 * 60 }
+
+fn default_email_notification_digest_window() -> u64 { 10 * 60 }
+
 fn default_turn_ttl() -> u64 { 60 * 60 * 24 }
 
 fn default_presence_idle_timeout_s() -> u64 { 5 * 60 }
@@ -2132,6 +2773,8 @@ fn default_admin_log_capture() -> String {
 
 fn default_admin_room_tag() -> String { "m.server_notice".to_owned() }
 
+fn default_server_notices_name() -> String { "Server Notices".to_owned() }
+
 #[allow(clippy::as_conversions, clippy::cast_precision_loss)]
 fn parallelism_scaled_f64(val: f64) -> f64 { val * (sys::available_parallelism() as f64) }
 
@@ -1,4 +1,4 @@
-use ruma::events::relation::RelationType;
+use ruma::{events::relation::RelationType, OwnedEventId};
 use serde::Deserialize;
 
 use crate::implement;
@@ -13,6 +13,17 @@ struct ExtractRelatesToEventId {
 	relates_to: ExtractRelType,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+struct ExtractRelation {
+	rel_type: RelationType,
+	event_id: OwnedEventId,
+}
+#[derive(Clone, Debug, Deserialize)]
+struct ExtractRelatesTo {
+	#[serde(rename = "m.relates_to")]
+	relates_to: ExtractRelation,
+}
+
 #[implement(super::Pdu)]
 #[must_use]
 pub fn relation_type_equal(&self, rel_type: &RelationType) -> bool {
@@ -20,3 +31,15 @@ pub fn relation_type_equal(&self, rel_type: &RelationType) -> bool {
 		.map(|c: ExtractRelatesToEventId| c.relates_to.rel_type)
 		.is_ok_and(|r| r == *rel_type)
 }
+
+/// Root of the thread the event is in. The relation is not encrypted, so
+/// this works for encrypted events too.
+#[implement(super::Pdu)]
+#[must_use]
+pub fn thread_root(&self) -> Option<OwnedEventId> {
+	self.get_content()
+		.ok()
+		.map(|c: ExtractRelatesTo| c.relates_to)
+		.filter(|relation| relation.rel_type == RelationType::Thread)
+		.map(|relation| relation.event_id)
+}
@@ -28,6 +28,21 @@ pub fn maximize_fd_limit() -> Result<(), nix::errno::Errno> {
 	Ok(())
 }
 
+/// Limits the resources of the current process, for a child process between
+/// fork and exec: `cpu` seconds of CPU time and `memory` bytes of address
+/// space, with no core dumps and no files written.
+#[cfg(unix)]
+pub fn limit_resources(cpu: u64, memory: u64) -> Result<(), nix::errno::Errno> {
+	use nix::sys::resource::{setrlimit, Resource};
+
+	setrlimit(Resource::RLIMIT_CPU, cpu, cpu)?;
+	setrlimit(Resource::RLIMIT_AS, memory, memory)?;
+	setrlimit(Resource::RLIMIT_CORE, 0, 0)?;
+	setrlimit(Resource::RLIMIT_FSIZE, 0, 0)?;
+
+	Ok(())
+}
+
 /// Return a possibly corrected std::env::current_exe() even if the path is
 /// marked deleted.
 ///
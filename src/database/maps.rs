@@ -33,6 +33,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "aliasid_alias",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "appserviceid_devicechange",
+		..descriptor::SEQUENTIAL_SMALL
+	},
 	Descriptor {
 		name: "backupid_algorithm",
 		..descriptor::RANDOM_SMALL
@@ -49,6 +53,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "bannedroomids",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "clientsecretaddress_sessionid",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "disabledroomids",
 		..descriptor::RANDOM_SMALL
@@ -80,6 +88,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		index_size: 512,
 		..descriptor::RANDOM
 	},
+	Descriptor {
+		name: "expiresat_sessionid",
+		..descriptor::SEQUENTIAL_SMALL
+	},
 	Descriptor {
 		name: "global",
 		..descriptor::RANDOM_SMALL
@@ -100,14 +112,46 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "lazyloadedids",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "mediahash_file",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "mediahash_refs",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "mediahash_scan",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "mediaid_access",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "mediaid_file",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "mediaid_hash",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "mediaid_quarantined",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "mediaid_user",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "mediumaddress_userid",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "mxc_referenced",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "onetimekeyid_onetimekeys",
 		..descriptor::RANDOM_SMALL
@@ -137,6 +181,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "referencedevents",
 		..descriptor::RANDOM
 	},
+	Descriptor {
+		name: "reportid_report",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "roomid_invitedcount",
 		..descriptor::RANDOM_SMALL
@@ -243,6 +291,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "serverroomids",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "sessionid_threepidvalidation",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "shorteventid_authchain",
 		cache_disp: CacheDisp::Unique,
@@ -328,6 +380,10 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "userdeviceid_token",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "userdeviceid_useragent",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "userdevicesessionid_uiaainfo",
 		..descriptor::RANDOM_SMALL
@@ -364,6 +420,14 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "userid_masterkeyid",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "userid_mediaquota",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "userid_mediausage",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "userid_password",
 		..descriptor::RANDOM
@@ -376,14 +440,42 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "userid_selfsigningkeyid",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "userid_servernoticeroomid",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "userid_shadowbanned",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "userid_usersigningkeyid",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "useridcount_notification",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "useridmediumaddress_idserver",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "useridmediumaddress_threepid",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "useridprofilekey_value",
 		..descriptor::RANDOM_SMALL
 	},
+	Descriptor {
+		name: "userpushkey_emaildigest",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "userpushkey_unsubscribe",
+		..descriptor::RANDOM_SMALL
+	},
 	Descriptor {
 		name: "openidtoken_expiresatuserid",
 		..descriptor::RANDOM_SMALL
@@ -416,4 +508,12 @@ pub(super) fn open_list(db: &Arc<Engine>, maps: &[Descriptor]) -> Result<Maps> {
 		name: "userroomid_notificationcount",
 		..descriptor::RANDOM
 	},
+	Descriptor {
+		name: "userroomthreadid_highlightcount",
+		..descriptor::RANDOM_SMALL
+	},
+	Descriptor {
+		name: "userroomthreadid_notificationcount",
+		..descriptor::RANDOM_SMALL
+	},
 ];
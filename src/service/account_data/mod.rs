@@ -17,7 +17,7 @@
 };
 use serde::Deserialize;
 
-use crate::{globals, Dep};
+use crate::{globals, pusher, Dep};
 
 pub struct Service {
 	services: Services,
@@ -31,6 +31,7 @@ struct Data {
 
 struct Services {
 	globals: Dep<globals::Service>,
+	pusher: Dep<pusher::Service>,
 }
 
 impl crate::Service for Service {
@@ -38,6 +39,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			services: Services {
 				globals: args.depend::<globals::Service>("globals"),
+				pusher: args.depend::<pusher::Service>("pusher"),
 			},
 			db: Data {
 				roomuserdataid_accountdata: args.db["roomuserdataid_accountdata"].clone(),
@@ -79,6 +81,12 @@ pub async fn update(
 		self.db.roomuserdataid_accountdata.remove(&prev);
 	}
 
+	if room_id.is_none()
+		&& event_type.to_cow_str() == GlobalAccountDataEventType::PushRules.to_cow_str()
+	{
+		self.services.pusher.invalidate_ruleset(user_id);
+	}
+
 	Ok(())
 }
 
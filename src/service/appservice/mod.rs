@@ -10,7 +10,10 @@
 use ruma::{api::appservice::Registration, RoomAliasId, RoomId, UserId};
 use tokio::sync::RwLock;
 
-pub use self::{namespace_regex::NamespaceRegex, registration_info::RegistrationInfo};
+pub use self::{
+	namespace_regex::NamespaceRegex,
+	registration_info::{RegistrationInfo, Unstable},
+};
 use crate::{sending, Dep};
 
 pub struct Service {
@@ -43,14 +46,13 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 
 	async fn worker(self: Arc<Self>) -> Result<()> {
 		// Inserting registrations into cache
-		for appservice in self.iter_db_ids().await? {
-			self.registration_info.write().await.insert(
-				appservice.0,
-				appservice
-					.1
-					.try_into()
-					.expect("Should be validated on registration"),
-			);
+		for (id, registration) in self.iter_db_ids().await? {
+			let mut info: RegistrationInfo = registration
+				.try_into()
+				.expect("Should be validated on registration");
+
+			info.unstable = self.get_db_unstable(&id).await?;
+			self.registration_info.write().await.insert(id, info);
 		}
 
 		Ok(())
@@ -67,10 +69,13 @@ pub async fn register_appservice(
 		appservice_config_body: &str,
 	) -> Result {
 		//TODO: Check for collisions between exclusive appservice namespaces
+		let mut info: RegistrationInfo = registration.clone().try_into()?;
+		info.unstable = serde_yaml::from_str(appservice_config_body)?;
+
 		self.registration_info
 			.write()
 			.await
-			.insert(registration.id.clone(), registration.clone().try_into()?);
+			.insert(registration.id.clone(), info);
 
 		self.db
 			.id_appserviceregistrations
@@ -175,6 +180,15 @@ pub async fn get_db_registration(&self, id: &str) -> Result<Registration> {
 			.map_err(|e| err!(Database("Invalid appservice {id:?} registration: {e:?}")))
 	}
 
+	async fn get_db_unstable(&self, id: &str) -> Result<Unstable> {
+		self.db
+			.id_appserviceregistrations
+			.get(id)
+			.await
+			.and_then(|ref bytes| serde_yaml::from_slice(bytes).map_err(Into::into))
+			.map_err(|e| err!(Database("Invalid appservice {id:?} registration: {e:?}")))
+	}
+
 	async fn iter_db_ids(&self) -> Result<Vec<(String, Registration)>> {
 		self.db
 			.id_appserviceregistrations
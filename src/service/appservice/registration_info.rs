@@ -1,5 +1,6 @@
 use conduwuit::Result;
 use ruma::{api::appservice::Registration, UserId};
+use serde::Deserialize;
 
 use super::NamespaceRegex;
 
@@ -10,6 +11,21 @@ pub struct RegistrationInfo {
 	pub users: NamespaceRegex,
 	pub aliases: NamespaceRegex,
 	pub rooms: NamespaceRegex,
+	pub unstable: Unstable,
+}
+
+/// Registration keys for unstable features which are not part of ruma's
+/// `Registration`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Unstable {
+	/// MSC3202: receive device list changes and one-time key counts in
+	/// transactions.
+	#[serde(default, rename = "org.matrix.msc3202")]
+	pub msc3202: bool,
+
+	/// MSC4190: create and delete devices of namespaced users without UIAA.
+	#[serde(default, rename = "io.element.msc4190")]
+	pub msc4190: bool,
 }
 
 impl RegistrationInfo {
@@ -19,6 +35,27 @@ pub fn is_user_match(&self, user_id: &UserId) -> bool {
 			|| self.registration.sender_localpart == user_id.localpart()
 	}
 
+	/// Whether to-device messages for the appservice's users are pushed in
+	/// transactions (MSC2409).
+	#[inline]
+	#[must_use]
+	pub fn receives_to_device(&self) -> bool { self.registration.receive_ephemeral }
+
+	/// Whether device list changes and one-time key counts are pushed in
+	/// transactions (MSC3202). Like to-device messages this requires
+	/// `receive_ephemeral`.
+	#[inline]
+	#[must_use]
+	pub fn receives_device_changes(&self) -> bool {
+		self.registration.receive_ephemeral && self.unstable.msc3202
+	}
+
+	/// Whether the appservice creates and deletes devices of its users
+	/// without UIAA (MSC4190).
+	#[inline]
+	#[must_use]
+	pub fn manages_devices(&self) -> bool { self.unstable.msc4190 }
+
 	#[inline]
 	#[must_use]
 	pub fn is_exclusive_user_match(&self, user_id: &UserId) -> bool {
@@ -35,6 +72,7 @@ fn try_from(value: Registration) -> Result<Self, regex::Error> {
 			users: value.namespaces.users.clone().try_into()?,
 			aliases: value.namespaces.aliases.clone().try_into()?,
 			rooms: value.namespaces.rooms.clone().try_into()?,
+			unstable: Unstable::default(),
 			registration: value,
 		})
 	}
@@ -0,0 +1,293 @@
+mod tests;
+
+use std::{
+	borrow::Borrow,
+	collections::HashMap,
+	fmt::Write,
+	hash::Hash,
+	net::IpAddr,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use conduwuit::{debug_warn, warn, Err, Error, Result, Server};
+use http::StatusCode;
+use ruma::{
+	api::client::error::{ErrorKind, RetryAfter},
+	events::room::message::RoomMessageEventContent,
+	OwnedUserId, UserId,
+};
+
+use crate::{admin, Dep};
+
+/// Tracks failed password attempts per account and per client address and
+/// temporarily locks them out with an exponentially growing duration.
+///
+/// Account failures are counted regardless of the address they come from, so
+/// spreading guesses over many addresses does not avoid the lock. The count
+/// per address is a further limit on top of it. Both are forgotten after
+/// `login_lockout_decay` seconds without failures.
+///
+/// State is kept in memory only; restarting the server clears all lockouts.
+pub struct Service {
+	accounts: Mutex<FailureMap<OwnedUserId>>,
+	addresses: Mutex<FailureMap<IpAddr>>,
+	services: Services,
+}
+
+struct Services {
+	server: Arc<Server>,
+	admin: Dep<admin::Service>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Failures {
+	/// Consecutive failed attempts.
+	count: u32,
+
+	/// Time of the last failed attempt.
+	last: Instant,
+
+	/// Attempts are rejected until this time.
+	locked_until: Option<Instant>,
+}
+
+impl Failures {
+	/// Time since the last failed attempt or the end of the lock, whichever
+	/// is later.
+	fn idle(&self, now: Instant) -> Duration {
+		let since = self
+			.locked_until
+			.map_or(self.last, |locked_until| locked_until.max(self.last));
+
+		now.saturating_duration_since(since)
+	}
+}
+
+type FailureMap<K> = HashMap<K, Failures>;
+
+/// How failures turn into locks.
+#[derive(Clone, Copy, Debug)]
+struct Backoff {
+	/// Failures before the first lock; 0 never locks.
+	threshold: u32,
+
+	/// Duration of the first lock.
+	base: Duration,
+
+	/// Longest a lock can grow to.
+	max: Duration,
+
+	/// Idle time after which the failures are forgotten.
+	decay: Duration,
+}
+
+impl Backoff {
+	/// Increments the failure count for the key, returning the lock duration
+	/// if this failure caused it to become locked.
+	fn record<K>(&self, map: &mut FailureMap<K>, key: K, now: Instant) -> Option<Duration>
+	where
+		K: Eq + Hash,
+	{
+		if self.threshold == 0 {
+			return None;
+		}
+
+		if map.len() >= PRUNE_THRESHOLD {
+			map.retain(|_, failures| failures.idle(now) < self.decay);
+		}
+
+		let failures =
+			map.entry(key)
+				.or_insert(Failures { count: 0, last: now, locked_until: None });
+
+		if failures.idle(now) >= self.decay {
+			failures.count = 0;
+			failures.locked_until = None;
+		}
+
+		failures.count = failures.count.saturating_add(1);
+		failures.last = now;
+		if failures.count < self.threshold {
+			return None;
+		}
+
+		let duration = self.duration(failures.count);
+		failures.locked_until = Some(now.checked_add(duration).unwrap_or(now));
+
+		Some(duration)
+	}
+
+	/// Lock duration after `count` consecutive failures, doubling with each
+	/// failure beyond the threshold.
+	fn duration(&self, count: u32) -> Duration {
+		let exponent = count.saturating_sub(self.threshold).min(31);
+		self.base
+			.saturating_mul(2_u32.saturating_pow(exponent))
+			.min(self.max)
+	}
+}
+
+/// Entries are pruned of stale failures once a map grows beyond this size.
+const PRUNE_THRESHOLD: usize = 4096;
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			accounts: FailureMap::new().into(),
+			addresses: FailureMap::new().into(),
+			services: Services {
+				server: args.server.clone(),
+				admin: args.depend::<admin::Service>("admin"),
+			},
+		}))
+	}
+
+	fn memory_usage(&self, out: &mut dyn Write) -> Result<()> {
+		let accounts = self.accounts.lock().expect("locked").len();
+		writeln!(out, "lockout_accounts: {accounts}")?;
+
+		let addresses = self.addresses.lock().expect("locked").len();
+		writeln!(out, "lockout_addresses: {addresses}")?;
+
+		Ok(())
+	}
+
+	fn clear_cache(&self) {
+		self.accounts.lock().expect("locked").clear();
+		self.addresses.lock().expect("locked").clear();
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+impl Service {
+	/// Rejects a password attempt for the account or from the client address
+	/// while either is locked out.
+	pub fn check(&self, user_id: &UserId, client: Option<IpAddr>) -> Result {
+		let now = Instant::now();
+		if let Some(client) = client {
+			if let Some(remaining) = remaining(&self.addresses, &client, now) {
+				return Err(Error::Request(
+					ErrorKind::LimitExceeded {
+						retry_after: Some(RetryAfter::Delay(remaining)),
+					},
+					"Too many failed login attempts from this address.".into(),
+					StatusCode::TOO_MANY_REQUESTS,
+				));
+			}
+		}
+
+		if remaining(&self.accounts, user_id, now).is_some() {
+			return Err!(Request(UserLocked(debug_warn!(
+				"Account {user_id} is temporarily locked due to too many failed login attempts."
+			))));
+		}
+
+		Ok(())
+	}
+
+	/// Records a failed password attempt, locking the account or client
+	/// address once its threshold is reached. Admins are notified when an
+	/// account becomes locked.
+	pub async fn failed(&self, user_id: &UserId, client: Option<IpAddr>) {
+		if let Some(client) = client {
+			self.failed_address(client);
+		}
+
+		let config = &self.services.server.config;
+		let backoff = self.backoff(config.login_lockout_threshold);
+		let Some(duration) = backoff.record(
+			&mut self.accounts.lock().expect("locked"),
+			user_id.to_owned(),
+			Instant::now(),
+		) else {
+			return;
+		};
+
+		warn!(
+			%user_id, ?client, ?duration,
+			"Locking account after too many failed password attempts"
+		);
+		if config.admin_room_notices {
+			let client =
+				client.map_or_else(|| "an unknown address".to_owned(), |c| c.to_string());
+			self.services
+				.admin
+				.send_message(RoomMessageEventContent::notice_plain(format!(
+					"Account {user_id} was locked for {} seconds after too many failed password \
+					 attempts, the last from {client}.",
+					duration.as_secs()
+				)))
+				.await
+				.ok();
+		}
+	}
+
+	/// Records a failed password attempt from a client address which did not
+	/// target an existing account.
+	pub fn failed_address(&self, client: IpAddr) {
+		let backoff = self.backoff(self.services.server.config.login_lockout_ip_threshold);
+		let locked =
+			backoff.record(&mut self.addresses.lock().expect("locked"), client, Instant::now());
+
+		if let Some(duration) = locked {
+			warn!(%client, ?duration, "Rate limiting password attempts from address");
+		}
+	}
+
+	/// Clears the failed attempts of an account after a successful login.
+	pub fn succeeded(&self, user_id: &UserId) {
+		self.accounts.lock().expect("locked").remove(user_id);
+	}
+
+	/// Removes the lockout of an account. Returns false if the account had no
+	/// recorded failures.
+	pub fn unlock(&self, user_id: &UserId) -> bool {
+		self.accounts
+			.lock()
+			.expect("locked")
+			.remove(user_id)
+			.is_some()
+	}
+
+	/// Returns the accounts which are currently locked and the time remaining
+	/// on the lock.
+	#[must_use]
+	pub fn locked_accounts(&self) -> Vec<(OwnedUserId, Duration)> {
+		let now = Instant::now();
+		self.accounts
+			.lock()
+			.expect("locked")
+			.iter()
+			.filter_map(|(user_id, failures)| {
+				let locked_until = failures.locked_until?;
+				(locked_until > now)
+					.then(|| (user_id.clone(), locked_until.saturating_duration_since(now)))
+			})
+			.collect()
+	}
+
+	fn backoff(&self, threshold: u32) -> Backoff {
+		let config = &self.services.server.config;
+		Backoff {
+			threshold,
+			base: Duration::from_secs(config.login_lockout_base_duration),
+			max: Duration::from_secs(config.login_lockout_max_duration),
+			decay: Duration::from_secs(config.login_lockout_decay),
+		}
+	}
+}
+
+fn remaining<K, Q>(map: &Mutex<FailureMap<K>>, key: &Q, now: Instant) -> Option<Duration>
+where
+	K: Eq + Hash + Borrow<Q>,
+	Q: Eq + Hash + ?Sized,
+{
+	map.lock()
+		.expect("locked")
+		.get(key)
+		.and_then(|failures| failures.locked_until)
+		.filter(|locked_until| *locked_until > now)
+		.map(|locked_until| locked_until.saturating_duration_since(now))
+}
@@ -0,0 +1,92 @@
+#![cfg(test)]
+
+use std::time::{Duration, Instant};
+
+use super::{Backoff, FailureMap};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+fn backoff() -> Backoff {
+	Backoff {
+		threshold: 3,
+		base: MINUTE,
+		max: MINUTE.saturating_mul(4),
+		decay: MINUTE.saturating_mul(60),
+	}
+}
+
+fn later(now: Instant, duration: Duration) -> Instant {
+	now.checked_add(duration).expect("instant in range")
+}
+
+#[test]
+fn locks_at_threshold() {
+	let backoff = backoff();
+	let mut map = FailureMap::new();
+	let now = Instant::now();
+
+	assert_eq!(backoff.record(&mut map, "alice", now), None);
+	assert_eq!(backoff.record(&mut map, "alice", now), None);
+	assert_eq!(backoff.record(&mut map, "alice", now), Some(MINUTE));
+	assert_eq!(map["alice"].locked_until, Some(later(now, MINUTE)));
+}
+
+#[test]
+fn counts_keys_separately() {
+	let backoff = backoff();
+	let mut map = FailureMap::new();
+	let now = Instant::now();
+
+	backoff.record(&mut map, "alice", now);
+	backoff.record(&mut map, "alice", now);
+	assert_eq!(backoff.record(&mut map, "bob", now), None, "bob was locked for alice");
+	assert_eq!(backoff.record(&mut map, "alice", now), Some(MINUTE));
+}
+
+#[test]
+fn duration_doubles_up_to_max() {
+	let backoff = backoff();
+
+	assert_eq!(backoff.duration(3), MINUTE);
+	assert_eq!(backoff.duration(4), MINUTE.saturating_mul(2));
+	assert_eq!(backoff.duration(5), MINUTE.saturating_mul(4));
+	assert_eq!(backoff.duration(6), MINUTE.saturating_mul(4), "lock grew beyond the maximum");
+	assert_eq!(
+		backoff.duration(u32::MAX),
+		MINUTE.saturating_mul(4),
+		"lock grew beyond the maximum"
+	);
+}
+
+#[test]
+fn failures_decay() {
+	let backoff = backoff();
+	let mut map = FailureMap::new();
+	let now = Instant::now();
+
+	for _ in 0..3 {
+		backoff.record(&mut map, "alice", now);
+	}
+
+	// The decay is counted from the end of the lock.
+	let idle = backoff.decay.saturating_sub(Duration::from_secs(1));
+	let before = later(now, MINUTE.saturating_add(idle));
+	assert_eq!(backoff.record(&mut map, "alice", before), Some(MINUTE.saturating_mul(2)));
+
+	let after = later(before, MINUTE.saturating_mul(2).saturating_add(backoff.decay));
+	assert_eq!(backoff.record(&mut map, "alice", after), None, "failures did not decay");
+	assert_eq!(map["alice"].count, 1);
+}
+
+#[test]
+fn zero_threshold_never_locks() {
+	let backoff = Backoff { threshold: 0, ..backoff() };
+	let mut map = FailureMap::new();
+	let now = Instant::now();
+
+	for _ in 0..10 {
+		assert_eq!(backoff.record(&mut map, "alice", now), None);
+	}
+
+	assert!(map.is_empty(), "failures were recorded while disabled");
+}
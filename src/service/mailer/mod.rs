@@ -0,0 +1,81 @@
+mod tests;
+pub mod transport;
+
+use std::sync::Arc;
+
+use conduwuit::{debug, err, Err, Result};
+use lettre::{
+	message::{header::ContentType, Mailbox},
+	Message,
+};
+
+pub use self::transport::Transport;
+
+/// Sends email through the transport configured in the `email` section.
+pub struct Service {
+	mailer: Option<Mailer>,
+}
+
+struct Mailer {
+	from: Mailbox,
+	transport: Box<dyn Transport>,
+}
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		let config = &args.server.config.email;
+		if !config.enable {
+			return Ok(Arc::new(Self { mailer: None }));
+		}
+
+		let from = config
+			.from
+			.parse()
+			.map_err(|e| err!(Config("email.from", "Invalid sender address: {e}")))?;
+
+		Ok(Arc::new(Self {
+			mailer: Some(Mailer {
+				from,
+				transport: transport::from_config(config)?,
+			}),
+		}))
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+impl Service {
+	/// Whether sending email is enabled.
+	#[inline]
+	#[must_use]
+	pub fn is_enabled(&self) -> bool { self.mailer.is_some() }
+
+	/// Sends a plain text email to a single recipient.
+	pub async fn send(&self, to: &str, subject: &str, body: String) -> Result {
+		let Some(mailer) = &self.mailer else {
+			return Err!(Request(ThreepidDenied("Sending email is not enabled on this server.")));
+		};
+
+		let message = message(mailer.from.clone(), to, subject, body)?;
+		mailer.transport.send(message).await?;
+
+		debug!(%to, %subject, "Sent email");
+
+		Ok(())
+	}
+}
+
+/// Composes a plain text message.
+pub fn message(from: Mailbox, to: &str, subject: &str, body: String) -> Result<Message> {
+	let to: Mailbox = to
+		.parse()
+		.map_err(|e| err!(Request(InvalidParam("Invalid email address: {e}"))))?;
+
+	Message::builder()
+		.from(from)
+		.to(to)
+		.subject(subject)
+		.header(ContentType::TEXT_PLAIN)
+		.body(body)
+		.map_err(|e| err!("Failed to compose email: {e}"))
+}
@@ -0,0 +1,104 @@
+#![cfg(test)]
+
+use std::sync::{Arc, Mutex};
+
+use tokio::{
+	io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+	net::TcpListener,
+};
+
+use super::{message, transport::Smtp, Transport};
+
+/// A message received by the sink.
+#[derive(Debug, Default)]
+struct Received {
+	from: String,
+	to: Vec<String>,
+	data: String,
+}
+
+/// Minimal SMTP server which accepts every message and records it.
+async fn smtp_sink() -> (u16, Arc<Mutex<Vec<Received>>>) {
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let port = listener.local_addr().unwrap().port();
+	let received = Arc::new(Mutex::new(Vec::new()));
+
+	let sink = received.clone();
+	tokio::spawn(async move {
+		loop {
+			let (stream, _) = listener.accept().await.unwrap();
+			let (reader, mut writer) = stream.into_split();
+			let mut lines = BufReader::new(reader).lines();
+			let mut current = Received::default();
+
+			writer
+				.write_all(b"220 localhost ESMTP sink\r\n")
+				.await
+				.unwrap();
+			while let Ok(Some(line)) = lines.next_line().await {
+				let command = line.to_ascii_uppercase();
+				let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+					b"250 localhost\r\n"
+				} else if let Some(from) =
+					line.get(10..).filter(|_| command.starts_with("MAIL FROM:"))
+				{
+					current.from = from.to_owned();
+					b"250 OK\r\n"
+				} else if let Some(to) = line.get(8..).filter(|_| command.starts_with("RCPT TO:"))
+				{
+					current.to.push(to.to_owned());
+					b"250 OK\r\n"
+				} else if command == "DATA" {
+					writer
+						.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+						.await
+						.unwrap();
+					while let Ok(Some(line)) = lines.next_line().await {
+						if line == "." {
+							break;
+						}
+
+						current.data.push_str(&line);
+						current.data.push('\n');
+					}
+
+					sink.lock().unwrap().push(std::mem::take(&mut current));
+					b"250 OK\r\n"
+				} else if command == "QUIT" {
+					writer.write_all(b"221 Bye\r\n").await.unwrap();
+					break;
+				} else {
+					b"250 OK\r\n"
+				};
+
+				writer.write_all(reply).await.unwrap();
+			}
+		}
+	});
+
+	(port, received)
+}
+
+#[tokio::test]
+async fn smtp_delivers_message() {
+	let (port, received) = smtp_sink().await;
+	let transport = Smtp::unencrypted("127.0.0.1", port);
+
+	let from = "Homeserver <noreply@example.com>".parse().unwrap();
+	let body = "Your validation token is 123456".to_owned();
+	let message = message(from, "alice@example.com", "Validate your email", body).unwrap();
+	transport.send(message).await.unwrap();
+
+	let received = received.lock().unwrap();
+	assert_eq!(received.len(), 1);
+	assert_eq!(received[0].from, "<noreply@example.com>");
+	assert_eq!(received[0].to, ["<alice@example.com>"]);
+	assert!(received[0].data.contains("Subject: Validate your email"));
+	assert!(received[0].data.contains("Your validation token is 123456"));
+}
+
+#[test]
+fn message_rejects_invalid_recipient() {
+	let from = "noreply@example.com".parse().unwrap();
+	assert!(message(from, "not an address", "subject", String::new()).is_err());
+}
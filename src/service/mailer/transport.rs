@@ -0,0 +1,90 @@
+use async_trait::async_trait;
+use conduwuit::{config::EmailConfig, err, Result};
+use lettre::{
+	transport::smtp::authentication::Credentials, AsyncSendmailTransport, AsyncSmtpTransport,
+	AsyncTransport, Message, Tokio1Executor,
+};
+
+/// Delivers composed messages. Implementations are selected by the
+/// `email.transport` config option.
+#[async_trait]
+pub trait Transport: Send + Sync {
+	async fn send(&self, message: Message) -> Result;
+}
+
+/// Relays messages through an SMTP server.
+pub struct Smtp(AsyncSmtpTransport<Tokio1Executor>);
+
+/// Pipes messages to a sendmail-compatible command.
+pub struct Sendmail(AsyncSendmailTransport<Tokio1Executor>);
+
+/// Builds the transport described by the config.
+pub(super) fn from_config(config: &EmailConfig) -> Result<Box<dyn Transport>> {
+	match config.transport.as_str() {
+		| "sendmail" => Ok(Box::new(Sendmail::new(&config.sendmail_command))),
+		| _ => Smtp::from_config(config).map(|smtp| Box::new(smtp) as _),
+	}
+}
+
+impl Smtp {
+	fn from_config(config: &EmailConfig) -> Result<Self> {
+		let host = config.smtp_host.as_str();
+		let builder = match config.smtp_security.as_str() {
+			| "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host),
+			| "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host),
+			| _ => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)),
+		}
+		.map_err(|e| err!(Config("email.smtp_host", "Invalid SMTP relay: {e}")))?;
+
+		let builder = match config.smtp_port {
+			| Some(port) => builder.port(port),
+			| None => builder,
+		};
+
+		let builder = match (&config.smtp_username, &config.smtp_password) {
+			| (Some(username), Some(password)) =>
+				builder.credentials(Credentials::new(username.clone(), password.clone())),
+			| _ => builder,
+		};
+
+		Ok(Self(builder.build()))
+	}
+
+	/// Sends in plaintext to the given host and port without authenticating.
+	#[must_use]
+	pub fn unencrypted(host: &str, port: u16) -> Self {
+		Self(
+			AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+				.port(port)
+				.build(),
+		)
+	}
+}
+
+#[async_trait]
+impl Transport for Smtp {
+	async fn send(&self, message: Message) -> Result {
+		self.0
+			.send(message)
+			.await
+			.map(|_| ())
+			.map_err(|e| err!("Failed to send email over SMTP: {e}"))
+	}
+}
+
+impl Sendmail {
+	#[must_use]
+	pub fn new(command: &str) -> Self {
+		Self(AsyncSendmailTransport::<Tokio1Executor>::new_with_command(command))
+	}
+}
+
+#[async_trait]
+impl Transport for Sendmail {
+	async fn send(&self, message: Message) -> Result {
+		self.0
+			.send(message)
+			.await
+			.map_err(|e| err!("Failed to send email through sendmail: {e}"))
+	}
+}
@@ -1,35 +1,68 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+	collections::HashSet,
+	sync::Arc,
+	time::{Duration, SystemTime},
+};
 
 use conduwuit::{
 	debug, debug_info, err,
-	utils::{str_from_bytes, stream::TryIgnore, string_from_bytes, ReadyExt},
+	utils::{self, str_from_bytes, stream::TryIgnore, string_from_bytes, ReadyExt},
 	Err, Result,
 };
-use database::{Database, Interfix, Map};
-use futures::StreamExt;
-use ruma::{http_headers::ContentDisposition, Mxc, OwnedMxcUri, UserId};
+use database::{Database, Deserialized, Interfix, Map};
+use futures::{Stream, StreamExt};
+use ruma::{http_headers::ContentDisposition, Mxc, OwnedMxcUri, OwnedUserId, UserId};
 
-use super::{preview::UrlPreviewData, thumbnail::Dim};
+use super::{
+	dedup::ContentHash, preview::UrlPreviewData, retention::from_millis, scan::Verdict,
+	thumbnail::Dim,
+};
 
 pub(crate) struct Data {
+	global: Arc<Map>,
+	mediahash_file: Arc<Map>,
+	mediahash_refs: Arc<Map>,
+	mediahash_scan: Arc<Map>,
+	mediaid_access: Arc<Map>,
+	mediaid_hash: Arc<Map>,
 	mediaid_file: Arc<Map>,
+	mediaid_quarantined: Arc<Map>,
 	mediaid_user: Arc<Map>,
+	mxc_referenced: Arc<Map>,
 	url_previews: Arc<Map>,
+	userid_mediaquota: Arc<Map>,
+	userid_mediausage: Arc<Map>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(super) struct Metadata {
 	pub(super) content_disposition: Option<ContentDisposition>,
 	pub(super) content_type: Option<String>,
 	pub(super) key: Vec<u8>,
+	pub(super) quarantined: bool,
+	/// When the media was created, unless it was before this was recorded.
+	pub(super) created: Option<SystemTime>,
+	/// When the media was last served, unless it never was since this was
+	/// recorded.
+	pub(super) last_access: Option<SystemTime>,
 }
 
 impl Data {
 	pub(super) fn new(db: &Arc<Database>) -> Self {
 		Self {
+			global: db["global"].clone(),
+			mediahash_file: db["mediahash_file"].clone(),
+			mediahash_refs: db["mediahash_refs"].clone(),
+			mediahash_scan: db["mediahash_scan"].clone(),
+			mediaid_access: db["mediaid_access"].clone(),
+			mediaid_hash: db["mediaid_hash"].clone(),
 			mediaid_file: db["mediaid_file"].clone(),
+			mediaid_quarantined: db["mediaid_quarantined"].clone(),
 			mediaid_user: db["mediaid_user"].clone(),
+			mxc_referenced: db["mxc_referenced"].clone(),
 			url_previews: db["url_previews"].clone(),
+			userid_mediaquota: db["userid_mediaquota"].clone(),
+			userid_mediausage: db["userid_mediausage"].clone(),
 		}
 	}
 
@@ -41,8 +74,8 @@ pub(super) fn create_file_metadata(
 		content_disposition: Option<&ContentDisposition>,
 		content_type: Option<&str>,
 	) -> Result<Vec<u8>> {
-		let dim: &[u32] = &[dim.width, dim.height];
-		let key = (mxc, dim, content_disposition, content_type);
+		let dim = dim.key();
+		let key = (mxc, dim.as_slice(), content_disposition, content_type);
 		let key = database::serialize_key(key)?;
 		self.mediaid_file.insert(&key, []);
 		if let Some(user) = user {
@@ -56,6 +89,8 @@ pub(super) fn create_file_metadata(
 	pub(super) async fn delete_file_mxc(&self, mxc: &Mxc<'_>) {
 		debug!("MXC URI: {mxc}");
 
+		self.mediaid_access.remove(&mxc.to_string());
+
 		let prefix = (mxc, Interfix);
 		self.mediaid_file
 			.keys_prefix_raw(&prefix)
@@ -102,13 +137,16 @@ pub(super) async fn search_mxc_metadata_prefix(&self, mxc: &Mxc<'_>) -> Result<V
 		Ok(keys)
 	}
 
+	/// Deletes the metadata of a single file of an MXC, such as a thumbnail.
+	pub(super) fn delete_file_key(&self, key: &[u8]) { self.mediaid_file.remove(key); }
+
 	pub(super) async fn search_file_metadata(
 		&self,
 		mxc: &Mxc<'_>,
 		dim: &Dim,
 	) -> Result<Metadata> {
-		let dim: &[u32] = &[dim.width, dim.height];
-		let prefix = (mxc, dim, Interfix);
+		let dim = dim.key();
+		let prefix = (mxc, dim.as_slice(), Interfix);
 
 		let key = self
 			.mediaid_file
@@ -139,7 +177,200 @@ pub(super) async fn search_file_metadata(
 			.map(str::parse)
 			.transpose()?;
 
-		Ok(Metadata { content_disposition, content_type, key })
+		let quarantined = self.is_quarantined(mxc).await;
+		let (created, accessed) = self.get_media_access(mxc).await;
+
+		Ok(Metadata {
+			content_disposition,
+			content_type,
+			key,
+			quarantined,
+			created: from_millis(created),
+			last_access: from_millis(accessed),
+		})
+	}
+
+	/// When media was created and last accessed, in milliseconds since the
+	/// epoch; 0 when not recorded.
+	pub(super) async fn get_media_access(&self, mxc: &Mxc<'_>) -> (u64, u64) {
+		let Ok(access) = self.mediaid_access.get(&mxc.to_string()).await else {
+			return (0, 0);
+		};
+
+		let created = access
+			.get(..8)
+			.and_then(|created| utils::u64_from_bytes(created).ok());
+		let accessed = access
+			.get(8..)
+			.and_then(|accessed| utils::u64_from_bytes(accessed).ok());
+
+		(created.unwrap_or(0), accessed.unwrap_or(0))
+	}
+
+	pub(super) fn set_media_access(&self, mxc: &Mxc<'_>, created: u64, accessed: u64) {
+		let access = [created.to_be_bytes(), accessed.to_be_bytes()].concat();
+		self.mediaid_access.insert(&mxc.to_string(), access);
+	}
+
+	/// Quarantined media is kept but never served, and never fetched again
+	/// from remote servers. The MXC does not need to be in our database.
+	pub(super) fn set_quarantined(&self, mxc: &Mxc<'_>, quarantined: bool) {
+		let key = mxc.to_string();
+		if quarantined {
+			self.mediaid_quarantined.insert(&key, []);
+		} else {
+			self.mediaid_quarantined.remove(&key);
+		}
+	}
+
+	pub(super) async fn is_quarantined(&self, mxc: &Mxc<'_>) -> bool {
+		self.mediaid_quarantined.get(&mxc.to_string()).await.is_ok()
+	}
+
+	pub(super) fn quarantined_mxcs(&self) -> impl Stream<Item = OwnedMxcUri> + Send + '_ {
+		self.mediaid_quarantined
+			.keys()
+			.ignore_err()
+			.map(|mxc: &str| mxc.into())
+	}
+
+	/// The cached verdict of scanning media with a content hash. Infected
+	/// media is stored with the name of the signature, clean media without.
+	pub(super) async fn get_scan_verdict(&self, hash: &[u8]) -> Option<Verdict> {
+		let signature = self.mediahash_scan.get(hash).await.ok()?;
+		if signature.is_empty() {
+			return Some(Verdict::Clean);
+		}
+
+		str_from_bytes(&signature)
+			.ok()
+			.map(|signature| Verdict::Infected(signature.to_owned()))
+	}
+
+	pub(super) fn set_scan_verdict(&self, hash: &[u8], verdict: &Verdict) {
+		match verdict {
+			| Verdict::Clean => self.mediahash_scan.insert(hash, []),
+			| Verdict::Infected(signature) => self.mediahash_scan.insert(hash, signature),
+		}
+	}
+
+	/// The hash of the content of a media file, unless it was stored before
+	/// media was deduplicated.
+	pub(super) async fn get_file_hash(&self, key: &[u8]) -> Option<ContentHash> {
+		let hash = self.mediaid_hash.get(key).await.ok()?;
+		ContentHash::try_from(&*hash).ok()
+	}
+
+	/// The storage key of the content with a hash, and how many media files
+	/// have that content.
+	pub(super) async fn get_blob(&self, hash: &ContentHash) -> Option<(Vec<u8>, u64)> {
+		let storage_key = self.mediahash_file.get(hash).await.ok()?.to_vec();
+		let refs = self
+			.mediahash_refs
+			.get(hash)
+			.await
+			.ok()
+			.and_then(|refs| utils::u64_from_bytes(&refs).ok())
+			.unwrap_or(0);
+
+		Some((storage_key, refs))
+	}
+
+	/// Stores that a media file has content which is stored already under
+	/// `storage_key`, or newly stored there.
+	pub(super) fn add_blob_ref(
+		&self,
+		key: &[u8],
+		hash: &ContentHash,
+		storage_key: &[u8],
+		refs: u64,
+	) {
+		self.mediahash_file.insert(hash, storage_key);
+		self.mediahash_refs.insert(hash, refs.to_be_bytes());
+		self.mediaid_hash.insert(key, hash);
+	}
+
+	/// Removes the reference of a media file to its content. Returns the
+	/// storage key of the content once it has no references left, for it to
+	/// be deleted.
+	pub(super) async fn remove_blob_ref(
+		&self,
+		key: &[u8],
+		hash: &ContentHash,
+	) -> Option<Vec<u8>> {
+		self.mediaid_hash.remove(key);
+		let (storage_key, refs) = self.get_blob(hash).await?;
+		let refs = refs.saturating_sub(1);
+		if refs > 0 {
+			self.mediahash_refs.insert(hash, refs.to_be_bytes());
+			return None;
+		}
+
+		self.mediahash_file.remove(hash);
+		self.mediahash_refs.remove(hash);
+
+		Some(storage_key)
+	}
+
+	/// All stored contents with their storage key and how many media files
+	/// have them.
+	pub(super) fn all_blobs(&self) -> impl Stream<Item = (Vec<u8>, u64)> + Send + '_ {
+		self.mediahash_refs
+			.raw_stream()
+			.ignore_err()
+			.filter_map(|(hash, refs)| async move {
+				let hash: ContentHash = hash.try_into().ok()?;
+				let (storage_key, _) = self.get_blob(&hash).await?;
+				Some((storage_key, utils::u64_from_bytes(refs).ok()?))
+			})
+	}
+
+	/// Gets the user who uploaded an MXC
+	pub(super) async fn get_mxc_user(&self, mxc: &Mxc<'_>) -> Option<OwnedUserId> {
+		let prefix = (mxc, Interfix);
+		self.mediaid_user
+			.stream_prefix_raw(&prefix)
+			.ignore_err()
+			.ready_filter_map(|(_, user)| UserId::parse(str_from_bytes(user).ok()?).ok())
+			.next()
+			.await
+	}
+
+	/// Bytes of media uploaded by a user which have not been deleted
+	pub(super) async fn get_media_usage(&self, user_id: &UserId) -> u64 {
+		self.userid_mediausage
+			.get(user_id)
+			.await
+			.deserialized()
+			.unwrap_or(0)
+	}
+
+	#[inline]
+	pub(super) fn set_media_usage(&self, user_id: &UserId, usage: u64) {
+		self.userid_mediausage.put(user_id, usage);
+	}
+
+	pub(super) fn all_media_usage(&self) -> impl Stream<Item = (OwnedUserId, u64)> + Send + '_ {
+		self.userid_mediausage
+			.stream()
+			.ignore_err()
+			.map(|(user_id, usage): (&UserId, u64)| (user_id.to_owned(), usage))
+	}
+
+	pub(super) async fn get_media_quota(&self, user_id: &UserId) -> Option<u64> {
+		self.userid_mediaquota
+			.get(user_id)
+			.await
+			.deserialized()
+			.ok()
+	}
+
+	pub(super) fn set_media_quota(&self, user_id: &UserId, quota: Option<u64>) {
+		if let Some(quota) = quota {
+			self.userid_mediaquota.put(user_id, quota);
+		} else {
+			self.userid_mediaquota.remove(user_id);
+		}
 	}
 
 	/// Gets all the MXCs associated with a user
@@ -165,6 +396,64 @@ pub(crate) async fn get_all_media_keys(&self) -> Vec<Vec<u8>> {
 			.await
 	}
 
+	/// Records that an event references an MXC, as an avatar or otherwise.
+	pub(super) async fn add_mxc_reference(&self, mxc: &str, avatar: bool) {
+		// Media referenced as an avatar once stays an avatar.
+		if !avatar && self.mxc_referenced.get(mxc).await.is_ok() {
+			return;
+		}
+
+		self.mxc_referenced.insert(mxc, [u8::from(avatar)]);
+	}
+
+	/// All MXCs referenced by events, and whether each is an avatar.
+	pub(super) fn mxc_references(&self) -> impl Stream<Item = (OwnedMxcUri, bool)> + Send + '_ {
+		self.mxc_referenced
+			.raw_stream()
+			.ignore_err()
+			.ready_filter_map(|(mxc, avatar)| {
+				let mxc = str_from_bytes(mxc).ok()?;
+				Some((mxc.into(), avatar.first() == Some(&1)))
+			})
+	}
+
+	/// Whether the references of the events from before they were indexed as
+	/// they arrived are indexed.
+	pub(super) async fn mxc_references_indexed(&self) -> bool {
+		self.global.get(b"mxc_references_indexed").await.is_ok()
+	}
+
+	pub(super) fn set_mxc_references_indexed(&self) {
+		self.global.insert(b"mxc_references_indexed", []);
+	}
+
+	pub(super) fn unset_mxc_references_indexed(&self) {
+		self.global.remove(b"mxc_references_indexed");
+	}
+
+	/// Removes the cached previews which show one of `images`.
+	pub(super) async fn remove_url_previews_showing(&self, images: &HashSet<OwnedMxcUri>) {
+		let urls: Vec<String> = self
+			.url_previews
+			.raw_keys()
+			.ignore_err()
+			.ready_filter_map(|url| str_from_bytes(url).ok().map(ToOwned::to_owned))
+			.collect()
+			.await;
+
+		for url in urls {
+			let Ok(UrlPreviewData { image: Some(image), .. }) = self.get_url_preview(&url).await
+			else {
+				continue;
+			};
+
+			if images.contains(&OwnedMxcUri::from(image)) {
+				debug!(%url, "Removing URL preview of deleted image");
+				self.url_previews.remove(url.as_bytes());
+			}
+		}
+	}
+
 	#[inline]
 	pub(super) fn remove_url_preview(&self, url: &str) -> Result<()> {
 		self.url_previews.remove(url.as_bytes());
@@ -206,6 +495,10 @@ pub(super) fn set_url_preview(
 		value.extend_from_slice(&data.image_width.unwrap_or(0).to_be_bytes());
 		value.push(0xFF);
 		value.extend_from_slice(&data.image_height.unwrap_or(0).to_be_bytes());
+		for field in [&data.video, &data.site_name, &data.kind] {
+			value.push(0xFF);
+			value.extend_from_slice(field.as_ref().map(String::as_bytes).unwrap_or_default());
+		}
 
 		self.url_previews.insert(url.as_bytes(), &value);
 
@@ -267,6 +560,17 @@ pub(super) async fn get_url_preview(&self, url: &str) -> Result<UrlPreviewData>
 			| x => x,
 		};
 
+		let mut string = || {
+			values
+				.next()
+				.and_then(|b| String::from_utf8(b.to_vec()).ok())
+				.filter(|s| !s.is_empty())
+		};
+
+		let video = string();
+		let site_name = string();
+		let kind = string();
+
 		Ok(UrlPreviewData {
 			title,
 			description,
@@ -274,6 +578,9 @@ pub(super) async fn get_url_preview(&self, url: &str) -> Result<UrlPreviewData>
 			image_size,
 			image_width,
 			image_height,
+			video,
+			site_name,
+			kind,
 		})
 	}
 }
@@ -0,0 +1,207 @@
+//! Media Deduplication
+//!
+//! The content of media files is stored once per SHA-256 hash, however many
+//! MXCs and thumbnails have it; files forwarded across many rooms take the
+//! space of one. Each media key maps to the hash of its content, and each
+//! hash to the storage key of the content and the number of media keys
+//! referencing it. Content is deleted with its last reference.
+//!
+//! Content is stored under a key made of its hash, except uploads streamed
+//! into the storage, which keep the key of the first upload to avoid copying
+//! them once their hash is known. Media stored before deduplication keeps its
+//! own key until the `dedupe-media` admin command has run over it.
+
+use conduwuit::{debug, implement, Result};
+use futures::{StreamExt, TryStreamExt};
+use sha2::{Digest, Sha256};
+
+/// The SHA-256 hash of the content of media.
+pub(super) type ContentHash = [u8; 32];
+
+/// How much space deduplication saves.
+#[derive(Debug, Default)]
+pub struct DedupStats {
+	/// Media files, including thumbnails.
+	pub files: u64,
+	/// Distinct contents stored for them.
+	pub blobs: u64,
+	/// Bytes stored for the contents.
+	pub stored: u64,
+	/// Bytes which would be stored for the duplicates.
+	pub saved: u64,
+}
+
+#[must_use]
+fn content_hash(content: &[u8]) -> ContentHash { Sha256::digest(content).into() }
+
+/// The storage key of content stored by its hash.
+#[must_use]
+fn blob_key(hash: &ContentHash) -> Vec<u8> { [b"sha256:".as_slice(), hash].concat() }
+
+/// Stores the content of a media file, unless the same content is stored
+/// already. Returns the hash of the content.
+#[implement(super::Service)]
+pub(super) async fn put_media_file(&self, key: &[u8], content: &[u8]) -> Result<ContentHash> {
+	let hash = content_hash(content);
+	if self.replace_media_file(key, &hash).await? {
+		return Ok(hash);
+	}
+
+	let _lock = self.blob_mutex.lock(hash.as_slice()).await;
+	if let Some((storage_key, refs)) = self.db.get_blob(&hash).await {
+		debug!(?key, "Media content is stored already");
+		self.db
+			.add_blob_ref(key, &hash, &storage_key, refs.saturating_add(1));
+		self.store.link(key, &storage_key).await;
+
+		return Ok(hash);
+	}
+
+	let storage_key = blob_key(&hash);
+	self.store.put(&storage_key, content).await?;
+	self.db.add_blob_ref(key, &hash, &storage_key, 1);
+	self.store.link(key, &storage_key).await;
+
+	Ok(hash)
+}
+
+/// Deduplicates a media file stored under its own key, which has the content
+/// with `hash`. The file is deleted when the content is stored already.
+#[implement(super::Service)]
+pub(super) async fn dedupe_media_file(&self, key: &[u8], hash: &ContentHash) -> Result {
+	if self.replace_media_file(key, hash).await? {
+		if self.storage_key(key).await != key {
+			self.delete_media_object(key).await?;
+		}
+
+		return Ok(());
+	}
+
+	let _lock = self.blob_mutex.lock(hash.as_slice()).await;
+	let Some((storage_key, refs)) = self.db.get_blob(hash).await else {
+		self.db.add_blob_ref(key, hash, key, 1);
+		self.store.link(key, key).await;
+		return Ok(());
+	};
+
+	debug!(?key, "Media content is stored already");
+	self.db
+		.add_blob_ref(key, hash, &storage_key, refs.saturating_add(1));
+	self.store.link(key, &storage_key).await;
+
+	if storage_key != key {
+		self.delete_media_object(key).await?;
+	}
+
+	Ok(())
+}
+
+/// Prepares a media file for being stored again with the content of `hash`.
+/// Returns whether it has that content already, or else drops its reference
+/// to its old content, which is deleted when nothing else references it.
+#[implement(super::Service)]
+async fn replace_media_file(&self, key: &[u8], hash: &ContentHash) -> Result<bool> {
+	let Some(old) = self.db.get_file_hash(key).await else {
+		return Ok(false);
+	};
+
+	if old == *hash {
+		return Ok(true);
+	}
+
+	debug!(?key, "Media content has changed");
+	let _lock = self.blob_mutex.lock(old.as_slice()).await;
+	let storage_key = self.db.remove_blob_ref(key, &old).await;
+
+	// Content stored under the key itself was overwritten already.
+	if let Some(storage_key) = storage_key.filter(|storage_key| storage_key != key) {
+		self.delete_media_object(&storage_key).await?;
+	}
+
+	Ok(false)
+}
+
+/// The key under which the content of a media file is stored.
+#[implement(super::Service)]
+pub(super) async fn storage_key(&self, key: &[u8]) -> Vec<u8> {
+	let Some(hash) = self.db.get_file_hash(key).await else {
+		return key.to_vec();
+	};
+
+	self.db
+		.get_blob(&hash)
+		.await
+		.map_or_else(|| key.to_vec(), |(storage_key, _)| storage_key)
+}
+
+/// Drops the reference of a media file to its content. Returns the storage
+/// key of the content when nothing else references it, so it is deleted.
+#[implement(super::Service)]
+pub(super) async fn release_media_file(&self, key: &[u8]) -> Option<Vec<u8>> {
+	let Some(hash) = self.db.get_file_hash(key).await else {
+		return Some(key.to_vec());
+	};
+
+	let _lock = self.blob_mutex.lock(hash.as_slice()).await;
+	self.db.remove_blob_ref(key, &hash).await
+}
+
+/// Deduplicates the media files stored before media was deduplicated, by
+/// hashing their content. Returns the number of files and the number of
+/// them which turned out to be duplicates.
+#[implement(super::Service)]
+pub async fn dedupe_media_files(&self) -> Result<(usize, usize)> {
+	let (mut hashed, mut duplicates) = (0_usize, 0_usize);
+	for key in self.db.get_all_media_keys().await {
+		if self.db.get_file_hash(&key).await.is_some() {
+			continue;
+		}
+
+		let Some((store, ..)) = self.find_media_file(&key).await? else {
+			debug!(?key, "Media file to deduplicate is missing");
+			continue;
+		};
+
+		let Some(content) = store.get_stream(&key, None).await? else {
+			continue;
+		};
+
+		let hasher = content
+			.try_fold(Sha256::new(), |mut hasher, chunk| async move {
+				hasher.update(&chunk);
+				Ok(hasher)
+			})
+			.await?;
+
+		let hash: ContentHash = hasher.finalize().into();
+		self.dedupe_media_file(&key, &hash).await?;
+		if self.storage_key(&key).await != key {
+			duplicates = duplicates.saturating_add(1);
+		}
+
+		hashed = hashed.saturating_add(1);
+	}
+
+	Ok((hashed, duplicates))
+}
+
+/// Counts how much space deduplication saves.
+#[implement(super::Service)]
+pub async fn dedup_stats(&self) -> Result<DedupStats> {
+	let mut stats = DedupStats::default();
+	let mut blobs = self.db.all_blobs().boxed();
+	while let Some((storage_key, refs)) = blobs.next().await {
+		let Some((_, stat)) = self.find_stored_object(&storage_key).await? else {
+			continue;
+		};
+
+		stats.files = stats.files.saturating_add(refs);
+		stats.blobs = stats.blobs.saturating_add(1);
+		stats.stored = stats.stored.saturating_add(stat.len);
+		stats.saved = stats
+			.saved
+			.saturating_add(stat.len.saturating_mul(refs.saturating_sub(1)));
+	}
+
+	Ok(stats)
+}
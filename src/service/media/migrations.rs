@@ -69,13 +69,20 @@ pub(crate) async fn checkup_sha256_media(services: &Services) -> Result<()> {
 	let dbs = (mediaid_file, mediaid_user);
 	let timer = Instant::now();
 
+	// Media missing from the directory may be in another storage.
+	if config.media_storage != "filesystem" || config.media_storage_fallback.is_some() {
+		debug!("Skipping check of media directory not in sole use");
+		return Ok(());
+	}
+
 	let dir = media.get_media_dir();
 	let files: HashSet<OsString> = fs::read_dir(dir)?
 		.filter_map(|ent| ent.map_or(None, |ent| Some(ent.path().into_os_string())))
 		.collect();
 
 	for key in media.db.get_all_media_keys().await {
-		let new_path = media.get_media_file_sha256(&key).into_os_string();
+		let storage_key = media.storage_key(&key).await;
+		let new_path = media.get_media_file_sha256(&storage_key).into_os_string();
 		let old_path = media.get_media_file_b64(&key).into_os_string();
 		if let Err(e) = handle_media_check(&dbs, config, &files, &key, &new_path, &old_path).await
 		{
@@ -1,11 +1,32 @@
 pub mod blurhash;
 mod data;
+mod dedup;
 pub(super) mod migrations;
+#[cfg(feature = "url_preview")]
+mod oembed;
 mod preview;
+mod quarantine;
+mod quota;
+mod range;
 mod remote;
+mod retention;
+mod sanitize;
+mod scan;
+mod signed;
+pub mod store;
 mod tests;
 mod thumbnail;
-use std::{path::PathBuf, sync::Arc, time::SystemTime};
+#[cfg(feature = "media_thumbnail")]
+mod video;
+use std::{
+	collections::HashSet,
+	path::PathBuf,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Mutex,
+	},
+	time::SystemTime,
+};
 
 use async_trait::async_trait;
 use base64::{engine::general_purpose, Engine as _};
@@ -14,15 +35,21 @@
 	utils::{self, MutexMap},
 	warn, Err, Result, Server,
 };
-use ruma::{http_headers::ContentDisposition, Mxc, OwnedMxcUri, UserId};
-use tokio::{
-	fs,
-	io::{AsyncReadExt, AsyncWriteExt, BufReader},
+use futures::{stream, StreamExt, TryStreamExt};
+use loole::{Receiver, Sender};
+use ruma::{http_headers::ContentDisposition, Mxc, OwnedMxcUri, OwnedUserId, UserId};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use self::{
+	data::{Data, Metadata},
+	store::{MediaStore, Stat},
 };
-
-use self::data::{Data, Metadata};
-pub use self::thumbnail::Dim;
-use crate::{client, globals, sending, Dep};
+pub use self::{
+	dedup::DedupStats, range::ByteRange, retention::RetentionReport, store::MediaStream,
+	thumbnail::Dim,
+};
+use crate::{client, globals, rooms, sending, server_notices, users, Dep};
 
 #[derive(Debug)]
 pub struct FileMeta {
@@ -31,8 +58,27 @@ pub struct FileMeta {
 	pub content_disposition: Option<ContentDisposition>,
 }
 
+/// A media file read in chunks, see [`Service::get_stream`].
+pub struct FileStream {
+	pub content: MediaStream,
+	pub content_type: Option<String>,
+	pub content_disposition: Option<ContentDisposition>,
+	/// Length of the whole file.
+	pub len: u64,
+	/// The part of the file being read; `None` when it is all of it.
+	pub range: Option<ByteRange>,
+}
+
 pub struct Service {
 	url_preview_mutex: MutexMap<String, ()>,
+	usage_mutex: MutexMap<OwnedUserId, ()>,
+	blob_mutex: MutexMap<dedup::ContentHash, ()>,
+	mxc_mutex: MutexMap<String, ()>,
+	signing_key: Vec<u8>,
+	quota_notified: Mutex<HashSet<OwnedUserId>>,
+	thumbnail_queue: (Sender<OwnedMxcUri>, Receiver<OwnedMxcUri>),
+	store: Box<dyn MediaStore>,
+	fallback: Option<Box<dyn MediaStore>>,
 	pub(super) db: Data,
 	services: Services,
 }
@@ -42,6 +88,12 @@ struct Services {
 	client: Dep<client::Service>,
 	globals: Dep<globals::Service>,
 	sending: Dep<sending::Service>,
+	server_notices: Dep<server_notices::Service>,
+	timeline: Dep<rooms::timeline::Service>,
+	metadata: Dep<rooms::metadata::Service>,
+	state_accessor: Dep<rooms::state_accessor::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	users: Dep<users::Service>,
 }
 
 /// generated MXC ID (`media-id`) length
@@ -56,14 +108,41 @@ struct Services {
 #[async_trait]
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		let config = &args.server.config;
+		let client = &args.require::<client::Service>("client").default;
+		let store = store::build(&config.media_storage, config, client)?;
+		let fallback = config
+			.media_storage_fallback
+			.as_deref()
+			.map(|name| store::build(name, config, client))
+			.transpose()?;
+
 		Ok(Arc::new(Self {
 			url_preview_mutex: MutexMap::new(),
+			usage_mutex: MutexMap::new(),
+			blob_mutex: MutexMap::new(),
+			mxc_mutex: MutexMap::new(),
+			signing_key: config.media_redirect_secret.clone().map_or_else(
+				|| utils::random_string(MXC_LENGTH).into_bytes(),
+				String::into_bytes,
+			),
+			quota_notified: Mutex::new(HashSet::new()),
+			thumbnail_queue: loole::unbounded(),
+			store,
+			fallback,
 			db: Data::new(args.db),
 			services: Services {
 				server: args.server.clone(),
 				client: args.depend::<client::Service>("client"),
 				globals: args.depend::<globals::Service>("globals"),
 				sending: args.depend::<sending::Service>("sending"),
+				server_notices: args.depend::<server_notices::Service>("server_notices"),
+				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
+				metadata: args.depend::<rooms::metadata::Service>("rooms::metadata"),
+				state_accessor: args
+					.depend::<rooms::state_accessor::Service>("rooms::state_accessor"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				users: args.depend::<users::Service>("users"),
 			},
 		}))
 	}
@@ -71,9 +150,29 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 	async fn worker(self: Arc<Self>) -> Result<()> {
 		self.create_media_dir().await?;
 
+		let receiver = self.thumbnail_queue.1.clone();
+		let thumbnails = async {
+			while let Ok(mxc) = receiver.recv_async().await {
+				self.pregenerate_thumbnails(&mxc).await;
+			}
+		};
+
+		// The retention policies stop with the thumbnail queue when interrupted.
+		tokio::select! {
+			() = thumbnails => {},
+			() = self.retention_worker() => {},
+		}
+
 		Ok(())
 	}
 
+	fn interrupt(&self) {
+		let (sender, _) = &self.thumbnail_queue;
+		if !sender.is_closed() {
+			sender.close();
+		}
+	}
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
@@ -87,6 +186,14 @@ pub async fn create(
 		content_type: Option<&str>,
 		file: &[u8],
 	) -> Result<()> {
+		// Remote media cached for a user does not count towards their usage.
+		let local = self.services.globals.server_is_ours(mxc.server_name);
+		let charged_user = user.filter(|_| local);
+		let len: u64 = file.len().try_into()?;
+		if let Some(user) = charged_user {
+			self.add_media_usage(user, len).await?;
+		}
+
 		// Width, Height = 0 if it's not a thumbnail
 		let key = self.db.create_file_metadata(
 			mxc,
@@ -94,11 +201,160 @@ pub async fn create(
 			&Dim::default(),
 			content_disposition,
 			content_type,
-		)?;
+		);
+
+		let stored = match key {
+			| Ok(key) => self
+				.put_media_file(&key, file)
+				.await
+				.map(|hash| (key, hash)),
+			| Err(e) => Err(e),
+		};
+
+		// Give back the charge for a file which could not be stored.
+		let (key, hash) = match stored {
+			| Ok(stored) => stored,
+			| Err(e) => {
+				if let Some(user) = charged_user {
+					self.refund_media_usage(user, len).await;
+				}
+
+				self.db.delete_file_mxc(mxc).await;
+				return Err(e);
+			},
+		};
 
-		//TODO: Dangling metadata in database if creation fails
-		let mut f = self.create_media_file(&key).await?;
-		f.write_all(file).await?;
+		self.scan_media(mxc, &key, &hash).await?;
+		self.record_media_created(mxc);
+
+		if local {
+			self.queue_thumbnails(mxc);
+		}
+
+		Ok(())
+	}
+
+	/// Uploads a file as its chunks arrive, without holding it in memory.
+	/// `len` is the length announced by the uploader, if any; a file which
+	/// turns out longer or shorter is refused.
+	pub async fn create_stream(
+		&self,
+		mxc: &Mxc<'_>,
+		user: Option<&UserId>,
+		content_disposition: Option<&ContentDisposition>,
+		content_type: Option<&str>,
+		len: Option<u64>,
+		content: MediaStream,
+	) -> Result<()> {
+		// Remote media cached for a user does not count towards their usage.
+		let local = self.services.globals.server_is_ours(mxc.server_name);
+		let charged_user = user.filter(|_| local);
+
+		// Images have their metadata stripped, which needs all of them at once.
+		let (head, content) = sniff_stream(content).await?;
+		if local && self.is_sanitized(&head) {
+			if len.is_some_and(|len| len > sanitize::MAX_SANITIZED_SIZE) {
+				return Err!(Request(TooLarge("Image is too large to strip its metadata.")));
+			}
+
+			let max_len = utils::math::usize_from_u64_truncated(sanitize::MAX_SANITIZED_SIZE);
+			let file: Vec<u8> = content
+				.try_fold(Vec::new(), |mut file, chunk| async move {
+					if file.len().saturating_add(chunk.len()) > max_len {
+						return Err!(Request(TooLarge(
+							"Image is too large to strip its metadata."
+						)));
+					}
+
+					file.extend_from_slice(&chunk);
+					Ok(file)
+				})
+				.await?;
+
+			let file_len: u64 = utils::math::try_into(file.len())?;
+			if len.is_some_and(|len| len != file_len) {
+				return Err!(Request(InvalidParam("Upload does not match its Content-Length.")));
+			}
+
+			let file = self.sanitize_media(&file)?;
+			return self
+				.create(mxc, user, content_disposition, content_type, &file)
+				.await;
+		}
+
+		// Refuse uploads over quota before receiving them when we can.
+		if let (Some(user), Some(len)) = (charged_user, len) {
+			self.add_media_usage(user, len).await?;
+		}
+
+		// Width, Height = 0 if it's not a thumbnail
+		let key = match self.db.create_file_metadata(
+			mxc,
+			user,
+			&Dim::default(),
+			content_disposition,
+			content_type,
+		) {
+			| Ok(key) => key,
+			| Err(e) => {
+				if let (Some(len), Some(user)) = (len, charged_user) {
+					self.refund_media_usage(user, len).await;
+				}
+
+				return Err(e);
+			},
+		};
+
+		// Uploads of unknown length are cut off once they exceed the quota left.
+		let remaining = match (charged_user, len) {
+			| (Some(user), None) => self.remaining_media_quota(user).await,
+			| _ => None,
+		};
+
+		let written = Arc::new(AtomicU64::new(0));
+		let hasher = Arc::new(Mutex::new(Sha256::new()));
+		let content = count_stream(content, len, remaining, written.clone());
+		let content = hash_stream(content, hasher.clone());
+		let mut result = self.store.put_stream(&key, len, content).await;
+
+		let written = written.load(Ordering::Acquire);
+		if result.is_ok() && len.is_some_and(|len| written < len) {
+			result = Err!(Request(InvalidParam("Upload is shorter than its Content-Length.")));
+		}
+
+		if let (Some(user), Some(remaining)) = (charged_user, remaining) {
+			if result.is_err() && written > remaining {
+				let quota = self.media_quota(user).await.unwrap_or_default();
+				result = Err(self.quota_exceeded(user, quota).await);
+			}
+		}
+
+		if let (true, Some(user), None) = (result.is_ok(), charged_user, len) {
+			result = self.add_media_usage(user, written).await;
+		}
+
+		if let Err(e) = result {
+			if let (Some(len), Some(user)) = (len, charged_user) {
+				self.refund_media_usage(user, len).await;
+			}
+
+			if let Err(e) = self.remove_media_file(&key).await {
+				debug_error!(?mxc, "Failed to remove media file of failed upload: {e}");
+			}
+
+			self.db.delete_file_mxc(mxc).await;
+
+			return Err(e);
+		}
+
+		let hash: dedup::ContentHash = hasher.lock().expect("locked").clone().finalize().into();
+		self.dedupe_media_file(&key, &hash).await?;
+		self.scan_media(mxc, &key, &hash).await?;
+		self.record_media_created(mxc);
+
+		if local {
+			self.queue_thumbnails(mxc);
+		}
 
 		Ok(())
 	}
@@ -106,9 +362,18 @@ pub async fn create(
 	/// Deletes a file in the database and from the media directory via an MXC
 	pub async fn delete(&self, mxc: &Mxc<'_>) -> Result<()> {
 		if let Ok(keys) = self.db.search_mxc_metadata_prefix(mxc).await {
+			// Quarantined media was taken off the usage already.
+			if let Ok(metadata) = self.db.search_file_metadata(mxc, &Dim::default()).await {
+				if let Ok(Some(stat)) = self.stat_media_file(&metadata.key).await {
+					if !metadata.quarantined {
+						self.sub_media_usage(mxc, stat.len).await;
+					}
+				}
+			}
+
 			for key in keys {
 				trace!(?mxc, "MXC Key: {key:?}");
-				debug_info!(?mxc, "Deleting from filesystem");
+				debug_info!(?mxc, "Deleting from media storage");
 
 				if let Err(e) = self.remove_media_file(&key).await {
 					debug_error!(?mxc, "Failed to remove media file: {e}");
@@ -154,14 +419,21 @@ pub async fn delete_from_user(&self, user: &UserId) -> Result<usize> {
 
 	/// Downloads a file.
 	pub async fn get(&self, mxc: &Mxc<'_>) -> Result<Option<FileMeta>> {
-		if let Ok(Metadata { content_disposition, content_type, key }) =
-			self.db.search_file_metadata(mxc, &Dim::default()).await
+		if let Ok(Metadata {
+			content_disposition,
+			content_type,
+			key,
+			quarantined,
+			..
+		}) = self.db.search_file_metadata(mxc, &Dim::default()).await
 		{
-			let mut content = Vec::with_capacity(8192);
-			let path = self.get_media_file(&key);
-			BufReader::new(fs::File::open(path).await?)
-				.read_to_end(&mut content)
-				.await?;
+			if quarantined {
+				return Err!(Request(NotFound("Media not found.")));
+			}
+
+			self.record_media_access(mxc).await;
+
+			let content = self.read_media_file(&key).await?;
 
 			Ok(Some(FileMeta {
 				content: Some(content),
@@ -173,6 +445,53 @@ pub async fn get(&self, mxc: &Mxc<'_>) -> Result<Option<FileMeta>> {
 		}
 	}
 
+	/// Downloads a file in chunks. `range` is the value of a `Range` header,
+	/// which is honoured for a single range of bytes.
+	pub async fn get_stream(
+		&self,
+		mxc: &Mxc<'_>,
+		range: Option<&str>,
+	) -> Result<Option<FileStream>> {
+		let Ok(Metadata {
+			content_disposition,
+			content_type,
+			key,
+			quarantined,
+			..
+		}) = self.db.search_file_metadata(mxc, &Dim::default()).await
+		else {
+			return Ok(None);
+		};
+
+		if quarantined {
+			return Err!(Request(NotFound("Media not found.")));
+		}
+
+		self.record_media_access(mxc).await;
+
+		let Some((store, storage_key, Stat { len, .. })) = self.find_media_file(&key).await?
+		else {
+			return Err!(Request(NotFound("Media file not found.")));
+		};
+
+		let range = range
+			.map(|range| ByteRange::parse(range, len))
+			.transpose()?
+			.flatten();
+
+		let Some(content) = store.get_stream(&storage_key, range).await? else {
+			return Err!(Request(NotFound("Media file not found.")));
+		};
+
+		Ok(Some(FileStream {
+			content,
+			content_type,
+			content_disposition,
+			len,
+			range,
+		}))
+	}
+
 	/// Gets all the MXC URIs in our media database
 	pub async fn get_all_mxcs(&self) -> Result<Vec<OwnedMxcUri>> {
 		let all_keys = self.db.get_all_media_keys().await;
@@ -259,29 +578,14 @@ pub async fn delete_all_remote_media_at_after_time(
 				continue;
 			}
 
-			let path = self.get_media_file(&key);
-
-			let file_metadata = match fs::metadata(path.clone()).await {
-				| Ok(file_metadata) => file_metadata,
-				| Err(e) => {
-					error!(
-						"Failed to obtain file metadata for MXC {mxc} at file path \
-						 \"{path:?}\", skipping: {e}"
-					);
+			let file_created_at = match self.stat_media_file(&key).await {
+				| Ok(Some(stat)) => stat.modified,
+				| Ok(None) => {
+					debug_warn!("Media file of MXC {mxc} is missing, skipping");
 					continue;
 				},
-			};
-
-			trace!(%mxc, ?path, "File metadata: {file_metadata:?}");
-
-			let file_created_at = match file_metadata.created() {
-				| Ok(value) => value,
-				| Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {
-					debug!("btime is unsupported, using mtime instead");
-					file_metadata.modified()?
-				},
-				| Err(err) => {
-					error!("Could not delete MXC {mxc} at path {path:?}: {err:?}. Skipping...");
+				| Err(e) => {
+					error!("Failed to obtain file metadata for MXC {mxc}, skipping: {e}");
 					continue;
 				},
 			};
@@ -338,39 +642,76 @@ pub async fn create_media_dir(&self) -> Result<()> {
 		Ok(fs::create_dir_all(dir).await?)
 	}
 
+	/// Deletes a media file, and its content from the storage unless another
+	/// media file has the same content.
 	async fn remove_media_file(&self, key: &[u8]) -> Result<()> {
-		let path = self.get_media_file(key);
-		let legacy = self.get_media_file_b64(key);
-		debug!(?key, ?path, ?legacy, "Removing media file");
-
-		let file_rm = fs::remove_file(&path);
-		let legacy_rm = fs::remove_file(&legacy);
-		let (file_rm, legacy_rm) = tokio::join!(file_rm, legacy_rm);
-		if let Err(e) = legacy_rm {
-			if self.services.server.config.media_compat_file_link {
-				debug_error!(?key, ?legacy, "Failed to remove legacy media symlink: {e}");
+		self.store.unlink(key).await;
+		match self.release_media_file(key).await {
+			| Some(storage_key) => self.delete_media_object(&storage_key).await,
+			| None => Ok(()),
+		}
+	}
+
+	/// Deletes stored content from the storage, and from the fallback storage
+	/// so it does not come back from there.
+	async fn delete_media_object(&self, storage_key: &[u8]) -> Result<()> {
+		if let Some(fallback) = &self.fallback {
+			fallback.delete(storage_key).await?;
+		}
+
+		self.store.delete(storage_key).await
+	}
+
+	/// Reads a media file from the storage, or else from the fallback storage.
+	async fn read_media_file(&self, key: &[u8]) -> Result<Vec<u8>> {
+		let key = self.storage_key(key).await;
+		if let Some(content) = self.store.get(&key).await? {
+			return Ok(content);
+		}
+
+		if let Some(fallback) = &self.fallback {
+			if let Some(content) = fallback.get(&key).await? {
+				return Ok(content);
 			}
 		}
 
-		Ok(file_rm?)
+		Err!(Request(NotFound("Media file not found.")))
 	}
 
-	async fn create_media_file(&self, key: &[u8]) -> Result<fs::File> {
-		let path = self.get_media_file(key);
-		debug!(?key, ?path, "Creating media file");
-
-		let file = fs::File::create(&path).await?;
-		if self.services.server.config.media_compat_file_link {
-			let legacy = self.get_media_file_b64(key);
-			if let Err(e) = fs::symlink(&path, &legacy).await {
-				debug_error!(
-					key = ?encode_key(key), ?path, ?legacy,
-					"Failed to create legacy media symlink: {e}"
-				);
+	async fn stat_media_file(&self, key: &[u8]) -> Result<Option<Stat>> {
+		Ok(self.find_media_file(key).await?.map(|(_, _, stat)| stat))
+	}
+
+	/// Finds the storage holding a media file, and the key its content is
+	/// stored under.
+	async fn find_media_file(
+		&self,
+		key: &[u8],
+	) -> Result<Option<(&dyn MediaStore, Vec<u8>, Stat)>> {
+		let storage_key = self.storage_key(key).await;
+		Ok(self
+			.find_stored_object(&storage_key)
+			.await?
+			.map(|(store, stat)| (store, storage_key, stat)))
+	}
+
+	/// Finds the storage holding stored content, looking in the fallback
+	/// storage when it is not in the configured one.
+	async fn find_stored_object(
+		&self,
+		storage_key: &[u8],
+	) -> Result<Option<(&dyn MediaStore, Stat)>> {
+		if let Some(stat) = self.store.stat(storage_key).await? {
+			return Ok(Some((&*self.store, stat)));
+		}
+
+		if let Some(fallback) = &self.fallback {
+			if let Some(stat) = fallback.stat(storage_key).await? {
+				return Ok(Some((&**fallback, stat)));
 			}
 		}
 
-		Ok(file)
+		Ok(None)
 	}
 
 	#[inline]
@@ -395,12 +736,7 @@ pub fn get_media_file(&self, key: &[u8]) -> PathBuf { self.get_media_file_sha256
 	#[must_use]
 	pub fn get_media_file_sha256(&self, key: &[u8]) -> PathBuf {
 		let mut r = self.get_media_dir();
-		// Using the hash of the base64 key as the filename
-		// This is to prevent the total length of the path from exceeding the maximum
-		// length in most filesystems
-		let digest = <sha2::Sha256 as sha2::Digest>::digest(key);
-		let encoded = encode_key(&digest);
-		r.push(encoded);
+		r.push(store::object_name(key));
 		r
 	}
 
@@ -416,12 +752,61 @@ pub fn get_media_file_b64(&self, key: &[u8]) -> PathBuf {
 	}
 
 	#[must_use]
-	pub fn get_media_dir(&self) -> PathBuf {
-		let mut r = PathBuf::new();
-		r.push(self.services.server.config.database_path.clone());
-		r.push("media");
-		r
+	pub fn get_media_dir(&self) -> PathBuf { store::media_dir(&self.services.server.config) }
+}
+
+/// Reads the first bytes of an upload to tell what it is, returning them with
+/// the whole upload.
+async fn sniff_stream(mut content: MediaStream) -> Result<(Vec<u8>, MediaStream)> {
+	let mut head = Vec::with_capacity(sanitize::SNIFF_LEN);
+	let mut chunks = Vec::new();
+	while head.len() < sanitize::SNIFF_LEN {
+		let Some(chunk) = content.next().await.transpose()? else {
+			break;
+		};
+
+		let missing = sanitize::SNIFF_LEN.saturating_sub(head.len());
+		head.extend_from_slice(&chunk[..missing.min(chunk.len())]);
+		chunks.push(Ok(chunk));
 	}
+
+	Ok((head, stream::iter(chunks).chain(content).boxed()))
+}
+
+/// Counts the bytes of an upload into `written` as they pass, refusing more
+/// than the `len` announced or the `remaining` quota of the uploader.
+fn count_stream(
+	content: MediaStream,
+	len: Option<u64>,
+	remaining: Option<u64>,
+	written: Arc<AtomicU64>,
+) -> MediaStream {
+	content
+		.map(move |chunk| {
+			let chunk = chunk?;
+			let chunk_len: u64 = utils::math::try_into(chunk.len())?;
+			let total = written
+				.fetch_add(chunk_len, Ordering::AcqRel)
+				.saturating_add(chunk_len);
+
+			if len.is_some_and(|len| total > len) {
+				return Err!(Request(InvalidParam("Upload is longer than its Content-Length.")));
+			}
+
+			if remaining.is_some_and(|remaining| total > remaining) {
+				return Err!(Request(TooLarge("Upload exceeds your media storage quota.")));
+			}
+
+			Ok(chunk)
+		})
+		.boxed()
+}
+
+/// Hashes an upload into `hasher` as it passes.
+fn hash_stream(content: MediaStream, hasher: Arc<Mutex<Sha256>>) -> MediaStream {
+	content
+		.inspect_ok(move |chunk| hasher.lock().expect("locked").update(chunk))
+		.boxed()
 }
 
 #[inline]
@@ -0,0 +1,151 @@
+//! oEmbed
+//!
+//! Sites offer oEmbed data for embedding their videos, posts and code, which
+//! often describes them better than the OpenGraph tags of their pages. The
+//! endpoint of a page is found through a link tag in it or by matching the
+//! URL against the schemes of `url_preview_oembed_providers`.
+
+use conduwuit::{config::OembedProvider, err, Result};
+use serde::Deserialize;
+use url::Url;
+
+use super::preview::{read_body, unescape, UrlPreviewData};
+
+/// The longest description taken from the HTML of an oEmbed response.
+const MAX_DESCRIPTION_LEN: usize = 500;
+
+/// An oEmbed response, with the fields a preview uses.
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct Oembed {
+	#[serde(rename = "type")]
+	pub(super) kind: Option<String>,
+	pub(super) title: Option<String>,
+	pub(super) author_name: Option<String>,
+	pub(super) provider_name: Option<String>,
+	/// The image itself, for the "photo" type.
+	pub(super) url: Option<String>,
+	pub(super) thumbnail_url: Option<String>,
+	/// The markup to embed, for the "video" and "rich" types.
+	pub(super) html: Option<String>,
+}
+
+impl Oembed {
+	/// Fills a preview with the oEmbed data, which is preferred over the
+	/// OpenGraph tags of the page.
+	pub(super) fn apply(&self, data: &mut UrlPreviewData) {
+		if self.title.is_some() {
+			data.title.clone_from(&self.title);
+		}
+
+		if self.provider_name.is_some() {
+			data.site_name.clone_from(&self.provider_name);
+		}
+
+		if self.kind.as_deref() == Some("video") {
+			data.kind = Some("video.other".to_owned());
+		}
+
+		if data.description.is_none() {
+			data.description = self
+				.html
+				.as_deref()
+				.map(html_text)
+				.filter(|text| !text.is_empty())
+				.or_else(|| self.author_name.clone());
+		}
+	}
+
+	/// The URL of the image to show in the preview.
+	pub(super) fn image(&self) -> Option<&str> {
+		match self.kind.as_deref() {
+			| Some("photo") => self.url.as_deref().or(self.thumbnail_url.as_deref()),
+			| _ => self.thumbnail_url.as_deref(),
+		}
+	}
+}
+
+/// Reads an oEmbed response of at most `max_size` bytes.
+pub(super) async fn read_oembed(response: reqwest::Response, max_size: usize) -> Result<Oembed> {
+	let body = read_body(response, max_size).await?;
+
+	serde_json::from_slice(&body).map_err(|e| err!(Request(Unknown("Invalid oEmbed data: {e}"))))
+}
+
+/// The endpoint of the first provider with a scheme matching `url`.
+pub(super) fn provider_endpoint(providers: &[OembedProvider], url: &Url) -> Option<Url> {
+	providers
+		.iter()
+		.find(|provider| {
+			provider
+				.schemes
+				.iter()
+				.any(|scheme| matches_scheme(scheme, url.as_str()))
+		})
+		.map(|provider| provider.endpoint.clone())
+}
+
+/// The request for the oEmbed data of `url` at a provider's endpoint.
+pub(super) fn endpoint_url(endpoint: &Url, url: &Url) -> Result<Url> {
+	// Braces are escaped in a parsed URL.
+	let endpoint = endpoint.as_str().replace("%7Bformat%7D", "json");
+	let mut endpoint =
+		Url::parse(&endpoint).map_err(|e| err!("Invalid oEmbed endpoint {endpoint}: {e}"))?;
+	endpoint
+		.query_pairs_mut()
+		.append_pair("url", url.as_str())
+		.append_pair("format", "json");
+
+	Ok(endpoint)
+}
+
+/// Whether `url` matches an oEmbed URL scheme, in which "*" matches anything.
+#[allow(clippy::string_slice)]
+pub(super) fn matches_scheme(scheme: &str, url: &str) -> bool {
+	let mut parts = scheme.split('*');
+	let first = parts.next().unwrap_or_default();
+	let Some(mut rest) = url.strip_prefix(first) else {
+		return false;
+	};
+
+	let mut parts = parts.peekable();
+	while let Some(part) = parts.next() {
+		if parts.peek().is_none() {
+			return rest.ends_with(part);
+		}
+
+		let Some(at) = rest.find(part) else {
+			return false;
+		};
+
+		rest = &rest[at.saturating_add(part.len())..];
+	}
+
+	// No "*" at all
+	rest.is_empty()
+}
+
+/// The text of HTML markup, with the tags dropped and whitespace collapsed.
+#[allow(clippy::string_slice)]
+fn html_text(html: &str) -> String {
+	let mut text = String::with_capacity(html.len());
+	let mut in_tag = false;
+	for c in html.chars() {
+		match c {
+			| '<' => in_tag = true,
+			| '>' if in_tag => {
+				in_tag = false;
+				text.push(' ');
+			},
+			| _ if !in_tag => text.push(c),
+			| _ => {},
+		}
+	}
+
+	let text = unescape(&text);
+	let text: Vec<&str> = text.split_whitespace().collect();
+	let text = text.join(" ");
+	match text.char_indices().nth(MAX_DESCRIPTION_LEN) {
+		| Some((end, _)) => format!("{}…", &text[..end]),
+		| None => text,
+	}
+}
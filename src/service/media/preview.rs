@@ -29,6 +29,21 @@ pub struct UrlPreviewData {
 	pub image_width: Option<u32>,
 	#[serde(skip_serializing_if = "Option::is_none", rename(serialize = "og:image:height"))]
 	pub image_height: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none", rename(serialize = "og:video"))]
+	pub video: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none", rename(serialize = "og:site_name"))]
+	pub site_name: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none", rename(serialize = "og:type"))]
+	pub kind: Option<String>,
+}
+
+/// What a page says about itself: its preview without the image, and the
+/// URLs of its image and of its oEmbed data.
+#[cfg(feature = "url_preview")]
+pub(super) struct Page {
+	pub(super) data: UrlPreviewData,
+	pub(super) image: Option<Url>,
+	pub(super) oembed: Option<Url>,
 }
 
 #[implement(Service)]
@@ -62,22 +77,11 @@ pub async fn get_url_preview(&self, url: &Url) -> Result<UrlPreviewData> {
 
 #[implement(Service)]
 async fn request_url_preview(&self, url: &Url) -> Result<UrlPreviewData> {
-	if let Ok(ip) = IPAddress::parse(url.host_str().expect("URL previously validated")) {
-		if !self.services.client.valid_cidr_range(&ip) {
-			return Err!(BadServerResponse("Requesting from this address is forbidden"));
-		}
-	}
+	self.check_url_host(url)?;
 
 	let client = &self.services.client.url_preview;
 	let response = client.head(url.as_str()).send().await?;
-
-	if let Some(remote_addr) = response.remote_addr() {
-		if let Ok(ip) = IPAddress::parse(remote_addr.ip().to_string()) {
-			if !self.services.client.valid_cidr_range(&ip) {
-				return Err!(BadServerResponse("Requesting from this address is forbidden"));
-			}
-		}
-	}
+	self.check_remote_addr(&response)?;
 
 	let Some(content_type) = response
 		.headers()
@@ -87,7 +91,7 @@ async fn request_url_preview(&self, url: &Url) -> Result<UrlPreviewData> {
 		return Err!(Request(Unknown("Unknown Content-Type")));
 	};
 	let data = match content_type {
-		| html if html.starts_with("text/html") => self.download_html(url.as_str()).await?,
+		| html if html.starts_with("text/html") => self.download_html(url).await?,
 		| img if img.starts_with("image/") => self.download_image(url.as_str()).await?,
 		| _ => return Err!(Request(Unknown("Unsupported Content-Type"))),
 	};
@@ -97,6 +101,33 @@ async fn request_url_preview(&self, url: &Url) -> Result<UrlPreviewData> {
 	Ok(data)
 }
 
+/// Refuses to request a host which is an address on a forbidden range.
+#[implement(Service)]
+fn check_url_host(&self, url: &Url) -> Result {
+	if let Ok(ip) = IPAddress::parse(url.host_str().unwrap_or_default()) {
+		if !self.services.client.valid_cidr_range(&ip) {
+			return Err!(BadServerResponse("Requesting from this address is forbidden"));
+		}
+	}
+
+	Ok(())
+}
+
+/// Refuses a response from an address on a forbidden range, which a name may
+/// resolve to.
+#[implement(Service)]
+fn check_remote_addr(&self, response: &reqwest::Response) -> Result {
+	if let Some(remote_addr) = response.remote_addr() {
+		if let Ok(ip) = IPAddress::parse(remote_addr.ip().to_string()) {
+			if !self.services.client.valid_cidr_range(&ip) {
+				return Err!(BadServerResponse("Requesting from this address is forbidden"));
+			}
+		}
+	}
+
+	Ok(())
+}
+
 #[cfg(feature = "url_preview")]
 #[implement(Service)]
 pub async fn download_image(&self, url: &str) -> Result<UrlPreviewData> {
@@ -106,6 +137,7 @@ pub async fn download_image(&self, url: &str) -> Result<UrlPreviewData> {
 
 	let image = self.services.client.url_preview.get(url).send().await?;
 	let image = image.bytes().await?;
+	let image = self.sanitize_media(&image)?;
 	let mxc = Mxc {
 		server_name: self.services.globals.server_name(),
 		media_id: &random_string(super::MXC_LENGTH),
@@ -113,7 +145,7 @@ pub async fn download_image(&self, url: &str) -> Result<UrlPreviewData> {
 
 	self.create(&mxc, None, None, None, &image).await?;
 
-	let cursor = std::io::Cursor::new(&image);
+	let cursor = std::io::Cursor::new(&*image);
 	let (width, height) = match ImageReader::new(cursor).with_guessed_format() {
 		| Err(_) => (None, None),
 		| Ok(reader) => match reader.into_dimensions() {
@@ -139,49 +171,297 @@ pub async fn download_image(&self, _url: &str) -> Result<UrlPreviewData> {
 
 #[cfg(feature = "url_preview")]
 #[implement(Service)]
-async fn download_html(&self, url: &str) -> Result<UrlPreviewData> {
-	use webpage::HTML;
+async fn download_html(&self, url: &Url) -> Result<UrlPreviewData> {
+	use conduwuit::debug_warn;
+
+	use super::oembed::{endpoint_url, provider_endpoint, Oembed};
+
+	let client = &self.services.client.url_preview;
+	let response = client.get(url.as_str()).send().await?;
+	self.check_remote_addr(&response)?;
+
+	let max_size = self.services.globals.url_preview_max_spider_size();
+	let html = read_html(response, max_size).await?;
+	let Page { mut data, image, oembed } = parse_html(html, url)?;
+
+	let config = &self.services.server.config;
+	let oembed = provider_endpoint(&config.url_preview_oembed_providers, url)
+		.map(|endpoint| endpoint_url(&endpoint, url))
+		.transpose()?
+		.or(oembed
+			.filter(|endpoint| config.url_preview_oembed && self.url_preview_allowed(endpoint)));
+
+	let oembed = match oembed {
+		| None => None,
+		| Some(endpoint) => self
+			.request_oembed(&endpoint)
+			.await
+			.inspect_err(|e| debug_warn!(%endpoint, "Failed to request oEmbed data: {e}"))
+			.ok(),
+	};
+
+	if let Some(oembed) = &oembed {
+		oembed.apply(&mut data);
+	}
+
+	let image = oembed
+		.as_ref()
+		.and_then(Oembed::image)
+		.and_then(|image| url.join(image).ok())
+		.filter(|image| self.url_preview_allowed(image))
+		.or(image);
+
+	if let Some(image) = image {
+		let image = self.download_image(image.as_str()).await?;
+		data.image = image.image;
+		data.image_size = image.image_size;
+		data.image_width = image.image_width;
+		data.image_height = image.image_height;
+	}
+
+	Ok(data)
+}
+
+#[cfg(not(feature = "url_preview"))]
+#[implement(Service)]
+async fn download_html(&self, _url: &Url) -> Result<UrlPreviewData> {
+	Err!(FeatureDisabled("url_preview"))
+}
+
+#[cfg(feature = "url_preview")]
+#[implement(Service)]
+async fn request_oembed(&self, endpoint: &Url) -> Result<super::oembed::Oembed> {
+	if !matches!(endpoint.scheme(), "http" | "https") {
+		return Err!(Request(Unknown("oEmbed endpoint is not HTTP")));
+	}
+
+	self.check_url_host(endpoint)?;
 
 	let client = &self.services.client.url_preview;
-	let mut response = client.get(url).send().await?;
+	let response = client
+		.get(endpoint.as_str())
+		.send()
+		.await?
+		.error_for_status()?;
+
+	self.check_remote_addr(&response)?;
 
+	let max_size = self.services.globals.url_preview_max_spider_size();
+	super::oembed::read_oembed(response, max_size).await
+}
+
+/// Reads the body of a response up to `url_preview_max_spider_size`.
+#[cfg(feature = "url_preview")]
+pub(super) async fn read_body(
+	mut response: reqwest::Response,
+	max_size: usize,
+) -> Result<Vec<u8>> {
 	let mut bytes: Vec<u8> = Vec::new();
 	while let Some(chunk) = response.chunk().await? {
 		bytes.extend_from_slice(&chunk);
-		if bytes.len() > self.services.globals.url_preview_max_spider_size() {
+		if bytes.len() > max_size {
 			debug!(
 				"Response body from URL {} exceeds url_preview_max_spider_size ({}), not \
 				 processing the rest of the response body and assuming our necessary data is in \
 				 this range.",
-				url,
-				self.services.globals.url_preview_max_spider_size()
+				response.url(),
+				max_size
 			);
 			break;
 		}
 	}
-	let body = String::from_utf8_lossy(&bytes);
-	let Ok(html) = HTML::from_string(body.to_string(), Some(url.to_owned())) else {
+
+	Ok(bytes)
+}
+
+/// Reads an HTML page, decoded from the charset its Content-Type or a meta
+/// tag declares.
+#[cfg(feature = "url_preview")]
+pub(super) async fn read_html(response: reqwest::Response, max_size: usize) -> Result<String> {
+	let content_type = response
+		.headers()
+		.get(reqwest::header::CONTENT_TYPE)
+		.and_then(|x| x.to_str().ok())
+		.map(ToOwned::to_owned);
+
+	let body = read_body(response, max_size).await?;
+
+	Ok(decode_html(&body, content_type.as_deref()))
+}
+
+/// Decodes an HTML page from the charset of its byte order mark, of its
+/// Content-Type or of a meta tag, in that order, or else from UTF-8.
+#[cfg(feature = "url_preview")]
+pub(super) fn decode_html(body: &[u8], content_type: Option<&str>) -> String {
+	use encoding_rs::{Encoding, UTF_8};
+
+	let encoding = content_type
+		.and_then(|content_type| {
+			content_type.split(';').find_map(|param| {
+				let (name, value) = param.split_once('=')?;
+				name.trim()
+					.eq_ignore_ascii_case("charset")
+					.then(|| value.trim().trim_matches('"'))
+			})
+		})
+		.and_then(|label| Encoding::for_label(label.as_bytes()))
+		.or_else(|| meta_charset(body))
+		.unwrap_or(UTF_8);
+
+	// A byte order mark overrides the encoding.
+	let (html, ..) = encoding.decode(body);
+
+	html.into_owned()
+}
+
+/// The charset of a `<meta charset>` or `<meta http-equiv="Content-Type">`
+/// tag, which has to be in the first 1024 bytes of a page.
+#[cfg(feature = "url_preview")]
+#[allow(clippy::string_slice)]
+fn meta_charset(body: &[u8]) -> Option<&'static encoding_rs::Encoding> {
+	use encoding_rs::Encoding;
+
+	let head = body.get(..1024).unwrap_or(body);
+	let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+	head.match_indices("<meta").find_map(|(at, _)| {
+		let tag = &head[at..];
+		let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+		let (_, charset) = tag.split_once("charset")?;
+		let label = charset
+			.trim_start()
+			.strip_prefix('=')?
+			.trim_start()
+			.trim_start_matches(['"', '\'']);
+
+		let end = label
+			.find(|c: char| !c.is_ascii_alphanumeric() && !"-_.:".contains(c))
+			.unwrap_or(label.len());
+
+		// A page cannot be in an encoding which its meta tag is unreadable in.
+		Encoding::for_label(label[..end].as_bytes()).map(Encoding::output_encoding)
+	})
+}
+
+/// Reads the OpenGraph tags of a page, falling back to its title and
+/// description, and finds the oEmbed data it links to.
+#[cfg(feature = "url_preview")]
+pub(super) fn parse_html(html: String, url: &Url) -> Result<Page> {
+	use webpage::HTML;
+
+	let oembed = oembed_link(&html).and_then(|href| url.join(&href).ok());
+	let Ok(html) = HTML::from_string(html, Some(url.to_string())) else {
 		return Err!(Request(Unknown("Failed to parse HTML")));
 	};
 
-	let mut data = match html.opengraph.images.first() {
-		| None => UrlPreviewData::default(),
-		| Some(obj) => self.download_image(&obj.url).await?,
+	let opengraph = html.opengraph;
+	let props = opengraph.properties;
+
+	/* use OpenGraph title/description, but fall back to HTML if not available */
+	let data = UrlPreviewData {
+		title: props.get("title").cloned().or(html.title),
+		description: props.get("description").cloned().or(html.description),
+		video: opengraph
+			.videos
+			.first()
+			.and_then(|video| url.join(&video.url).ok())
+			.map(String::from),
+		site_name: props.get("site_name").cloned(),
+		kind: Some(opengraph.og_type).filter(|kind| !kind.is_empty()),
+		..Default::default()
 	};
 
-	let props = html.opengraph.properties;
+	let image = opengraph
+		.images
+		.first()
+		.and_then(|image| url.join(&image.url).ok());
 
-	/* use OpenGraph title/description, but fall back to HTML if not available */
-	data.title = props.get("title").cloned().or(html.title);
-	data.description = props.get("description").cloned().or(html.description);
+	Ok(Page { data, image, oembed })
+}
 
-	Ok(data)
+/// The `href` of the first link to JSON oEmbed data in a page.
+#[cfg(feature = "url_preview")]
+#[allow(clippy::string_slice)]
+fn oembed_link(html: &str) -> Option<String> {
+	// Lowercasing ASCII keeps the offsets of the original.
+	let lower = html.to_ascii_lowercase();
+	lower.match_indices("<link").find_map(|(at, _)| {
+		let end = lower[at..]
+			.find('>')
+			.map_or(lower.len(), |end| at.saturating_add(end));
+
+		let attributes = attributes(&html[at..end]);
+		let attribute = |name: &str| {
+			attributes
+				.iter()
+				.find(|(key, _)| key == name)
+				.map(|(_, value)| value.clone())
+		};
+
+		attribute("type")
+			.is_some_and(|kind| kind.eq_ignore_ascii_case("application/json+oembed"))
+			.then(|| attribute("href"))
+			.flatten()
+	})
 }
 
-#[cfg(not(feature = "url_preview"))]
-#[implement(Service)]
-async fn download_html(&self, _url: &str) -> Result<UrlPreviewData> {
-	Err!(FeatureDisabled("url_preview"))
+/// The attributes of an HTML start tag, with lowercase names and unescaped
+/// values.
+#[cfg(feature = "url_preview")]
+#[allow(clippy::string_slice)]
+fn attributes(tag: &str) -> Vec<(String, String)> {
+	let mut attributes = Vec::new();
+	let mut rest = tag
+		.trim_start_matches('<')
+		.trim_start_matches(|c: char| !c.is_whitespace() && c != '/' && c != '>');
+
+	loop {
+		rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+		let end = rest
+			.find(|c: char| c.is_whitespace() || matches!(c, '=' | '/' | '>'))
+			.unwrap_or(rest.len());
+
+		let name = rest[..end].to_ascii_lowercase();
+		if name.is_empty() {
+			break;
+		}
+
+		rest = rest[end..].trim_start();
+		let mut value = String::new();
+		if let Some(after) = rest.strip_prefix('=') {
+			let after = after.trim_start();
+			let (raw, remaining) = match after.chars().next() {
+				| Some(quote @ ('"' | '\'')) => {
+					let quoted = &after[1..];
+					let end = quoted.find(quote).unwrap_or(quoted.len());
+					(&quoted[..end], quoted.get(end.saturating_add(1)..).unwrap_or_default())
+				},
+				| _ => {
+					let end = after
+						.find(|c: char| c.is_whitespace() || c == '>')
+						.unwrap_or(after.len());
+					after.split_at(end)
+				},
+			};
+
+			value = unescape(raw);
+			rest = remaining;
+		}
+
+		attributes.push((name, value));
+	}
+
+	attributes
+}
+
+/// Unescapes the character references HTML attributes and text commonly have.
+#[cfg(feature = "url_preview")]
+pub(super) fn unescape(text: &str) -> String {
+	text.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&#39;", "'")
+		.replace("&#x27;", "'")
+		.replace("&amp;", "&")
 }
 
 #[implement(Service)]
@@ -0,0 +1,117 @@
+use conduwuit::{debug_warn, implement, utils::ReadyExt};
+use futures::Stream;
+use ruma::{Mxc, OwnedMxcUri, RoomId, UserId};
+use serde_json::Value as JsonValue;
+
+/// Quarantines media or releases it from quarantine. Quarantined media is
+/// neither served to clients and servers nor fetched again from remote
+/// servers, and does not count towards the usage of its uploader.
+#[implement(super::Service)]
+pub async fn set_quarantined(&self, mxc: &Mxc<'_>, quarantined: bool) {
+	let _lock = self.mxc_mutex.lock(mxc.to_string().as_str()).await;
+	if self.db.is_quarantined(mxc).await == quarantined {
+		return;
+	}
+
+	self.db.set_quarantined(mxc, quarantined);
+	self.quarantine_media_usage(mxc, quarantined).await;
+}
+
+#[implement(super::Service)]
+pub async fn is_quarantined(&self, mxc: &Mxc<'_>) -> bool { self.db.is_quarantined(mxc).await }
+
+/// Quarantines or releases all media uploaded by a local user. Returns the
+/// number of MXCs changed.
+#[implement(super::Service)]
+pub async fn set_quarantined_from_user(&self, user: &UserId, quarantined: bool) -> usize {
+	let mxcs = self.db.get_all_user_mxcs(user).await;
+
+	self.set_quarantined_mxcs(&mxcs, quarantined).await
+}
+
+/// Quarantines or releases all media referenced by the events in a room's
+/// timeline. Returns the number of MXCs changed.
+#[implement(super::Service)]
+pub async fn set_quarantined_from_room(&self, room_id: &RoomId, quarantined: bool) -> usize {
+	let mut mxcs = Vec::new();
+	self.services
+		.timeline
+		.pdus(None, room_id, None)
+		.ready_filter_map(Result::ok)
+		.ready_for_each(|(_, pdu)| {
+			if let Ok(content) = serde_json::from_str(pdu.content.get()) {
+				find_mxcs(&content, &mut mxcs);
+			}
+		})
+		.await;
+
+	mxcs.sort_unstable();
+	mxcs.dedup();
+
+	self.set_quarantined_mxcs(&mxcs, quarantined).await
+}
+
+/// Lists the MXCs in quarantine.
+#[implement(super::Service)]
+pub fn quarantined(&self) -> impl Stream<Item = OwnedMxcUri> + Send + '_ {
+	self.db.quarantined_mxcs()
+}
+
+#[implement(super::Service)]
+async fn set_quarantined_mxcs(&self, mxcs: &[OwnedMxcUri], quarantined: bool) -> usize {
+	let mut count = 0_usize;
+	for mxc in mxcs {
+		let mxc: Mxc<'_> = match mxc.as_str().try_into() {
+			| Ok(mxc) => mxc,
+			| Err(e) => {
+				debug_warn!(?mxc, "Skipping invalid MXC: {e}");
+				continue;
+			},
+		};
+
+		self.set_quarantined(&mxc, quarantined).await;
+		count = count.saturating_add(1);
+	}
+
+	count
+}
+
+/// Collects the MXC URIs anywhere in an event's content, including
+/// thumbnails, encrypted files and images inlined in formatted bodies.
+pub(super) fn find_mxcs(value: &JsonValue, mxcs: &mut Vec<OwnedMxcUri>) {
+	match value {
+		| JsonValue::String(s) if s.starts_with("mxc://") => mxcs.push(s.as_str().into()),
+		| JsonValue::Array(values) => values.iter().for_each(|v| find_mxcs(v, mxcs)),
+		| JsonValue::Object(map) =>
+			for (key, value) in map {
+				match value {
+					| JsonValue::String(html) if key == "formatted_body" =>
+						find_html_mxcs(html, mxcs),
+					| value => find_mxcs(value, mxcs),
+				}
+			},
+		| _ => {},
+	}
+}
+
+/// Collects the MXC URIs quoted as `src` attributes in HTML, as in
+/// `<img src="mxc://…">`.
+fn find_html_mxcs(html: &str, mxcs: &mut Vec<OwnedMxcUri>) {
+	let parts: Vec<&str> = html.split(['"', '\'']).collect();
+	for pair in parts.windows(2) {
+		let [before, quoted] = pair else {
+			continue;
+		};
+
+		let is_src = before
+			.trim_end()
+			.strip_suffix('=')
+			.map(str::trim_end)
+			.and_then(|attribute| attribute.rsplit(|c: char| c.is_ascii_whitespace()).next())
+			.is_some_and(|name| name.eq_ignore_ascii_case("src"));
+
+		if is_src && quoted.starts_with("mxc://") {
+			mxcs.push((*quoted).into());
+		}
+	}
+}
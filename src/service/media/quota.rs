@@ -0,0 +1,182 @@
+use conduwuit::{implement, result::LogErr, Error, Result};
+use futures::StreamExt;
+use http::StatusCode;
+use ruma::{api::client::error::ErrorKind, Mxc, OwnedUserId, UserId};
+
+use super::{store::Stat, Dim};
+
+/// Adds an upload of a local user to their media usage, refusing it when it
+/// exceeds their quota.
+#[implement(super::Service)]
+pub(super) async fn add_media_usage(&self, user: &UserId, len: u64) -> Result {
+	let _lock = self.usage_mutex.lock(user).await;
+	let usage = self.db.get_media_usage(user).await;
+	let quota = self.media_quota(user).await;
+	match charge(usage, len, quota) {
+		| Ok(usage) => self.db.set_media_usage(user, usage),
+		| Err(quota) => return Err(self.quota_exceeded(user, quota).await),
+	}
+
+	Ok(())
+}
+
+/// Bytes a local user can still upload before their quota is exceeded;
+/// `None` when they have no quota.
+#[implement(super::Service)]
+pub(super) async fn remaining_media_quota(&self, user: &UserId) -> Option<u64> {
+	let quota = self.media_quota(user).await?;
+	let usage = self.db.get_media_usage(user).await;
+
+	Some(quota.saturating_sub(usage))
+}
+
+/// Tells the user their quota is exceeded and returns the error refusing
+/// their upload.
+#[implement(super::Service)]
+pub(super) async fn quota_exceeded(&self, user: &UserId, quota: u64) -> Error {
+	self.notify_quota_exceeded(user, quota).await;
+
+	Error::Request(
+		ErrorKind::ResourceLimitExceeded { admin_contact: self.admin_contact() },
+		"Your media storage quota is exceeded.".into(),
+		StatusCode::FORBIDDEN,
+	)
+}
+
+/// Removes deleted media from the usage of the user who uploaded it.
+#[implement(super::Service)]
+pub(super) async fn sub_media_usage(&self, mxc: &Mxc<'_>, len: u64) {
+	if !self.services.globals.server_is_ours(mxc.server_name) {
+		return;
+	}
+
+	let Some(user) = self.db.get_mxc_user(mxc).await else {
+		return;
+	};
+
+	self.refund_media_usage(&user, len).await;
+}
+
+/// Quarantined media does not count towards the usage of the user who
+/// uploaded it; it is counted again when it is released, whatever their quota.
+#[implement(super::Service)]
+pub(super) async fn quarantine_media_usage(&self, mxc: &Mxc<'_>, quarantined: bool) {
+	if !self.services.globals.server_is_ours(mxc.server_name) {
+		return;
+	}
+
+	let Ok(metadata) = self.db.search_file_metadata(mxc, &Dim::default()).await else {
+		return;
+	};
+
+	let Ok(Some(Stat { len, .. })) = self.stat_media_file(&metadata.key).await else {
+		return;
+	};
+
+	let Some(user) = self.db.get_mxc_user(mxc).await else {
+		return;
+	};
+
+	if quarantined {
+		self.refund_media_usage(&user, len).await;
+		return;
+	}
+
+	let _lock = self.usage_mutex.lock(&user).await;
+	let usage = self.db.get_media_usage(&user).await.saturating_add(len);
+	self.db.set_media_usage(&user, usage);
+}
+
+/// Gives back usage charged for an upload which failed or was removed.
+#[implement(super::Service)]
+pub(super) async fn refund_media_usage(&self, user: &UserId, len: u64) {
+	let _lock = self.usage_mutex.lock(user).await;
+	let usage = self.db.get_media_usage(user).await.saturating_sub(len);
+	self.db.set_media_usage(user, usage);
+	self.quota_notified.lock().expect("locked").remove(user);
+}
+
+/// The quota of a local user in bytes; `None` when they have none.
+#[implement(super::Service)]
+pub async fn media_quota(&self, user: &UserId) -> Option<u64> {
+	let quota = self.db.get_media_quota(user).await;
+
+	effective_quota(quota, self.services.server.config.media_quota)
+}
+
+/// Sets the quota of a single user, overriding `media_quota`. `Some(0)` lifts
+/// their quota, `None` returns them to the configured one.
+#[implement(super::Service)]
+pub fn set_media_quota(&self, user: &UserId, quota: Option<u64>) {
+	self.db.set_media_quota(user, quota);
+	self.quota_notified.lock().expect("locked").remove(user);
+}
+
+/// Bytes of media a user uploaded which have not been deleted.
+#[implement(super::Service)]
+pub async fn media_usage(&self, user: &UserId) -> u64 { self.db.get_media_usage(user).await }
+
+/// The users who uploaded the most media, with their usage in bytes.
+#[implement(super::Service)]
+pub async fn top_uploaders(&self, limit: usize) -> Vec<(OwnedUserId, u64)> {
+	let mut usage: Vec<_> = self.db.all_media_usage().collect().await;
+	usage.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+	usage.truncate(limit);
+	usage
+}
+
+/// Tells the user once that their quota is exceeded, until their usage or
+/// quota changes.
+#[implement(super::Service)]
+async fn notify_quota_exceeded(&self, user: &UserId, quota: u64) {
+	let server_notices = &self.services.server_notices;
+	if !server_notices.is_enabled()
+		|| !self
+			.quota_notified
+			.lock()
+			.expect("locked")
+			.insert(user.to_owned())
+	{
+		return;
+	}
+
+	let body = format!(
+		"You have reached your media storage quota of {quota} bytes. Contact a server admin to \
+		 raise your quota or to remove media you no longer need."
+	);
+
+	server_notices
+		.send_usage_limit_notice(user, &body)
+		.await
+		.log_err()
+		.ok();
+}
+
+/// The usage after adding an upload of `len` bytes, or the quota it would
+/// exceed.
+pub(super) fn charge(usage: u64, len: u64, quota: Option<u64>) -> Result<u64, u64> {
+	let usage = usage.saturating_add(len);
+	match quota {
+		| Some(quota) if usage > quota => Err(quota),
+		| _ => Ok(usage),
+	}
+}
+
+/// The quota of a user from their own, if set, or else the configured one,
+/// where 0 means no quota.
+pub(super) fn effective_quota(user_quota: Option<u64>, config_quota: u64) -> Option<u64> {
+	let quota = user_quota.unwrap_or(config_quota);
+
+	(quota != 0).then_some(quota)
+}
+
+#[implement(super::Service)]
+fn admin_contact(&self) -> String {
+	let well_known = &self.services.server.config.well_known;
+	well_known
+		.support_email
+		.as_ref()
+		.map(|email| format!("mailto:{email}"))
+		.or_else(|| well_known.support_page.as_ref().map(ToString::to_string))
+		.unwrap_or_default()
+}
@@ -0,0 +1,62 @@
+use conduwuit::{Error, Result};
+use http::StatusCode;
+use ruma::api::client::error::ErrorKind;
+
+/// A range of bytes of a media file, from `start` up to but excluding `end`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ByteRange {
+	pub start: u64,
+	pub end: u64,
+}
+
+impl ByteRange {
+	/// Parses the value of a `Range` header for a file of `len` bytes.
+	///
+	/// Only a single range of bytes is supported; for anything else `None` is
+	/// returned and the whole file should be sent. Ranges outside the file
+	/// are refused with `416 Range Not Satisfiable`.
+	pub fn parse(header: &str, len: u64) -> Result<Option<Self>> {
+		let Some(spec) = header.trim().strip_prefix("bytes=") else {
+			return Ok(None);
+		};
+
+		let Some((start, end)) = spec.trim().split_once('-') else {
+			return Ok(None);
+		};
+
+		let (start, end) = (start.trim(), end.trim());
+		let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+			// bytes=start-end, both inclusive
+			| (Ok(start), Ok(end)) if start <= end => Self {
+				start,
+				end: end.saturating_add(1).min(len),
+			},
+			// bytes=start-
+			| (Ok(start), Err(_)) if end.is_empty() => Self { start, end: len },
+			// bytes=-suffix
+			| (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => Self {
+				start: len.saturating_sub(suffix),
+				end: len,
+			},
+			| _ => return Ok(None),
+		};
+
+		if range.start >= len {
+			return Err(Error::Request(
+				ErrorKind::Unknown,
+				format!("Range {header:?} is outside of the file of {len} bytes.").into(),
+				StatusCode::RANGE_NOT_SATISFIABLE,
+			));
+		}
+
+		Ok(Some(range))
+	}
+
+	#[inline]
+	#[must_use]
+	pub fn len(&self) -> u64 { self.end.saturating_sub(self.start) }
+
+	#[inline]
+	#[must_use]
+	pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
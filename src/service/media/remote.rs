@@ -29,7 +29,7 @@ pub async fn fetch_remote_thumbnail(
 	timeout_ms: Duration,
 	dim: &Dim,
 ) -> Result<FileMeta> {
-	self.check_fetch_authorized(mxc)?;
+	self.check_fetch_authorized(mxc).await?;
 
 	let result = self
 		.fetch_thumbnail_unauthenticated(mxc, user, server, timeout_ms, dim)
@@ -52,7 +52,7 @@ pub async fn fetch_remote_content(
 	server: Option<&ServerName>,
 	timeout_ms: Duration,
 ) -> Result<FileMeta> {
-	self.check_fetch_authorized(mxc)?;
+	self.check_fetch_authorized(mxc).await?;
 
 	let result = self
 		.fetch_content_unauthenticated(mxc, user, server, timeout_ms)
@@ -83,7 +83,7 @@ async fn fetch_thumbnail_authenticated(
 		method: dim.method.clone().into(),
 		width: dim.width.into(),
 		height: dim.height.into(),
-		animated: true.into(),
+		animated: dim.animated.into(),
 		timeout_ms,
 	};
 
@@ -134,7 +134,7 @@ async fn fetch_thumbnail_unauthenticated(
 	let request = Request {
 		allow_remote: true,
 		allow_redirect: true,
-		animated: true.into(),
+		animated: dim.animated.into(),
 		method: dim.method.clone().into(),
 		width: dim.width.into(),
 		height: dim.height.into(),
@@ -355,7 +355,7 @@ pub async fn fetch_remote_thumbnail_legacy(
 	};
 
 	self.check_legacy_freeze()?;
-	self.check_fetch_authorized(&mxc)?;
+	self.check_fetch_authorized(&mxc).await?;
 	let reponse = self
 		.services
 		.sending
@@ -372,7 +372,7 @@ pub async fn fetch_remote_thumbnail_legacy(
 		})
 		.await?;
 
-	let dim = Dim::from_ruma(body.width, body.height, body.method.clone())?;
+	let dim = Dim::from_ruma(body.width, body.height, body.method.clone(), body.animated)?;
 	self.upload_thumbnail(&mxc, None, None, reponse.content_type.as_deref(), &dim, &reponse.file)
 		.await?;
 
@@ -388,7 +388,7 @@ pub async fn fetch_remote_content_legacy(
 	timeout_ms: Duration,
 ) -> Result<media::get_content::v3::Response, Error> {
 	self.check_legacy_freeze()?;
-	self.check_fetch_authorized(mxc)?;
+	self.check_fetch_authorized(mxc).await?;
 	let response = self
 		.services
 		.sending
@@ -420,7 +420,7 @@ pub async fn fetch_remote_content_legacy(
 }
 
 #[implement(super::Service)]
-fn check_fetch_authorized(&self, mxc: &Mxc<'_>) -> Result<()> {
+async fn check_fetch_authorized(&self, mxc: &Mxc<'_>) -> Result<()> {
 	if self
 		.services
 		.server
@@ -434,6 +434,11 @@ fn check_fetch_authorized(&self, mxc: &Mxc<'_>) -> Result<()> {
 		return Err!(Request(NotFound("Media not found.")));
 	}
 
+	if self.db.is_quarantined(mxc).await {
+		debug_warn!(%mxc, "Received request for quarantined media");
+		return Err!(Request(NotFound("Media not found.")));
+	}
+
 	Ok(())
 }
 
@@ -0,0 +1,383 @@
+//! Media Retention
+//!
+//! The media worker purges media periodically: remote media nobody accessed
+//! for `media_retention_remote_days`, and local media older than
+//! `media_retention_local_days` which no event references. Avatars of users
+//! and rooms are kept, and quarantined media is left for the admins.
+//!
+//! While local media is retained, the local MXCs events reference are indexed
+//! as events arrive. Events which are redacted later keep their references,
+//! and events in encrypted rooms cannot be read, so local media uploaded by
+//! anyone who has been in an encrypted room is kept for the longer
+//! `media_retention_encrypted_days`.
+
+use std::{
+	collections::{HashMap, HashSet},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use conduwuit::{
+	debug_info, debug_warn, implement, info,
+	utils::{time::now_millis, ReadyExt},
+	warn, PduEvent, Result,
+};
+use futures::{future, StreamExt};
+use ruma::{events::TimelineEventType, Mxc, OwnedMxcUri, OwnedRoomId, OwnedUserId};
+use tokio::time::sleep;
+
+use super::{data::Metadata, quarantine::find_mxcs, Dim};
+
+/// Accesses closer together than this, in milliseconds, are recorded once,
+/// so serving media does not write to the database each time.
+const ACCESS_RESOLUTION: u64 = 60 * 60 * 1000;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// The media a pass of the retention policies deleted, or would delete in a
+/// dry run.
+#[derive(Debug, Default)]
+pub struct RetentionReport {
+	pub remote: Vec<OwnedMxcUri>,
+	pub local: Vec<OwnedMxcUri>,
+	/// Bytes of media files, counting those with shared content each time.
+	pub bytes: u64,
+	/// How many were deleted; none in a dry run.
+	pub deleted: usize,
+}
+
+/// Applies the retention policies every `media_retention_interval`, unless
+/// there are none.
+#[implement(super::Service)]
+pub(super) async fn retention_worker(&self) {
+	let config = &self.services.server.config;
+	// Events arriving meanwhile are not indexed, so they are indexed again
+	// once local media is retained again.
+	if config.media_retention_local_days == 0 {
+		self.db.unset_mxc_references_indexed();
+	}
+
+	if config.media_retention_remote_days == 0 && config.media_retention_local_days == 0 {
+		return future::pending().await;
+	}
+
+	let interval = Duration::from_secs(config.media_retention_interval);
+	loop {
+		sleep(interval).await;
+
+		let dry_run = config.media_retention_dry_run;
+		match self.apply_retention(dry_run).await {
+			| Ok(report) => info!(
+				dry_run,
+				remote = report.remote.len(),
+				local = report.local.len(),
+				bytes = report.bytes,
+				deleted = report.deleted,
+				"Applied media retention policies"
+			),
+			| Err(e) => warn!("Failed to apply media retention policies: {e}"),
+		}
+	}
+}
+
+/// Deletes the media which expired under the retention policies, or only
+/// reports it when `dry_run` is set. Deletions are spread out to at most
+/// `media_retention_rate` per second.
+#[implement(super::Service)]
+pub async fn apply_retention(&self, dry_run: bool) -> Result<RetentionReport> {
+	let config = &self.services.server.config;
+	let now = SystemTime::now();
+	let cutoff = |days: u64| {
+		(days > 0)
+			.then(|| now.checked_sub(Duration::from_secs(days.saturating_mul(DAY))))
+			.flatten()
+	};
+
+	let remote_cutoff = cutoff(config.media_retention_remote_days);
+	let local_cutoff = cutoff(config.media_retention_local_days);
+	let encrypted_cutoff = cutoff(
+		config
+			.media_retention_local_days
+			.max(config.media_retention_encrypted_days),
+	);
+	let mut report = RetentionReport::default();
+	if remote_cutoff.is_none() && local_cutoff.is_none() {
+		return Ok(report);
+	}
+
+	let (referenced, avatars) = self.referenced_mxcs(local_cutoff.is_some()).await;
+	let mut encrypted = EncryptedUploaders::default();
+
+	let mut mxcs = self.get_all_mxcs().await?;
+	mxcs.sort_unstable();
+	mxcs.dedup();
+	for mxc in mxcs {
+		if avatars.contains(&mxc) {
+			continue;
+		}
+
+		let Ok(parsed) = Mxc::try_from(mxc.as_str()) else {
+			continue;
+		};
+
+		let Ok(Metadata {
+			key,
+			quarantined: false,
+			created,
+			last_access,
+			..
+		}) = self.retention_metadata(&parsed).await
+		else {
+			continue;
+		};
+
+		let Ok(Some(stat)) = self.stat_media_file(&key).await else {
+			continue;
+		};
+
+		// Media from before its creation was recorded is as old as its file.
+		let created = created.unwrap_or(stat.modified);
+		let local = self.services.globals.server_is_ours(parsed.server_name);
+		let expired = if local {
+			local_cutoff.is_some_and(|cutoff| created < cutoff)
+				&& !referenced.contains(&mxc)
+				&& (encrypted_cutoff.is_some_and(|cutoff| created < cutoff)
+					|| !self.uploaded_encrypted(&parsed, &mut encrypted).await)
+		} else {
+			remote_cutoff.is_some_and(|cutoff| last_access.unwrap_or(created) < cutoff)
+		};
+
+		if !expired {
+			continue;
+		}
+
+		debug_info!(%mxc, local, dry_run, "Media expired");
+		report.bytes = report.bytes.saturating_add(stat.len);
+		if local {
+			report.local.push(mxc);
+		} else {
+			report.remote.push(mxc);
+		}
+	}
+
+	if dry_run {
+		return Ok(report);
+	}
+
+	let delay = Duration::from_secs(1)
+		.checked_div(config.media_retention_rate)
+		.unwrap_or_default();
+
+	let mut deleted_local = HashSet::new();
+	for mxc in report.remote.iter().chain(&report.local) {
+		let Ok(parsed) = Mxc::try_from(mxc.as_str()) else {
+			continue;
+		};
+
+		match self.delete(&parsed).await {
+			| Err(e) => debug_warn!(%mxc, "Failed to delete expired media: {e}"),
+			| Ok(()) => {
+				report.deleted = report.deleted.saturating_add(1);
+				if self.services.globals.server_is_ours(parsed.server_name) {
+					deleted_local.insert(mxc.clone());
+				}
+			},
+		}
+
+		sleep(delay).await;
+	}
+
+	// Previews would show the deleted images of URLs.
+	if !deleted_local.is_empty() {
+		self.db.remove_url_previews_showing(&deleted_local).await;
+	}
+
+	Ok(report)
+}
+
+/// The metadata of media, or of one of its thumbnails when only thumbnails of
+/// it are stored, as of remote media which was only ever fetched as a
+/// thumbnail.
+#[implement(super::Service)]
+async fn retention_metadata(&self, mxc: &Mxc<'_>) -> Result<Metadata> {
+	if let Ok(metadata) = self.db.search_file_metadata(mxc, &Dim::default()).await {
+		return Ok(metadata);
+	}
+
+	let keys = self.db.search_mxc_metadata_prefix(mxc).await?;
+	let key = keys.into_iter().next().unwrap_or_default();
+	let (created, accessed) = self.db.get_media_access(mxc).await;
+
+	Ok(Metadata {
+		content_disposition: None,
+		content_type: None,
+		key,
+		quarantined: self.db.is_quarantined(mxc).await,
+		created: from_millis(created),
+		last_access: from_millis(accessed),
+	})
+}
+
+/// The encrypted rooms, found once per pass, and which uploaders of local
+/// media have been in them.
+#[derive(Default)]
+struct EncryptedUploaders {
+	rooms: Option<Vec<OwnedRoomId>>,
+	uploaders: HashMap<OwnedUserId, bool>,
+}
+
+/// Whether the uploader of local media has been in an encrypted room, where
+/// the events referencing the media cannot be read.
+#[implement(super::Service)]
+async fn uploaded_encrypted(&self, mxc: &Mxc<'_>, encrypted: &mut EncryptedUploaders) -> bool {
+	let Some(user_id) = self.db.get_mxc_user(mxc).await else {
+		return false;
+	};
+
+	if let Some(&uploaded) = encrypted.uploaders.get(&user_id) {
+		return uploaded;
+	}
+
+	if encrypted.rooms.is_none() {
+		let rooms = self
+			.services
+			.metadata
+			.iter_ids()
+			.filter_map(|room_id| async move {
+				self.services
+					.state_accessor
+					.is_encrypted_room(room_id)
+					.await
+					.then(|| room_id.to_owned())
+			})
+			.collect()
+			.await;
+
+		encrypted.rooms = Some(rooms);
+	}
+
+	let mut uploaded = false;
+	for room_id in encrypted.rooms.iter().flatten() {
+		if self
+			.services
+			.state_cache
+			.once_joined(&user_id, room_id)
+			.await
+		{
+			uploaded = true;
+			break;
+		}
+	}
+
+	encrypted.uploaders.insert(user_id, uploaded);
+	uploaded
+}
+
+/// Collects the MXCs referenced by any event when local media is retained,
+/// and those which are avatars of users and rooms together with the avatars
+/// of our users.
+#[implement(super::Service)]
+async fn referenced_mxcs(&self, local: bool) -> (HashSet<OwnedMxcUri>, HashSet<OwnedMxcUri>) {
+	if local {
+		self.index_all_media_references().await;
+	}
+
+	let (mut referenced, mut avatars) = (HashSet::new(), HashSet::new());
+	let mut references = self.db.mxc_references().boxed();
+	while let Some((mxc, avatar)) = references.next().await {
+		if avatar {
+			avatars.insert(mxc.clone());
+		}
+
+		if local {
+			referenced.insert(mxc);
+		}
+	}
+
+	let mut users = self.services.users.stream().boxed();
+	while let Some(user_id) = users.next().await {
+		if let Ok(avatar_url) = self.services.users.avatar_url(user_id).await {
+			avatars.insert(avatar_url);
+		}
+	}
+
+	(referenced, avatars)
+}
+
+/// Indexes the local MXCs an event references while local media is retained,
+/// and the avatars it sets while remote media is, which are kept.
+#[implement(super::Service)]
+pub async fn index_media_references(&self, pdu: &PduEvent) {
+	let config = &self.services.server.config;
+	let avatar =
+		matches!(pdu.kind, TimelineEventType::RoomMember | TimelineEventType::RoomAvatar);
+	let local = config.media_retention_local_days > 0;
+	let remote = avatar && config.media_retention_remote_days > 0;
+	if !local && !remote {
+		return;
+	}
+
+	let Ok(content) = serde_json::from_str(pdu.content.get()) else {
+		return;
+	};
+
+	let mut mxcs = Vec::new();
+	find_mxcs(&content, &mut mxcs);
+	for mxc in mxcs {
+		let ours = mxc
+			.server_name()
+			.is_ok_and(|server_name| self.services.globals.server_is_ours(server_name));
+
+		if (local && ours) || remote {
+			self.db.add_mxc_reference(mxc.as_str(), avatar).await;
+		}
+	}
+}
+
+/// Indexes the MXCs referenced by the events from before they were indexed
+/// as they arrived, once.
+#[implement(super::Service)]
+async fn index_all_media_references(&self) {
+	if self.db.mxc_references_indexed().await {
+		return;
+	}
+
+	warn!("Indexing the media referenced by events, this may take a while");
+	let mut rooms = self.services.metadata.iter_ids().boxed();
+	while let Some(room_id) = rooms.next().await {
+		let mut pdus = self
+			.services
+			.timeline
+			.pdus(None, room_id, None)
+			.ready_filter_map(Result::ok)
+			.boxed();
+
+		while let Some((_, pdu)) = pdus.next().await {
+			self.index_media_references(&pdu).await;
+		}
+	}
+
+	self.db.set_mxc_references_indexed();
+	info!("Finished indexing the media referenced by events");
+}
+
+/// Records that media was created now.
+#[implement(super::Service)]
+pub(super) fn record_media_created(&self, mxc: &Mxc<'_>) {
+	let now = now_millis();
+	self.db.set_media_access(mxc, now, now);
+}
+
+/// Records that media was accessed now, for the retention of remote media.
+#[implement(super::Service)]
+pub(super) async fn record_media_access(&self, mxc: &Mxc<'_>) {
+	let now = now_millis();
+	let (created, accessed) = self.db.get_media_access(mxc).await;
+	if now.saturating_sub(accessed) >= ACCESS_RESOLUTION {
+		self.db.set_media_access(mxc, created, now);
+	}
+}
+
+/// Converts milliseconds since the epoch as recorded, where 0 is unknown.
+pub(super) fn from_millis(millis: u64) -> Option<SystemTime> {
+	(millis > 0).then(|| UNIX_EPOCH.checked_add(Duration::from_millis(millis)))?
+}
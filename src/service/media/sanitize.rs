@@ -0,0 +1,361 @@
+//! Media Metadata Sanitization
+//!
+//! Uploaded JPEG, PNG and WebP images are stripped of their EXIF, XMP and
+//! other textual metadata, which routinely includes the location a photo was
+//! taken at. The image data itself is kept byte for byte, unless the EXIF
+//! orientation has to be applied to the pixels; that needs the 'image'
+//! dependency, without which the orientation is dropped with the rest.
+
+use std::borrow::Cow;
+
+use conduwuit::{debug_warn, err, implement, Err, Result};
+
+/// JPEG markers of segments which are dropped: APP1 (EXIF and XMP), APP13
+/// (IPTC) and comments.
+const JPEG_DROPPED: [u8; 3] = [0xE1, 0xED, 0xFE];
+
+/// PNG chunks which are dropped: EXIF, text (including XMP) and the time of
+/// the last modification.
+const PNG_DROPPED: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+/// How many bytes are needed to tell the format of an image.
+pub(super) const SNIFF_LEN: usize = 12;
+
+/// Largest image which has its metadata stripped, as it is held in memory
+/// whole for it; larger ones are refused.
+pub(super) const MAX_SANITIZED_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Flags of the WebP VP8X chunk.
+const WEBP_ANIMATION: u8 = 0x02;
+const WEBP_XMP: u8 = 0x04;
+const WEBP_EXIF: u8 = 0x08;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+	Jpeg,
+	Png,
+	WebP,
+}
+
+/// An image without its metadata, and the EXIF orientation it had.
+struct Stripped {
+	content: Vec<u8>,
+	orientation: Option<u16>,
+	animated: bool,
+}
+
+/// Strips identifying metadata from an image when `media_strip_metadata` is
+/// enabled. Anything else is returned as it is, while an image which cannot
+/// be read is refused rather than kept with its metadata.
+#[implement(super::Service)]
+pub(super) fn sanitize_media<'a>(&self, content: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+	if !self.services.server.config.media_strip_metadata {
+		return Ok(Cow::Borrowed(content));
+	}
+
+	match strip_metadata(content) {
+		| Ok(Some(stripped)) => Ok(Cow::Owned(stripped)),
+		| Ok(None) => Ok(Cow::Borrowed(content)),
+		| Err(e) => {
+			debug_warn!("Failed to strip metadata from media: {e}");
+			Err!(Request(InvalidParam("Image is invalid; its metadata cannot be stripped.")))
+		},
+	}
+}
+
+/// Whether the metadata of media starting with `head` would be stripped, so
+/// it has to be read whole rather than streamed into the storage.
+#[implement(super::Service)]
+pub(super) fn is_sanitized(&self, head: &[u8]) -> bool {
+	self.services.server.config.media_strip_metadata && format(head).is_some()
+}
+
+/// Returns the image without EXIF, XMP and textual metadata, with its EXIF
+/// orientation applied. Returns `None` for content which is not a JPEG, PNG
+/// or WebP image.
+pub(super) fn strip_metadata(content: &[u8]) -> Result<Option<Vec<u8>>> {
+	let Some(format) = format(content) else {
+		return Ok(None);
+	};
+
+	let stripped = match format {
+		| Format::Jpeg => strip_jpeg(content)?,
+		| Format::Png => strip_png(content)?,
+		| Format::WebP => strip_webp(content)?,
+	};
+
+	match stripped.orientation {
+		| Some(2..=8) if !stripped.animated => apply_orientation(format, &stripped),
+		| _ => Ok(Some(stripped.content)),
+	}
+}
+
+fn format(content: &[u8]) -> Option<Format> {
+	if content.starts_with(&[0xFF, 0xD8, 0xFF]) {
+		Some(Format::Jpeg)
+	} else if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+		Some(Format::Png)
+	} else if content.starts_with(b"RIFF") && content.get(8..12) == Some(b"WEBP") {
+		Some(Format::WebP)
+	} else {
+		None
+	}
+}
+
+fn strip_jpeg(content: &[u8]) -> Result<Stripped> {
+	let mut stripped = Vec::with_capacity(content.len());
+	let mut orientation = None;
+	stripped.extend_from_slice(&content[..2]);
+
+	let mut at = 2_usize;
+	loop {
+		let Some(&[0xFF, marker]) = content.get(at..at.saturating_add(2)) else {
+			return Err!("JPEG segment expected at {at}.");
+		};
+
+		// Fill bytes before a marker
+		if marker == 0xFF {
+			at = at.saturating_add(1);
+			continue;
+		}
+
+		// Anything after the end of the image is dropped with the metadata.
+		if marker == 0xD9 {
+			stripped.extend_from_slice(&content[at..at.saturating_add(2)]);
+			break;
+		}
+
+		// Markers without a segment
+		if matches!(marker, 0x01 | 0xD0..=0xD7) {
+			stripped.extend_from_slice(&content[at..at.saturating_add(2)]);
+			at = at.saturating_add(2);
+			continue;
+		}
+
+		let len = content
+			.get(at.saturating_add(2)..at.saturating_add(4))
+			.map(|len| usize::from(u16::from_be_bytes([len[0], len[1]])))
+			.ok_or_else(|| err!("JPEG segment at {at} has no length."))?;
+
+		let end = at.saturating_add(2).saturating_add(len);
+		let Some(segment) = content.get(at..end) else {
+			return Err!("JPEG segment at {at} is truncated.");
+		};
+
+		if marker == 0xE1 {
+			if let Some(exif) = segment
+				.get(4..)
+				.and_then(|data| data.strip_prefix(b"Exif\0\0"))
+			{
+				orientation = orientation.or_else(|| exif_orientation(exif));
+			}
+		}
+
+		if !JPEG_DROPPED.contains(&marker) {
+			stripped.extend_from_slice(segment);
+		}
+
+		at = end;
+
+		// The image data of a scan follows its header, up to the next marker.
+		if marker == 0xDA {
+			let Some(len) = scan_len(&content[at..]) else {
+				return Err!("JPEG scan at {at} has no end.");
+			};
+
+			let end = at.saturating_add(len);
+			stripped.extend_from_slice(&content[at..end]);
+			at = end;
+		}
+	}
+
+	Ok(Stripped {
+		content: stripped,
+		orientation,
+		animated: false,
+	})
+}
+
+/// The length of the image data of a JPEG scan, which ends at the first
+/// marker other than a restart marker or an escaped 0xFF byte.
+fn scan_len(data: &[u8]) -> Option<usize> {
+	data.windows(2)
+		.position(|pair| pair[0] == 0xFF && !matches!(pair[1], 0x00 | 0xD0..=0xD7))
+}
+
+fn strip_png(content: &[u8]) -> Result<Stripped> {
+	let mut stripped = Vec::with_capacity(content.len());
+	let mut orientation = None;
+	let mut animated = false;
+	stripped.extend_from_slice(&content[..8]);
+
+	let mut at = 8_usize;
+	while at < content.len() {
+		let Some(len) = content.get(at..at.saturating_add(4)) else {
+			return Err!("PNG chunk at {at} has no length.");
+		};
+
+		let len: usize = u32::from_be_bytes([len[0], len[1], len[2], len[3]]).try_into()?;
+
+		// Length, type, data and CRC
+		let end = at.saturating_add(12).saturating_add(len);
+		let Some(chunk) = content.get(at..end) else {
+			return Err!("PNG chunk at {at} is truncated.");
+		};
+
+		let kind = &chunk[4..8];
+		let data = &chunk[8..chunk.len().saturating_sub(4)];
+		match kind {
+			| b"eXIf" => orientation = orientation.or_else(|| exif_orientation(data)),
+			| b"acTL" => animated = true,
+			| _ => {},
+		}
+
+		if !PNG_DROPPED.iter().any(|dropped| dropped == &kind) {
+			stripped.extend_from_slice(chunk);
+		}
+
+		at = end;
+	}
+
+	Ok(Stripped { content: stripped, orientation, animated })
+}
+
+fn strip_webp(content: &[u8]) -> Result<Stripped> {
+	let mut stripped = Vec::with_capacity(content.len());
+	let mut orientation = None;
+	let mut animated = false;
+	stripped.extend_from_slice(&content[..12]);
+
+	let mut at = 12_usize;
+	while at < content.len() {
+		let Some(header) = content.get(at..at.saturating_add(8)) else {
+			return Err!("WebP chunk at {at} is truncated.");
+		};
+
+		let len: usize =
+			u32::from_le_bytes([header[4], header[5], header[6], header[7]]).try_into()?;
+
+		// Chunks are padded to an even length.
+		let padded = len.saturating_add(len % 2);
+		let end = at
+			.saturating_add(8)
+			.saturating_add(padded)
+			.min(content.len());
+		let Some(chunk) = content.get(at..end) else {
+			return Err!("WebP chunk at {at} is truncated.");
+		};
+
+		match &header[..4] {
+			| b"EXIF" => {
+				let data = chunk.get(8..).unwrap_or_default();
+				orientation = orientation.or_else(|| exif_orientation(data));
+			},
+			| b"XMP " => {},
+			| b"VP8X" => {
+				let mut chunk = chunk.to_vec();
+				if let Some(flags) = chunk.get_mut(8) {
+					animated = *flags & WEBP_ANIMATION != 0;
+					*flags &= !(WEBP_EXIF | WEBP_XMP);
+				}
+
+				stripped.extend_from_slice(&chunk);
+			},
+			| _ => stripped.extend_from_slice(chunk),
+		}
+
+		at = end;
+	}
+
+	let riff_len: u32 = stripped.len().saturating_sub(8).try_into()?;
+	stripped[4..8].copy_from_slice(&riff_len.to_le_bytes());
+
+	Ok(Stripped { content: stripped, orientation, animated })
+}
+
+/// Reads the orientation from the first IFD of EXIF data.
+fn exif_orientation(tiff: &[u8]) -> Option<u16> {
+	const ORIENTATION: u16 = 0x0112;
+
+	let tiff = tiff.strip_prefix(b"Exif\0\0").unwrap_or(tiff);
+	let big_endian = match tiff.get(..4)? {
+		| b"MM\0*" => true,
+		| b"II*\0" => false,
+		| _ => return None,
+	};
+
+	let u16_at = |at: usize| {
+		let bytes = tiff.get(at..at.checked_add(2)?)?;
+		let bytes = [bytes[0], bytes[1]];
+		Some(if big_endian {
+			u16::from_be_bytes(bytes)
+		} else {
+			u16::from_le_bytes(bytes)
+		})
+	};
+
+	let u32_at = |at: usize| {
+		let bytes = tiff.get(at..at.checked_add(4)?)?;
+		let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+		Some(if big_endian {
+			u32::from_be_bytes(bytes)
+		} else {
+			u32::from_le_bytes(bytes)
+		})
+	};
+
+	let ifd: usize = u32_at(4)?.try_into().ok()?;
+	let entries = u16_at(ifd)?;
+	(0..usize::from(entries)).find_map(|entry| {
+		let entry = ifd.checked_add(2)?.checked_add(entry.checked_mul(12)?)?;
+		if u16_at(entry)? != ORIENTATION {
+			return None;
+		}
+
+		u16_at(entry.checked_add(8)?)
+	})
+}
+
+/// Rotates and flips the pixels as the EXIF orientation says, writing the
+/// image anew without any metadata.
+#[cfg(feature = "media_thumbnail")]
+fn apply_orientation(format: Format, stripped: &Stripped) -> Result<Option<Vec<u8>>> {
+	use image::{codecs::jpeg::JpegEncoder, metadata::Orientation, ImageFormat};
+
+	let image_format = match format {
+		| Format::Jpeg => ImageFormat::Jpeg,
+		| Format::Png => ImageFormat::Png,
+		| Format::WebP => ImageFormat::WebP,
+	};
+
+	let Some(orientation) = stripped
+		.orientation
+		.and_then(|orientation| u8::try_from(orientation).ok())
+		.and_then(Orientation::from_exif)
+	else {
+		return Ok(Some(stripped.content.clone()));
+	};
+
+	let mut image = image::load_from_memory_with_format(&stripped.content, image_format)
+		.map_err(|e| err!("Failed to read image to apply its orientation: {e}"))?;
+
+	image.apply_orientation(orientation);
+
+	let mut content = Vec::new();
+	let written = match format {
+		| Format::Jpeg =>
+			image.write_with_encoder(JpegEncoder::new_with_quality(&mut content, 90)),
+		| Format::Png | Format::WebP =>
+			image.write_to(&mut std::io::Cursor::new(&mut content), image_format),
+	};
+
+	written.map_err(|e| err!("Failed to write image with its orientation: {e}"))?;
+
+	Ok(Some(content))
+}
+
+#[cfg(not(feature = "media_thumbnail"))]
+#[allow(clippy::unnecessary_wraps)]
+fn apply_orientation(_format: Format, stripped: &Stripped) -> Result<Option<Vec<u8>>> {
+	Ok(Some(stripped.content.clone()))
+}
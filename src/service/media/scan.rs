@@ -0,0 +1,217 @@
+//! Media Scanning
+//!
+//! Uploads and media fetched from remote servers are scanned for malware
+//! once they are stored, by a clamd daemon or by running a command. The
+//! verdicts are cached by the SHA-256 hash of the content, so the same file
+//! is scanned only once however often it is uploaded.
+
+use std::{process::Stdio, time::Duration};
+
+use conduwuit::{
+	config::MediaScanAction, debug, debug_warn, err, implement, warn, Err, Error, Result,
+};
+use futures::StreamExt;
+use http::StatusCode;
+use ruma::{api::client::error::ErrorKind, Mxc};
+use serde_json::json;
+use tokio::{
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+	net::{TcpStream, UnixStream},
+	process::Command,
+	time::timeout,
+};
+
+use super::{dedup::ContentHash, MediaStream};
+
+/// The error code of the Matrix Content Scanner for media which is not clean,
+/// which clients already understand.
+const NOT_CLEAN: &str = "MCS_MEDIA_NOT_CLEAN";
+
+/// The longest reply of clamd which is read.
+const MAX_REPLY_LEN: u64 = 4096;
+
+/// The result of scanning media.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(super) enum Verdict {
+	Clean,
+	/// The name of the signature which matched.
+	Infected(String),
+}
+
+/// Scans stored media, unless no scanner is configured. Infected media is
+/// deleted or quarantined as `media_scan_action` says, and refused with the
+/// `MCS_MEDIA_NOT_CLEAN` error code.
+#[implement(super::Service)]
+#[tracing::instrument(name = "scan", level = "debug", skip(self, key, hash))]
+pub(super) async fn scan_media(&self, mxc: &Mxc<'_>, key: &[u8], hash: &ContentHash) -> Result {
+	let config = &self.services.server.config;
+	if config.media_scan_clamd.is_none() && config.media_scan_command.is_empty() {
+		return Ok(());
+	}
+
+	let verdict = match self.db.get_scan_verdict(hash).await {
+		| Some(verdict) => verdict,
+		| None => match self.scan_media_file(key).await {
+			| Ok(verdict) => {
+				self.db.set_scan_verdict(hash, &verdict);
+				verdict
+			},
+			| Err(e) if config.media_scan_fail_closed => {
+				warn!(%mxc, "Refusing media which could not be scanned: {e}");
+				self.delete(mxc).await?;
+				return Err(Error::Request(
+					ErrorKind::Unknown,
+					"Media could not be scanned for malware.".into(),
+					StatusCode::SERVICE_UNAVAILABLE,
+				));
+			},
+			| Err(e) => {
+				warn!(%mxc, "Storing media which could not be scanned: {e}");
+				return Ok(());
+			},
+		},
+	};
+
+	let Verdict::Infected(signature) = verdict else {
+		return Ok(());
+	};
+
+	warn!(%mxc, %signature, "Infected media");
+	if config.media_scan_action == MediaScanAction::Quarantine {
+		self.set_quarantined(mxc, true).await;
+	} else {
+		self.delete(mxc).await?;
+	}
+
+	Err(not_clean())
+}
+
+#[implement(super::Service)]
+async fn scan_media_file(&self, key: &[u8]) -> Result<Verdict> {
+	let config = &self.services.server.config;
+	let Some((store, storage_key, _)) = self.find_media_file(key).await? else {
+		return Err!(Request(NotFound("Media file not found.")));
+	};
+
+	let content = store
+		.get_stream(&storage_key, None)
+		.await?
+		.ok_or_else(|| err!(Request(NotFound("Media file not found."))))?;
+
+	let scan_timeout = Duration::from_secs(config.media_scan_timeout);
+	let verdict = match &config.media_scan_clamd {
+		| Some(address) => timeout(scan_timeout, clamd_scan(address, content)).await,
+		| None => timeout(scan_timeout, command_scan(&config.media_scan_command, content)).await,
+	};
+
+	verdict.map_err(|_| err!("Scanning media took too long."))?
+}
+
+/// Sends media to clamd with the INSTREAM command. `address` is the path of a
+/// unix socket, or else a host and port to connect to.
+pub(super) async fn clamd_scan(address: &str, content: MediaStream) -> Result<Verdict> {
+	debug!(?address, "Scanning media with clamd");
+	if address.starts_with('/') {
+		clamd_instream(UnixStream::connect(address).await?, content).await
+	} else {
+		clamd_instream(TcpStream::connect(address).await?, content).await
+	}
+}
+
+async fn clamd_instream<S>(mut socket: S, mut content: MediaStream) -> Result<Verdict>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	socket.write_all(b"zINSTREAM\0").await?;
+	while let Some(chunk) = content.next().await {
+		let chunk = chunk?;
+		let len: u32 = chunk.len().try_into()?;
+		socket.write_all(&len.to_be_bytes()).await?;
+		socket.write_all(&chunk).await?;
+	}
+
+	socket.write_all(&0_u32.to_be_bytes()).await?;
+	socket.flush().await?;
+
+	let mut reply = Vec::new();
+	socket.take(MAX_REPLY_LEN).read_to_end(&mut reply).await?;
+	let reply = String::from_utf8_lossy(&reply);
+	let reply = reply.trim_end_matches(['\0', '\n']);
+
+	// "stream: OK", "stream: <signature> FOUND" or "<message> ERROR"
+	let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+	if result == "OK" {
+		Ok(Verdict::Clean)
+	} else if let Some(signature) = result.strip_suffix(" FOUND") {
+		Ok(Verdict::Infected(signature.to_owned()))
+	} else {
+		Err!("clamd failed to scan media: {reply}")
+	}
+}
+
+/// Runs a command with media on its standard input. Like clamscan, it exits
+/// with 0 for clean media and 1 for infected media; its last line of output
+/// names the signature.
+pub(super) async fn command_scan(
+	command: &[String],
+	mut content: MediaStream,
+) -> Result<Verdict> {
+	let Some((program, args)) = command.split_first() else {
+		return Err!("No command to scan media with.");
+	};
+
+	debug!(?program, "Scanning media with a command");
+	let mut child = Command::new(program)
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.kill_on_drop(true)
+		.spawn()?;
+
+	let mut stdin = child
+		.stdin
+		.take()
+		.ok_or_else(|| err!("Failed to open the standard input of the scanner."))?;
+
+	let write = async move {
+		while let Some(chunk) = content.next().await {
+			stdin.write_all(&chunk?).await?;
+		}
+
+		Ok::<_, Error>(())
+	};
+
+	let (written, output) = tokio::join!(write, child.wait_with_output());
+	let output = output?;
+	match output.status.code() {
+		| Some(0) => Ok(Verdict::Clean),
+		| Some(1) => {
+			let stdout = String::from_utf8_lossy(&output.stdout);
+			let signature = stdout
+				.lines()
+				.rev()
+				.find(|line| !line.trim().is_empty())
+				.unwrap_or("unknown")
+				.trim();
+
+			Ok(Verdict::Infected(signature.to_owned()))
+		},
+		| status => {
+			// A scanner may exit before it reads all of its input.
+			if let Err(e) = written {
+				debug_warn!("Failed to write media to the scanner: {e}");
+			}
+
+			let stderr = String::from_utf8_lossy(&output.stderr);
+			Err!("Scanner exited with {status:?}: {}", stderr.trim())
+		},
+	}
+}
+
+fn not_clean() -> Error {
+	let kind = serde_json::from_value(json!({ "errcode": NOT_CLEAN }))
+		.unwrap_or_else(|_| ErrorKind::forbidden());
+
+	Error::Request(kind, "Media contains malware and was refused.".into(), StatusCode::FORBIDDEN)
+}
@@ -0,0 +1,142 @@
+//! Signed Media URLs
+//!
+//! Federation downloads of local media may be answered with a redirect rather
+//! than the file, as authenticated media allows. The redirect points at a
+//! short-lived URL carrying its own authorization: a URL presigned by the
+//! object storage when there is one, so the storage serves the file, or else
+//! the client download route of this server with an HMAC signature of the
+//! MXC and the expiry, which a CDN in front of this server may serve too.
+
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use conduwuit::{
+	err, implement,
+	utils::{content_disposition::make_content_disposition, time::now_millis},
+	Err, Result,
+};
+use hmac::{Hmac, Mac};
+use ruma::Mxc;
+use sha2::Sha256;
+use url::Url;
+
+use super::{data::Metadata, Dim};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A short-lived URL to download local media from, or `None` when
+/// `media_redirect` is disabled.
+#[implement(super::Service)]
+pub async fn media_redirect(&self, mxc: &Mxc<'_>) -> Result<Option<Url>> {
+	let config = &self.services.server.config;
+	if !config.media_redirect {
+		return Ok(None);
+	}
+
+	let Ok(Metadata {
+		content_disposition,
+		content_type,
+		key,
+		quarantined: false,
+		..
+	}) = self.db.search_file_metadata(mxc, &Dim::default()).await
+	else {
+		return Err!(Request(NotFound("Media not found.")));
+	};
+
+	let Some((store, storage_key, _)) = self.find_media_file(&key).await? else {
+		return Err!(Request(NotFound("Media file not found.")));
+	};
+
+	self.record_media_access(mxc).await;
+
+	let duration = Duration::from_secs(config.media_redirect_ttl);
+	let content_disposition =
+		make_content_disposition(content_disposition.as_ref(), content_type.as_deref(), None);
+
+	if let Some(url) = store.presigned_url(
+		&storage_key,
+		duration,
+		content_type.as_deref(),
+		&content_disposition.to_string(),
+	) {
+		return Ok(Some(url));
+	}
+
+	self.signed_media_url(mxc, duration).map(Some)
+}
+
+/// A URL of the client download route which serves local media without an
+/// access token for `duration`.
+#[implement(super::Service)]
+pub fn signed_media_url(&self, mxc: &Mxc<'_>, duration: Duration) -> Result<Url> {
+	let config = &self.services.server.config;
+	let duration: u64 = duration.as_millis().try_into()?;
+	let expires = now_millis().saturating_add(duration);
+
+	let mut url = config
+		.media_redirect_base_url
+		.clone()
+		.or_else(|| config.well_known.client.clone())
+		.unwrap_or_else(|| {
+			let server_name = self.services.globals.server_name();
+			Url::parse(&format!("https://{server_name}")).expect("server name is a valid host")
+		});
+
+	url.path_segments_mut()
+		.map_err(|()| err!(Config("media_redirect_base_url", "Cannot be a base URL.")))?
+		.pop_if_empty()
+		.extend(["_matrix", "client", "v1", "media", "download"])
+		.extend([mxc.server_name.as_str(), mxc.media_id]);
+
+	url.query_pairs_mut()
+		.append_pair("expires", &expires.to_string())
+		.append_pair("signature", &sign(&self.signing_key, mxc, expires));
+
+	Ok(url)
+}
+
+/// Checks the signature of a signed URL to download local media, which stands
+/// in for an access token.
+#[implement(super::Service)]
+pub fn verify_media_signature(&self, mxc: &Mxc<'_>, expires: u64, signature: &str) -> Result {
+	if !self.services.globals.server_is_ours(mxc.server_name) {
+		return Err!(Request(Forbidden("Only local media has signed URLs.")));
+	}
+
+	verify(&self.signing_key, mxc, expires, signature, now_millis())
+}
+
+/// Signs the MXC and the expiry, in milliseconds since the epoch.
+pub(super) fn sign(key: &[u8], mxc: &Mxc<'_>, expires: u64) -> String {
+	URL_SAFE_NO_PAD.encode(mac(key, mxc, expires).finalize().into_bytes())
+}
+
+pub(super) fn verify(
+	key: &[u8],
+	mxc: &Mxc<'_>,
+	expires: u64,
+	signature: &str,
+	now: u64,
+) -> Result {
+	if expires < now {
+		return Err!(Request(Forbidden("Signed media URL has expired.")));
+	}
+
+	let signature = URL_SAFE_NO_PAD
+		.decode(signature)
+		.map_err(|_| err!(Request(Forbidden("Invalid media signature."))))?;
+
+	mac(key, mxc, expires)
+		.verify_slice(&signature)
+		.map_err(|_| err!(Request(Forbidden("Invalid media signature."))))
+}
+
+fn mac(key: &[u8], mxc: &Mxc<'_>, expires: u64) -> HmacSha256 {
+	let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take key of any size");
+	mac.update(mxc.to_string().as_bytes());
+	mac.update(b"\n");
+	mac.update(expires.to_string().as_bytes());
+
+	mac
+}
@@ -0,0 +1,198 @@
+use std::{
+	io::{ErrorKind, SeekFrom},
+	path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use conduwuit::{debug, debug_error, utils, Result};
+use futures::TryStreamExt;
+use tokio::{
+	fs,
+	io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use super::{object_name, read_stream, MediaStore, MediaStream, Stat};
+use crate::media::{encode_key, range::ByteRange};
+
+/// Media files in a local directory.
+pub struct FsStore {
+	dir: PathBuf,
+	compat_file_link: bool,
+}
+
+impl FsStore {
+	#[must_use]
+	pub fn new(dir: PathBuf, compat_file_link: bool) -> Self { Self { dir, compat_file_link } }
+
+	#[must_use]
+	pub fn path(&self, key: &[u8]) -> PathBuf { self.dir.join(object_name(key)) }
+
+	/// Path of the symlink kept for compatibility with Conduit, named after
+	/// the full base64 media key.
+	#[must_use]
+	pub fn legacy_path(&self, key: &[u8]) -> PathBuf { self.dir.join(encode_key(key)) }
+
+	/// Moves a file written to `partial` under the path of `key`, or removes
+	/// it when writing failed. Files only appear under their path once they
+	/// are whole, as other media may point at their content.
+	async fn finish_partial(&self, key: &[u8], partial: &Path, written: Result) -> Result {
+		let finished = match written {
+			| Ok(()) => fs::rename(partial, self.path(key))
+				.await
+				.map_err(Into::into),
+			| Err(e) => Err(e),
+		};
+
+		if finished.is_err() {
+			if let Err(e) = fs::remove_file(partial).await {
+				debug_error!(?key, ?partial, "Failed to remove incomplete media file: {e}");
+			}
+		}
+
+		finished
+	}
+
+	/// Path of a file while it is being written, unique to the write.
+	fn partial_path(&self, key: &[u8]) -> PathBuf {
+		let suffix = utils::random_string(8);
+		self.dir
+			.join(format!(".{}.{suffix}.partial", object_name(key)))
+	}
+}
+
+#[async_trait]
+impl MediaStore for FsStore {
+	fn name(&self) -> &'static str { "filesystem" }
+
+	async fn put(&self, key: &[u8], content: &[u8]) -> Result {
+		let path = self.path(key);
+		debug!(?key, ?path, "Creating media file");
+
+		let partial = self.partial_path(key);
+		let written: Result = async {
+			let mut file = fs::File::create(&partial).await?;
+			file.write_all(content).await?;
+			file.sync_all().await?;
+
+			Ok(())
+		}
+		.await;
+
+		self.finish_partial(key, &partial, written).await
+	}
+
+	async fn put_stream(
+		&self,
+		key: &[u8],
+		_len: Option<u64>,
+		mut content: MediaStream,
+	) -> Result {
+		let path = self.path(key);
+		debug!(?key, ?path, "Creating media file from stream");
+
+		let partial = self.partial_path(key);
+		let written: Result = async {
+			let mut file = fs::File::create(&partial).await?;
+			while let Some(chunk) = content.try_next().await? {
+				file.write_all(&chunk).await?;
+			}
+
+			file.flush().await?;
+			file.sync_all().await?;
+
+			Ok(())
+		}
+		.await;
+
+		self.finish_partial(key, &partial, written).await
+	}
+
+	async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+		match fs::read(self.path(key)).await {
+			| Ok(content) => Ok(Some(content)),
+			| Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+			| Err(e) => Err(e.into()),
+		}
+	}
+
+	async fn get_stream(
+		&self,
+		key: &[u8],
+		range: Option<ByteRange>,
+	) -> Result<Option<MediaStream>> {
+		let mut file = match fs::File::open(self.path(key)).await {
+			| Ok(file) => file,
+			| Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+			| Err(e) => return Err(e.into()),
+		};
+
+		let Some(range) = range else {
+			return Ok(Some(read_stream(file)));
+		};
+
+		file.seek(SeekFrom::Start(range.start)).await?;
+
+		Ok(Some(read_stream(file.take(range.len()))))
+	}
+
+	async fn stat(&self, key: &[u8]) -> Result<Option<Stat>> {
+		let metadata = match fs::metadata(self.path(key)).await {
+			| Ok(metadata) => metadata,
+			| Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+			| Err(e) => return Err(e.into()),
+		};
+
+		let modified = match metadata.created() {
+			| Ok(created) => created,
+			| Err(e) if e.kind() == ErrorKind::Unsupported => {
+				debug!("btime is unsupported, using mtime instead");
+				metadata.modified()?
+			},
+			| Err(e) => return Err(e.into()),
+		};
+
+		Ok(Some(Stat { len: metadata.len(), modified }))
+	}
+
+	async fn delete(&self, key: &[u8]) -> Result {
+		let path = self.path(key);
+		debug!(?key, ?path, "Removing media file");
+
+		match fs::remove_file(&path).await {
+			| Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+			| _ => Ok(()),
+		}
+	}
+
+	async fn link(&self, key: &[u8], storage_key: &[u8]) {
+		if !self.compat_file_link {
+			return;
+		}
+
+		let path = self.path(storage_key);
+		let legacy = self.legacy_path(key);
+		// A link made before may point at content the media no longer has.
+		if fs::symlink_metadata(&legacy)
+			.await
+			.is_ok_and(|md| md.is_symlink())
+		{
+			fs::remove_file(&legacy).await.ok();
+		}
+
+		if let Err(e) = fs::symlink(&path, &legacy).await {
+			debug_error!(
+				key = ?encode_key(key), ?path, ?legacy,
+				"Failed to create legacy media symlink: {e}"
+			);
+		}
+	}
+
+	async fn unlink(&self, key: &[u8]) {
+		let legacy = self.legacy_path(key);
+		if let Err(e) = fs::remove_file(&legacy).await {
+			if e.kind() != ErrorKind::NotFound && self.compat_file_link {
+				debug_error!(?key, ?legacy, "Failed to remove legacy media symlink: {e}");
+			}
+		}
+	}
+}
@@ -0,0 +1,189 @@
+mod fs;
+mod s3;
+mod tests;
+
+use std::{
+	path::PathBuf,
+	time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use conduwuit::{debug_warn, implement, info, Config, Err, Result};
+use futures::{stream, stream::BoxStream, StreamExt, TryStreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use url::Url;
+
+pub use self::{fs::FsStore, s3::S3Store};
+use super::{encode_key, range::ByteRange};
+
+/// Chunks of a media file as they are read or received, so that files are
+/// never held in memory whole.
+pub type MediaStream = BoxStream<'static, Result<Bytes>>;
+
+/// Largest chunk read from a media file at once.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Where the contents of media files are kept. Files are addressed by their
+/// media key in the database.
+#[async_trait]
+pub trait MediaStore: Send + Sync {
+	/// The name of the storage in the config.
+	fn name(&self) -> &'static str;
+
+	async fn put(&self, key: &[u8], content: &[u8]) -> Result;
+
+	/// Writes a file as its chunks arrive. `len` is the length of the file
+	/// when it is known in advance. Nothing is kept when the stream fails.
+	async fn put_stream(&self, key: &[u8], len: Option<u64>, content: MediaStream) -> Result;
+
+	/// Returns `None` when the file does not exist.
+	async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+	/// Reads a file, or the `range` of it, in chunks. Returns `None` when the
+	/// file does not exist.
+	async fn get_stream(
+		&self,
+		key: &[u8],
+		range: Option<ByteRange>,
+	) -> Result<Option<MediaStream>>;
+
+	/// Returns `None` when the file does not exist.
+	async fn stat(&self, key: &[u8]) -> Result<Option<Stat>>;
+
+	/// Deleting a file which does not exist succeeds.
+	async fn delete(&self, key: &[u8]) -> Result;
+
+	/// Makes the content stored under `storage_key` reachable under the name
+	/// of the media key `key` too, for storages which keep such names for
+	/// compatibility.
+	async fn link(&self, _key: &[u8], _storage_key: &[u8]) {}
+
+	/// Removes the name made by [`link`](Self::link).
+	async fn unlink(&self, _key: &[u8]) {}
+
+	/// A URL which serves a file directly for `duration`, with the given
+	/// headers, so it need not pass through this server. Returns `None` when
+	/// the storage has no such URLs.
+	fn presigned_url(
+		&self,
+		_key: &[u8],
+		_duration: Duration,
+		_content_type: Option<&str>,
+		_content_disposition: &str,
+	) -> Option<Url> {
+		None
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Stat {
+	pub len: u64,
+	pub modified: SystemTime,
+}
+
+/// Builds the media storage configured under `name`.
+pub fn build(
+	name: &str,
+	config: &Config,
+	client: &reqwest::Client,
+) -> Result<Box<dyn MediaStore>> {
+	match name {
+		| "filesystem" =>
+			Ok(Box::new(FsStore::new(media_dir(config), config.media_compat_file_link))),
+		| "s3" => Ok(Box::new(S3Store::from_config(config, client.clone())?)),
+		| _ => Err!(Config("media_storage", "Unknown media storage {name:?}.")),
+	}
+}
+
+/// Copies all media files from one storage to another, skipping those the
+/// other storage has already. Returns the number of files copied and the
+/// number missing from the source storage.
+#[implement(super::Service)]
+pub async fn copy_storage(&self, from: &str, to: &str) -> Result<(usize, usize)> {
+	let config = &self.services.server.config;
+	let client = &self.services.client.default;
+	let (from, to) = (build(from, config, client)?, build(to, config, client)?);
+
+	// Deduplicated media files share their stored content.
+	let mut keys = Vec::new();
+	for key in self.db.get_all_media_keys().await {
+		keys.push(self.storage_key(&key).await);
+	}
+
+	keys.sort_unstable();
+	keys.dedup();
+
+	let (mut copied, mut missing) = (0_usize, 0_usize);
+	for key in keys {
+		if to.stat(&key).await?.is_some() {
+			continue;
+		}
+
+		let (Some(stat), Some(content)) =
+			(from.stat(&key).await?, from.get_stream(&key, None).await?)
+		else {
+			debug_warn!(?key, "Media file missing from {} storage", from.name());
+			missing = missing.saturating_add(1);
+			continue;
+		};
+
+		to.put_stream(&key, Some(stat.len), content).await?;
+		copied = copied.saturating_add(1);
+	}
+
+	info!(copied, missing, "Copied media from {} to {} storage", from.name(), to.name());
+
+	Ok((copied, missing))
+}
+
+/// Name of the file of a media key, the same in every storage. It is the
+/// SHA256 hash of the key, because keys can be longer than file names may be
+/// in most filesystems.
+#[must_use]
+pub fn object_name(key: &[u8]) -> String {
+	let digest = <sha2::Sha256 as sha2::Digest>::digest(key);
+	encode_key(&digest)
+}
+
+/// Streams a reader in chunks of at most [`CHUNK_SIZE`] bytes.
+pub(super) fn read_stream<R>(reader: R) -> MediaStream
+where
+	R: AsyncRead + Send + Unpin + 'static,
+{
+	stream::try_unfold(reader, |mut reader| async move {
+		let mut chunk = vec![0; CHUNK_SIZE];
+		let read = reader.read(&mut chunk).await?;
+		if read == 0 {
+			return Ok(None);
+		}
+
+		chunk.truncate(read);
+		Ok(Some((chunk.into(), reader)))
+	})
+	.boxed()
+}
+
+/// Splits the chunks of a stream which are larger than [`CHUNK_SIZE`] bytes.
+pub(super) fn bounded_stream(content: MediaStream) -> MediaStream {
+	content
+		.map_ok(|mut chunk| {
+			let mut pieces: Vec<Result<Bytes>> = Vec::new();
+			while chunk.len() > CHUNK_SIZE {
+				pieces.push(Ok(chunk.split_to(CHUNK_SIZE)));
+			}
+
+			pieces.push(Ok(chunk));
+			stream::iter(pieces)
+		})
+		.try_flatten()
+		.boxed()
+}
+
+#[must_use]
+pub(super) fn media_dir(config: &Config) -> PathBuf {
+	let mut r = PathBuf::new();
+	r.push(config.database_path.clone());
+	r.push("media");
+	r
+}
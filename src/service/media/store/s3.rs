@@ -0,0 +1,378 @@
+use std::{
+	io,
+	time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use conduwuit::{debug, debug_error, err, Config, Err, Result};
+use futures::{StreamExt, TryStreamExt};
+use reqwest::{
+	header::{CONTENT_LENGTH, ETAG, LAST_MODIFIED, RANGE},
+	Body, Client, Response, StatusCode,
+};
+use rusty_s3::{actions::CreateMultipartUpload, Bucket, Credentials, S3Action, UrlStyle};
+use url::Url;
+
+use super::{bounded_stream, object_name, MediaStore, MediaStream, Stat};
+use crate::media::{range::ByteRange, CACHE_CONTROL_IMMUTABLE};
+
+/// How long the signed URL of a request stays valid.
+const SIGNATURE_DURATION: Duration = Duration::from_secs(300);
+
+/// Size of the parts of a multipart upload, above the 5 MiB minimum of S3.
+pub(super) const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Media files as objects in a bucket of an S3-compatible object storage.
+pub struct S3Store {
+	bucket: Bucket,
+	credentials: Option<Credentials>,
+	prefix: String,
+	client: Client,
+}
+
+impl S3Store {
+	pub fn new(
+		endpoint: Url,
+		bucket: &str,
+		region: &str,
+		path_style: bool,
+		credentials: Option<Credentials>,
+		prefix: &str,
+		client: Client,
+	) -> Result<Self> {
+		let url_style = if path_style {
+			UrlStyle::Path
+		} else {
+			UrlStyle::VirtualHost
+		};
+		let bucket = Bucket::new(endpoint, url_style, bucket.to_owned(), region.to_owned())
+			.map_err(|e| err!(Config("media_s3_endpoint", "Invalid S3 bucket: {e}")))?;
+
+		Ok(Self {
+			bucket,
+			credentials,
+			prefix: prefix.to_owned(),
+			client,
+		})
+	}
+
+	pub(super) fn from_config(config: &Config, client: Client) -> Result<Self> {
+		let Some(endpoint) = config.media_s3_endpoint.clone() else {
+			return Err!(Config("media_s3_endpoint", "The s3 media storage needs an endpoint."));
+		};
+
+		let Some(bucket) = &config.media_s3_bucket else {
+			return Err!(Config("media_s3_bucket", "The s3 media storage needs a bucket."));
+		};
+
+		let credentials = match (&config.media_s3_access_key, &config.media_s3_secret_key) {
+			| (Some(key), Some(secret)) => Some(Credentials::new(key, secret)),
+			| (None, None) => None,
+			| _ => {
+				return Err!(Config(
+					"media_s3_secret_key",
+					"Set both media_s3_access_key and media_s3_secret_key, or neither."
+				));
+			},
+		};
+
+		Self::new(
+			endpoint,
+			bucket,
+			&config.media_s3_region,
+			config.media_s3_path_style,
+			credentials,
+			&config.media_s3_prefix,
+			client,
+		)
+	}
+
+	fn object(&self, key: &[u8]) -> String { format!("{}{}", self.prefix, object_name(key)) }
+
+	/// Uploads an object in parts of [`PART_SIZE`] bytes, so that no more than
+	/// a part is held in memory. An object smaller than a part is uploaded in
+	/// a single request.
+	async fn put_multipart(&self, key: &[u8], mut content: MediaStream) -> Result {
+		let mut part = Vec::new();
+		while part.len() < PART_SIZE {
+			let Some(chunk) = content.try_next().await? else {
+				return self.put(key, &part).await;
+			};
+
+			part.extend_from_slice(&chunk);
+		}
+
+		let object = self.object(key);
+		debug!(?key, ?object, "Uploading media object in parts");
+
+		let url = self
+			.bucket
+			.create_multipart_upload(self.credentials.as_ref(), &object)
+			.sign(SIGNATURE_DURATION);
+
+		let response = self
+			.client
+			.post(url)
+			.send()
+			.await?
+			.error_for_status()?
+			.text()
+			.await?;
+
+		let upload = CreateMultipartUpload::parse_response(&response)
+			.map_err(|e| err!(BadServerResponse("Invalid multipart upload from S3: {e}")))?;
+
+		let upload_id = upload.upload_id();
+		let uploaded = self.upload_parts(&object, upload_id, part, content).await;
+
+		let etags = match uploaded {
+			| Ok(etags) => etags,
+			| Err(e) => {
+				let url = self
+					.bucket
+					.abort_multipart_upload(self.credentials.as_ref(), &object, upload_id)
+					.sign(SIGNATURE_DURATION);
+
+				if let Err(e) = self.send(self.client.delete(url)).await {
+					debug_error!(?key, ?object, "Failed to abort multipart upload: {e}");
+				}
+
+				return Err(e);
+			},
+		};
+
+		let action = self.bucket.complete_multipart_upload(
+			self.credentials.as_ref(),
+			&object,
+			upload_id,
+			etags.iter().map(String::as_str),
+		);
+
+		let url = action.sign(SIGNATURE_DURATION);
+		self.client
+			.post(url)
+			.body(action.body())
+			.send()
+			.await?
+			.error_for_status()?;
+
+		Ok(())
+	}
+
+	/// Uploads the parts of a multipart upload, starting from `part`, and
+	/// returns their ETags.
+	async fn upload_parts(
+		&self,
+		object: &str,
+		upload_id: &str,
+		mut part: Vec<u8>,
+		mut content: MediaStream,
+	) -> Result<Vec<String>> {
+		let mut etags = Vec::new();
+		let mut done = false;
+		while !done {
+			while !done && part.len() < PART_SIZE {
+				match content.try_next().await? {
+					| Some(chunk) => part.extend_from_slice(&chunk),
+					| None => done = true,
+				}
+			}
+
+			if part.is_empty() {
+				break;
+			}
+
+			let number: u16 = etags
+				.len()
+				.saturating_add(1)
+				.try_into()
+				.map_err(|_| err!(Request(TooLarge("Media file has too many parts."))))?;
+
+			let url = self
+				.bucket
+				.upload_part(self.credentials.as_ref(), object, number, upload_id)
+				.sign(SIGNATURE_DURATION);
+
+			let response = self
+				.client
+				.put(url)
+				.body(std::mem::take(&mut part))
+				.send()
+				.await?
+				.error_for_status()?;
+
+			let etag = response
+				.headers()
+				.get(ETAG)
+				.and_then(|etag| etag.to_str().ok())
+				.ok_or_else(|| err!(BadServerResponse("S3 part upload has no ETag.")))?;
+
+			etags.push(etag.to_owned());
+		}
+
+		Ok(etags)
+	}
+
+	/// Sends a request, mapping a missing object to `None`.
+	async fn send(&self, request: reqwest::RequestBuilder) -> Result<Option<Response>> {
+		let response = request.send().await?;
+		if response.status() == StatusCode::NOT_FOUND {
+			return Ok(None);
+		}
+
+		Ok(Some(response.error_for_status()?))
+	}
+}
+
+#[async_trait]
+impl MediaStore for S3Store {
+	fn name(&self) -> &'static str { "s3" }
+
+	async fn put(&self, key: &[u8], content: &[u8]) -> Result {
+		let object = self.object(key);
+		debug!(?key, ?object, "Uploading media object");
+
+		let url = self
+			.bucket
+			.put_object(self.credentials.as_ref(), &object)
+			.sign(SIGNATURE_DURATION);
+
+		self.client
+			.put(url)
+			.body(content.to_vec())
+			.send()
+			.await?
+			.error_for_status()?;
+
+		Ok(())
+	}
+
+	async fn put_stream(&self, key: &[u8], len: Option<u64>, content: MediaStream) -> Result {
+		// Objects of unknown length are uploaded in parts instead.
+		let Some(len) = len else {
+			return self.put_multipart(key, content).await;
+		};
+
+		let object = self.object(key);
+		debug!(?key, ?object, len, "Uploading media object from stream");
+
+		let url = self
+			.bucket
+			.put_object(self.credentials.as_ref(), &object)
+			.sign(SIGNATURE_DURATION);
+
+		let body = content.map_err(|e| io::Error::other(e.to_string()));
+		self.client
+			.put(url)
+			.header(CONTENT_LENGTH, len)
+			.body(Body::wrap_stream(body))
+			.send()
+			.await?
+			.error_for_status()?;
+
+		Ok(())
+	}
+
+	async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+		let url = self
+			.bucket
+			.get_object(self.credentials.as_ref(), &self.object(key))
+			.sign(SIGNATURE_DURATION);
+
+		let Some(response) = self.send(self.client.get(url)).await? else {
+			return Ok(None);
+		};
+
+		Ok(Some(response.bytes().await?.to_vec()))
+	}
+
+	async fn get_stream(
+		&self,
+		key: &[u8],
+		range: Option<ByteRange>,
+	) -> Result<Option<MediaStream>> {
+		let url = self
+			.bucket
+			.get_object(self.credentials.as_ref(), &self.object(key))
+			.sign(SIGNATURE_DURATION);
+
+		let mut request = self.client.get(url);
+		if let Some(range) = range {
+			let last = range.end.saturating_sub(1);
+			request = request.header(RANGE, format!("bytes={}-{last}", range.start));
+		}
+
+		let Some(response) = self.send(request).await? else {
+			return Ok(None);
+		};
+
+		if range.is_some() && response.status() != StatusCode::PARTIAL_CONTENT {
+			return Err!(BadServerResponse("S3 storage did not honour the requested range."));
+		}
+
+		let content = response.bytes_stream().map_err(Into::into).boxed();
+
+		Ok(Some(bounded_stream(content)))
+	}
+
+	async fn stat(&self, key: &[u8]) -> Result<Option<Stat>> {
+		let url = self
+			.bucket
+			.head_object(self.credentials.as_ref(), &self.object(key))
+			.sign(SIGNATURE_DURATION);
+
+		let Some(response) = self.send(self.client.head(url)).await? else {
+			return Ok(None);
+		};
+
+		let headers = response.headers();
+		let len = headers
+			.get(CONTENT_LENGTH)
+			.and_then(|len| len.to_str().ok()?.parse().ok())
+			.ok_or_else(|| err!(BadServerResponse("S3 object has no valid Content-Length.")))?;
+
+		let modified = headers
+			.get(LAST_MODIFIED)
+			.and_then(|modified| modified.to_str().ok())
+			.and_then(|modified| chrono::DateTime::parse_from_rfc2822(modified).ok())
+			.map(SystemTime::from)
+			.ok_or_else(|| err!(BadServerResponse("S3 object has no valid Last-Modified.")))?;
+
+		Ok(Some(Stat { len, modified }))
+	}
+
+	async fn delete(&self, key: &[u8]) -> Result {
+		let object = self.object(key);
+		debug!(?key, ?object, "Deleting media object");
+
+		let url = self
+			.bucket
+			.delete_object(self.credentials.as_ref(), &object)
+			.sign(SIGNATURE_DURATION);
+
+		self.send(self.client.delete(url)).await?;
+
+		Ok(())
+	}
+
+	fn presigned_url(
+		&self,
+		key: &[u8],
+		duration: Duration,
+		content_type: Option<&str>,
+		content_disposition: &str,
+	) -> Option<Url> {
+		let object = self.object(key);
+		let mut action = self.bucket.get_object(self.credentials.as_ref(), &object);
+
+		let query = action.query_mut();
+		if let Some(content_type) = content_type {
+			query.insert("response-content-type", content_type);
+		}
+
+		query.insert("response-content-disposition", content_disposition);
+		query.insert("response-cache-control", CACHE_CONTROL_IMMUTABLE);
+
+		Some(action.sign(duration))
+	}
+}
@@ -0,0 +1,412 @@
+#![cfg(test)]
+
+use std::{
+	collections::HashMap,
+	path::PathBuf,
+	sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use futures::{stream, StreamExt, TryStreamExt};
+use rusty_s3::Credentials;
+use tokio::{
+	io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+	net::TcpStream,
+};
+
+use super::{FsStore, MediaStore, MediaStream, S3Store, CHUNK_SIZE};
+use crate::media::{tests::tcp_stand_in, ByteRange};
+
+type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+/// Serves the object requests of the S3 API from memory, like a local MinIO.
+async fn s3_stand_in() -> (url::Url, Objects) {
+	let objects = Objects::default();
+	let served = objects.clone();
+	let address = tcp_stand_in(move |stream| serve(stream, served.clone())).await;
+	let endpoint = format!("http://{address}");
+
+	(endpoint.parse().expect("valid url"), objects)
+}
+
+async fn serve(stream: TcpStream, objects: Objects) {
+	let mut stream = BufReader::new(stream);
+	let mut request_line = String::new();
+	stream.read_line(&mut request_line).await.expect("request");
+	let mut parts = request_line.split_whitespace();
+	let method = parts.next().expect("method").to_owned();
+	let target = parts.next().expect("target").to_owned();
+	let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+
+	let mut len = 0;
+	let mut range = None;
+	loop {
+		let mut header = String::new();
+		stream.read_line(&mut header).await.expect("header");
+		if header.trim().is_empty() {
+			break;
+		}
+
+		if let Some((name, value)) = header.split_once(':') {
+			if name.eq_ignore_ascii_case("content-length") {
+				len = value.trim().parse().expect("length");
+			} else if name.eq_ignore_ascii_case("range") {
+				let (start, end) = value
+					.trim()
+					.strip_prefix("bytes=")
+					.and_then(|range| range.split_once('-'))
+					.expect("single range");
+
+				let start: usize = start.parse().expect("start");
+				let end: usize = end.parse().expect("end");
+				range = Some(start..end.saturating_add(1));
+			}
+		}
+	}
+
+	let mut body = vec![0; len];
+	stream.read_exact(&mut body).await.expect("body");
+
+	let signed = query.contains("X-Amz-Signature=");
+	let param = |name: &str| {
+		query.split('&').find_map(|pair| {
+			let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+			(key == name).then(|| value.to_owned())
+		})
+	};
+
+	// Parts of multipart uploads are kept among the objects until completed.
+	let part_prefix = |upload: &str| format!("{path}?upload={upload}&part=");
+	let mut objects = objects.lock().expect("locked");
+	let (status, headers, content) = match (signed, method.as_str()) {
+		| (false, _) => ("403 Forbidden", String::new(), Vec::new()),
+		| (true, "POST") if param("uploads").is_some() => {
+			let upload = conduwuit::utils::random_string(8);
+			let result = "InitiateMultipartUploadResult";
+			let content = format!("<{result}><UploadId>{upload}</UploadId></{result}>");
+
+			("200 OK", String::new(), content.into_bytes())
+		},
+		| (true, "PUT") if param("uploadId").is_some() => {
+			let upload = param("uploadId").expect("upload");
+			let number: u16 = param("partNumber").expect("part").parse().expect("number");
+			let etag = format!("\"{upload}-{number}\"");
+			objects.insert(format!("{}{number:05}", part_prefix(&upload)), body);
+			("200 OK", format!("ETag: {etag}\r\n"), Vec::new())
+		},
+		| (true, "POST") if param("uploadId").is_some() => {
+			let prefix = part_prefix(&param("uploadId").expect("upload"));
+			let mut parts: Vec<_> = objects
+				.keys()
+				.filter(|key| key.starts_with(&prefix))
+				.cloned()
+				.collect();
+
+			parts.sort();
+			let object = parts
+				.iter()
+				.filter_map(|part| objects.remove(part))
+				.flatten()
+				.collect();
+
+			objects.insert(path.to_owned(), object);
+			("200 OK", String::new(), Vec::new())
+		},
+		| (true, "DELETE") if param("uploadId").is_some() => {
+			let prefix = part_prefix(&param("uploadId").expect("upload"));
+			objects.retain(|key, _| !key.starts_with(&prefix));
+			("204 No Content", String::new(), Vec::new())
+		},
+		| (true, "PUT") => {
+			objects.insert(path.to_owned(), body);
+			("200 OK", String::new(), Vec::new())
+		},
+		| (true, "GET" | "HEAD") => match objects.get(path) {
+			| Some(object) if range.is_some() =>
+				("206 Partial Content", String::new(), object[range.expect("range")].to_vec()),
+			| Some(object) => (
+				"200 OK",
+				"Last-Modified: Wed, 21 Oct 2015 07:28:00 GMT\r\n".to_owned(),
+				object.clone(),
+			),
+			| None => ("404 Not Found", String::new(), Vec::new()),
+		},
+		| (true, "DELETE") => {
+			objects.remove(path);
+			("204 No Content", String::new(), Vec::new())
+		},
+		| _ => ("405 Method Not Allowed", String::new(), Vec::new()),
+	};
+	drop(objects);
+
+	let head = format!(
+		"HTTP/1.1 {status}\r\nContent-Length: {}\r\n{headers}Connection: close\r\n\r\n",
+		content.len()
+	);
+
+	let stream = stream.get_mut();
+	stream.write_all(head.as_bytes()).await.expect("written");
+	if method != "HEAD" {
+		stream.write_all(&content).await.expect("written");
+	}
+}
+
+fn s3_store(endpoint: url::Url) -> S3Store {
+	S3Store::new(
+		endpoint,
+		"media",
+		"us-east-1",
+		true,
+		Some(Credentials::new("access", "secret")),
+		"pqchat/",
+		reqwest::Client::new(),
+	)
+	.expect("valid store")
+}
+
+/// A new empty directory, which the test removes when it is done.
+async fn temp_dir() -> PathBuf {
+	let dir =
+		std::env::temp_dir().join(format!("pqchat-media-{}", conduwuit::utils::random_string(8)));
+	tokio::fs::create_dir_all(&dir).await.expect("created");
+
+	dir
+}
+
+async fn round_trip(store: &dyn MediaStore) {
+	let key = b"mxc://example.com/media\xFF\0\0\0\0\0\0\0\0\xFF\xFFimage/png";
+
+	assert!(store.get(key).await.expect("got").is_none());
+	assert!(store.stat(key).await.expect("stat").is_none());
+
+	store.put(key, b"content").await.expect("put");
+	assert_eq!(store.get(key).await.expect("got").as_deref(), Some(&b"content"[..]));
+	assert_eq!(store.stat(key).await.expect("stat").expect("exists").len, 7);
+
+	store.delete(key).await.expect("deleted");
+	assert!(store.get(key).await.expect("got").is_none());
+	store.delete(key).await.expect("deleting again succeeds");
+}
+
+/// A file of `len` bytes arriving in small chunks, generated as it is read.
+fn generated(len: usize) -> MediaStream {
+	stream::iter((0..len).step_by(1000))
+		.map(move |start| {
+			let chunk: Vec<u8> = (start..len.min(start.saturating_add(1000)))
+				.map(|i| u8::try_from(i % 251).expect("byte"))
+				.collect();
+
+			Ok(Bytes::from(chunk))
+		})
+		.boxed()
+}
+
+async fn read_all(content: MediaStream) -> Vec<u8> {
+	content
+		.try_fold(Vec::new(), |mut content, chunk| async move {
+			content.extend_from_slice(&chunk);
+			Ok(content)
+		})
+		.await
+		.expect("read")
+}
+
+async fn stream_round_trip(store: &dyn MediaStore) {
+	let key = b"mxc://example.com/stream";
+	let len = CHUNK_SIZE.saturating_mul(4).saturating_add(123);
+	let expected = read_all(generated(len)).await;
+
+	assert!(store.get_stream(key, None).await.expect("got").is_none());
+
+	store
+		.put_stream(key, Some(len.try_into().expect("u64")), generated(len))
+		.await
+		.expect("put");
+
+	let content = store
+		.get_stream(key, None)
+		.await
+		.expect("got")
+		.expect("exists");
+	assert_eq!(read_all(content).await, expected);
+
+	let range = ByteRange { start: 1000, end: 70_000 };
+	let content = store
+		.get_stream(key, Some(range))
+		.await
+		.expect("got")
+		.expect("exists");
+	assert_eq!(read_all(content).await, expected[1000..70_000]);
+
+	let failing = generated(len)
+		.chain(stream::once(async { Err(conduwuit::err!("connection lost")) }))
+		.boxed();
+	assert!(store.put_stream(b"failed", None, failing).await.is_err());
+	assert!(store.stat(b"failed").await.expect("stat").is_none());
+
+	store.delete(key).await.expect("deleted");
+}
+
+#[tokio::test]
+async fn s3_store_round_trip() {
+	let (endpoint, objects) = s3_stand_in().await;
+	let store = s3_store(endpoint);
+
+	round_trip(&store).await;
+	stream_round_trip(&store).await;
+
+	store.put(b"key", b"content").await.expect("put");
+	let objects = objects.lock().expect("locked");
+	let (path, _) = objects.iter().next().expect("stored object");
+	assert!(path.starts_with("/media/pqchat/"), "{path}");
+}
+
+/// Files of unknown length are uploaded in parts rather than held whole.
+#[tokio::test]
+async fn s3_store_multipart_upload() {
+	use super::s3::PART_SIZE;
+
+	let (endpoint, objects) = s3_stand_in().await;
+	let store = s3_store(endpoint);
+
+	let len = PART_SIZE.saturating_mul(2).saturating_add(123);
+	store
+		.put_stream(b"large", None, generated(len))
+		.await
+		.expect("put");
+
+	let content = store
+		.get_stream(b"large", None)
+		.await
+		.expect("got")
+		.expect("exists");
+	assert_eq!(read_all(content).await, read_all(generated(len)).await);
+	assert_eq!(objects.lock().expect("locked").len(), 1, "parts were left behind");
+
+	let failing = generated(PART_SIZE.saturating_add(1000))
+		.chain(stream::once(async { Err(conduwuit::err!("connection lost")) }))
+		.boxed();
+	assert!(store.put_stream(b"failed", None, failing).await.is_err());
+	assert!(store.stat(b"failed").await.expect("stat").is_none());
+	assert_eq!(objects.lock().expect("locked").len(), 1, "aborted parts were left behind");
+}
+
+#[tokio::test]
+async fn s3_store_presigned_url() {
+	use std::time::Duration;
+
+	let (endpoint, _) = s3_stand_in().await;
+	let store = s3_store(endpoint);
+
+	store.put(b"key", b"content").await.expect("put");
+	let url = store
+		.presigned_url(b"key", Duration::from_secs(60), Some("image/png"), "inline")
+		.expect("presigned URL");
+
+	let query: HashMap<_, _> = url.query_pairs().collect();
+	assert_eq!(query.get("response-content-type").map(AsRef::as_ref), Some("image/png"));
+	assert_eq!(query.get("response-content-disposition").map(AsRef::as_ref), Some("inline"));
+	assert_eq!(query.get("X-Amz-Expires").map(AsRef::as_ref), Some("60"));
+
+	let content = reqwest::get(url).await.expect("response");
+	assert_eq!(content.bytes().await.expect("content").as_ref(), b"content");
+
+	let dir = std::env::temp_dir();
+	let fs = FsStore::new(dir, false);
+	assert!(fs
+		.presigned_url(b"key", Duration::from_secs(60), None, "inline")
+		.is_none());
+}
+
+#[tokio::test]
+async fn fs_store_round_trip() {
+	let dir = temp_dir().await;
+
+	round_trip(&FsStore::new(dir.clone(), false)).await;
+	stream_round_trip(&FsStore::new(dir.clone(), false)).await;
+
+	let mut files = tokio::fs::read_dir(&dir).await.expect("listed");
+	let leftover = files.next_entry().await.expect("entry");
+	assert!(leftover.is_none(), "left behind {leftover:?}");
+
+	tokio::fs::remove_dir_all(&dir).await.expect("removed");
+}
+
+#[tokio::test]
+async fn fs_store_links_media_key() {
+	let dir = temp_dir().await;
+	let store = FsStore::new(dir.clone(), true);
+	let (blob, first, second) =
+		(b"sha256:blob".as_slice(), b"first".as_slice(), b"second".as_slice());
+
+	store.put(blob, b"content").await.expect("put");
+	store.link(first, blob).await;
+	store.link(second, blob).await;
+	for key in [first, second] {
+		let content = tokio::fs::read(store.legacy_path(key))
+			.await
+			.expect("linked");
+		assert_eq!(content, b"content");
+	}
+
+	assert!(
+		tokio::fs::symlink_metadata(store.legacy_path(blob))
+			.await
+			.is_err(),
+		"linked under the storage key"
+	);
+
+	store.link(first, b"sha256:other").await;
+	let target = tokio::fs::read_link(store.legacy_path(first))
+		.await
+		.expect("link");
+	assert_eq!(target, store.path(b"sha256:other"), "link was not replaced");
+
+	store.unlink(first).await;
+	store.unlink(second).await;
+	store.delete(blob).await.expect("deleted");
+
+	let mut files = tokio::fs::read_dir(&dir).await.expect("listed");
+	let leftover = files.next_entry().await.expect("entry");
+	assert!(leftover.is_none(), "left behind {leftover:?}");
+
+	tokio::fs::remove_dir(&dir).await.expect("removed");
+}
+
+/// Reading a file holds no more than a chunk of it in memory at a time.
+async fn reads_bounded_chunks(store: &dyn MediaStore) {
+	let len = CHUNK_SIZE.saturating_mul(64);
+	store
+		.put_stream(b"large", None, generated(len))
+		.await
+		.expect("put");
+
+	let mut read = 0_usize;
+	let mut content = store
+		.get_stream(b"large", None)
+		.await
+		.expect("got")
+		.expect("exists");
+	while let Some(chunk) = content.try_next().await.expect("chunk") {
+		assert!(chunk.len() <= CHUNK_SIZE, "chunk of {} bytes", chunk.len());
+		read = read.saturating_add(chunk.len());
+	}
+
+	assert_eq!(read, len);
+}
+
+#[tokio::test]
+async fn fs_store_reads_bounded_chunks() {
+	let dir = temp_dir().await;
+
+	reads_bounded_chunks(&FsStore::new(dir.clone(), false)).await;
+
+	tokio::fs::remove_dir_all(&dir).await.expect("removed");
+}
+
+#[tokio::test]
+async fn s3_store_reads_bounded_chunks() {
+	let (endpoint, _) = s3_stand_in().await;
+
+	reads_bounded_chunks(&s3_store(endpoint)).await;
+}
@@ -103,3 +103,716 @@ fn get_url_preview(&self, _url: &str) -> Option<UrlPreviewData> { todo!() }
 		r.to_str().unwrap().len()
 	);
 }
+
+#[test]
+fn find_mxcs_in_content() {
+	use ruma::OwnedMxcUri;
+	use serde_json::json;
+
+	let content = json!({
+		"msgtype": "m.image",
+		"body": "mxc in the body is not a reference",
+		"url": "mxc://example.com/image",
+		"info": {
+			"thumbnail_file": { "url": "mxc://example.com/thumbnail" },
+		},
+		"formatted_body": "<img src=\"mxc://example.com/inline\"><IMG alt='x' SRC = \
+		                   'mxc://example.com/shouting'><a href=\"mxc://example.com/link\">",
+	});
+
+	let mut mxcs = Vec::new();
+	super::quarantine::find_mxcs(&content, &mut mxcs);
+	mxcs.sort_unstable();
+
+	let expected: Vec<OwnedMxcUri> = vec![
+		"mxc://example.com/image".into(),
+		"mxc://example.com/inline".into(),
+		"mxc://example.com/shouting".into(),
+		"mxc://example.com/thumbnail".into(),
+	];
+	assert_eq!(mxcs, expected);
+}
+
+#[test]
+fn parse_byte_ranges() {
+	use http::StatusCode;
+
+	use super::ByteRange;
+
+	let range = |header| ByteRange::parse(header, 1000).expect("satisfiable");
+
+	assert_eq!(range("bytes=0-499"), Some(ByteRange { start: 0, end: 500 }));
+	assert_eq!(range("bytes=500-"), Some(ByteRange { start: 500, end: 1000 }));
+	assert_eq!(range("bytes=-100"), Some(ByteRange { start: 900, end: 1000 }));
+	assert_eq!(range("bytes=900-5000"), Some(ByteRange { start: 900, end: 1000 }));
+	assert_eq!(range("bytes=-5000"), Some(ByteRange { start: 0, end: 1000 }));
+
+	// Anything but a single range of bytes is answered with the whole file.
+	assert_eq!(range("bytes=0-1,5-6"), None);
+	assert_eq!(range("items=0-1"), None);
+	assert_eq!(range("bytes=5-1"), None);
+	assert_eq!(range("bytes=-0"), None);
+
+	let error = ByteRange::parse("bytes=1000-", 1000).expect_err("outside the file");
+	assert_eq!(error.status_code(), StatusCode::RANGE_NOT_SATISFIABLE);
+}
+
+fn thumbnail_sizes() -> Vec<conduwuit::config::ThumbnailSize> {
+	use conduwuit::config::ThumbnailSize;
+	use ruma::media::Method;
+
+	[
+		(32, 32, Method::Crop),
+		(96, 96, Method::Crop),
+		(320, 240, Method::Scale),
+		(640, 480, Method::Scale),
+		(800, 600, Method::Scale),
+		(800, 600, Method::Crop),
+	]
+	.into_iter()
+	.map(|(width, height, method)| ThumbnailSize { width, height, method })
+	.collect()
+}
+
+#[test]
+fn thumbnails_snap_to_sizes() {
+	use ruma::media::Method;
+
+	use super::Dim;
+
+	let snapped = |width, height, method| {
+		let dim = Dim::new(width, height, Some(method)).normalized(&thumbnail_sizes());
+		(dim.width, dim.height, dim.method)
+	};
+
+	assert_eq!(snapped(20, 20, Method::Crop), (32, 32, Method::Crop));
+	assert_eq!(snapped(20, 20, Method::Scale), (320, 240, Method::Scale));
+	assert_eq!(snapped(50, 96, Method::Crop), (96, 96, Method::Crop));
+	assert_eq!(snapped(100, 10, Method::Crop), (96, 96, Method::Crop));
+	assert_eq!(snapped(100, 100, Method::Crop), (800, 600, Method::Crop));
+	assert_eq!(snapped(700, 10, Method::Crop), (800, 600, Method::Crop));
+	assert_eq!(snapped(640, 500, Method::Scale), (800, 600, Method::Scale));
+
+	let sizes = &thumbnail_sizes()[..3];
+	let dim = Dim::new(200, 200, Some(Method::Crop)).normalized(sizes);
+	assert_eq!((dim.width, dim.height, dim.method), (320, 240, Method::Scale));
+	assert_eq!(snapped(567, 567, Method::Scale), (800, 600, Method::Scale));
+	assert_eq!(snapped(567, 567, Method::Crop), (800, 600, Method::Crop));
+	assert_eq!(snapped(801, 10, Method::Scale), (0, 0, Method::Scale));
+	assert_eq!(Dim::new(10, 10, None).normalized(&[]).key(), Dim::default().key());
+}
+
+#[test]
+fn animated_thumbnails_keyed_apart() {
+	use ruma::media::Method;
+
+	use super::Dim;
+
+	let still = Dim::new(300, 200, None).normalized(&thumbnail_sizes());
+	let animated = Dim {
+		animated: true,
+		..Dim::new(300, 200, None)
+	}
+	.normalized(&thumbnail_sizes());
+
+	assert_eq!((animated.width, animated.height), (still.width, still.height));
+	assert_eq!(animated.method, Method::Scale);
+	assert!(animated.animated);
+	assert_ne!(animated.key(), still.key());
+	assert_eq!(Dim::default().key(), [0, 0]);
+}
+
+#[test]
+fn cropped_thumbnails_keyed_apart() {
+	use ruma::media::Method;
+
+	use super::Dim;
+
+	let scaled = Dim::new(700, 500, Some(Method::Scale)).normalized(&thumbnail_sizes());
+	let cropped = Dim::new(700, 500, Some(Method::Crop)).normalized(&thumbnail_sizes());
+	assert_eq!((scaled.width, scaled.height), (cropped.width, cropped.height));
+	assert_eq!((scaled.method, cropped.method), (Method::Scale, Method::Crop));
+	assert_ne!(scaled.key(), cropped.key());
+	assert_eq!(scaled.key(), [800, 600], "scaled thumbnails were keyed anew");
+
+	let animated = |dim: Dim| Dim { animated: true, ..dim };
+	let animated_scaled = animated(Dim::new(800, 600, Some(Method::Scale)));
+	let animated_cropped = animated(Dim::new(800, 600, Some(Method::Crop)));
+	assert_ne!(animated_scaled.key(), animated_cropped.key());
+	assert_ne!(animated_cropped.key(), cropped.key());
+}
+
+#[test]
+#[cfg(feature = "media_thumbnail")]
+fn animated_gif_thumbnail() {
+	use std::io::Cursor;
+
+	use image::{
+		codecs::gif::{GifDecoder, GifEncoder},
+		AnimationDecoder, Delay, Frame, ImageDecoder, Rgba, RgbaImage,
+	};
+
+	use super::{thumbnail::thumbnail_animated, Dim};
+
+	let gif = |frames: &[Rgba<u8>]| {
+		let mut gif = Vec::new();
+		let frames = frames.iter().map(|&colour| {
+			let image = RgbaImage::from_pixel(400, 400, colour);
+			Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(100, 1))
+		});
+
+		GifEncoder::new(&mut gif)
+			.encode_frames(frames)
+			.expect("encoded");
+
+		gif
+	};
+
+	let dim = Dim { animated: true, ..Dim::new(96, 96, None) }.normalized(&thumbnail_sizes());
+
+	// Two frames of 400 by 400 pixels take 1.28 MB to decode.
+	let budget = 2_000_000;
+	let animated = gif(&[Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])]);
+	let thumbnail = thumbnail_animated(&animated, &dim, budget)
+		.expect("generated")
+		.expect("animated");
+
+	let decoder = GifDecoder::new(Cursor::new(&thumbnail)).expect("gif");
+	assert_eq!(decoder.dimensions(), (96, 96));
+	assert_eq!(decoder.into_frames().count(), 2);
+
+	assert!(thumbnail_animated(&animated, &dim, 1_000_000)
+		.expect("generated")
+		.is_none());
+	assert!(thumbnail_animated(&animated, &dim, 500_000)
+		.expect("generated")
+		.is_none());
+
+	let still = gif(&[Rgba([255, 0, 0, 255])]);
+	assert!(thumbnail_animated(&still, &dim, budget)
+		.expect("generated")
+		.is_none());
+	assert!(thumbnail_animated(b"not an image", &dim, budget)
+		.expect("generated")
+		.is_none());
+}
+
+/// EXIF data in big endian with just an orientation and a trailing string
+/// standing in for the location.
+fn exif(orientation: u16) -> Vec<u8> {
+	let mut exif = b"MM\0*\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+	exif.extend_from_slice(&orientation.to_be_bytes());
+	exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+	exif.extend_from_slice(b"GPSLatitude");
+	exif
+}
+
+fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+	let len = u32::try_from(data.len()).expect("short chunk");
+	let mut chunk = len.to_be_bytes().to_vec();
+	chunk.extend_from_slice(kind);
+	chunk.extend_from_slice(data);
+	chunk.extend_from_slice(&[0, 0, 0, 0]);
+	chunk
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+	haystack
+		.windows(needle.len())
+		.any(|window| window == needle)
+}
+
+#[test]
+fn strip_jpeg_metadata() {
+	use super::sanitize::strip_metadata;
+
+	let app1 = [b"Exif\0\0".as_slice(), &exif(1)].concat();
+	let app1_len = u16::try_from(app1.len().saturating_add(2)).expect("short segment");
+
+	let mut jpeg = vec![0xFF, 0xD8];
+	jpeg.extend_from_slice(b"\xFF\xE0\x00\x07JFIF\0");
+	jpeg.extend_from_slice(&[0xFF, 0xE1]);
+	jpeg.extend_from_slice(&app1_len.to_be_bytes());
+	jpeg.extend_from_slice(&app1);
+	jpeg.extend_from_slice(b"\xFF\xFE\x00\x09comment");
+	jpeg.extend_from_slice(b"\xFF\xDA\x00\x02scan data\xFF\xD9");
+
+	let stripped = strip_metadata(&jpeg).expect("stripped").expect("jpeg");
+	assert_eq!(stripped, b"\xFF\xD8\xFF\xE0\x00\x07JFIF\0\xFF\xDA\x00\x02scan data\xFF\xD9");
+
+	// Metadata between the scans of a progressive image, or after its end
+	let mut progressive = jpeg[..jpeg.len().saturating_sub(2)].to_vec();
+	progressive.extend_from_slice(b"\xFF\xFE\x00\x09comment");
+	progressive.extend_from_slice(b"\xFF\xDA\x00\x02more\xFF\x00\xFF\xD0data\xFF\xD9");
+	progressive.extend_from_slice(b"trailing GPS 51.5N 0.1W");
+	let stripped = strip_metadata(&progressive)
+		.expect("stripped")
+		.expect("jpeg");
+	assert!(!contains(&stripped, b"comment"));
+	assert!(!contains(&stripped, b"GPS"));
+	assert!(stripped.ends_with(b"\xFF\xDA\x00\x02more\xFF\x00\xFF\xD0data\xFF\xD9"));
+
+	let truncated = &jpeg[..jpeg.len().saturating_sub(2)];
+	assert!(strip_metadata(truncated).is_err(), "scan without an end was kept");
+
+	assert!(strip_metadata(&stripped[..12]).is_err());
+	assert!(strip_metadata(b"not an image").expect("ignored").is_none());
+}
+
+#[test]
+fn strip_png_metadata() {
+	use super::sanitize::strip_metadata;
+
+	let signature = b"\x89PNG\r\n\x1a\n";
+	let ihdr = png_chunk(b"IHDR", &[0; 13]);
+	let idat = png_chunk(b"IDAT", b"pixels");
+	let iend = png_chunk(b"IEND", &[]);
+	let png = [
+		signature.as_slice(),
+		&ihdr,
+		&png_chunk(b"eXIf", &exif(1)),
+		&png_chunk(b"iTXt", b"XML:com.adobe.xmp\0\0\0\0\0<x:xmpmeta/>"),
+		&png_chunk(b"tEXt", b"Comment\0hello"),
+		&idat,
+		&iend,
+	]
+	.concat();
+
+	let stripped = strip_metadata(&png).expect("stripped").expect("png");
+	assert_eq!(stripped, [signature.as_slice(), &ihdr, &idat, &iend].concat());
+	assert!(!contains(&stripped, b"GPSLatitude"));
+}
+
+#[test]
+fn strip_webp_metadata() {
+	use super::sanitize::strip_metadata;
+
+	let chunk = |kind: &[u8; 4], data: &[u8]| {
+		let len = u32::try_from(data.len()).expect("short chunk");
+		let mut chunk = kind.to_vec();
+		chunk.extend_from_slice(&len.to_le_bytes());
+		chunk.extend_from_slice(data);
+		if data.len() % 2 == 1 {
+			chunk.push(0);
+		}
+
+		chunk
+	};
+
+	let webp = |chunks: &[Vec<u8>]| {
+		let chunks = chunks.concat();
+		let len = u32::try_from(chunks.len().saturating_add(4)).expect("short file");
+		[b"RIFF".as_slice(), &len.to_le_bytes(), b"WEBP", &chunks].concat()
+	};
+
+	let image = chunk(b"VP8L", b"pixels!");
+	let original = webp(&[
+		chunk(b"VP8X", &[0x0C, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+		image.clone(),
+		chunk(b"EXIF", &exif(1)),
+		chunk(b"XMP ", b"<x:xmpmeta/>"),
+	]);
+
+	let stripped = strip_metadata(&original).expect("stripped").expect("webp");
+	assert_eq!(stripped, webp(&[chunk(b"VP8X", &[0; 10]), image]));
+	assert!(!contains(&stripped, b"GPSLatitude"));
+}
+
+#[test]
+#[cfg(feature = "media_thumbnail")]
+fn strip_metadata_applies_orientation() {
+	use std::io::Cursor;
+
+	use image::{GenericImageView, ImageFormat, RgbImage};
+
+	use super::sanitize::strip_metadata;
+
+	let mut png = Vec::new();
+	RgbImage::new(4, 2)
+		.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+		.expect("encoded");
+
+	// Right after the signature and the IHDR chunk
+	let at = 33;
+	let rotated = [&png[..at], &png_chunk(b"eXIf", &exif(6)), &png[at..]].concat();
+
+	let stripped = strip_metadata(&rotated).expect("stripped").expect("png");
+	let image = image::load_from_memory(&stripped).expect("decoded");
+	assert_eq!(image.dimensions(), (2, 4));
+	assert!(!contains(&stripped, b"eXIf"));
+}
+
+/// The test signature of antivirus scanners, split so this file is not taken
+/// for malware itself.
+const EICAR: &str =
+	concat!(r"X5O!P%@AP[4\PZX54(P^)7CC)7}$", r"EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*");
+
+fn media_stream(content: &'static [u8]) -> super::MediaStream {
+	use bytes::Bytes;
+	use futures::{stream, StreamExt};
+
+	// Small chunks, so the scanners see the file in pieces.
+	stream::iter(content.chunks(7).map(|chunk| Ok(Bytes::from_static(chunk)))).boxed()
+}
+
+/// Listens on a local port like the servers media is fetched from, stored in
+/// or scanned by, serving each connection with `serve`.
+pub(super) async fn tcp_stand_in<F, Fut>(serve: F) -> std::net::SocketAddr
+where
+	F: Fn(tokio::net::TcpStream) -> Fut + Send + 'static,
+	Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+	let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+		.await
+		.expect("bound");
+	let address = listener.local_addr().expect("address");
+	tokio::spawn(async move {
+		while let Ok((socket, _)) = listener.accept().await {
+			tokio::spawn(serve(socket));
+		}
+	});
+
+	address
+}
+
+/// Answers the INSTREAM command like clamd, finding the EICAR test file.
+async fn clamd_stand_in() -> String {
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	let address = tcp_stand_in(|mut socket| async move {
+		let mut command = [0; 10];
+		socket.read_exact(&mut command).await.expect("command");
+		assert_eq!(&command, b"zINSTREAM\0");
+
+		let mut content = Vec::new();
+		loop {
+			let len = socket.read_u32().await.expect("chunk length");
+			if len == 0 {
+				break;
+			}
+
+			let mut chunk = vec![0; len.try_into().expect("chunk length")];
+			socket.read_exact(&mut chunk).await.expect("chunk");
+			content.extend_from_slice(&chunk);
+		}
+
+		let reply: &[u8] = if contains(&content, EICAR.as_bytes()) {
+			b"stream: Eicar-Test-Signature FOUND\0"
+		} else {
+			b"stream: OK\0"
+		};
+
+		socket.write_all(reply).await.expect("reply");
+	})
+	.await;
+
+	address.to_string()
+}
+
+#[tokio::test]
+async fn scan_media_with_clamd() {
+	use super::scan::{clamd_scan, Verdict};
+
+	let address = clamd_stand_in().await;
+
+	let clean = clamd_scan(&address, media_stream(b"an ordinary file"))
+		.await
+		.expect("scanned");
+	assert_eq!(clean, Verdict::Clean);
+
+	let infected = clamd_scan(&address, media_stream(EICAR.as_bytes()))
+		.await
+		.expect("scanned");
+	assert_eq!(infected, Verdict::Infected("Eicar-Test-Signature".to_owned()));
+}
+
+#[tokio::test]
+async fn scan_media_with_command() {
+	use super::scan::{command_scan, Verdict};
+
+	let scanner = |script: &str| vec!["sh".to_owned(), "-c".to_owned(), script.to_owned()];
+	let finds_eicar = scanner(
+		"if grep -q EICAR-STANDARD; then echo 'stdin: Eicar-Test-Signature FOUND'; exit 1; fi",
+	);
+
+	let clean = command_scan(&finds_eicar, media_stream(b"an ordinary file"))
+		.await
+		.expect("scanned");
+	assert_eq!(clean, Verdict::Clean);
+
+	let infected = command_scan(&finds_eicar, media_stream(EICAR.as_bytes()))
+		.await
+		.expect("scanned");
+	assert_eq!(infected, Verdict::Infected("stdin: Eicar-Test-Signature FOUND".to_owned()));
+
+	let broken = scanner("echo 'database missing' >&2; exit 2");
+	assert!(command_scan(&broken, media_stream(b"an ordinary file"))
+		.await
+		.is_err());
+}
+
+#[test]
+fn retention_times_from_millis() {
+	use std::time::{Duration, UNIX_EPOCH};
+
+	use super::retention::from_millis;
+
+	assert_eq!(from_millis(0), None);
+	assert_eq!(from_millis(1_500), UNIX_EPOCH.checked_add(Duration::from_millis(1_500)));
+}
+
+/// Serves fixed responses over HTTP: the content type and body for each
+/// path, and 404 for anything else.
+#[cfg(feature = "url_preview")]
+async fn http_fixture(routes: &'static [(&'static str, &'static str, &'static [u8])]) -> url::Url {
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	let address = tcp_stand_in(move |mut socket| async move {
+		let mut request = Vec::new();
+		while !contains(&request, b"\r\n\r\n") {
+			let mut buf = [0; 1024];
+			let read = socket.read(&mut buf).await.expect("request");
+			if read == 0 {
+				break;
+			}
+
+			request.extend_from_slice(&buf[..read]);
+		}
+
+		let request = String::from_utf8_lossy(&request);
+		let path = request.split(' ').nth(1).unwrap_or_default();
+		let path = path.split('?').next().unwrap_or_default();
+		let response = match routes.iter().find(|(route, ..)| *route == path) {
+			| Some((_, content_type, body)) => [
+				format!(
+					"HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: \
+					 {}\r\nConnection: close\r\n\r\n",
+					body.len()
+				)
+				.as_bytes(),
+				body,
+			]
+			.concat(),
+			| None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+				.to_vec(),
+		};
+
+		socket.write_all(&response).await.expect("response");
+	})
+	.await;
+
+	url::Url::parse(&format!("http://{address}/")).expect("valid URL")
+}
+
+#[tokio::test]
+#[cfg(feature = "url_preview")]
+async fn preview_html_with_oembed() {
+	use super::{
+		oembed::read_oembed,
+		preview::{parse_html, read_html, Page},
+	};
+
+	// A Latin-1 page declaring its charset only in a meta tag
+	const PAGE: &[u8] = b"<html><head><meta charset=\"iso-8859-1\">\
+		<meta property=\"og:title\" content=\"Caf\xe9 tour\">\
+		<meta property=\"og:site_name\" content=\"Example\">\
+		<meta property=\"og:type\" content=\"video.other\">\
+		<meta property=\"og:video\" content=\"/video.mp4\">\
+		<link rel=\"alternate\" type=\"application/json+oembed\" \
+		href=\"/oembed?url=x&amp;format=json\" title=\"Caf\xe9 tour\">\
+		</head><body></body></html>";
+
+	const OEMBED: &[u8] = br#"{
+		"type": "video",
+		"version": "1.0",
+		"title": "Café tour, the video",
+		"author_name": "Someone",
+		"provider_name": "Tube",
+		"thumbnail_url": "https://tube.example/thumbnail.jpg",
+		"html": "<iframe src=\"https://tube.example/embed\"></iframe>"
+	}"#;
+
+	let base = http_fixture(&[
+		("/page", "text/html", PAGE),
+		("/oembed", "application/json+oembed", OEMBED),
+	])
+	.await;
+
+	let client = reqwest::Client::new();
+	let url = base.join("page").expect("valid URL");
+	let response = client.get(url.as_str()).send().await.expect("page");
+	let html = read_html(response, 65536).await.expect("read");
+	let Page { mut data, image, oembed } = parse_html(html, &url).expect("parsed");
+
+	assert_eq!(data.title.as_deref(), Some("Café tour"));
+	assert_eq!(data.site_name.as_deref(), Some("Example"));
+	assert_eq!(data.kind.as_deref(), Some("video.other"));
+	assert_eq!(data.video, Some(base.join("video.mp4").expect("valid URL").into()));
+	assert_eq!(image, None);
+
+	let oembed = oembed.expect("oEmbed link");
+	assert_eq!(oembed, base.join("oembed?url=x&format=json").expect("valid URL"));
+
+	let response = client.get(oembed.as_str()).send().await.expect("oEmbed");
+	let oembed = read_oembed(response, 65536).await.expect("read");
+	oembed.apply(&mut data);
+
+	assert_eq!(data.title.as_deref(), Some("Café tour, the video"));
+	assert_eq!(data.site_name.as_deref(), Some("Tube"));
+	assert_eq!(data.description.as_deref(), Some("Someone"));
+	assert_eq!(oembed.image(), Some("https://tube.example/thumbnail.jpg"));
+
+	let missing = client
+		.get(base.join("missing").expect("valid URL").as_str())
+		.send()
+		.await
+		.expect("response");
+	assert!(read_oembed(missing, 65536).await.is_err());
+}
+
+#[test]
+#[cfg(feature = "url_preview")]
+fn decode_html_charsets() {
+	use super::preview::decode_html;
+
+	let latin1 = b"<p>caf\xe9</p>";
+	assert_eq!(decode_html(latin1, Some("text/html; charset=ISO-8859-1")), "<p>café</p>");
+	assert_eq!(decode_html(latin1, Some("text/html; charset=\"latin1\"")), "<p>café</p>");
+
+	let meta = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=windows-1252\">\x93";
+	assert!(decode_html(meta, Some("text/html")).ends_with('\u{201C}'));
+
+	// The Content-Type wins over a meta tag, and a byte order mark over both.
+	let utf8 = "<meta charset=\"iso-8859-1\"><p>café</p>".as_bytes();
+	assert!(decode_html(utf8, Some("text/html; charset=utf-8")).ends_with("café</p>"));
+	let bom = [b"\xef\xbb\xbf".as_slice(), utf8].concat();
+	assert!(decode_html(&bom, Some("text/html; charset=iso-8859-1")).ends_with("café</p>"));
+
+	assert_eq!(decode_html("<p>café</p>".as_bytes(), None), "<p>café</p>");
+}
+
+#[test]
+#[cfg(feature = "url_preview")]
+fn oembed_provider_schemes() {
+	use conduwuit::config::OembedProvider;
+	use url::Url;
+
+	use super::oembed::{endpoint_url, matches_scheme, provider_endpoint};
+
+	assert!(matches_scheme("https://youtu.be/*", "https://youtu.be/abc"));
+	assert!(matches_scheme("https://*.example.com/*/status/*", "https://x.example.com/a/status/1"));
+	assert!(!matches_scheme("https://*.example.com/*/status/*", "https://x.example.com/a/likes/1"));
+	assert!(matches_scheme("https://example.com/exact", "https://example.com/exact"));
+	assert!(!matches_scheme("https://example.com/exact", "https://example.com/exact/more"));
+
+	let providers = [OembedProvider {
+		endpoint: Url::parse("https://tube.example/oembed.{format}").expect("valid URL"),
+		schemes: vec!["https://tube.example/watch*".to_owned()],
+	}];
+
+	let video = Url::parse("https://tube.example/watch?v=1").expect("valid URL");
+	let endpoint = provider_endpoint(&providers, &video).expect("matching provider");
+	assert_eq!(
+		endpoint_url(&endpoint, &video).expect("endpoint").as_str(),
+		"https://tube.example/oembed.json?url=https%3A%2F%2Ftube.example%2Fwatch%3Fv%3D1&format=json"
+	);
+
+	let other = Url::parse("https://other.example/watch").expect("valid URL");
+	assert!(provider_endpoint(&providers, &other).is_none());
+}
+
+#[test]
+fn signed_media_urls() {
+	use ruma::{server_name, Mxc};
+
+	use super::signed::{sign, verify};
+
+	let key = b"secret";
+	let mxc = Mxc {
+		server_name: server_name!("example.com"),
+		media_id: "abcdef",
+	};
+
+	let signature = sign(key, &mxc, 2_000);
+	assert!(verify(key, &mxc, 2_000, &signature, 1_000).is_ok());
+
+	// Expired, extended, for other media, by another key or mangled
+	assert!(verify(key, &mxc, 2_000, &signature, 3_000).is_err());
+	assert!(verify(key, &mxc, 9_000, &signature, 1_000).is_err());
+	let other = Mxc { media_id: "ghijkl", ..mxc };
+	assert!(verify(key, &other, 2_000, &signature, 1_000).is_err());
+	assert!(verify(b"other", &mxc, 2_000, &signature, 1_000).is_err());
+	assert!(verify(key, &mxc, 2_000, "not base64!", 1_000).is_err());
+}
+
+#[test]
+fn quota_charges() {
+	use super::quota::charge;
+
+	assert_eq!(charge(100, 50, Some(200)), Ok(150));
+	assert_eq!(charge(100, 100, Some(200)), Ok(200), "upload filling the quota was refused");
+	assert_eq!(charge(100, 101, Some(200)), Err(200));
+	assert_eq!(charge(300, 0, Some(200)), Err(200), "usage over a lowered quota was allowed");
+	assert_eq!(charge(u64::MAX, 1, None), Ok(u64::MAX));
+	assert_eq!(charge(u64::MAX, 1, Some(u64::MAX)), Ok(u64::MAX));
+}
+
+#[test]
+fn quota_of_users() {
+	use super::quota::effective_quota;
+
+	assert_eq!(effective_quota(None, 1_000), Some(1_000));
+	assert_eq!(effective_quota(Some(5_000), 1_000), Some(5_000));
+	assert_eq!(effective_quota(Some(0), 1_000), None, "a quota of 0 is unlimited");
+	assert_eq!(effective_quota(Some(5_000), 0), Some(5_000));
+	assert_eq!(effective_quota(None, 0), None);
+}
+
+#[tokio::test]
+async fn quota_cuts_off_streams() {
+	use std::sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	};
+
+	use futures::StreamExt;
+
+	use super::count_stream;
+
+	// 20 bytes in chunks of 7, 7 and 6.
+	let content = b"twenty bytes of data";
+
+	let written = Arc::new(AtomicU64::new(0));
+	let chunks: Vec<_> = count_stream(media_stream(content), None, Some(20), written.clone())
+		.collect()
+		.await;
+	assert!(chunks.iter().all(Result::is_ok), "upload filling the quota was cut off");
+	assert_eq!(written.load(Ordering::Acquire), 20);
+
+	let written = Arc::new(AtomicU64::new(0));
+	let chunks: Vec<_> = count_stream(media_stream(content), None, Some(10), written.clone())
+		.collect()
+		.await;
+	assert!(chunks[0].is_ok());
+	assert!(chunks[1].is_err(), "upload went on past the quota");
+	assert_eq!(written.load(Ordering::Acquire), 14);
+
+	let written = Arc::new(AtomicU64::new(0));
+	let chunks: Vec<_> = count_stream(media_stream(content), None, None, written.clone())
+		.collect()
+		.await;
+	assert!(chunks.iter().all(Result::is_ok), "upload without quota was cut off");
+}
+
+#[test]
+#[cfg(feature = "media_thumbnail_video")]
+fn video_demuxers_pinned() {
+	use super::video::demuxer;
+
+	assert_eq!(demuxer("video/mp4"), Some("mov"));
+	assert_eq!(demuxer("Video/WebM; codecs=\"vp9\""), Some("matroska"));
+	assert_eq!(demuxer("video/mp2t"), Some("mpegts"));
+	assert_eq!(demuxer("video/x-ms-asf"), None, "unlisted container was thumbnailed");
+	assert_eq!(demuxer("application/vnd.apple.mpegurl"), None, "playlist was thumbnailed");
+	assert_eq!(demuxer("image/png"), None);
+}
@@ -7,12 +7,8 @@
 
 use std::{cmp, num::Saturating as Sat};
 
-use conduwuit::{checked, err, implement, Result};
-use ruma::{http_headers::ContentDisposition, media::Method, Mxc, UInt, UserId};
-use tokio::{
-	fs,
-	io::{AsyncReadExt, AsyncWriteExt},
-};
+use conduwuit::{checked, config::ThumbnailSize, debug_warn, err, implement, Err, Result};
+use ruma::{http_headers::ContentDisposition, media::Method, Mxc, OwnedMxcUri, UInt, UserId};
 
 use super::{data::Metadata, FileMeta};
 
@@ -22,10 +18,29 @@ pub struct Dim {
 	pub width: u32,
 	pub height: u32,
 	pub method: Method,
+	/// Whether an animated thumbnail is wanted for animated images (MSC2705).
+	pub animated: bool,
 }
 
+/// Most frames kept in an animated thumbnail; longer animations get a still
+/// thumbnail instead.
+#[cfg(feature = "media_thumbnail")]
+const MAX_ANIMATED_FRAMES: usize = 200;
+
+/// Largest animated thumbnail in bytes; larger ones are replaced by a still
+/// thumbnail.
+#[cfg(feature = "media_thumbnail")]
+const MAX_ANIMATED_SIZE: usize = 4 * 1024 * 1024;
+
+/// Most bytes of frames decoded for an animated thumbnail, counting every
+/// frame at the full size of the image; larger animations get a still
+/// thumbnail instead.
+#[cfg(feature = "media_thumbnail")]
+const MAX_ANIMATED_DECODED: u64 = 256 * 1024 * 1024;
+
 impl super::Service {
-	/// Uploads or replaces a file thumbnail.
+	/// Uploads or replaces a file thumbnail, such as one fetched from a remote
+	/// server, which is scanned like the file itself.
 	#[allow(clippy::too_many_arguments)]
 	pub async fn upload_thumbnail(
 		&self,
@@ -41,8 +56,8 @@ pub async fn upload_thumbnail(
 				.create_file_metadata(mxc, user, dim, content_disposition, content_type)?;
 
 		//TODO: Dangling metadata in database if creation fails
-		let mut f = self.create_media_file(&key).await?;
-		f.write_all(file).await?;
+		let hash = self.put_media_file(&key, file).await?;
+		self.scan_media(mxc, &key, &hash).await?;
 
 		Ok(())
 	}
@@ -52,22 +67,29 @@ pub async fn upload_thumbnail(
 	/// Here's an example on how it works:
 	///
 	/// - Client requests an image with width=567, height=567
-	/// - Server rounds that up to (800, 600), so it doesn't have to save too
-	///   many thumbnails
+	/// - Server rounds that up to the nearest of `media_thumbnail_sizes`, by
+	///   default (800, 600), so it doesn't have to save too many thumbnails
 	/// - Server rounds that up again to (958, 600) to fix the aspect ratio
-	///   (only for width,height>96)
-	/// - Server creates the thumbnail and sends it to the user
+	///   (only for sizes with the "scale" method)
+	/// - Server creates the thumbnail, unless it was generated after upload,
+	///   and sends it to the user
 	///
-	/// For width,height <= 96 the server uses another thumbnailing algorithm
-	/// which crops the image afterwards.
+	/// For sizes with the "crop" method the server uses another thumbnailing
+	/// algorithm which crops the image afterwards.
 	#[tracing::instrument(skip(self), name = "thumbnail", level = "debug")]
 	pub async fn get_thumbnail(&self, mxc: &Mxc<'_>, dim: &Dim) -> Result<Option<FileMeta>> {
 		// 0, 0 because that's the original file
-		let dim = dim.normalized();
+		let dim = dim.normalized(&self.services.server.config.media_thumbnail_sizes);
+
+		if self.db.is_quarantined(mxc).await {
+			return Err!(Request(NotFound("Media not found.")));
+		}
 
 		if let Ok(metadata) = self.db.search_file_metadata(mxc, &dim).await {
+			self.record_media_access(mxc).await;
 			self.get_thumbnail_saved(metadata).await
 		} else if let Ok(metadata) = self.db.search_file_metadata(mxc, &Dim::default()).await {
+			self.record_media_access(mxc).await;
 			self.get_thumbnail_generate(mxc, &dim, metadata).await
 		} else {
 			Ok(None)
@@ -75,16 +97,82 @@ pub async fn get_thumbnail(&self, mxc: &Mxc<'_>, dim: &Dim) -> Result<Option<Fil
 	}
 }
 
+/// Queues generating the thumbnails of a local upload in all
+/// `media_thumbnail_sizes`, which the media worker does in the background.
+#[implement(super::Service)]
+pub(super) fn queue_thumbnails(&self, mxc: &Mxc<'_>) {
+	if !cfg!(feature = "media_thumbnail")
+		|| !self.services.server.config.media_thumbnail_pregenerate
+	{
+		return;
+	}
+
+	let (sender, _) = &self.thumbnail_queue;
+	if let Err(e) = sender.send(mxc.to_string().into()) {
+		debug_warn!(%mxc, "Failed to queue thumbnail generation: {e}");
+	}
+}
+
+/// Deletes the generated thumbnails of a file and queues generating them
+/// again.
+#[implement(super::Service)]
+pub async fn regenerate_thumbnails(&self, mxc: &Mxc<'_>) -> Result {
+	let original = self.db.search_file_metadata(mxc, &Dim::default()).await?;
+	for key in self.db.search_mxc_metadata_prefix(mxc).await? {
+		if key != original.key {
+			self.remove_media_file(&key).await?;
+			self.db.delete_file_key(&key);
+		}
+	}
+
+	self.queue_thumbnails(mxc);
+
+	Ok(())
+}
+
+/// Generates the thumbnails of an image or video in all sizes, and animated
+/// ones too for GIF and WebP.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), name = "pregenerate", level = "debug")]
+pub(super) async fn pregenerate_thumbnails(&self, mxc: &OwnedMxcUri) {
+	let Ok(mxc) = mxc.as_str().try_into() else {
+		return;
+	};
+
+	let Ok(Metadata { content_type: Some(content_type), .. }) =
+		self.db.search_file_metadata(&mxc, &Dim::default()).await
+	else {
+		return;
+	};
+
+	if !content_type.starts_with("image/") && !content_type.starts_with("video/") {
+		return;
+	}
+
+	let animations: &[bool] = match content_type.as_str() {
+		| "image/gif" | "image/webp" => &[false, true],
+		| _ => &[false],
+	};
+
+	for size in &self.services.server.config.media_thumbnail_sizes {
+		for &animated in animations {
+			let dim = Dim {
+				animated,
+				..Dim::new(size.width, size.height, Some(size.method.clone()))
+			};
+
+			if let Err(e) = self.get_thumbnail(&mxc, &dim).await {
+				debug_warn!(%mxc, ?dim, "Failed to generate thumbnail: {e}");
+			}
+		}
+	}
+}
+
 /// Using saved thumbnail
 #[implement(super::Service)]
 #[tracing::instrument(name = "saved", level = "debug", skip(self, data))]
 async fn get_thumbnail_saved(&self, data: Metadata) -> Result<Option<FileMeta>> {
-	let mut content = Vec::new();
-	let path = self.get_media_file(&data.key);
-	fs::File::open(path)
-		.await?
-		.read_to_end(&mut content)
-		.await?;
+	let content = self.read_media_file(&data.key).await?;
 
 	Ok(Some(into_filemeta(data, content)))
 }
@@ -99,15 +187,27 @@ async fn get_thumbnail_generate(
 	dim: &Dim,
 	data: Metadata,
 ) -> Result<Option<FileMeta>> {
-	let mut content = Vec::new();
-	let path = self.get_media_file(&data.key);
-	fs::File::open(path)
-		.await?
-		.read_to_end(&mut content)
-		.await?;
+	if cfg!(feature = "media_thumbnail_video")
+		&& data
+			.content_type
+			.as_deref()
+			.is_some_and(|content_type| content_type.starts_with("video/"))
+	{
+		return self.get_video_thumbnail(mxc, dim, data).await;
+	}
+
+	let content = self.read_media_file(&data.key).await?;
+
+	if dim.animated {
+		if let Some(thumbnail) = thumbnail_animated(&content, dim, MAX_ANIMATED_DECODED)? {
+			return self
+				.save_thumbnail(mxc, dim, data, thumbnail, "image/gif")
+				.await;
+		}
+	}
 
+	// Couldn't parse file to generate thumbnail, send original
 	let Ok(image) = image::load_from_memory(&content) else {
-		// Couldn't parse file to generate thumbnail, send original
 		return Ok(Some(into_filemeta(data, content)));
 	};
 
@@ -115,26 +215,106 @@ async fn get_thumbnail_generate(
 		return Ok(Some(into_filemeta(data, content)));
 	}
 
-	let mut thumbnail_bytes = Vec::new();
 	let thumbnail = thumbnail_generate(&image, dim)?;
-	let mut cursor = std::io::Cursor::new(&mut thumbnail_bytes);
-	thumbnail
-		.write_to(&mut cursor, image::ImageFormat::Png)
+	self.save_thumbnail(mxc, dim, data, encode_png(&thumbnail)?, "image/png")
+		.await
+}
+
+/// Generate the thumbnails of a video in all `media_thumbnail_sizes` at once,
+/// so ffmpeg runs once per upload rather than once per size.
+#[cfg(feature = "media_thumbnail")]
+#[implement(super::Service)]
+async fn get_video_thumbnail(
+	&self,
+	mxc: &Mxc<'_>,
+	dim: &Dim,
+	data: Metadata,
+) -> Result<Option<FileMeta>> {
+	let _lock = self.mxc_mutex.lock(mxc.to_string().as_str()).await;
+
+	// Another request may have generated it while this one waited.
+	if let Ok(metadata) = self.db.search_file_metadata(mxc, dim).await {
+		return self.get_thumbnail_saved(metadata).await;
+	}
+
+	// Videos are copied out of the storage for ffmpeg rather than read whole.
+	let Some(frame) = self
+		.video_frame(data.content_type.as_deref(), &data.key)
+		.await?
+	else {
+		return self.get_thumbnail_saved(data).await;
+	};
+
+	let mut dims: Vec<_> = self
+		.services
+		.server
+		.config
+		.media_thumbnail_sizes
+		.iter()
+		.map(|size| Dim::new(size.width, size.height, Some(size.method.clone())))
+		.filter(|size| size.key() != dim.key())
+		.collect();
+
+	// The requested thumbnail comes last so it is the one returned.
+	dims.push(Dim { method: dim.method.clone(), ..*dim });
+
+	let mut requested = None;
+	for size in dims {
+		if size.key() != dim.key() && self.db.search_file_metadata(mxc, &size).await.is_ok() {
+			continue;
+		}
+
+		// The original video is no thumbnail, so a small frame is sent as it is.
+		let thumbnail = if size.width > frame.width() || size.height > frame.height() {
+			encode_png(&frame)?
+		} else {
+			encode_png(&thumbnail_generate(&frame, &size)?)?
+		};
+
+		requested = self
+			.save_thumbnail(mxc, &size, data.clone(), thumbnail, "image/png")
+			.await?;
+	}
+
+	Ok(requested)
+}
+
+#[cfg(feature = "media_thumbnail")]
+fn encode_png(image: &image::DynamicImage) -> Result<Vec<u8>> {
+	let mut bytes = Vec::new();
+	image
+		.write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png)
 		.map_err(|error| err!(error!(?error, "Error writing PNG thumbnail.")))?;
 
-	// Save thumbnail in database so we don't have to generate it again next time
+	Ok(bytes)
+}
+
+/// Save thumbnail in database so we don't have to generate it again next time
+#[cfg(feature = "media_thumbnail")]
+#[implement(super::Service)]
+async fn save_thumbnail(
+	&self,
+	mxc: &Mxc<'_>,
+	dim: &Dim,
+	data: Metadata,
+	thumbnail: Vec<u8>,
+	content_type: &str,
+) -> Result<Option<FileMeta>> {
 	let thumbnail_key = self.db.create_file_metadata(
 		mxc,
 		None,
 		dim,
 		data.content_disposition.as_ref(),
-		data.content_type.as_deref(),
+		Some(content_type),
 	)?;
 
-	let mut f = self.create_media_file(&thumbnail_key).await?;
-	f.write_all(&thumbnail_bytes).await?;
+	self.put_media_file(&thumbnail_key, &thumbnail).await?;
 
-	Ok(Some(into_filemeta(data, thumbnail_bytes)))
+	Ok(Some(FileMeta {
+		content: Some(thumbnail),
+		content_type: Some(content_type.to_owned()),
+		content_disposition: data.content_disposition,
+	}))
 }
 
 #[cfg(not(feature = "media_thumbnail"))]
@@ -149,6 +329,96 @@ async fn get_thumbnail_generate(
 	self.get_thumbnail_saved(data).await
 }
 
+/// Generates an animated GIF thumbnail of an animated GIF or WebP. Returns
+/// `None` when the image is not animated, or its animation is too long, takes
+/// more than `budget` bytes of frames to decode or is too large to keep.
+#[cfg(feature = "media_thumbnail")]
+pub(super) fn thumbnail_animated(
+	content: &[u8],
+	requested: &Dim,
+	budget: u64,
+) -> Result<Option<Vec<u8>>> {
+	use std::io::Cursor;
+
+	use image::{
+		codecs::{
+			gif::{GifDecoder, GifEncoder, Repeat},
+			webp::WebPDecoder,
+		},
+		AnimationDecoder, DynamicImage, Frame, ImageDecoder, ImageFormat,
+	};
+
+	let frames = match image::guess_format(content) {
+		| Ok(ImageFormat::Gif) => GifDecoder::new(Cursor::new(content)).map(|decoder| {
+			let (width, height) = decoder.dimensions();
+			((width, height), decoder.into_frames())
+		}),
+		| Ok(ImageFormat::WebP) => match WebPDecoder::new(Cursor::new(content)) {
+			| Ok(decoder) if decoder.has_animation() => {
+				let (width, height) = decoder.dimensions();
+				Ok(((width, height), decoder.into_frames()))
+			},
+			| _ => return Ok(None),
+		},
+		| _ => return Ok(None),
+	};
+
+	let Ok(((width, height), frames)) = frames else {
+		return Ok(None);
+	};
+
+	if requested.width > width || requested.height > height {
+		return Ok(None);
+	}
+
+	// Every frame is decoded at the full size of the image, so an animation
+	// is given up on once decoding it would take more than the budget.
+	let frame_size = u64::from(width)
+		.saturating_mul(height.into())
+		.saturating_mul(4);
+	if frame_size > budget {
+		return Ok(None);
+	}
+
+	// Frames are scaled down as they are decoded, so one full frame is held at
+	// a time. One frame more than allowed tells an animation which is too long.
+	let mut frames = frames.take(MAX_ANIMATED_FRAMES.saturating_add(1));
+	let mut thumbnails = Vec::new();
+	let mut decoded = 0_u64;
+	loop {
+		decoded = decoded.saturating_add(frame_size);
+		if decoded > budget {
+			return Ok(None);
+		}
+
+		let frame = match frames.next() {
+			| Some(Ok(frame)) => frame,
+			| Some(Err(_)) => return Ok(None),
+			| None => break,
+		};
+
+		let delay = frame.delay();
+		let image = DynamicImage::ImageRgba8(frame.into_buffer());
+		let thumbnail = thumbnail_generate(&image, requested)?;
+		thumbnails.push(Frame::from_parts(thumbnail.into_rgba8(), 0, 0, delay));
+	}
+
+	if thumbnails.len() < 2 || thumbnails.len() > MAX_ANIMATED_FRAMES {
+		return Ok(None);
+	}
+
+	let mut thumbnail = Vec::new();
+	{
+		let mut encoder = GifEncoder::new(&mut thumbnail);
+		encoder
+			.set_repeat(Repeat::Infinite)
+			.and_then(|()| encoder.encode_frames(thumbnails))
+			.map_err(|error| err!(error!(?error, "Error writing GIF thumbnail.")))?;
+	}
+
+	Ok((thumbnail.len() <= MAX_ANIMATED_SIZE).then_some(thumbnail))
+}
+
 #[cfg(feature = "media_thumbnail")]
 fn thumbnail_generate(
 	image: &image::DynamicImage,
@@ -179,8 +449,14 @@ fn into_filemeta(data: Metadata, content: Vec<u8>) -> FileMeta {
 }
 
 impl Dim {
-	/// Instantiate a Dim from Ruma integers with optional method.
-	pub fn from_ruma(width: UInt, height: UInt, method: Option<Method>) -> Result<Self> {
+	/// Instantiate a Dim from Ruma integers with optional method and
+	/// animation.
+	pub fn from_ruma(
+		width: UInt,
+		height: UInt,
+		method: Option<Method>,
+		animated: Option<bool>,
+	) -> Result<Self> {
 		let width = width
 			.try_into()
 			.map_err(|e| err!(Request(InvalidParam("Width is invalid: {e:?}"))))?;
@@ -188,7 +464,10 @@ pub fn from_ruma(width: UInt, height: UInt, method: Option<Method>) -> Result<Se
 			.try_into()
 			.map_err(|e| err!(Request(InvalidParam("Height is invalid: {e:?}"))))?;
 
-		Ok(Self::new(width, height, method))
+		Ok(Self {
+			animated: animated.unwrap_or(false),
+			..Self::new(width, height, method)
+		})
 	}
 
 	/// Instantiate a Dim with optional method
@@ -199,6 +478,7 @@ pub fn new(width: u32, height: u32, method: Option<Method>) -> Self {
 			width,
 			height,
 			method: method.unwrap_or(Method::Scale),
+			animated: false,
 		}
 	}
 
@@ -229,22 +509,45 @@ pub fn scaled(&self, image: &Self) -> Result<Self> {
 			width: x,
 			height: y,
 			method: Method::Scale,
+			animated: false,
 		})
 	}
 
-	/// Returns width, height of the thumbnail and whether it should be cropped.
-	/// Returns None when the server should send the original file.
-	/// Ignores the input Method.
+	/// Snaps the requested dimensions to the smallest of the thumbnail `sizes`
+	/// covering them, preferring sizes of the requested method over smaller
+	/// ones of the other method. Returns the default (0, 0) when the server
+	/// should send the original file.
 	#[must_use]
-	pub fn normalized(&self) -> Self {
-		match (self.width, self.height) {
-			| (0..=32, 0..=32) => Self::new(32, 32, Some(Method::Crop)),
-			| (0..=96, 0..=96) => Self::new(96, 96, Some(Method::Crop)),
-			| (0..=320, 0..=240) => Self::new(320, 240, Some(Method::Scale)),
-			| (0..=640, 0..=480) => Self::new(640, 480, Some(Method::Scale)),
-			| (0..=800, 0..=600) => Self::new(800, 600, Some(Method::Scale)),
-			| _ => Self::default(),
+	pub fn normalized(&self, sizes: &[ThumbnailSize]) -> Self {
+		let Some(size) = sizes
+			.iter()
+			.filter(|size| size.width >= self.width && size.height >= self.height)
+			.min_by_key(|size| {
+				let area = u64::from(size.width).saturating_mul(size.height.into());
+				(size.method != self.method, area)
+			})
+		else {
+			return Self::default();
+		};
+
+		Self {
+			animated: self.animated,
+			..Self::new(size.width, size.height, Some(size.method.clone()))
+		}
+	}
+
+	/// The dimensions as keyed in the database. Animated and cropped
+	/// thumbnails are kept apart from scaled still ones of the same size,
+	/// which keep the key of the width and height alone.
+	#[must_use]
+	pub(super) fn key(&self) -> Vec<u32> {
+		let flags = u32::from(self.animated) | (u32::from(self.crop()) << 1);
+		let mut key = vec![self.width, self.height];
+		if flags != 0 {
+			key.push(flags);
 		}
+
+		key
 	}
 
 	/// Returns true if the method is Crop.
@@ -260,6 +563,7 @@ fn default() -> Self {
 			width: 0,
 			height: 0,
 			method: Method::Scale,
+			animated: false,
 		}
 	}
 }
@@ -0,0 +1,159 @@
+//! Video Thumbnails
+//!
+//! The first frame of a video is extracted by running ffmpeg, which reads the
+//! common containers and codecs without linking to it. This is gated by
+//! 'media_thumbnail_video'; when not featured videos get no thumbnail.
+
+#[cfg(feature = "media_thumbnail_video")]
+use std::{path::Path, time::Duration};
+
+#[cfg(feature = "media_thumbnail_video")]
+use conduwuit::{debug_warn, utils, Err};
+use conduwuit::{implement, Result};
+
+#[cfg(feature = "media_thumbnail_video")]
+use super::MediaStream;
+
+/// How long ffmpeg may take to extract a frame.
+#[cfg(feature = "media_thumbnail_video")]
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most CPU time in seconds ffmpeg may use to extract a frame.
+#[cfg(feature = "media_thumbnail_video")]
+const FFMPEG_CPU_LIMIT: u64 = 30;
+
+/// Most memory in bytes ffmpeg may map to extract a frame.
+#[cfg(feature = "media_thumbnail_video")]
+const FFMPEG_MEMORY_LIMIT: u64 = 1024 * 1024 * 1024;
+
+/// Extracts the first frame of a video. Returns `None` for content which is
+/// not a video, or when the frame cannot be extracted.
+#[cfg(feature = "media_thumbnail_video")]
+#[implement(super::Service)]
+#[tracing::instrument(name = "video", level = "debug", skip(self, key))]
+pub(super) async fn video_frame(
+	&self,
+	content_type: Option<&str>,
+	key: &[u8],
+) -> Result<Option<image::DynamicImage>> {
+	use tokio::{fs, process::Command, time::timeout};
+
+	let Some(demuxer) = content_type.and_then(demuxer) else {
+		return Ok(None);
+	};
+
+	let Some((store, storage_key, _)) = self.find_media_file(key).await? else {
+		return Err!(Request(NotFound("Media file not found.")));
+	};
+
+	let Some(content) = store.get_stream(&storage_key, None).await? else {
+		return Err!(Request(NotFound("Media file not found.")));
+	};
+
+	// Containers like MP4 may keep their index at the end, so ffmpeg needs to
+	// seek in a copy of the file rather than read it from a pipe.
+	let name = format!(".video-{}.partial", utils::random_string(16));
+	let path = self.get_media_dir().join(name);
+	let ffmpeg = &self.services.server.config.media_ffmpeg_path;
+	let output = match write_private(&path, content).await {
+		| Ok(()) => {
+			// The input may only be the local file, read by the demuxer of its
+			// content type. Without probing ffmpeg never picks demuxers such as
+			// concat or HLS which open further files or URLs named in the
+			// upload, so `-safe` never comes into play.
+			let mut command = Command::new(ffmpeg);
+			command
+				.args(["-nostdin", "-loglevel", "error", "-threads", "1"])
+				.args(["-protocol_whitelist", "file", "-f", demuxer, "-i"])
+				.arg(&path)
+				.args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "pipe:1"])
+				.kill_on_drop(true);
+
+			#[cfg(unix)]
+			// SAFETY: setrlimit is async-signal-safe and allocates nothing.
+			unsafe {
+				command.pre_exec(|| {
+					utils::sys::limit_resources(FFMPEG_CPU_LIMIT, FFMPEG_MEMORY_LIMIT)
+						.map_err(Into::into)
+				});
+			}
+
+			Ok(timeout(FFMPEG_TIMEOUT, command.output()).await)
+
+			Ok(timeout(FFMPEG_TIMEOUT, output).await)
+		},
+		| Err(e) => Err(e),
+	};
+
+	if let Err(e) = fs::remove_file(&path).await {
+		debug_warn!(?path, "Failed to remove temporary video file: {e}");
+	}
+
+	let output = match output? {
+		| Ok(Ok(output)) if output.status.success() => output,
+		| Ok(Ok(output)) => {
+			let error = String::from_utf8_lossy(&output.stderr);
+			debug_warn!(status = ?output.status, "ffmpeg failed to extract a frame: {error}");
+			return Ok(None);
+		},
+		| Ok(Err(e)) => {
+			debug_warn!(?ffmpeg, "Failed to run ffmpeg: {e}");
+			return Ok(None);
+		},
+		| Err(_) => {
+			debug_warn!("ffmpeg took too long to extract a frame");
+			return Ok(None);
+		},
+	};
+
+	Ok(image::load_from_memory_with_format(&output.stdout, image::ImageFormat::Png).ok())
+}
+
+/// The ffmpeg demuxer reading videos of a content type, of the containers
+/// allowed to be thumbnailed.
+#[cfg(feature = "media_thumbnail_video")]
+pub(super) fn demuxer(content_type: &str) -> Option<&'static str> {
+	let essence = content_type.split(';').next()?.trim();
+	let demuxer = match essence.to_ascii_lowercase().as_str() {
+		| "video/mp4" | "video/quicktime" | "video/3gpp" | "video/3gpp2" => "mov",
+		| "video/webm" | "video/x-matroska" => "matroska",
+		| "video/ogg" => "ogg",
+		| "video/x-msvideo" => "avi",
+		| "video/mpeg" => "mpeg",
+		| "video/mp2t" => "mpegts",
+		| _ => return None,
+	};
+
+	Some(demuxer)
+}
+
+/// Writes a stream to a new file which only this server may read.
+#[cfg(feature = "media_thumbnail_video")]
+async fn write_private(path: &Path, mut content: MediaStream) -> Result {
+	use futures::TryStreamExt;
+	use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+	let mut file = OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.mode(0o600)
+		.open(path)
+		.await?;
+
+	while let Some(chunk) = content.try_next().await? {
+		file.write_all(&chunk).await?;
+	}
+
+	Ok(file.flush().await?)
+}
+
+#[cfg(not(feature = "media_thumbnail_video"))]
+#[implement(super::Service)]
+#[allow(clippy::unused_async)]
+pub(super) async fn video_frame(
+	&self,
+	_content_type: Option<&str>,
+	_key: &[u8],
+) -> Result<Option<image::DynamicImage>> {
+	Ok(None)
+}
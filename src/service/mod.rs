@@ -1,4 +1,8 @@
 #![allow(refining_impl_trait)]
+#![cfg_attr(conduwuit_bench, feature(test))]
+
+#[cfg(conduwuit_bench)]
+extern crate test;
 
 mod manager;
 mod migrations;
@@ -14,14 +18,19 @@
 pub mod federation;
 pub mod globals;
 pub mod key_backups;
+pub mod lockout;
+pub mod mailer;
 pub mod media;
 pub mod presence;
 pub mod pusher;
+pub mod reports;
 pub mod resolver;
 pub mod rooms;
 pub mod sending;
 pub mod server_keys;
+pub mod server_notices;
 pub mod sync;
+pub mod threepid;
 pub mod transaction_ids;
 pub mod uiaa;
 pub mod updates;
@@ -138,6 +138,11 @@ pub async fn set_presence(
 		last_active_ago: Option<UInt>,
 		status_msg: Option<String>,
 	) -> Result<()> {
+		// Nobody sees the presence of shadow-banned users change.
+		if self.services.users.is_shadow_banned(user_id).await {
+			return Ok(());
+		}
+
 		let presence_state = match state.as_str() {
 			| "" => &PresenceState::Offline, // default an empty string to 'offline'
 			| &_ => state,
@@ -0,0 +1,110 @@
+#![cfg(conduwuit_bench)]
+
+use ruma::{
+	events::{push_rules::PushRulesEvent, AnySyncTimelineEvent},
+	int, owned_room_id,
+	push::{PushConditionPowerLevelsCtx, PushConditionRoomCtx, Ruleset},
+	serde::Raw,
+	uint, OwnedUserId,
+};
+use serde_json::json;
+use test::{black_box, Bencher};
+
+use super::Evaluation;
+
+const MEMBERS: usize = 10_000;
+
+fn members() -> Vec<OwnedUserId> {
+	(0..MEMBERS)
+		.map(|i| {
+			format!("@user{i}:example.com")
+				.try_into()
+				.expect("valid user id")
+		})
+		.collect()
+}
+
+fn room_ctx(members: &[OwnedUserId]) -> PushConditionRoomCtx {
+	let moderators = members
+		.iter()
+		.take(100)
+		.map(|user| (user.clone(), int!(50)));
+
+	PushConditionRoomCtx {
+		room_id: owned_room_id!("!large:example.com"),
+		member_count: uint!(10_000),
+		user_id: members[0].clone(),
+		user_display_name: members[0].localpart().to_owned(),
+		power_levels: Some(PushConditionPowerLevelsCtx {
+			users: moderators.collect(),
+			users_default: int!(0),
+			notifications: Default::default(),
+		}),
+	}
+}
+
+fn event(kind: &str, content: serde_json::Value) -> Raw<AnySyncTimelineEvent> {
+	serde_json::from_value(json!({
+		"type": kind,
+		"event_id": "$event:example.com",
+		"sender": "@sender:example.com",
+		"origin_server_ts": 1_700_000_000_000_u64,
+		"content": content,
+	}))
+	.expect("valid event")
+}
+
+fn evaluate_for_members(bencher: &mut Bencher, event: &Raw<AnySyncTimelineEvent>) {
+	let members = members();
+	let rulesets: Vec<_> = members.iter().map(Ruleset::server_default).collect();
+	let mut ctx = room_ctx(&members);
+
+	bencher.iter(|| {
+		for (user, ruleset) in members.iter().zip(&rulesets) {
+			ctx.user_id = user.clone();
+			ctx.user_display_name = user.localpart().to_owned();
+			black_box(Evaluation::new(ruleset, &ctx, event));
+		}
+	});
+}
+
+#[bench]
+fn evaluate_message_large_room(bencher: &mut Bencher) {
+	let event = event("m.room.message", json!({ "msgtype": "m.text", "body": "hello world" }));
+
+	evaluate_for_members(bencher, &event);
+}
+
+#[bench]
+fn evaluate_encrypted_large_room(bencher: &mut Bencher) {
+	let event = event(
+		"m.room.encrypted",
+		json!({ "algorithm": "m.megolm.v1.aes-sha2", "ciphertext": "AwgAEnAC" }),
+	);
+
+	evaluate_for_members(bencher, &event);
+}
+
+/// What the ruleset cache saves: parsing every member's push rules for each
+/// event.
+#[bench]
+fn parse_rulesets_large_room(bencher: &mut Bencher) {
+	let members = members();
+	let stored: Vec<_> = members
+		.iter()
+		.map(|user| {
+			serde_json::to_string(&json!({
+				"type": "m.push_rules",
+				"content": { "global": Ruleset::server_default(user) },
+			}))
+			.expect("serializable ruleset")
+		})
+		.collect();
+
+	bencher.iter(|| {
+		for json in &stored {
+			let event: PushRulesEvent = serde_json::from_str(json).expect("valid push rules");
+			black_box(event.content.global);
+		}
+	});
+}
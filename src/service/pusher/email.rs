@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+
+use conduwuit::{
+	debug, err, implement,
+	utils::{self, stream::TryIgnore, time::now_millis, ReadyExt},
+	warn, Err, PduEvent, Result,
+};
+use database::{Deserialized, Interfix, Json};
+use futures::StreamExt;
+use ruma::{
+	api::client::push::{Pusher, PusherKind},
+	events::TimelineEventType,
+	push::Tweak,
+	OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::threepid::tokens_match;
+
+/// Path of the link in digest emails which removes the email pusher.
+pub const UNSUBSCRIBE_PATH: &str = "/_conduwuit/push/email/unsubscribe";
+
+const UNSUBSCRIBE_TOKEN_LENGTH: usize = 32;
+
+/// Message bodies are shortened to this many characters in digests.
+const PREVIEW_LENGTH: usize = 160;
+
+/// A highlight waiting to be sent in the next digest email.
+#[derive(Debug, Deserialize, Serialize)]
+struct DigestItem {
+	queued_ts: u64,
+	room_id: OwnedRoomId,
+	event_id: OwnedEventId,
+	sender: OwnedUserId,
+	preview: Option<String>,
+}
+
+type Digests = BTreeMap<(OwnedUserId, String), Vec<(u64, DigestItem)>>;
+
+/// Queues a notification for the email pusher's next digest. Only highlights
+/// are sent by email.
+#[implement(super::Service)]
+pub(super) fn queue_email_notification(
+	&self,
+	user: &UserId,
+	pusher: &Pusher,
+	tweaks: &[Tweak],
+	event: &PduEvent,
+) -> Result {
+	if !tweaks.iter().any(|t| matches!(t, Tweak::Highlight(true))) {
+		return Ok(());
+	}
+
+	let preview = (event.kind == TimelineEventType::RoomMessage)
+		.then(|| event.get_content_as_value())
+		.and_then(|content| content.get("body")?.as_str().map(shorten));
+
+	let count = self.services.globals.next_count()?;
+	let key = (user, pusher.ids.pushkey.as_str(), count);
+	self.db.userpushkey_emaildigest.put(
+		key,
+		Json(DigestItem {
+			queued_ts: now_millis(),
+			room_id: event.room_id.clone(),
+			event_id: event.event_id.clone(),
+			sender: event.sender.clone(),
+			preview,
+		}),
+	);
+
+	Ok(())
+}
+
+/// Sends the digests whose oldest highlight has waited for the configured
+/// window.
+#[implement(super::Service)]
+pub(super) async fn send_email_digests(&self) {
+	type KeyVal<'a> = ((&'a UserId, &'a str, u64), DigestItem);
+
+	let window = self
+		.services
+		.server
+		.config
+		.email
+		.notification_digest_window
+		.saturating_mul(1000);
+
+	let mut digests = Digests::new();
+	self.db
+		.userpushkey_emaildigest
+		.stream()
+		.ignore_err()
+		.ready_for_each(|((user_id, pushkey, count), item): KeyVal<'_>| {
+			digests
+				.entry((user_id.to_owned(), pushkey.to_owned()))
+				.or_default()
+				.push((count, item));
+		})
+		.await;
+
+	let now = now_millis();
+	for ((user_id, pushkey), items) in digests {
+		let oldest = items
+			.iter()
+			.map(|(_, item)| item.queued_ts)
+			.min()
+			.unwrap_or(now);
+
+		if now.saturating_sub(oldest) < window {
+			continue;
+		}
+
+		// Digests are only attempted once so an unreachable address can't
+		// make them pile up.
+		if let Err(e) = self.send_email_digest(&user_id, &pushkey, &items).await {
+			warn!(%user_id, "Failed to send notification digest email: {e}");
+		}
+
+		for (count, _) in &items {
+			self.db
+				.userpushkey_emaildigest
+				.del((&user_id, pushkey.as_str(), *count));
+		}
+	}
+}
+
+#[implement(super::Service)]
+async fn send_email_digest(
+	&self,
+	user_id: &UserId,
+	pushkey: &str,
+	items: &[(u64, DigestItem)],
+) -> Result {
+	let mut rooms = BTreeMap::<&RoomId, Vec<&DigestItem>>::new();
+	for (_, item) in items {
+		rooms.entry(&item.room_id).or_default().push(item);
+	}
+
+	let mut sections = Vec::with_capacity(rooms.len());
+	let mut total: usize = 0;
+	for (room_id, items) in rooms {
+		// The user read the room since; the highlights are no longer missed.
+		if self.services.user.highlight_count(user_id, room_id).await == 0 {
+			continue;
+		}
+
+		let mut section = self.room_display_name(room_id).await;
+		section.push_str(":\n");
+		for item in &items {
+			let sender = self
+				.services
+				.users
+				.displayname(&item.sender)
+				.await
+				.unwrap_or_else(|_| item.sender.to_string());
+
+			let line = match &item.preview {
+				| Some(preview) => format!("  {sender}: {preview}\n"),
+				| None => format!("  {sender} mentioned you\n"),
+			};
+
+			section.push_str(&line);
+		}
+
+		total = total.saturating_add(items.len());
+		sections.push(section);
+	}
+
+	if sections.is_empty() {
+		debug!(%user_id, "All highlights were read; skipping digest email");
+		return Ok(());
+	}
+
+	let server_name = self.services.globals.server_name();
+	let unsubscribe = self.unsubscribe_link(user_id, pushkey).await?;
+	let subject = match total {
+		| 1 => format!("You have 1 unread highlight on {server_name}"),
+		| n => format!("You have {n} unread highlights on {server_name}"),
+	};
+
+	let body = format!(
+		"You were mentioned while you were away:\n\n{}\nOpen your Matrix client to reply.\n\nTo \
+		 stop receiving these emails, open the following link:\n\n{unsubscribe}\n",
+		sections.join("\n"),
+	);
+
+	self.services.mailer.send(pushkey, &subject, body).await
+}
+
+#[implement(super::Service)]
+async fn room_display_name(&self, room_id: &RoomId) -> String {
+	let state_accessor = &self.services.state_accessor;
+	if let Ok(name) = state_accessor.get_name(room_id).await {
+		return name;
+	}
+
+	state_accessor
+		.get_canonical_alias(room_id)
+		.await
+		.map_or_else(|_| room_id.to_string(), |alias| alias.to_string())
+}
+
+/// Link which removes the email pusher. Its token is created with the first
+/// digest and stays valid until the pusher is removed.
+#[implement(super::Service)]
+async fn unsubscribe_link(&self, user_id: &UserId, pushkey: &str) -> Result<Url> {
+	let key = (user_id, pushkey);
+	let token: String = match self
+		.db
+		.userpushkey_unsubscribe
+		.qry(&key)
+		.await
+		.deserialized()
+	{
+		| Ok(token) => token,
+		| Err(_) => {
+			let token = utils::random_string(UNSUBSCRIBE_TOKEN_LENGTH);
+			self.db
+				.userpushkey_unsubscribe
+				.put_raw(key, token.as_bytes());
+
+			token
+		},
+	};
+
+	let mut link = self
+		.services
+		.threepid
+		.base_url()
+		.join(UNSUBSCRIBE_PATH)
+		.map_err(|e| err!("Failed to build unsubscribe link: {e}"))?;
+
+	link.query_pairs_mut()
+		.append_pair("user_id", user_id.as_str())
+		.append_pair("pushkey", pushkey)
+		.append_pair("token", &token);
+
+	Ok(link)
+}
+
+/// Removes the email pusher an unsubscribe link was created for.
+#[implement(super::Service)]
+pub async fn unsubscribe_email(&self, user_id: &UserId, pushkey: &str, token: &str) -> Result {
+	let key = (user_id, pushkey);
+	let expected: String = self
+		.db
+		.userpushkey_unsubscribe
+		.qry(&key)
+		.await
+		.deserialized()
+		.map_err(|_| err!(Request(NotFound("Unknown unsubscribe link."))))?;
+
+	if !tokens_match(&expected, token) {
+		return Err!(Request(Forbidden("Invalid unsubscribe link.")));
+	}
+
+	self.delete_pusher(user_id, pushkey).await;
+
+	Ok(())
+}
+
+/// Removes the email pushers delivering to an address, once it is no longer
+/// associated with the user.
+#[implement(super::Service)]
+pub async fn delete_email_pushers(&self, user_id: &UserId, address: &str) {
+	for pusher in self.get_pushers(user_id).await {
+		if matches!(pusher.kind, PusherKind::Email(_))
+			&& pusher.ids.pushkey.to_lowercase() == address
+		{
+			self.delete_pusher(user_id, &pusher.ids.pushkey).await;
+		}
+	}
+}
+
+/// Forgets pending highlights and the unsubscribe token of a pusher.
+#[implement(super::Service)]
+pub(super) async fn clear_email_digest(&self, user_id: &UserId, pushkey: &str) {
+	self.db.userpushkey_unsubscribe.del((user_id, pushkey));
+
+	let prefix = (user_id, pushkey, Interfix);
+	self.db
+		.userpushkey_emaildigest
+		.keys_prefix_raw(&prefix)
+		.ignore_err()
+		.ready_for_each(|key| self.db.userpushkey_emaildigest.remove(key))
+		.await;
+}
+
+fn shorten(body: &str) -> String {
+	let mut chars = body.chars();
+	let mut preview: String = chars.by_ref().take(PREVIEW_LENGTH).collect();
+	if chars.next().is_some() {
+		preview.push('…');
+	}
+
+	preview.replace('\n', " ")
+}
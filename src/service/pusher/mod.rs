@@ -1,14 +1,28 @@
-use std::{fmt::Debug, mem, sync::Arc};
+mod benches;
+mod email;
+mod notifications;
+mod rules;
+mod tests;
+mod webhook;
+
+use std::{
+	fmt::{Debug, Write},
+	mem,
+	sync::{atomic::AtomicU64, Arc, Mutex},
+	time::Duration,
+};
 
+use async_trait::async_trait;
 use bytes::BytesMut;
 use conduwuit::{
 	debug_warn, err, trace,
-	utils::{stream::TryIgnore, string_from_bytes},
-	warn, Err, PduEvent, Result,
+	utils::{math::usize_from_f64, stream::TryIgnore, string_from_bytes},
+	warn, Err, PduEvent, Result, Server,
 };
 use database::{Deserialized, Ignore, Interfix, Json, Map};
 use futures::{Stream, StreamExt};
 use ipaddress::IPAddress;
+use lru_cache::LruCache;
 use ruma::{
 	api::{
 		client::push::{set_pusher, Pusher, PusherKind},
@@ -22,51 +36,122 @@
 		room::power_levels::RoomPowerLevelsEventContent, AnySyncTimelineEvent, StateEventType,
 		TimelineEventType,
 	},
-	push::{
-		Action, PushConditionPowerLevelsCtx, PushConditionRoomCtx, PushFormat, Ruleset, Tweak,
-	},
+	push::{Action, PushFormat, Ruleset, Tweak},
 	serde::Raw,
-	uint, RoomId, UInt, UserId,
+	thirdparty::Medium,
+	uint, OwnedUserId, RoomId, UInt, UserId,
+};
+use tokio::{
+	sync::Notify,
+	time::{interval, MissedTickBehavior},
 };
 
-use crate::{client, globals, rooms, sending, users, Dep};
+pub use self::{
+	email::UNSUBSCRIBE_PATH, notifications::NotificationRecord, rules::Evaluation,
+	webhook::WEBHOOK_KIND,
+};
+use crate::{account_data, client, globals, mailer, rooms, sending, threepid, users, Dep};
 
 pub struct Service {
+	interrupt: Notify,
+	ruleset_cache: Mutex<LruCache<OwnedUserId, Arc<Ruleset>>>,
+	ruleset_generation: AtomicU64,
 	db: Data,
 	services: Services,
 }
 
 struct Services {
+	server: Arc<Server>,
+	account_data: Dep<account_data::Service>,
 	globals: Dep<globals::Service>,
 	client: Dep<client::Service>,
+	mailer: Dep<mailer::Service>,
 	state_accessor: Dep<rooms::state_accessor::Service>,
 	state_cache: Dep<rooms::state_cache::Service>,
+	threepid: Dep<threepid::Service>,
+	user: Dep<rooms::user::Service>,
 	users: Dep<users::Service>,
 	sending: Dep<sending::Service>,
 }
 
 struct Data {
 	senderkey_pusher: Arc<Map>,
+	useridcount_notification: Arc<Map>,
+	userpushkey_emaildigest: Arc<Map>,
+	userpushkey_unsubscribe: Arc<Map>,
 }
 
+/// How often pending email digests are checked.
+const EMAIL_DIGEST_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often expired notifications are pruned.
+const NOTIFICATION_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[async_trait]
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		let config = &args.server.config;
+		let ruleset_cache_capacity =
+			f64::from(config.pushrule_cache_capacity) * config.cache_capacity_modifier;
+
 		Ok(Arc::new(Self {
+			interrupt: Notify::new(),
+			ruleset_cache: Mutex::new(LruCache::new(usize_from_f64(ruleset_cache_capacity)?)),
+			ruleset_generation: AtomicU64::new(0),
 			db: Data {
 				senderkey_pusher: args.db["senderkey_pusher"].clone(),
+				useridcount_notification: args.db["useridcount_notification"].clone(),
+				userpushkey_emaildigest: args.db["userpushkey_emaildigest"].clone(),
+				userpushkey_unsubscribe: args.db["userpushkey_unsubscribe"].clone(),
 			},
 			services: Services {
+				server: args.server.clone(),
+				account_data: args.depend::<account_data::Service>("account_data"),
 				globals: args.depend::<globals::Service>("globals"),
 				client: args.depend::<client::Service>("client"),
+				mailer: args.depend::<mailer::Service>("mailer"),
 				state_accessor: args
 					.depend::<rooms::state_accessor::Service>("rooms::state_accessor"),
 				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				threepid: args.depend::<threepid::Service>("threepid"),
+				user: args.depend::<rooms::user::Service>("rooms::user"),
 				users: args.depend::<users::Service>("users"),
 				sending: args.depend::<sending::Service>("sending"),
 			},
 		}))
 	}
 
+	#[tracing::instrument(skip_all, name = "pusher", level = "debug")]
+	async fn worker(self: Arc<Self>) -> Result<()> {
+		let mut digests = interval(EMAIL_DIGEST_INTERVAL);
+		digests.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+		let mut prune = interval(NOTIFICATION_PRUNE_INTERVAL);
+		prune.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+		let mailer = self.services.mailer.is_enabled();
+		loop {
+			tokio::select! {
+				() = self.interrupt.notified() => break,
+				_ = digests.tick(), if mailer => self.send_email_digests().await,
+				_ = prune.tick() => self.prune_notifications().await,
+			}
+		}
+
+		Ok(())
+	}
+
+	fn interrupt(&self) { self.interrupt.notify_waiters(); }
+
+	fn memory_usage(&self, out: &mut dyn Write) -> Result {
+		let ruleset_cache = self.ruleset_cache.lock().expect("locked").len();
+		writeln!(out, "ruleset_cache: {ruleset_cache}")?;
+
+		Ok(())
+	}
+
+	fn clear_cache(&self) { self.ruleset_cache.lock().expect("locked").clear(); }
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
@@ -95,51 +180,101 @@ pub async fn set_pusher(
 				// add some validation to the pusher URL
 				let pusher_kind = &data.pusher.kind;
 				if let PusherKind::Http(http) = pusher_kind {
-					let url = &http.url;
-					let url = url::Url::parse(&http.url).map_err(|e| {
-						err!(Request(InvalidParam(
-							warn!(%url, "HTTP pusher URL is not a valid URL: {e}")
-						)))
-					})?;
-
-					if ["http", "https"]
-						.iter()
-						.all(|&scheme| scheme != url.scheme().to_lowercase())
-					{
+					self.check_pusher_url(&http.url)?;
+				}
+
+				if let PusherKind::Email(_) = pusher_kind {
+					self.check_email_pusher(sender, &data.pusher).await?;
+				}
+
+				if let Some(webhook) = webhook::data(pusher_kind) {
+					if self.services.server.config.webhook_pusher_secret.is_none() {
 						return Err!(Request(InvalidParam(
-							warn!(%url, "HTTP pusher URL is not a valid HTTP/HTTPS URL")
+							"Webhook pushers are not enabled on this server."
 						)));
 					}
 
-					if let Ok(ip) =
-						IPAddress::parse(url.host_str().expect("URL previously validated"))
-					{
-						if !self.services.client.valid_cidr_range(&ip) {
-							return Err!(Request(InvalidParam(
-								warn!(%url, "HTTP pusher URL is a forbidden remote address")
-							)));
-						}
-					}
+					self.check_pusher_url(&webhook.url)?;
 				}
 
 				let key = (sender, data.pusher.ids.pushkey.as_str());
 				self.db.senderkey_pusher.put(key, Json(pusher));
 			},
 			| set_pusher::v3::PusherAction::Delete(ids) => {
-				let key = (sender, ids.pushkey.as_str());
-				self.db.senderkey_pusher.del(key);
-
-				self.services
-					.sending
-					.cleanup_events(None, Some(sender), Some(ids.pushkey.as_str()))
-					.await
-					.ok();
+				self.delete_pusher(sender, ids.pushkey.as_str()).await;
 			},
 		}
 
 		Ok(())
 	}
 
+	pub async fn delete_pusher(&self, sender: &UserId, pushkey: &str) {
+		let key = (sender, pushkey);
+		self.db.senderkey_pusher.del(key);
+
+		self.clear_email_digest(sender, pushkey).await;
+
+		self.services
+			.sending
+			.cleanup_events(None, Some(sender), Some(pushkey))
+			.await
+			.ok();
+	}
+
+	/// Email pushers deliver to an address bound to the user's account.
+	async fn check_email_pusher(&self, sender: &UserId, pusher: &Pusher) -> Result {
+		if !self.services.mailer.is_enabled() {
+			return Err!(Request(InvalidParam(
+				"Email notifications are not enabled on this server."
+			)));
+		}
+
+		if pusher.ids.app_id != "m.email" {
+			return Err!(Request(InvalidParam("Email pushers must use the app ID m.email.")));
+		}
+
+		let owner = self
+			.services
+			.threepid
+			.find_user(&Medium::Email, &pusher.ids.pushkey)
+			.await;
+
+		if !owner.is_ok_and(|owner| owner == sender) {
+			return Err!(Request(InvalidParam(
+				"The push key must be an email address bound to your account."
+			)));
+		}
+
+		Ok(())
+	}
+
+	/// Parses a pusher URL and rejects it unless it is an HTTP(S) URL on an
+	/// allowed address.
+	fn check_pusher_url(&self, url: &str) -> Result<url::Url> {
+		let parsed = url::Url::parse(url).map_err(|e| {
+			err!(Request(InvalidParam(warn!(%url, "Pusher URL is not a valid URL: {e}"))))
+		})?;
+
+		if ["http", "https"]
+			.iter()
+			.all(|&scheme| scheme != parsed.scheme().to_lowercase())
+		{
+			return Err!(Request(InvalidParam(
+				warn!(%url, "Pusher URL is not a valid HTTP/HTTPS URL")
+			)));
+		}
+
+		if let Ok(ip) = IPAddress::parse(parsed.host_str().expect("URL previously validated")) {
+			if !self.services.client.valid_cidr_range(&ip) {
+				return Err!(Request(InvalidParam(
+					warn!(%url, "Pusher URL is a forbidden remote address")
+				)));
+			}
+		}
+
+		Ok(parsed)
+	}
+
 	pub async fn get_pusher(&self, sender: &UserId, pushkey: &str) -> Result<Pusher> {
 		let senderkey = (sender, pushkey);
 		self.db
@@ -263,7 +398,7 @@ pub async fn send_push_notice(
 		user: &UserId,
 		unread: UInt,
 		pusher: &Pusher,
-		ruleset: Ruleset,
+		ruleset: &Ruleset,
 		pdu: &PduEvent,
 	) -> Result<()> {
 		let mut notify = None;
@@ -282,7 +417,7 @@ pub async fn send_push_notice(
 			.unwrap_or_default();
 
 		for action in self
-			.get_actions(user, &ruleset, &power_levels, &pdu.to_sync_room_event(), &pdu.room_id)
+			.get_actions(user, ruleset, &power_levels, &pdu.to_sync_room_event(), &pdu.room_id)
 			.await
 		{
 			let n = match action {
@@ -304,7 +439,7 @@ pub async fn send_push_notice(
 		}
 
 		if notify == Some(true) {
-			self.send_notice(unread, pusher, tweaks, pdu).await?;
+			self.send_notice(user, unread, pusher, tweaks, pdu).await?;
 		}
 		// Else the event triggered no actions
 
@@ -320,75 +455,23 @@ pub async fn get_actions<'a>(
 		pdu: &Raw<AnySyncTimelineEvent>,
 		room_id: &RoomId,
 	) -> &'a [Action] {
-		let power_levels = PushConditionPowerLevelsCtx {
-			users: power_levels.users.clone(),
-			users_default: power_levels.users_default,
-			notifications: power_levels.notifications.clone(),
-		};
-
-		let room_joined_count = self
-			.services
-			.state_cache
-			.room_joined_count(room_id)
-			.await
-			.unwrap_or(1)
-			.try_into()
-			.unwrap_or_else(|_| uint!(0));
-
-		let user_display_name = self
-			.services
-			.users
-			.displayname(user)
-			.await
-			.unwrap_or_else(|_| user.localpart().to_owned());
-
-		let ctx = PushConditionRoomCtx {
-			room_id: room_id.to_owned(),
-			member_count: room_joined_count,
-			user_id: user.to_owned(),
-			user_display_name,
-			power_levels: Some(power_levels),
-		};
+		let ctx = self.room_ctx(room_id, power_levels, user).await;
 
 		ruleset.get_actions(pdu, &ctx)
 	}
 
-	#[tracing::instrument(skip(self, unread, pusher, tweaks, event))]
+	#[tracing::instrument(skip(self, user, unread, pusher, tweaks, event))]
 	async fn send_notice(
 		&self,
+		user: &UserId,
 		unread: UInt,
 		pusher: &Pusher,
 		tweaks: Vec<Tweak>,
 		event: &PduEvent,
 	) -> Result {
-		// TODO: email
 		match &pusher.kind {
 			| PusherKind::Http(http) => {
-				let url = &http.url;
-				let url = url::Url::parse(&http.url).map_err(|e| {
-					err!(Request(InvalidParam(
-						warn!(%url, "HTTP pusher URL is not a valid URL: {e}")
-					)))
-				})?;
-
-				if ["http", "https"]
-					.iter()
-					.all(|&scheme| scheme != url.scheme().to_lowercase())
-				{
-					return Err!(Request(InvalidParam(
-						warn!(%url, "HTTP pusher URL is not a valid HTTP/HTTPS URL")
-					)));
-				}
-
-				if let Ok(ip) =
-					IPAddress::parse(url.host_str().expect("URL previously validated"))
-				{
-					if !self.services.client.valid_cidr_range(&ip) {
-						return Err!(Request(InvalidParam(
-							warn!(%url, "HTTP pusher URL is a forbidden remote address")
-						)));
-					}
-				}
+				self.check_pusher_url(&http.url)?;
 
 				// TODO (timo): can pusher/devices have conflicting formats
 				let event_id_only = http.format == Some(PushFormat::EventIdOnly);
@@ -471,9 +554,13 @@ async fn send_notice(
 
 				Ok(())
 			},
-			// TODO: Handle email
-			//PusherKind::Email(_) => Ok(()),
-			| _ => Ok(()),
+			| PusherKind::Email(_) => self.queue_email_notification(user, pusher, &tweaks, event),
+			| kind => match webhook::data(kind) {
+				| Some(webhook) =>
+					self.send_webhook(user, unread, pusher, &webhook, &tweaks, event)
+						.await,
+				| None => Ok(()),
+			},
 		}
 	}
 }
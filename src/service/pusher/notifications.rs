@@ -0,0 +1,96 @@
+use conduwuit::{
+	debug, implement,
+	utils::{stream::TryIgnore, time::now_millis, ReadyExt},
+	PduEvent,
+};
+use database::Json;
+use futures::{Stream, StreamExt};
+use ruma::{
+	push::{Action, Tweak},
+	OwnedEventId, OwnedRoomId, UserId,
+};
+use serde::{Deserialize, Serialize};
+
+/// An event which notified a user, as listed by the notifications endpoint.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NotificationRecord {
+	pub room_id: OwnedRoomId,
+	pub event_id: OwnedEventId,
+	pub actions: Vec<Action>,
+	pub ts: u64,
+}
+
+impl NotificationRecord {
+	#[must_use]
+	pub fn is_highlight(&self) -> bool {
+		self.actions
+			.iter()
+			.any(|action| matches!(action, Action::SetTweak(Tweak::Highlight(true))))
+	}
+}
+
+/// Records that an event notified a user. `count` is the event's PDU count,
+/// which orders the records and serves as pagination token.
+#[implement(super::Service)]
+pub fn record_notification(&self, user: &UserId, count: u64, pdu: &PduEvent, actions: &[Action]) {
+	if self.services.server.config.notification_retention == 0 {
+		return;
+	}
+
+	let record = NotificationRecord {
+		room_id: pdu.room_id.clone(),
+		event_id: pdu.event_id.clone(),
+		actions: actions.to_vec(),
+		ts: now_millis(),
+	};
+
+	self.db
+		.useridcount_notification
+		.put((user, count), Json(record));
+}
+
+/// Notifications of a user, newest first, starting before the `from` count.
+#[implement(super::Service)]
+pub fn notifications<'a>(
+	&'a self,
+	user: &'a UserId,
+	from: Option<u64>,
+) -> impl Stream<Item = (u64, NotificationRecord)> + Send + 'a {
+	type KeyVal<'a> = ((&'a UserId, u64), NotificationRecord);
+
+	let from = from.map_or(u64::MAX, |count| count.saturating_sub(1));
+	self.db
+		.useridcount_notification
+		.rev_stream_from(&(user, from))
+		.ignore_err()
+		.ready_take_while(move |((user_, _), _): &KeyVal<'_>| *user_ == user)
+		.map(|((_, count), record): KeyVal<'_>| (count, record))
+}
+
+/// Removes the notifications older than the configured retention.
+#[implement(super::Service)]
+pub(super) async fn prune_notifications(&self) {
+	type KeyVal<'a> = ((&'a UserId, u64), NotificationRecord);
+
+	let retention = self
+		.services
+		.server
+		.config
+		.notification_retention
+		.saturating_mul(1000);
+
+	let cutoff = now_millis().saturating_sub(retention);
+	let mut pruned: usize = 0;
+	self.db
+		.useridcount_notification
+		.stream()
+		.ignore_err()
+		.ready_filter(|(_, record): &KeyVal<'_>| record.ts < cutoff)
+		.ready_for_each(|((user, count), _): KeyVal<'_>| {
+			self.db.useridcount_notification.del((user, count));
+			pruned = pruned.saturating_add(1);
+		})
+		.await;
+
+	debug!(pruned, "Pruned expired notifications");
+}
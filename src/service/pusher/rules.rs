@@ -0,0 +1,128 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use conduwuit::implement;
+use ruma::{
+	events::{
+		push_rules::PushRulesEvent, room::power_levels::RoomPowerLevelsEventContent,
+		AnySyncTimelineEvent, GlobalAccountDataEventType,
+	},
+	push::{Action, PushConditionPowerLevelsCtx, PushConditionRoomCtx, Ruleset, Tweak},
+	serde::Raw,
+	uint, RoomId, UserId,
+};
+
+/// Whether an event notifies a user and whether it highlights for them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Evaluation {
+	pub notify: bool,
+	pub highlight: bool,
+}
+
+impl Evaluation {
+	/// Evaluates a user's push rules for an event. The context must be
+	/// targeted at the user.
+	#[must_use]
+	pub fn new(
+		ruleset: &Ruleset,
+		ctx: &PushConditionRoomCtx,
+		event: &Raw<AnySyncTimelineEvent>,
+	) -> Self {
+		Self::from_actions(ruleset.get_actions(event, ctx))
+	}
+
+	#[must_use]
+	pub fn from_actions(actions: &[Action]) -> Self {
+		actions
+			.iter()
+			.fold(Self::default(), |evaluation, action| match action {
+				| Action::Notify => Self { notify: true, ..evaluation },
+				| Action::SetTweak(Tweak::Highlight(true)) =>
+					Self { highlight: true, ..evaluation },
+				| _ => evaluation,
+			})
+	}
+}
+
+/// Returns the push rules of a user. They are parsed from the account data
+/// once and cached until the user changes them.
+#[implement(super::Service)]
+pub async fn get_ruleset(&self, user: &UserId) -> Arc<Ruleset> {
+	if let Some(ruleset) = self.ruleset_cache.lock().expect("locked").get_mut(user) {
+		return ruleset.clone();
+	}
+
+	// Rules loaded while they are being changed must not be cached.
+	let generation = self.ruleset_generation.load(Ordering::Acquire);
+	let ruleset: Arc<Ruleset> = self
+		.services
+		.account_data
+		.get_global(user, GlobalAccountDataEventType::PushRules)
+		.await
+		.map_or_else(|_| Ruleset::server_default(user), |ev: PushRulesEvent| ev.content.global)
+		.into();
+
+	let mut cache = self.ruleset_cache.lock().expect("locked");
+	if generation == self.ruleset_generation.load(Ordering::Acquire) {
+		cache.insert(user.to_owned(), ruleset.clone());
+	}
+
+	ruleset
+}
+
+/// Drops the cached push rules of a user after they were changed.
+#[implement(super::Service)]
+pub fn invalidate_ruleset(&self, user: &UserId) {
+	let mut cache = self.ruleset_cache.lock().expect("locked");
+	self.ruleset_generation.fetch_add(1, Ordering::AcqRel);
+	cache.remove(user);
+}
+
+/// Builds the context for evaluating push rules in a room, targeted at
+/// `user`. Evaluating an event for many users should build it once and
+/// re-target it with `set_ctx_user`.
+#[implement(super::Service)]
+pub async fn room_ctx(
+	&self,
+	room_id: &RoomId,
+	power_levels: &RoomPowerLevelsEventContent,
+	user: &UserId,
+) -> PushConditionRoomCtx {
+	let member_count = self
+		.services
+		.state_cache
+		.room_joined_count(room_id)
+		.await
+		.unwrap_or(1)
+		.try_into()
+		.unwrap_or_else(|_| uint!(0));
+
+	let power_levels = PushConditionPowerLevelsCtx {
+		users: power_levels.users.clone(),
+		users_default: power_levels.users_default,
+		notifications: power_levels.notifications.clone(),
+	};
+
+	PushConditionRoomCtx {
+		room_id: room_id.to_owned(),
+		member_count,
+		user_id: user.to_owned(),
+		user_display_name: self.user_display_name(user).await,
+		power_levels: Some(power_levels),
+	}
+}
+
+/// Re-targets a room's push rule context at another user.
+#[implement(super::Service)]
+pub async fn set_ctx_user(&self, ctx: &mut PushConditionRoomCtx, user: &UserId) {
+	ctx.user_display_name = self.user_display_name(user).await;
+	ctx.user_id = user.to_owned();
+}
+
+#[implement(super::Service)]
+async fn user_display_name(&self, user: &UserId) -> String {
+	self.services
+		.users
+		.displayname(user)
+		.await
+		.unwrap_or_else(|_| user.localpart().to_owned())
+}
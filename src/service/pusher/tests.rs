@@ -0,0 +1,132 @@
+#![cfg(test)]
+
+use ruma::{
+	api::client::push::Pusher,
+	events::AnySyncTimelineEvent,
+	owned_event_id, owned_room_id, owned_user_id,
+	push::{Action, PushConditionRoomCtx, Ruleset, Tweak},
+	serde::Raw,
+	uint,
+};
+use serde_json::json;
+
+use super::{webhook, Evaluation, NotificationRecord};
+
+fn pusher(kind: &str, data: serde_json::Value) -> Pusher {
+	serde_json::from_value(json!({
+		"pushkey": "key",
+		"app_id": "org.example.app",
+		"app_display_name": "Example",
+		"device_display_name": "Device",
+		"lang": "en",
+		"kind": kind,
+		"data": data,
+	}))
+	.expect("valid pusher")
+}
+
+#[test]
+fn webhook_signature() {
+	let signature = webhook::sign("secret", 1_700_000_000_000, br#"{"a":1}"#);
+
+	assert_eq!(signature, "TvJzKw1jKmiXrzptAqbeKH9g1sXxXavbDOsEWjTjxac=");
+}
+
+#[test]
+fn webhook_data_from_custom_kind() {
+	let pusher = pusher(webhook::WEBHOOK_KIND, json!({ "url": "https://bus.example.com/push" }));
+	let webhook = webhook::data(&pusher.kind).expect("webhook pusher");
+
+	assert_eq!(webhook.url, "https://bus.example.com/push");
+}
+
+#[test]
+fn webhook_data_ignores_other_kinds() {
+	let http =
+		pusher("http", json!({ "url": "https://push.example.com/_matrix/push/v1/notify" }));
+	let email = pusher("email", json!({}));
+
+	assert!(webhook::data(&http.kind).is_none());
+	assert!(webhook::data(&email.kind).is_none());
+}
+
+fn room_ctx() -> PushConditionRoomCtx {
+	PushConditionRoomCtx {
+		room_id: owned_room_id!("!room:example.com"),
+		member_count: uint!(3),
+		user_id: owned_user_id!("@alice:example.com"),
+		user_display_name: "Alice".to_owned(),
+		power_levels: None,
+	}
+}
+
+fn event(kind: &str, content: serde_json::Value) -> Raw<AnySyncTimelineEvent> {
+	serde_json::from_value(json!({
+		"type": kind,
+		"event_id": "$event:example.com",
+		"sender": "@bob:example.com",
+		"origin_server_ts": 1_700_000_000_000_u64,
+		"content": content,
+	}))
+	.expect("valid event")
+}
+
+#[test]
+fn evaluation_from_actions() {
+	let actions = [Action::Notify, Action::SetTweak(Tweak::Highlight(true))];
+	let evaluation = Evaluation::from_actions(&actions);
+
+	assert!(evaluation.notify);
+	assert!(evaluation.highlight);
+	assert_eq!(Evaluation::from_actions(&[]), Evaluation::default());
+}
+
+#[test]
+fn evaluation_of_mention() {
+	let ctx = room_ctx();
+	let ruleset = Ruleset::server_default(&ctx.user_id);
+	let event = event(
+		"m.room.message",
+		json!({
+			"msgtype": "m.text",
+			"body": "alice: hi",
+			"m.mentions": { "user_ids": ["@alice:example.com"] },
+		}),
+	);
+
+	let evaluation = Evaluation::new(&ruleset, &ctx, &event);
+
+	assert!(evaluation.notify);
+	assert!(evaluation.highlight);
+}
+
+#[test]
+fn evaluation_of_encrypted_event() {
+	let ctx = room_ctx();
+	let ruleset = Ruleset::server_default(&ctx.user_id);
+	let event = event(
+		"m.room.encrypted",
+		json!({ "algorithm": "m.megolm.v1.aes-sha2", "ciphertext": "AwgAEnAC" }),
+	);
+
+	let evaluation = Evaluation::new(&ruleset, &ctx, &event);
+
+	assert!(evaluation.notify);
+	assert!(!evaluation.highlight);
+}
+
+#[test]
+fn notification_record_highlight() {
+	let record = |actions| NotificationRecord {
+		room_id: owned_room_id!("!room:example.com"),
+		event_id: owned_event_id!("$event:example.com"),
+		actions,
+		ts: 1_700_000_000_000,
+	};
+
+	assert!(record(vec![Action::Notify, Action::SetTweak(Tweak::Highlight(true))]).is_highlight());
+	assert!(
+		!record(vec![Action::Notify, Action::SetTweak(Tweak::Highlight(false))]).is_highlight()
+	);
+	assert!(!record(vec![Action::Notify]).is_highlight());
+}
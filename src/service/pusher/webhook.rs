@@ -0,0 +1,121 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use conduwuit::{
+	debug_warn, err, implement, utils::time::now_millis, warn, Err, PduEvent, Result,
+};
+use hmac::{Hmac, Mac};
+use ipaddress::IPAddress;
+use ruma::{
+	api::client::push::{Pusher, PusherKind},
+	push::Tweak,
+	UInt, UserId,
+};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+
+/// Pusher kind which posts signed notifications to an arbitrary URL.
+pub const WEBHOOK_KIND: &str = "io.conduwuit.webhook";
+
+const TIMESTAMP_HEADER: &str = "X-Conduwuit-Timestamp";
+const SIGNATURE_HEADER: &str = "X-Conduwuit-Signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Webhook {
+	pub(super) url: String,
+}
+
+#[derive(Deserialize)]
+struct CustomKind {
+	kind: String,
+	data: Webhook,
+}
+
+/// Returns the webhook of a pusher of the webhook kind. Ruma has no variant
+/// for it, so it is read back from the serialized custom kind.
+pub(super) fn data(kind: &PusherKind) -> Option<Webhook> {
+	let value = serde_json::to_value(kind).ok()?;
+	let custom: CustomKind = serde_json::from_value(value).ok()?;
+
+	(custom.kind == WEBHOOK_KIND).then_some(custom.data)
+}
+
+#[implement(super::Service)]
+pub(super) async fn send_webhook(
+	&self,
+	user: &UserId,
+	unread: UInt,
+	pusher: &Pusher,
+	webhook: &Webhook,
+	tweaks: &[Tweak],
+	event: &PduEvent,
+) -> Result {
+	let Some(secret) = &self.services.server.config.webhook_pusher_secret else {
+		return Err!(Config("webhook_pusher_secret", "Webhook pushers are not enabled."));
+	};
+
+	let url = self.check_pusher_url(&webhook.url)?;
+	let body = serde_json::to_vec(&json!({
+		"user_id": user,
+		"app_id": pusher.ids.app_id,
+		"pushkey": pusher.ids.pushkey,
+		"room_id": event.room_id,
+		"event_id": event.event_id,
+		"sender": event.sender,
+		"type": event.kind,
+		"unread": unread,
+		"highlight": tweaks.iter().any(|t| matches!(t, Tweak::Highlight(true))),
+	}))?;
+
+	let timestamp = now_millis();
+	let signature = sign(secret, timestamp, &body);
+	let request = self
+		.services
+		.client
+		.pusher
+		.post(url.clone())
+		.header(http::header::CONTENT_TYPE, "application/json")
+		.header(TIMESTAMP_HEADER, timestamp)
+		.header(SIGNATURE_HEADER, signature)
+		.body(body)
+		.build()?;
+
+	let response = self
+		.services
+		.client
+		.pusher
+		.execute(request)
+		.await
+		.map_err(|e| err!(BadServerResponse(warn!("Could not send webhook to {url}: {e}"))))?;
+
+	if let Some(remote_addr) = response.remote_addr() {
+		if let Ok(ip) = IPAddress::parse(remote_addr.ip().to_string()) {
+			if !self.services.client.valid_cidr_range(&ip) {
+				return Err!(BadServerResponse("Not allowed to send requests to this IP"));
+			}
+		}
+	}
+
+	let status = response.status();
+	if !status.is_success() {
+		debug_warn!("Webhook response body: {:?}", response.text().await);
+		return Err!(BadServerResponse(warn!(
+			"Webhook {url} returned unsuccessful HTTP response: {status}"
+		)));
+	}
+
+	Ok(())
+}
+
+/// Signs the timestamp and body of a webhook request.
+pub(super) fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+	let mut mac =
+		HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+
+	mac.update(timestamp.to_string().as_bytes());
+	mac.update(b".");
+	mac.update(body);
+
+	STANDARD.encode(mac.finalize().into_bytes())
+}
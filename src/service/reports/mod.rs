@@ -0,0 +1,202 @@
+use std::{fmt, str::FromStr, sync::Arc};
+
+use conduwuit::{
+	err, implement,
+	utils::{stream::TryIgnore, ReadyExt},
+	PduEvent, Result,
+};
+use database::{Deserialized, Json, Map};
+use futures::{Stream, StreamExt};
+use ruma::{
+	Int, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{globals, Dep};
+
+/// Abuse reports submitted by local users, kept until an admin triages them.
+pub struct Service {
+	services: Services,
+	db: Data,
+}
+
+struct Services {
+	globals: Dep<globals::Service>,
+}
+
+struct Data {
+	reportid_report: Arc<Map>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Report {
+	/// When the report was received.
+	pub received_ts: MilliSecondsSinceUnixEpoch,
+
+	/// The local user who submitted the report.
+	pub reporter: OwnedUserId,
+
+	/// The reported room, or the room of the reported event.
+	pub room_id: OwnedRoomId,
+
+	/// The reported event, if this is an event report.
+	pub event_id: Option<OwnedEventId>,
+
+	/// The sender of the reported event.
+	pub event_sender: Option<OwnedUserId>,
+
+	/// Offensiveness from -100 (most offensive) to 0 (inoffensive).
+	pub score: Option<Int>,
+
+	pub reason: Option<String>,
+
+	pub status: Status,
+
+	/// When the report was resolved or dismissed.
+	pub handled_ts: Option<MilliSecondsSinceUnixEpoch>,
+
+	/// Note left by the admin who resolved or dismissed the report.
+	pub note: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+	#[default]
+	Open,
+	Resolved,
+	Dismissed,
+}
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			services: Services {
+				globals: args.depend::<globals::Service>("globals"),
+			},
+			db: Data {
+				reportid_report: args.db["reportid_report"].clone(),
+			},
+		}))
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Stores a report about an event. Returns the ID of the new report.
+#[implement(Service)]
+pub fn report_event(
+	&self,
+	reporter: &UserId,
+	pdu: &PduEvent,
+	score: Option<Int>,
+	reason: Option<String>,
+) -> Result<u64> {
+	self.insert(Report {
+		event_id: Some(pdu.event_id.clone()),
+		event_sender: Some(pdu.sender.clone()),
+		score,
+		..Report::new(reporter, &pdu.room_id, reason)
+	})
+}
+
+/// Stores a report about a room. Returns the ID of the new report.
+#[implement(Service)]
+pub fn report_room(
+	&self,
+	reporter: &UserId,
+	room_id: &RoomId,
+	reason: Option<String>,
+) -> Result<u64> {
+	self.insert(Report::new(reporter, room_id, reason))
+}
+
+#[implement(Service)]
+fn insert(&self, report: Report) -> Result<u64> {
+	let id = self.services.globals.next_count()?;
+	self.db.reportid_report.put(id, Json(report));
+
+	Ok(id)
+}
+
+#[implement(Service)]
+pub async fn get(&self, id: u64) -> Result<Report> {
+	self.db
+		.reportid_report
+		.qry(&id)
+		.await
+		.deserialized()
+		.map_err(|_| err!(Request(NotFound("Report {id} not found."))))
+}
+
+/// Marks a report as resolved or dismissed, or reopens it.
+#[implement(Service)]
+pub async fn set_status(&self, id: u64, status: Status, note: Option<String>) -> Result<Report> {
+	let mut report = self.get(id).await?;
+	report.status = status;
+	report.note = note;
+	report.handled_ts = match status {
+		| Status::Open => None,
+		| Status::Resolved | Status::Dismissed => Some(MilliSecondsSinceUnixEpoch::now()),
+	};
+
+	self.db.reportid_report.put(id, Json(&report));
+
+	Ok(report)
+}
+
+/// All reports, newest first.
+#[implement(Service)]
+pub fn reports(&self) -> impl Stream<Item = (u64, Report)> + Send + '_ {
+	self.db.reportid_report.rev_stream().ignore_err()
+}
+
+/// Number of reports which have not been triaged yet.
+#[implement(Service)]
+pub async fn count_open(&self) -> usize {
+	self.reports()
+		.ready_filter(|(_, report)| report.status == Status::Open)
+		.count()
+		.await
+}
+
+impl Report {
+	fn new(reporter: &UserId, room_id: &RoomId, reason: Option<String>) -> Self {
+		Self {
+			received_ts: MilliSecondsSinceUnixEpoch::now(),
+			reporter: reporter.to_owned(),
+			room_id: room_id.to_owned(),
+			event_id: None,
+			event_sender: None,
+			score: None,
+			reason,
+			status: Status::Open,
+			handled_ts: None,
+			note: None,
+		}
+	}
+}
+
+impl fmt::Display for Status {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			| Self::Open => "open",
+			| Self::Resolved => "resolved",
+			| Self::Dismissed => "dismissed",
+		})
+	}
+}
+
+impl FromStr for Status {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			| "open" => Ok(Self::Open),
+			| "resolved" => Ok(Self::Resolved),
+			| "dismissed" => Ok(Self::Dismissed),
+			| _ =>
+				Err(format!("unknown report status {s:?}, expected open, resolved or dismissed")),
+		}
+	}
+}
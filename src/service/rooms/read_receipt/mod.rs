@@ -14,7 +14,7 @@
 };
 
 use self::data::{Data, ReceiptItem};
-use crate::{rooms, sending, Dep};
+use crate::{rooms, sending, users, Dep};
 
 pub struct Service {
 	services: Services,
@@ -25,6 +25,7 @@ struct Services {
 	sending: Dep<sending::Service>,
 	short: Dep<rooms::short::Service>,
 	timeline: Dep<rooms::timeline::Service>,
+	users: Dep<users::Service>,
 }
 
 impl crate::Service for Service {
@@ -34,6 +35,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				sending: args.depend::<sending::Service>("sending"),
 				short: args.depend::<rooms::short::Service>("rooms::short"),
 				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
+				users: args.depend::<users::Service>("users"),
 			},
 			db: Data::new(&args),
 		}))
@@ -50,6 +52,12 @@ pub async fn readreceipt_update(
 		room_id: &RoomId,
 		event: &ReceiptEvent,
 	) {
+		// Public receipts of shadow-banned users reach nobody; their private read
+		// marker is kept separately.
+		if self.services.users.is_shadow_banned(user_id).await {
+			return;
+		}
+
 		self.db.readreceipt_update(user_id, room_id, event).await;
 		self.services
 			.sending
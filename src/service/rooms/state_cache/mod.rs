@@ -267,6 +267,10 @@ pub async fn update_membership(
 			},
 			| MembershipState::Leave | MembershipState::Ban => {
 				self.mark_as_left(user_id, room_id);
+				self.services
+					.users
+					.mark_device_list_left(user_id, room_id)
+					.await;
 			},
 			| _ => {},
 		}
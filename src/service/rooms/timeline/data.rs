@@ -20,6 +20,8 @@ pub(super) struct Data {
 	pduid_pdu: Arc<Map>,
 	userroomid_highlightcount: Arc<Map>,
 	userroomid_notificationcount: Arc<Map>,
+	userroomthreadid_highlightcount: Arc<Map>,
+	userroomthreadid_notificationcount: Arc<Map>,
 	pub(super) db: Arc<Database>,
 	services: Services,
 }
@@ -39,6 +41,8 @@ pub(super) fn new(args: &crate::Args<'_>) -> Self {
 			pduid_pdu: db["pduid_pdu"].clone(),
 			userroomid_highlightcount: db["userroomid_highlightcount"].clone(),
 			userroomid_notificationcount: db["userroomid_notificationcount"].clone(),
+			userroomthreadid_highlightcount: db["userroomthreadid_highlightcount"].clone(),
+			userroomthreadid_notificationcount: db["userroomthreadid_notificationcount"].clone(),
 			db: args.db.clone(),
 			services: Services {
 				short: args.depend::<rooms::short::Service>("rooms::short"),
@@ -270,26 +274,33 @@ fn each_pdu((pdu_id, pdu): KeyVal<'_>, user_id: Option<&UserId>) -> Result<PdusI
 		Ok((pdu_id.pdu_count(), pdu))
 	}
 
+	/// Counts are kept for the whole room and, for events in a thread, for
+	/// the thread as well.
 	pub(super) fn increment_notification_counts(
 		&self,
 		room_id: &RoomId,
+		thread_root: Option<&EventId>,
 		notifies: Vec<OwnedUserId>,
 		highlights: Vec<OwnedUserId>,
 	) {
 		let _cork = self.db.cork();
 
 		for user in notifies {
-			let mut userroom_id = user.as_bytes().to_vec();
-			userroom_id.push(0xFF);
-			userroom_id.extend_from_slice(room_id.as_bytes());
-			increment(&self.userroomid_notificationcount, &userroom_id);
+			increment_userroom(
+				[&self.userroomid_notificationcount, &self.userroomthreadid_notificationcount],
+				&user,
+				room_id,
+				thread_root,
+			);
 		}
 
 		for user in highlights {
-			let mut userroom_id = user.as_bytes().to_vec();
-			userroom_id.push(0xFF);
-			userroom_id.extend_from_slice(room_id.as_bytes());
-			increment(&self.userroomid_highlightcount, &userroom_id);
+			increment_userroom(
+				[&self.userroomid_highlightcount, &self.userroomthreadid_highlightcount],
+				&user,
+				room_id,
+				thread_root,
+			);
 		}
 	}
 
@@ -317,6 +328,26 @@ async fn count_to_id(
 }
 
 //TODO: this is an ABA
+/// Increments the count of the room and, for an event in a thread, the count
+/// of the thread, which is kept in a map of its own.
+fn increment_userroom(
+	[room_db, thread_db]: [&Arc<Map>; 2],
+	user: &UserId,
+	room_id: &RoomId,
+	thread_root: Option<&EventId>,
+) {
+	let mut userroom_id = user.as_bytes().to_vec();
+	userroom_id.push(0xFF);
+	userroom_id.extend_from_slice(room_id.as_bytes());
+	increment(room_db, &userroom_id);
+
+	if let Some(thread_root) = thread_root {
+		userroom_id.push(0xFF);
+		userroom_id.extend_from_slice(thread_root.as_bytes());
+		increment(thread_db, &userroom_id);
+	}
+}
+
 fn increment(db: &Arc<Map>, key: &[u8]) {
 	let old = db.get_blocking(key);
 	let new = utils::increment(old.ok().as_deref());
@@ -1,4 +1,5 @@
 mod data;
+mod tests;
 
 use std::{
 	borrow::Borrow,
@@ -25,7 +26,7 @@
 	api::federation,
 	canonical_json::to_canonical_value,
 	events::{
-		push_rules::PushRulesEvent,
+		relation::RelationType,
 		room::{
 			create::RoomCreateEventContent,
 			encrypted::Relation,
@@ -33,9 +34,8 @@
 			power_levels::RoomPowerLevelsEventContent,
 			redaction::RoomRedactionEventContent,
 		},
-		GlobalAccountDataEventType, StateEventType, TimelineEventType,
+		StateEventType, TimelineEventType,
 	},
-	push::{Action, Ruleset, Tweak},
 	state_res::{self, Event, RoomVersion},
 	uint, CanonicalJsonObject, CanonicalJsonValue, EventId, OwnedEventId, OwnedRoomId,
 	OwnedServerName, OwnedUserId, RoomId, RoomVersionId, ServerName, UserId,
@@ -46,9 +46,11 @@
 use self::data::Data;
 pub use self::data::PdusIterItem;
 use crate::{
-	account_data, admin, appservice,
+	admin, appservice,
 	appservice::NamespaceRegex,
-	globals, pusher, rooms,
+	globals, media, pusher,
+	pusher::Evaluation,
+	rooms,
 	rooms::{short::ShortRoomId, state_compressor::CompressedState},
 	sending, server_keys, users, Dep,
 };
@@ -83,11 +85,11 @@ pub struct Service {
 
 struct Services {
 	server: Arc<Server>,
-	account_data: Dep<account_data::Service>,
 	appservice: Dep<appservice::Service>,
 	admin: Dep<admin::Service>,
 	alias: Dep<rooms::alias::Service>,
 	globals: Dep<globals::Service>,
+	media: Dep<media::Service>,
 	short: Dep<rooms::short::Service>,
 	state: Dep<rooms::state::Service>,
 	state_cache: Dep<rooms::state_cache::Service>,
@@ -113,11 +115,11 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			services: Services {
 				server: args.server.clone(),
-				account_data: args.depend::<account_data::Service>("account_data"),
 				appservice: args.depend::<appservice::Service>("appservice"),
 				admin: args.depend::<admin::Service>("admin"),
 				alias: args.depend::<rooms::alias::Service>("rooms::alias"),
 				globals: args.depend::<globals::Service>("globals"),
+				media: args.depend::<media::Service>("media"),
 				short: args.depend::<rooms::short::Service>("rooms::short"),
 				state: args.depend::<rooms::state::Service>("rooms::state"),
 				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
@@ -351,7 +353,8 @@ pub async fn append_pdu<'a, Leafs>(
 			.private_read_set(&pdu.room_id, &pdu.sender, count1);
 		self.services
 			.user
-			.reset_notification_counts(&pdu.sender, &pdu.room_id);
+			.reset_notification_counts(&pdu.sender, &pdu.room_id)
+			.await;
 
 		let count2 = PduCount::Normal(self.services.globals.next_count().unwrap());
 		let pdu_id: RawPduId = PduId { shortroomid, shorteventid: count2 }.into();
@@ -395,42 +398,35 @@ pub async fn append_pdu<'a, Leafs>(
 			}
 		}
 
-		for user in &push_target {
-			let rules_for_user = self
-				.services
-				.account_data
-				.get_global(user, GlobalAccountDataEventType::PushRules)
-				.await
-				.map_or_else(
-					|_| Ruleset::server_default(user),
-					|ev: PushRulesEvent| ev.content.global,
-				);
+		// Edits don't notify again for the event they replace. The relation is
+		// readable even when the edit is encrypted.
+		if pdu.relation_type_equal(&RelationType::Replacement) {
+			push_target.clear();
+		}
 
-			let mut highlight = false;
-			let mut notify = false;
+		// The member count and power levels are the same for every user, so the
+		// context is built once and only re-targeted at each user.
+		let mut push_ctx = self
+			.services
+			.pusher
+			.room_ctx(&pdu.room_id, &power_levels, &pdu.sender)
+			.await;
 
-			for action in self
-				.services
-				.pusher
-				.get_actions(user, &rules_for_user, &power_levels, &sync_pdu, &pdu.room_id)
-				.await
-			{
-				match action {
-					| Action::Notify => notify = true,
-					| Action::SetTweak(Tweak::Highlight(true)) => {
-						highlight = true;
-					},
-					| _ => {},
-				};
+		for user in &push_target {
+			let ruleset = self.services.pusher.get_ruleset(user).await;
+			self.services.pusher.set_ctx_user(&mut push_ctx, user).await;
 
-				// Break early if both conditions are true
-				if notify && highlight {
-					break;
-				}
-			}
+			let actions = ruleset.get_actions(&sync_pdu, &push_ctx);
+			let Evaluation { notify, highlight } = Evaluation::from_actions(actions);
 
 			if notify {
 				notifies.push(user.clone());
+				self.services.pusher.record_notification(
+					user,
+					count2.into_unsigned(),
+					pdu,
+					actions,
+				);
 			}
 
 			if highlight {
@@ -449,8 +445,12 @@ pub async fn append_pdu<'a, Leafs>(
 				.await;
 		}
 
-		self.db
-			.increment_notification_counts(&pdu.room_id, notifies, highlights);
+		self.db.increment_notification_counts(
+			&pdu.room_id,
+			pdu.thread_root().as_deref(),
+			notifies,
+			highlights,
+		);
 
 		match pdu.kind {
 			| TimelineEventType::RoomRedaction => {
@@ -539,6 +539,8 @@ pub async fn append_pdu<'a, Leafs>(
 			| _ => {},
 		}
 
+		self.services.media.index_media_references(pdu).await;
+
 		if let Ok(content) = pdu.get_content::<ExtractRelatesToEventId>() {
 			if let Ok(related_pducount) = self.get_pdu_count(&content.relates_to.event_id).await {
 				self.services
@@ -824,6 +826,15 @@ pub async fn build_and_append_pdu(
 		room_id: &RoomId,
 		state_lock: &RoomMutexGuard,
 	) -> Result<OwnedEventId> {
+		// Events of shadow-banned users are dropped before they could fail any
+		// checks, so that the user sees them succeed.
+		if self.services.users.is_shadow_banned(sender).await
+			&& self.is_shadow_dropped(&pdu_builder, sender, room_id).await
+		{
+			debug_warn!(%sender, %room_id, "Dropping event of shadow-banned user");
+			return Ok(shadow_event_id());
+		}
+
 		let (pdu, pdu_json) = self
 			.create_hash_and_sign_event(pdu_builder, sender, room_id, state_lock)
 			.await?;
@@ -942,6 +953,25 @@ pub async fn build_and_append_pdu(
 		Ok(pdu.event_id)
 	}
 
+	/// Whether an event of a shadow-banned user must not reach anyone. They
+	/// can still use rooms nobody else is in and leave rooms they are in.
+	async fn is_shadow_dropped(
+		&self,
+		pdu_builder: &PduBuilder,
+		sender: &UserId,
+		room_id: &RoomId,
+	) -> bool {
+		let state_cache = &self.services.state_cache;
+		let is_member = state_cache.is_joined(sender, room_id).await
+			|| state_cache.is_invited(sender, room_id).await;
+
+		// The member counts are cached, unlike the members.
+		let joined = state_cache.room_joined_count(room_id).await.unwrap_or(0);
+		let invited = state_cache.room_invited_count(room_id).await.unwrap_or(0);
+
+		shadow_drops(pdu_builder, sender, is_member, joined.saturating_add(invited))
+	}
+
 	/// Append the incoming event setting the state snapshot to the state from
 	/// the server that sent the event.
 	#[tracing::instrument(level = "debug", skip_all)]
@@ -1214,6 +1244,8 @@ pub async fn backfill_pdu(&self, origin: &ServerName, pdu: Box<RawJsonValue>) ->
 
 		drop(insert_lock);
 
+		self.services.media.index_media_references(&pdu).await;
+
 		if pdu.kind == TimelineEventType::RoomMessage {
 			let content: ExtractBody = pdu.get_content()?;
 			if let Some(body) = content.body {
@@ -1299,3 +1331,35 @@ async fn check_pdu_for_admin_room(&self, pdu: &PduEvent, sender: &UserId) -> Res
 
 	Ok(())
 }
+
+/// Whether the event of a shadow-banned sender must be dropped, given whether
+/// they are joined or invited to the room and how many users are.
+fn shadow_drops(
+	pdu_builder: &PduBuilder,
+	sender: &UserId,
+	is_member: bool,
+	members: u64,
+) -> bool {
+	if pdu_builder.event_type == TimelineEventType::RoomMember {
+		if pdu_builder.state_key.as_deref() != Some(sender.as_str()) {
+			return true;
+		}
+
+		let leave = serde_json::from_str::<RoomMemberEventContent>(pdu_builder.content.get())
+			.is_ok_and(|content| content.membership == MembershipState::Leave);
+
+		if leave {
+			return !is_member;
+		}
+	}
+
+	members > u64::from(is_member)
+}
+
+/// An event id which looks like one of a real event, returned for the dropped
+/// events of shadow-banned users.
+fn shadow_event_id() -> OwnedEventId {
+	format!("${}", utils::random_string(43))
+		.try_into()
+		.expect("valid event id")
+}
@@ -0,0 +1,52 @@
+#![cfg(test)]
+
+use conduwuit::PduBuilder;
+use ruma::{
+	events::room::{
+		member::{MembershipState, RoomMemberEventContent},
+		message::RoomMessageEventContent,
+	},
+	user_id, EventId,
+};
+
+use super::{shadow_drops, shadow_event_id};
+
+fn membership(state_key: &str, membership: MembershipState) -> PduBuilder {
+	PduBuilder::state(state_key.to_owned(), &RoomMemberEventContent::new(membership))
+}
+
+#[test]
+fn shadow_dropped_messages() {
+	let sender = user_id!("@spammer:example.com");
+	let message = PduBuilder::timeline(&RoomMessageEventContent::text_plain("spam"));
+
+	assert!(shadow_drops(&message, sender, true, 2), "message reached another member");
+	assert!(
+		!shadow_drops(&message, sender, true, 1),
+		"message dropped in a room of their own"
+	);
+	assert!(!shadow_drops(&message, sender, false, 0), "message dropped in an empty room");
+}
+
+#[test]
+fn shadow_dropped_memberships() {
+	let sender = user_id!("@spammer:example.com");
+	let invite = membership("@victim:example.com", MembershipState::Invite);
+	let leave = membership(sender.as_str(), MembershipState::Leave);
+	let join = membership(sender.as_str(), MembershipState::Join);
+
+	assert!(shadow_drops(&invite, sender, true, 1), "invite reached another user");
+	assert!(!shadow_drops(&leave, sender, true, 5), "could not leave a room");
+	assert!(shadow_drops(&leave, sender, false, 5), "leave of a room they are not in");
+	assert!(shadow_drops(&join, sender, false, 5), "joined a room with other members");
+	assert!(!shadow_drops(&join, sender, false, 0), "could not join an empty room");
+}
+
+#[test]
+fn shadow_event_ids() {
+	let event_id = shadow_event_id();
+
+	assert!(EventId::parse(event_id.as_str()).is_ok());
+	assert_eq!(event_id.as_str().len(), 44, "looks unlike a room version 4+ event id");
+	assert_ne!(event_id, shadow_event_id(), "event ids repeat");
+}
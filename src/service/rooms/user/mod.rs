@@ -1,8 +1,12 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
-use conduwuit::{implement, Result};
-use database::{Database, Deserialized, Map};
-use ruma::{RoomId, UserId};
+use conduwuit::{
+	implement,
+	utils::{stream::TryIgnore, ReadyExt},
+	Result,
+};
+use database::{Database, Deserialized, Interfix, Map};
+use ruma::{EventId, OwnedEventId, RoomId, UserId};
 
 use crate::{globals, rooms, rooms::short::ShortStateHash, Dep};
 
@@ -15,6 +19,8 @@ struct Data {
 	db: Arc<Database>,
 	userroomid_notificationcount: Arc<Map>,
 	userroomid_highlightcount: Arc<Map>,
+	userroomthreadid_notificationcount: Arc<Map>,
+	userroomthreadid_highlightcount: Arc<Map>,
 	roomuserid_lastnotificationread: Arc<Map>,
 	roomsynctoken_shortstatehash: Arc<Map>,
 }
@@ -31,6 +37,10 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				db: args.db.clone(),
 				userroomid_notificationcount: args.db["userroomid_notificationcount"].clone(),
 				userroomid_highlightcount: args.db["userroomid_highlightcount"].clone(),
+				userroomthreadid_notificationcount: args.db["userroomthreadid_notificationcount"]
+					.clone(),
+				userroomthreadid_highlightcount: args.db["userroomthreadid_highlightcount"]
+					.clone(),
 				roomuserid_lastnotificationread: args.db["userroomid_highlightcount"].clone(),
 				roomsynctoken_shortstatehash: args.db["roomsynctoken_shortstatehash"].clone(),
 			},
@@ -45,12 +55,96 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
+/// Unread notifications of a thread (MSC3773).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ThreadCounts {
+	pub notification_count: u64,
+	pub highlight_count: u64,
+}
+
+/// Marks the whole room as read, including its threads.
+#[implement(Service)]
+pub async fn reset_notification_counts(&self, user_id: &UserId, room_id: &RoomId) {
+	// The counts of the room include those of its threads, so a room without
+	// notifications has no threads to clear either, which is the usual case for
+	// the sender of an event.
+	let unread = self.notification_count(user_id, room_id).await != 0
+		|| self.highlight_count(user_id, room_id).await != 0;
+
+	if unread {
+		let userroom_id = (user_id, room_id);
+		self.db.userroomid_highlightcount.put(userroom_id, 0_u64);
+		self.db.userroomid_notificationcount.put(userroom_id, 0_u64);
+
+		let prefix = (user_id, room_id, Interfix);
+		for map in [
+			&self.db.userroomthreadid_notificationcount,
+			&self.db.userroomthreadid_highlightcount,
+		] {
+			map.keys_prefix_raw(&prefix)
+				.ignore_err()
+				.ready_for_each(|key| map.remove(key))
+				.await;
+		}
+	}
+
+	self.set_last_notification_read(user_id, room_id);
+}
+
+/// Marks the main timeline of a room as read; only the counts of its threads
+/// remain.
 #[implement(Service)]
-pub fn reset_notification_counts(&self, user_id: &UserId, room_id: &RoomId) {
+pub async fn reset_main_notification_counts(&self, user_id: &UserId, room_id: &RoomId) {
+	let (notification_count, highlight_count) = self
+		.thread_notification_counts(user_id, room_id)
+		.await
+		.values()
+		.fold((0_u64, 0_u64), |(notifications, highlights), counts| {
+			(
+				notifications.saturating_add(counts.notification_count),
+				highlights.saturating_add(counts.highlight_count),
+			)
+		});
+
 	let userroom_id = (user_id, room_id);
-	self.db.userroomid_highlightcount.put(userroom_id, 0_u64);
-	self.db.userroomid_notificationcount.put(userroom_id, 0_u64);
+	self.db
+		.userroomid_notificationcount
+		.put(userroom_id, notification_count);
+	self.db
+		.userroomid_highlightcount
+		.put(userroom_id, highlight_count);
 
+	self.set_last_notification_read(user_id, room_id);
+}
+
+/// Marks a thread as read and removes its counts from those of the room.
+#[implement(Service)]
+pub async fn reset_thread_notification_counts(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+	thread_root: &EventId,
+) {
+	let userroom_id = (user_id, room_id);
+	let thread_id = (user_id, room_id, thread_root);
+	for (room_map, thread_map) in [
+		(
+			&self.db.userroomid_notificationcount,
+			&self.db.userroomthreadid_notificationcount,
+		),
+		(&self.db.userroomid_highlightcount, &self.db.userroomthreadid_highlightcount),
+	] {
+		let thread: u64 = thread_map.qry(&thread_id).await.deserialized().unwrap_or(0);
+		let room: u64 = room_map.qry(&userroom_id).await.deserialized().unwrap_or(0);
+		room_map.put(userroom_id, room.saturating_sub(thread));
+		thread_map.del(thread_id);
+	}
+
+	self.set_last_notification_read(user_id, room_id);
+}
+
+#[implement(Service)]
+fn set_last_notification_read(&self, user_id: &UserId, room_id: &RoomId) {
 	let roomuser_id = (room_id, user_id);
 	let count = self.services.globals.next_count().unwrap();
 	self.db
@@ -58,6 +152,45 @@ pub fn reset_notification_counts(&self, user_id: &UserId, room_id: &RoomId) {
 		.put(roomuser_id, count);
 }
 
+/// Counts of the threads in a room with unread notifications. The counts of
+/// the room include them.
+#[implement(Service)]
+pub async fn thread_notification_counts(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+) -> BTreeMap<OwnedEventId, ThreadCounts> {
+	type KeyVal<'a> = ((&'a UserId, &'a RoomId, &'a EventId), u64);
+
+	let prefix = (user_id, room_id, Interfix);
+	let mut threads = BTreeMap::<OwnedEventId, ThreadCounts>::new();
+	self.db
+		.userroomthreadid_notificationcount
+		.stream_prefix(&prefix)
+		.ignore_err()
+		.ready_for_each(|((_, _, thread_root), count): KeyVal<'_>| {
+			threads
+				.entry(thread_root.to_owned())
+				.or_default()
+				.notification_count = count;
+		})
+		.await;
+
+	self.db
+		.userroomthreadid_highlightcount
+		.stream_prefix(&prefix)
+		.ignore_err()
+		.ready_for_each(|((_, _, thread_root), count): KeyVal<'_>| {
+			threads
+				.entry(thread_root.to_owned())
+				.or_default()
+				.highlight_count = count;
+		})
+		.await;
+
+	threads
+}
+
 #[implement(Service)]
 pub async fn notification_count(&self, user_id: &UserId, room_id: &RoomId) -> u64 {
 	let key = (user_id, room_id);
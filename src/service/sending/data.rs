@@ -5,11 +5,11 @@
 	utils::{stream::TryIgnore, ReadyExt},
 	Error, Result,
 };
-use database::{Database, Deserialized, Map};
+use database::{Database, Deserialized, Ignore, Interfix, Json, Map};
 use futures::{Stream, StreamExt};
 use ruma::{OwnedServerName, ServerName, UserId};
 
-use super::{Destination, SendingEvent};
+use super::{Destination, DeviceChange, SendingEvent};
 use crate::{globals, Dep};
 
 pub(super) type OutgoingItem = (Key, SendingEvent, Destination);
@@ -18,6 +18,7 @@
 pub(super) type Key = Vec<u8>;
 
 pub struct Data {
+	appserviceid_devicechange: Arc<Map>,
 	servercurrentevent_data: Arc<Map>,
 	servernameevent_data: Arc<Map>,
 	servername_educount: Arc<Map>,
@@ -33,6 +34,7 @@ impl Data {
 	pub(super) fn new(args: &crate::Args<'_>) -> Self {
 		let db = &args.db;
 		Self {
+			appserviceid_devicechange: db["appserviceid_devicechange"].clone(),
 			servercurrentevent_data: db["servercurrentevent_data"].clone(),
 			servernameevent_data: db["servernameevent_data"].clone(),
 			servername_educount: db["servername_educount"].clone(),
@@ -181,6 +183,39 @@ pub async fn get_latest_educount(&self, server_name: &ServerName) -> u64 {
 			.deserialized()
 			.unwrap_or(0)
 	}
+
+	/// Records a device change for the next transaction to the appservice.
+	pub(super) fn queue_device_change_appservice(&self, id: &str, change: &DeviceChange) {
+		let count = self.services.globals.next_count().unwrap();
+		self.appserviceid_devicechange
+			.put((id, count), Json(change));
+	}
+
+	/// Device changes recorded for the appservice, oldest first, up to and
+	/// including `until`.
+	pub(super) fn device_changes_appservice<'a>(
+		&'a self,
+		id: &'a str,
+		until: u64,
+	) -> impl Stream<Item = DeviceChange> + Send + 'a {
+		self.appserviceid_devicechange
+			.stream_prefix(&(id, Interfix))
+			.ignore_err()
+			.ready_take_while(move |((_, count), _): &((Ignore, u64), DeviceChange)| {
+				*count <= until
+			})
+			.map(|(_, change)| change)
+	}
+
+	/// Removes the device changes delivered to the appservice.
+	pub(super) async fn delete_device_changes_appservice(&self, id: &str, until: u64) {
+		self.appserviceid_devicechange
+			.keys_prefix(&(id, Interfix))
+			.ignore_err()
+			.ready_take_while(|&(_, count): &(Ignore, u64)| count <= until)
+			.ready_for_each(|(_, count)| self.appserviceid_devicechange.del((id, count)))
+			.await;
+	}
 }
 
 fn parse_servercurrentevent(key: &[u8], value: &[u8]) -> Result<(Destination, SendingEvent)> {
@@ -19,8 +19,9 @@
 use futures::{FutureExt, Stream, StreamExt};
 use ruma::{
 	api::{appservice::Registration, OutgoingRequest},
-	RoomId, ServerName, UserId,
+	OwnedUserId, RoomId, ServerName, UserId,
 };
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use tokio::task::JoinSet;
 
@@ -30,8 +31,7 @@
 	sender::{EDU_LIMIT, PDU_LIMIT},
 };
 use crate::{
-	account_data, client, federation, globals, presence, pusher, rooms,
-	rooms::timeline::RawPduId, users, Dep,
+	client, federation, globals, presence, pusher, rooms, rooms::timeline::RawPduId, users, Dep,
 };
 
 pub struct Service {
@@ -51,7 +51,6 @@ struct Services {
 	presence: Dep<presence::Service>,
 	read_receipt: Dep<rooms::read_receipt::Service>,
 	timeline: Dep<rooms::timeline::Service>,
-	account_data: Dep<account_data::Service>,
 	appservice: Dep<crate::appservice::Service>,
 	pusher: Dep<pusher::Service>,
 	federation: Dep<federation::Service>,
@@ -72,6 +71,27 @@ pub enum SendingEvent {
 	Flush,         // none
 }
 
+/// A change to the devices of a user which an appservice receiving device
+/// changes (MSC3202) is told about in its next transaction.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeviceChange {
+	pub user_id: OwnedUserId,
+	pub kind: DeviceChangeKind,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum DeviceChangeKind {
+	/// The device list of the user changed.
+	Changed,
+
+	/// The appservice no longer shares an encrypted room with the user.
+	Left,
+
+	/// One-time keys of the user, who is in the namespace, were uploaded or
+	/// claimed.
+	OneTimeKeys,
+}
+
 pub type EduBuf = SmallVec<[u8; EDU_BUF_CAP]>;
 pub type EduVec = SmallVec<[EduBuf; EDU_VEC_CAP]>;
 
@@ -95,7 +115,6 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				presence: args.depend::<presence::Service>("presence"),
 				read_receipt: args.depend::<rooms::read_receipt::Service>("rooms::read_receipt"),
 				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
-				account_data: args.depend::<account_data::Service>("account_data"),
 				appservice: args.depend::<crate::appservice::Service>("appservice"),
 				pusher: args.depend::<pusher::Service>("pusher"),
 				federation: args.depend::<federation::Service>("federation"),
@@ -168,6 +187,43 @@ pub fn send_pdu_appservice(&self, appservice_id: String, pdu_id: RawPduId) -> Re
 		})
 	}
 
+	#[tracing::instrument(skip(self, serialized), level = "debug")]
+	pub fn send_edu_appservice(&self, appservice_id: String, serialized: EduBuf) -> Result {
+		let dest = Destination::Appservice(appservice_id);
+		let event = SendingEvent::Edu(serialized);
+		let _cork = self.db.db.cork();
+		let keys = self.db.queue_requests(once((&event, &dest)));
+		self.dispatch(Msg {
+			dest,
+			event,
+			queue_id: keys.into_iter().next().expect("request queue key"),
+		})
+	}
+
+	/// Records a device change for the appservice and starts a transaction to
+	/// deliver it.
+	#[tracing::instrument(skip(self), level = "debug")]
+	pub fn send_device_change_appservice(
+		&self,
+		appservice_id: String,
+		change: &DeviceChange,
+	) -> Result {
+		self.db
+			.queue_device_change_appservice(&appservice_id, change);
+		self.flush_appservice(appservice_id)
+	}
+
+	/// Starts a transaction to the appservice even if nothing is queued for
+	/// it, so that device list changes and one-time key counts are delivered.
+	#[tracing::instrument(skip(self), level = "debug")]
+	pub fn flush_appservice(&self, appservice_id: String) -> Result {
+		self.dispatch(Msg {
+			dest: Destination::Appservice(appservice_id),
+			event: SendingEvent::Flush,
+			queue_id: Vec::<u8>::new(),
+		})
+	}
+
 	#[tracing::instrument(skip(self, room_id, pdu_id), level = "debug")]
 	pub async fn send_pdu_room(&self, room_id: &RoomId, pdu_id: &RawPduId) -> Result {
 		let servers = self
@@ -1,5 +1,5 @@
 use std::{
-	collections::{BTreeMap, HashMap, HashSet},
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet},
 	fmt::Debug,
 	sync::{
 		atomic::{AtomicU64, AtomicUsize, Ordering},
@@ -29,7 +29,7 @@
 };
 use ruma::{
 	api::{
-		appservice::event::push_events::v1::EphemeralData,
+		appservice::event::push_events::v1::{DeviceLists, EphemeralData},
 		federation::transactions::{
 			edu::{
 				DeviceListUpdateContent, Edu, PresenceContent, PresenceUpdate, ReceiptContent,
@@ -39,20 +39,18 @@
 		},
 	},
 	device_id,
-	events::{
-		push_rules::PushRulesEvent, receipt::ReceiptType, AnySyncEphemeralRoomEvent,
-		GlobalAccountDataEventType,
-	},
-	push,
+	events::{receipt::ReceiptType, AnySyncEphemeralRoomEvent, AnyToDeviceEvent},
 	serde::Raw,
-	uint, CanonicalJsonObject, MilliSecondsSinceUnixEpoch, OwnedRoomId, OwnedServerName,
-	OwnedUserId, RoomId, RoomVersionId, ServerName, UInt,
+	uint, CanonicalJsonObject, MilliSecondsSinceUnixEpoch, OneTimeKeyAlgorithm, OwnedDeviceId,
+	OwnedRoomId, OwnedServerName, OwnedUserId, RoomId, RoomVersionId, ServerName, UInt,
 };
 use serde_json::value::{to_raw_value, RawValue as RawJsonValue};
 
 use super::{
-	appservice, data::QueueItem, Destination, EduBuf, EduVec, Msg, SendingEvent, Service,
+	appservice, data::QueueItem, Destination, DeviceChange, DeviceChangeKind, EduBuf, EduVec,
+	Msg, SendingEvent, Service,
 };
+use crate::appservice::RegistrationInfo;
 
 #[derive(Debug)]
 enum TransactionStatus {
@@ -66,6 +64,8 @@ enum TransactionStatus {
 type SendingFuture<'a> = BoxFuture<'a, SendingResult>;
 type SendingFutures<'a> = FuturesUnordered<SendingFuture<'a>>;
 type CurTransactionStatus = HashMap<Destination, TransactionStatus>;
+type OneTimeKeyCounts =
+	BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, BTreeMap<OneTimeKeyAlgorithm, UInt>>>;
 
 const SELECT_PRESENCE_LIMIT: usize = 256;
 const SELECT_RECEIPT_LIMIT: usize = 256;
@@ -677,7 +677,7 @@ async fn send_events_dest_appservice(
 		id: String,
 		events: Vec<SendingEvent>,
 	) -> SendingResult {
-		let Some(appservice) = self.services.appservice.get_registration(&id).await else {
+		let Some(appservice) = self.services.appservice.read().await.get(&id).cloned() else {
 			return Err((
 				Destination::Appservice(id.clone()),
 				err!(Database(warn!(?id, "Missing appservice registration"))),
@@ -696,6 +696,7 @@ async fn send_events_dest_appservice(
 				.filter(|event| matches!(event, SendingEvent::Edu(_)))
 				.count(),
 		);
+		let mut to_device: Vec<Raw<AnyToDeviceEvent>> = Vec::new();
 		for event in &events {
 			match event {
 				| SendingEvent::Pdu(pdu_id) => {
@@ -704,7 +705,17 @@ async fn send_events_dest_appservice(
 					}
 				},
 				| SendingEvent::Edu(edu) =>
-					if appservice.receive_ephemeral {
+					if appservice.receives_to_device() {
+						// To-device messages carry their recipient (MSC4203), which
+						// distinguishes them from ephemeral events.
+						if let Ok(event) = serde_json::from_slice::<Raw<AnyToDeviceEvent>>(edu) {
+							let recipient = event.get_field::<OwnedDeviceId>("to_device_id");
+							if matches!(recipient, Ok(Some(_))) {
+								to_device.push(event);
+								continue;
+							}
+						}
+
 						if let Ok(edu) = serde_json::from_slice(edu) {
 							edu_jsons.push(edu);
 						}
@@ -713,34 +724,141 @@ async fn send_events_dest_appservice(
 			}
 		}
 
-		let txn_hash = calculate_hash(events.iter().filter_map(|e| match e {
-			| SendingEvent::Edu(b) => Some(&**b),
-			| SendingEvent::Pdu(b) => Some(b.as_ref()),
-			| SendingEvent::Flush => None,
-		}));
+		let device_changes: OptionFuture<_> = appservice
+			.receives_device_changes()
+			.then(|| self.select_device_changes_appservice(&appservice))
+			.into();
+
+		let device_changes = device_changes.await;
+		let last_count = device_changes
+			.as_ref()
+			.map(|(.., last_count)| last_count.to_be_bytes());
+
+		let (device_lists, device_one_time_keys_count) = device_changes
+			.map(|(device_lists, counts, _)| (device_lists, counts))
+			.unwrap_or_default();
+
+		if pdu_jsons.is_empty()
+			&& edu_jsons.is_empty()
+			&& to_device.is_empty()
+			&& device_lists.changed.is_empty()
+			&& device_lists.left.is_empty()
+			&& device_one_time_keys_count.is_empty()
+		{
+			if let Some(last_count) = last_count {
+				self.db
+					.delete_device_changes_appservice(&id, u64::from_be_bytes(last_count))
+					.await;
+			}
+
+			return Ok(Destination::Appservice(id));
+		}
+
+		let txn_hash = calculate_hash(
+			events
+				.iter()
+				.filter_map(|e| match e {
+					| SendingEvent::Edu(b) => Some(&**b),
+					| SendingEvent::Pdu(b) => Some(b.as_ref()),
+					| SendingEvent::Flush => None,
+				})
+				.chain(last_count.as_ref().map(|count| &count[..])),
+		);
 
 		let txn_id = &*URL_SAFE_NO_PAD.encode(txn_hash);
 
-		//debug_assert!(pdu_jsons.len() + edu_jsons.len() > 0, "sending empty
-		// transaction");
+		// Fallback keys are not stored, so there are no unused fallback key types
+		// to report and the field is left out.
+		let mut request =
+			ruma::api::appservice::event::push_events::v1::Request::new(txn_id.into(), pdu_jsons);
+		request.ephemeral = edu_jsons;
+		request.to_device = to_device;
+		request.device_lists = device_lists;
+		request.device_one_time_keys_count = device_one_time_keys_count;
+
 		let client = &self.services.client.appservice;
-		match appservice::send_request(
-			client,
-			appservice,
-			ruma::api::appservice::event::push_events::v1::Request {
-				events: pdu_jsons,
-				txn_id: txn_id.into(),
-				ephemeral: edu_jsons,
-				to_device: Vec::new(), // TODO
+		match appservice::send_request(client, appservice.registration, request).await {
+			| Ok(_) => {
+				if let Some(last_count) = last_count {
+					self.db
+						.delete_device_changes_appservice(&id, u64::from_be_bytes(last_count))
+						.await;
+				}
+
+				Ok(Destination::Appservice(id))
 			},
-		)
-		.await
-		{
-			| Ok(_) => Ok(Destination::Appservice(id)),
 			| Err(e) => Err((Destination::Appservice(id), e)),
 		}
 	}
 
+	/// Collects the device list changes and one-time key counts recorded for
+	/// the appservice (MSC3202) since its last successful transaction, along
+	/// with the count they were collected up to.
+	#[tracing::instrument(
+		name = "device_changes",
+		level = "trace",
+		skip_all,
+		fields(id = %appservice.registration.id),
+	)]
+	async fn select_device_changes_appservice(
+		&self,
+		appservice: &RegistrationInfo,
+	) -> (DeviceLists, OneTimeKeyCounts, u64) {
+		let id = &appservice.registration.id;
+		let until = self.services.globals.current_count().unwrap_or(0);
+
+		let mut changed = BTreeSet::new();
+		let mut left = BTreeSet::new();
+		let mut one_time_keys = BTreeSet::new();
+		self.db
+			.device_changes_appservice(id, until)
+			.ready_for_each(|DeviceChange { user_id, kind }| match kind {
+				| DeviceChangeKind::Changed => {
+					left.remove(&user_id);
+					changed.insert(user_id);
+				},
+				| DeviceChangeKind::Left => {
+					changed.remove(&user_id);
+					left.insert(user_id);
+				},
+				| DeviceChangeKind::OneTimeKeys => {
+					one_time_keys.insert(user_id);
+				},
+			})
+			.await;
+
+		let mut device_one_time_keys_count = OneTimeKeyCounts::new();
+		for user_id in one_time_keys {
+			let devices: Vec<OwnedDeviceId> = self
+				.services
+				.users
+				.all_device_ids(&user_id)
+				.map(ToOwned::to_owned)
+				.collect()
+				.await;
+
+			for device_id in devices {
+				let counts = self
+					.services
+					.users
+					.count_one_time_keys(&user_id, &device_id)
+					.await;
+
+				device_one_time_keys_count
+					.entry(user_id.clone())
+					.or_default()
+					.insert(device_id, counts);
+			}
+		}
+
+		let device_lists = DeviceLists {
+			changed: changed.into_iter().collect(),
+			left: left.into_iter().collect(),
+		};
+
+		(device_lists, device_one_time_keys_count, until)
+	}
+
 	#[tracing::instrument(
 		name = "push",
 		level = "info",
@@ -788,15 +906,7 @@ async fn send_events_dest_push(
 				continue;
 			}
 
-			let rules_for_user = self
-				.services
-				.account_data
-				.get_global(&user_id, GlobalAccountDataEventType::PushRules)
-				.await
-				.map_or_else(
-					|_| push::Ruleset::server_default(&user_id),
-					|ev: PushRulesEvent| ev.content.global,
-				);
+			let rules_for_user = self.services.pusher.get_ruleset(&user_id).await;
 
 			let unread: UInt = self
 				.services
@@ -809,7 +919,7 @@ async fn send_events_dest_push(
 			let _response = self
 				.services
 				.pusher
-				.send_push_notice(&user_id, unread, &pusher, rules_for_user, &pdu)
+				.send_push_notice(&user_id, unread, &pusher, &rules_for_user, &pdu)
 				.await
 				.map_err(|e| (Destination::Push(user_id.clone(), pushkey.clone()), e));
 		}
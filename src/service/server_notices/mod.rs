@@ -0,0 +1,317 @@
+mod tests;
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use conduwuit::{err, implement, pdu::PduBuilder, utils::MutexMap, Err, Result, Server};
+use database::{Deserialized, Map};
+use ruma::{
+	events::{
+		room::{
+			create::RoomCreateEventContent,
+			guest_access::{GuestAccess, RoomGuestAccessEventContent},
+			history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
+			join_rules::{JoinRule, RoomJoinRulesEventContent},
+			member::{MembershipState, RoomMemberEventContent},
+			message::{
+				MessageType, RoomMessageEventContent, ServerNoticeMessageEventContent,
+				ServerNoticeType,
+			},
+			name::RoomNameEventContent,
+			power_levels::RoomPowerLevelsEventContent,
+		},
+		tag::{TagEvent, TagEventContent, TagInfo, TagName},
+		RoomAccountDataEventType,
+	},
+	OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, RoomVersionId, UserId,
+};
+
+use crate::{account_data, globals, rooms, users, Dep};
+
+/// Notices from the server to local users. They are sent by a system user in
+/// a room of each user's own, tagged `m.server_notice`.
+pub struct Service {
+	/// The system user; server notices are disabled without one.
+	pub user: Option<OwnedUserId>,
+	creating: MutexMap<OwnedUserId, ()>,
+	services: Services,
+	db: Data,
+}
+
+struct Services {
+	server: Arc<Server>,
+	account_data: Dep<account_data::Service>,
+	globals: Dep<globals::Service>,
+	short: Dep<rooms::short::Service>,
+	state: Dep<rooms::state::Service>,
+	state_cache: Dep<rooms::state_cache::Service>,
+	timeline: Dep<rooms::timeline::Service>,
+	users: Dep<users::Service>,
+}
+
+struct Data {
+	userid_servernoticeroomid: Arc<Map>,
+}
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		let user = args
+			.server
+			.config
+			.server_notices_localpart
+			.as_deref()
+			.map(|localpart| UserId::parse_with_server_name(localpart, &args.server.name))
+			.transpose()
+			.map_err(|e| {
+				err!(Config("server_notices_localpart", "Invalid server notices user: {e}"))
+			})?;
+
+		Ok(Arc::new(Self {
+			user,
+			creating: MutexMap::new(),
+			services: Services {
+				server: args.server.clone(),
+				account_data: args.depend::<account_data::Service>("account_data"),
+				globals: args.depend::<globals::Service>("globals"),
+				short: args.depend::<rooms::short::Service>("rooms::short"),
+				state: args.depend::<rooms::state::Service>("rooms::state"),
+				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
+				timeline: args.depend::<rooms::timeline::Service>("rooms::timeline"),
+				users: args.depend::<users::Service>("users"),
+			},
+			db: Data {
+				userid_servernoticeroomid: args.db["userid_servernoticeroomid"].clone(),
+			},
+		}))
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+#[implement(Service)]
+#[inline]
+pub fn is_enabled(&self) -> bool { self.user.is_some() }
+
+/// Sends a markdown notice to a local user.
+#[implement(Service)]
+pub async fn send_text(&self, user_id: &UserId, body: &str) -> Result<OwnedEventId> {
+	self.send_notice(user_id, RoomMessageEventContent::notice_markdown(body))
+		.await
+}
+
+/// Warns a local user that they reached a limit of the server. Clients show
+/// these notices prominently.
+#[implement(Service)]
+pub async fn send_usage_limit_notice(
+	&self,
+	user_id: &UserId,
+	body: &str,
+) -> Result<OwnedEventId> {
+	let support_email = self
+		.services
+		.server
+		.config
+		.well_known
+		.support_email
+		.as_deref();
+
+	self.send_notice(user_id, usage_limit_notice(body, support_email))
+		.await
+}
+
+/// Sends a notice to a local user in their server notices room, which is
+/// created first if they have none.
+#[implement(Service)]
+pub async fn send_notice(
+	&self,
+	user_id: &UserId,
+	content: RoomMessageEventContent,
+) -> Result<OwnedEventId> {
+	let Some(notices_user) = &self.user else {
+		return Err!(Config("server_notices_localpart", "Server notices are not enabled."));
+	};
+
+	if !self.services.globals.user_is_local(user_id) {
+		return Err!(Request(InvalidParam("Server notices can only be sent to local users.")));
+	}
+
+	if !self.services.users.is_active_local(user_id).await {
+		return Err!(Request(NotFound("User {user_id} does not exist or is deactivated.")));
+	}
+
+	let room_id = self.notices_room(user_id).await?;
+	let state_lock = self.services.state.mutex.lock(&room_id).await;
+
+	self.services
+		.timeline
+		.build_and_append_pdu(PduBuilder::timeline(&content), notices_user, &room_id, &state_lock)
+		.await
+}
+
+/// Returns the user's server notices room, creating a new one if they left
+/// theirs.
+#[implement(Service)]
+async fn notices_room(&self, user_id: &UserId) -> Result<OwnedRoomId> {
+	let _lock = self.creating.lock(user_id).await;
+
+	let current: Result<OwnedRoomId> = self
+		.db
+		.userid_servernoticeroomid
+		.get(user_id)
+		.await
+		.deserialized();
+
+	if let Ok(room_id) = current {
+		let membership = self
+			.services
+			.state_cache
+			.user_membership(user_id, &room_id)
+			.await;
+
+		if is_reusable(membership.as_ref()) {
+			return Ok(room_id);
+		}
+	}
+
+	let room_id = self.create_notices_room(user_id).await?;
+	self.db.userid_servernoticeroomid.raw_put(user_id, &room_id);
+
+	Ok(room_id)
+}
+
+#[implement(Service)]
+async fn create_notices_room(&self, user_id: &UserId) -> Result<OwnedRoomId> {
+	let notices_user = self.user.as_deref().expect("server notices enabled");
+	let config = &self.services.server.config;
+
+	if !self.services.users.exists(notices_user).await {
+		self.services.users.create(notices_user, None)?;
+		self.services
+			.users
+			.set_displayname(notices_user, Some(config.server_notices_displayname.clone()));
+	}
+
+	let room_id = RoomId::new(self.services.globals.server_name());
+	self.services
+		.short
+		.get_or_create_shortroomid(&room_id)
+		.await;
+
+	let state_lock = self.services.state.mutex.lock(&room_id).await;
+
+	let state = room_state(
+		notices_user,
+		user_id,
+		&config.default_room_version,
+		&config.server_notices_room_name,
+	);
+
+	for pdu in state {
+		self.services
+			.timeline
+			.build_and_append_pdu(pdu, notices_user, &room_id, &state_lock)
+			.await?;
+	}
+
+	self.tag_notices_room(&room_id, user_id).await?;
+
+	Ok(room_id)
+}
+
+/// Tags the room for the user so clients can tell it apart from other rooms.
+#[implement(Service)]
+async fn tag_notices_room(&self, room_id: &RoomId, user_id: &UserId) -> Result {
+	let mut event = self
+		.services
+		.account_data
+		.get_room(room_id, user_id, RoomAccountDataEventType::Tag)
+		.await
+		.unwrap_or_else(|_| TagEvent {
+			content: TagEventContent { tags: BTreeMap::new() },
+		});
+
+	event
+		.content
+		.tags
+		.insert(TagName::ServerNotice, TagInfo::new());
+
+	self.services
+		.account_data
+		.update(
+			Some(room_id),
+			user_id,
+			RoomAccountDataEventType::Tag,
+			&serde_json::to_value(event)?,
+		)
+		.await
+}
+
+/// Whether a server notices room the user is in can be reused. Rooms the user
+/// left, or was never invited to, are replaced by a new one.
+fn is_reusable(membership: Option<&MembershipState>) -> bool {
+	matches!(membership, Some(MembershipState::Join | MembershipState::Invite))
+}
+
+/// The state of a new server notices room, ending with the user's invite.
+/// Users can read and leave the room, but only the system user can post.
+fn room_state(
+	notices_user: &UserId,
+	user_id: &UserId,
+	room_version: &RoomVersionId,
+	room_name: &str,
+) -> Vec<PduBuilder> {
+	let create_content = {
+		use RoomVersionId::*;
+		match room_version {
+			| V1 | V2 | V3 | V4 | V5 | V6 | V7 | V8 | V9 | V10 =>
+				RoomCreateEventContent::new_v1(notices_user.to_owned()),
+			| _ => RoomCreateEventContent::new_v11(),
+		}
+	};
+
+	let power_levels = RoomPowerLevelsEventContent {
+		users: BTreeMap::from_iter([(notices_user.to_owned(), 100.into())]),
+		events_default: 100.into(),
+		..Default::default()
+	};
+
+	vec![
+		PduBuilder::state(String::new(), &RoomCreateEventContent {
+			federate: false,
+			predecessor: None,
+			room_version: room_version.clone(),
+			..create_content
+		}),
+		PduBuilder::state(
+			notices_user.to_string(),
+			&RoomMemberEventContent::new(MembershipState::Join),
+		),
+		PduBuilder::state(String::new(), &power_levels),
+		PduBuilder::state(String::new(), &RoomJoinRulesEventContent::new(JoinRule::Invite)),
+		PduBuilder::state(
+			String::new(),
+			&RoomHistoryVisibilityEventContent::new(HistoryVisibility::Shared),
+		),
+		PduBuilder::state(
+			String::new(),
+			&RoomGuestAccessEventContent::new(GuestAccess::Forbidden),
+		),
+		PduBuilder::state(String::new(), &RoomNameEventContent::new(room_name.to_owned())),
+		PduBuilder::state(user_id.to_string(), &RoomMemberEventContent {
+			is_direct: Some(true),
+			..RoomMemberEventContent::new(MembershipState::Invite)
+		}),
+	]
+}
+
+/// A notice telling the user they reached a limit, pointing them at the
+/// server's support email if one is configured.
+fn usage_limit_notice(body: &str, support_email: Option<&str>) -> RoomMessageEventContent {
+	let notice = ServerNoticeMessageEventContent {
+		body: body.to_owned(),
+		server_notice_type: ServerNoticeType::UsageLimitReached,
+		admin_contact: support_email.map(|email| format!("mailto:{email}")),
+		limit_type: None,
+	};
+
+	RoomMessageEventContent::new(MessageType::ServerNotice(notice))
+}
@@ -0,0 +1,71 @@
+#![cfg(test)]
+
+use ruma::{
+	events::{
+		room::{member::MembershipState, message::MessageType},
+		StateEventType, TimelineEventType,
+	},
+	user_id, RoomVersionId,
+};
+use serde_json::Value;
+
+use super::{is_reusable, room_state, usage_limit_notice};
+
+#[test]
+fn notices_room_reuse() {
+	assert!(is_reusable(Some(&MembershipState::Join)));
+	assert!(is_reusable(Some(&MembershipState::Invite)), "pending invite was replaced");
+	assert!(!is_reusable(Some(&MembershipState::Leave)), "left room was reused");
+	assert!(!is_reusable(Some(&MembershipState::Ban)), "banned room was reused");
+	assert!(!is_reusable(None), "room without membership was reused");
+}
+
+#[test]
+fn notices_room_state() {
+	let notices_user = user_id!("@notices:example.com");
+	let user_id = user_id!("@alice:example.com");
+	let state = room_state(notices_user, user_id, &RoomVersionId::V11, "Server Notices");
+
+	let content = |kind: StateEventType| -> Value {
+		let pdu = state
+			.iter()
+			.find(|pdu| pdu.event_type.to_string() == kind.to_string())
+			.expect("state event present");
+
+		serde_json::from_str(pdu.content.get()).expect("valid content")
+	};
+
+	assert_eq!(content(StateEventType::RoomCreate)["m.federate"], false);
+	assert_eq!(content(StateEventType::RoomJoinRules)["join_rule"], "invite");
+	assert_eq!(content(StateEventType::RoomName)["name"], "Server Notices");
+
+	let power_levels = content(StateEventType::RoomPowerLevels);
+	assert_eq!(power_levels["events_default"], 100, "users can post notices");
+	assert_eq!(power_levels["users"]["@notices:example.com"], 100);
+
+	let invite = state.last().expect("invite is sent last");
+	assert_eq!(invite.event_type, TimelineEventType::RoomMember);
+	assert_eq!(invite.state_key.as_deref(), Some("@alice:example.com"));
+
+	let invite: Value = serde_json::from_str(invite.content.get()).expect("valid content");
+	assert_eq!(invite["membership"], "invite");
+	assert_eq!(invite["is_direct"], true);
+}
+
+#[test]
+fn usage_limit_notices() {
+	let notice = usage_limit_notice("You ran out of space.", Some("admin@example.com"));
+	let MessageType::ServerNotice(notice) = notice.msgtype else {
+		panic!("not a server notice");
+	};
+
+	assert_eq!(notice.body, "You ran out of space.");
+	assert_eq!(notice.admin_contact.as_deref(), Some("mailto:admin@example.com"));
+
+	let notice = usage_limit_notice("You ran out of space.", None);
+	let MessageType::ServerNotice(notice) = notice.msgtype else {
+		panic!("not a server notice");
+	};
+
+	assert_eq!(notice.admin_contact, None, "contact without a support email");
+}
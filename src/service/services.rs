@@ -11,10 +11,12 @@
 
 use crate::{
 	account_data, admin, appservice, client, config, emergency, federation, globals, key_backups,
+	lockout, mailer,
 	manager::Manager,
-	media, presence, pusher, resolver, rooms, sending, server_keys, service,
+	media, presence, pusher, reports, resolver, rooms, sending, server_keys, server_notices,
+	service,
 	service::{Args, Map, Service},
-	sync, transaction_ids, uiaa, updates, users,
+	sync, threepid, transaction_ids, uiaa, updates, users,
 };
 
 pub struct Services {
@@ -26,15 +28,20 @@ pub struct Services {
 	pub emergency: Arc<emergency::Service>,
 	pub globals: Arc<globals::Service>,
 	pub key_backups: Arc<key_backups::Service>,
+	pub lockout: Arc<lockout::Service>,
+	pub mailer: Arc<mailer::Service>,
 	pub media: Arc<media::Service>,
 	pub presence: Arc<presence::Service>,
 	pub pusher: Arc<pusher::Service>,
+	pub reports: Arc<reports::Service>,
 	pub resolver: Arc<resolver::Service>,
 	pub rooms: rooms::Service,
 	pub federation: Arc<federation::Service>,
 	pub sending: Arc<sending::Service>,
 	pub server_keys: Arc<server_keys::Service>,
+	pub server_notices: Arc<server_notices::Service>,
 	pub sync: Arc<sync::Service>,
+	pub threepid: Arc<threepid::Service>,
 	pub transaction_ids: Arc<transaction_ids::Service>,
 	pub uiaa: Arc<uiaa::Service>,
 	pub updates: Arc<updates::Service>,
@@ -73,9 +80,12 @@ macro_rules! build {
 			emergency: build!(emergency::Service),
 			globals: build!(globals::Service),
 			key_backups: build!(key_backups::Service),
+			lockout: build!(lockout::Service),
+			mailer: build!(mailer::Service),
 			media: build!(media::Service),
 			presence: build!(presence::Service),
 			pusher: build!(pusher::Service),
+			reports: build!(reports::Service),
 			rooms: rooms::Service {
 				alias: build!(rooms::alias::Service),
 				auth_chain: build!(rooms::auth_chain::Service),
@@ -101,7 +111,9 @@ macro_rules! build {
 			federation: build!(federation::Service),
 			sending: build!(sending::Service),
 			server_keys: build!(server_keys::Service),
+			server_notices: build!(server_notices::Service),
 			sync: build!(sync::Service),
+			threepid: build!(threepid::Service),
 			transaction_ids: build!(transaction_ids::Service),
 			uiaa: build!(uiaa::Service),
 			updates: build!(updates::Service),
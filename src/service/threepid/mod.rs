@@ -0,0 +1,697 @@
+mod tests;
+
+use std::{
+	collections::HashMap,
+	hash::Hash,
+	net::IpAddr,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use conduwuit::{
+	debug_warn, err, info,
+	utils::{self, stream::TryIgnore, time::now_millis, ReadyExt},
+	warn, Err, Error, Result, Server,
+};
+use database::{Deserialized, Ignore, Interfix, Json, Map};
+use futures::{Stream, StreamExt};
+use http::StatusCode;
+use ruma::{
+	api::client::error::{ErrorKind, RetryAfter},
+	thirdparty::{Medium, ThirdPartyIdentifier},
+	ClientSecret, MilliSecondsSinceUnixEpoch, OwnedClientSecret, OwnedSessionId, OwnedUserId,
+	SessionId, UInt, UserId,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+use crate::{client, globals, mailer, pusher, Dep};
+
+/// Third-party identifiers (currently only email addresses) associated with
+/// local accounts, and the sessions used to validate ownership of them.
+pub struct Service {
+	services: Services,
+	db: Data,
+	sent_to_addresses: Mutex<SentMap<String>>,
+	sent_for_clients: Mutex<SentMap<IpAddr>>,
+}
+
+struct Services {
+	server: Arc<Server>,
+	client: Dep<client::Service>,
+	globals: Dep<globals::Service>,
+	mailer: Dep<mailer::Service>,
+	pusher: Dep<pusher::Service>,
+}
+
+struct Data {
+	clientsecretaddress_sessionid: Arc<Map>,
+	expiresat_sessionid: Arc<Map>,
+	mediumaddress_userid: Arc<Map>,
+	sessionid_threepidvalidation: Arc<Map>,
+	useridmediumaddress_idserver: Arc<Map>,
+	useridmediumaddress_threepid: Arc<Map>,
+}
+
+/// What a validation session is for. A session can only be used for the
+/// purpose it was requested for.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Purpose {
+	AddThreepid,
+	PasswordReset,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Session {
+	client_secret: OwnedClientSecret,
+	medium: Medium,
+	address: String,
+	token: String,
+	purpose: Purpose,
+	send_attempt: UInt,
+	expires_at: u64,
+	validated_at: Option<u64>,
+}
+
+/// Validation emails sent for a key within the current window.
+#[derive(Clone, Copy, Debug)]
+struct Sent {
+	count: u32,
+	since: Instant,
+}
+
+type SentMap<K> = HashMap<K, Sent>;
+
+/// Entries are pruned of past windows once a map grows beyond this size.
+const PRUNE_THRESHOLD: usize = 4096;
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Metadata {
+	added_at: u64,
+	validated_at: u64,
+}
+
+/// Association returned by an identity server after binding.
+#[derive(Deserialize)]
+struct Binding {
+	medium: Medium,
+	address: String,
+	mxid: OwnedUserId,
+}
+
+/// Path of the endpoint which validation links point to.
+pub const SUBMIT_TOKEN_PATH: &str = "/_conduwuit/threepid/email/submit_token";
+
+const SESSION_ID_LENGTH: usize = 32;
+const TOKEN_LENGTH: usize = 32;
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			services: Services {
+				server: args.server.clone(),
+				client: args.depend::<client::Service>("client"),
+				globals: args.depend::<globals::Service>("globals"),
+				mailer: args.depend::<mailer::Service>("mailer"),
+				pusher: args.depend::<pusher::Service>("pusher"),
+			},
+			db: Data {
+				clientsecretaddress_sessionid: args.db["clientsecretaddress_sessionid"].clone(),
+				expiresat_sessionid: args.db["expiresat_sessionid"].clone(),
+				mediumaddress_userid: args.db["mediumaddress_userid"].clone(),
+				sessionid_threepidvalidation: args.db["sessionid_threepidvalidation"].clone(),
+				useridmediumaddress_idserver: args.db["useridmediumaddress_idserver"].clone(),
+				useridmediumaddress_threepid: args.db["useridmediumaddress_threepid"].clone(),
+			},
+			sent_to_addresses: SentMap::new().into(),
+			sent_for_clients: SentMap::new().into(),
+		}))
+	}
+
+	fn clear_cache(&self) {
+		self.sent_to_addresses.lock().expect("locked").clear();
+		self.sent_for_clients.lock().expect("locked").clear();
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+impl Service {
+	/// Starts or resumes a validation session for an email address and sends
+	/// the validation link. Retries with the same client secret and a
+	/// `send_attempt` which was already seen return the existing session
+	/// without sending another email.
+	///
+	/// Emails to each address and requested by each client address are
+	/// limited to `email.validation_rate_limit_address` and
+	/// `email.validation_rate_limit_client` per `email.validation_rate_window`.
+	pub async fn request_email_validation(
+		&self,
+		client_secret: &ClientSecret,
+		email: &str,
+		send_attempt: UInt,
+		purpose: Purpose,
+		client: IpAddr,
+	) -> Result<OwnedSessionId> {
+		if !self.services.mailer.is_enabled() {
+			return Err!(Request(ThreepidDenied("Email is not enabled on this server.")));
+		}
+
+		let address = canonical_email(email)?;
+		self.prune_sessions().await;
+
+		let existing: Option<(OwnedSessionId, Session)> = async {
+			let sid: OwnedSessionId = self
+				.db
+				.clientsecretaddress_sessionid
+				.qry(&(client_secret.as_str(), address.as_str()))
+				.await
+				.deserialized()
+				.ok()?;
+
+			let session = self.session(&sid, client_secret).await.ok()?;
+			(session.purpose == purpose).then_some((sid, session))
+		}
+		.await;
+
+		if let Some((sid, session)) = &existing {
+			if send_attempt <= session.send_attempt {
+				return Ok(sid.clone());
+			}
+		}
+
+		self.throttle(client, &address)?;
+
+		let lifetime =
+			Duration::from_secs(self.services.server.config.email.validation_token_lifetime);
+		let session = Session {
+			client_secret: client_secret.to_owned(),
+			medium: Medium::Email,
+			address,
+			token: utils::random_string(TOKEN_LENGTH),
+			purpose,
+			send_attempt,
+			expires_at: now_millis().saturating_add(lifetime.as_millis().try_into()?),
+			validated_at: None,
+		};
+
+		let sid = match existing {
+			| Some((sid, existing)) => {
+				self.db
+					.expiresat_sessionid
+					.del((existing.expires_at, sid.as_str()));
+				sid
+			},
+			| None => SessionId::parse(utils::random_string(SESSION_ID_LENGTH))?,
+		};
+
+		let (subject, body) = self.validation_email(&sid, &session, purpose)?;
+		self.services
+			.mailer
+			.send(&session.address, subject, body)
+			.await?;
+
+		self.db
+			.sessionid_threepidvalidation
+			.raw_put(sid.as_str(), Json(&session));
+		self.db
+			.clientsecretaddress_sessionid
+			.put((session.client_secret.as_str(), session.address.as_str()), sid.as_str());
+		self.db
+			.expiresat_sessionid
+			.put_raw((session.expires_at, sid.as_str()), []);
+
+		Ok(sid)
+	}
+
+	/// Marks a session as validated if the token matches.
+	pub async fn submit_token(
+		&self,
+		sid: &SessionId,
+		client_secret: &ClientSecret,
+		token: &str,
+	) -> Result {
+		let mut session = self.session(sid, client_secret).await?;
+		if !tokens_match(&session.token, token) {
+			return Err!(Request(Forbidden(debug_warn!(
+				"Invalid validation token for session {sid}."
+			))));
+		}
+
+		session.validated_at.get_or_insert_with(now_millis);
+		self.db
+			.sessionid_threepidvalidation
+			.raw_put(sid.as_str(), Json(&session));
+
+		Ok(())
+	}
+
+	/// Returns the medium and address of a session once it has been
+	/// validated, if it was requested for `purpose`.
+	pub async fn validated(
+		&self,
+		sid: &SessionId,
+		client_secret: &ClientSecret,
+		purpose: Purpose,
+	) -> Result<(Medium, String)> {
+		let session = self.session(sid, client_secret).await?;
+		session.validated_for(purpose)?;
+
+		Ok((session.medium, session.address))
+	}
+
+	/// Removes a session after it has been used.
+	pub async fn consume_session(&self, sid: &SessionId) {
+		let Ok(session) = self
+			.db
+			.sessionid_threepidvalidation
+			.get(sid.as_str())
+			.await
+			.deserialized::<Session>()
+		else {
+			return;
+		};
+
+		self.remove_session(sid.as_str(), &session).await;
+	}
+
+	/// Associates a validated third-party identifier with a local user.
+	pub async fn add(&self, user_id: &UserId, medium: &Medium, address: &str) -> Result {
+		let key = (medium.as_str(), address);
+		if let Ok(owner) = self
+			.db
+			.mediumaddress_userid
+			.qry(&key)
+			.await
+			.deserialized::<OwnedUserId>()
+		{
+			if owner != user_id {
+				return Err!(Request(ThreepidInUse("This address is already in use.")));
+			}
+		}
+
+		let now = now_millis();
+		let metadata = Metadata { added_at: now, validated_at: now };
+		self.db.mediumaddress_userid.put(key, user_id);
+		self.db
+			.useridmediumaddress_threepid
+			.put((user_id, medium.as_str(), address), Json(metadata));
+
+		info!(%user_id, %medium, %address, "Added third-party identifier");
+
+		Ok(())
+	}
+
+	/// Removes a third-party identifier from a user. Returns the identity
+	/// server it was bound to, if any.
+	pub async fn delete(
+		&self,
+		user_id: &UserId,
+		medium: &Medium,
+		address: &str,
+	) -> Result<Option<String>> {
+		let address = address.to_lowercase();
+		let key = (user_id, medium.as_str(), address.as_str());
+		if self
+			.db
+			.useridmediumaddress_threepid
+			.qry(&key)
+			.await
+			.is_err()
+		{
+			return Err!(Request(ThreepidNotFound(
+				"This address is not associated with your account."
+			)));
+		}
+
+		let id_server = self
+			.db
+			.useridmediumaddress_idserver
+			.qry(&key)
+			.await
+			.deserialized()
+			.ok();
+
+		self.db.useridmediumaddress_threepid.del(key);
+		self.db.useridmediumaddress_idserver.del(key);
+		self.db
+			.mediumaddress_userid
+			.del((medium.as_str(), address.as_str()));
+
+		if *medium == Medium::Email {
+			self.services
+				.pusher
+				.delete_email_pushers(user_id, &address)
+				.await;
+		}
+
+		info!(%user_id, %medium, %address, "Removed third-party identifier");
+
+		Ok(id_server)
+	}
+
+	/// Removes all third-party identifiers of a user, e.g. on deactivation.
+	pub async fn delete_all(&self, user_id: &UserId) {
+		let threepids: Vec<_> = self.threepids(user_id).collect().await;
+		for threepid in threepids {
+			self.delete(user_id, &threepid.medium, &threepid.address)
+				.await
+				.ok();
+		}
+	}
+
+	/// Returns the third-party identifiers associated with a user.
+	pub fn threepids<'a>(
+		&'a self,
+		user_id: &'a UserId,
+	) -> impl Stream<Item = ThirdPartyIdentifier> + Send + 'a {
+		let prefix = (user_id, Interfix);
+		self.db
+			.useridmediumaddress_threepid
+			.stream_prefix(&prefix)
+			.ignore_err()
+			.map(|((_, medium, address), metadata): ((Ignore, &str, &str), Metadata)| {
+				ThirdPartyIdentifier {
+					address: address.to_owned(),
+					medium: medium.into(),
+					validated_at: millis(metadata.validated_at),
+					added_at: millis(metadata.added_at),
+				}
+			})
+	}
+
+	/// Returns the local user a third-party identifier belongs to.
+	pub async fn find_user(&self, medium: &Medium, address: &str) -> Result<OwnedUserId> {
+		let address = address.to_lowercase();
+		self.db
+			.mediumaddress_userid
+			.qry(&(medium.as_str(), address.as_str()))
+			.await
+			.deserialized()
+			.map_err(|_| {
+				err!(Request(ThreepidNotFound("No account is associated with this address.")))
+			})
+	}
+
+	/// Asks an identity server to bind a third-party identifier it validated
+	/// to the user, and records the binding. The identifier must already be
+	/// validated and added to the user's account on this server.
+	pub async fn bind(
+		&self,
+		user_id: &UserId,
+		id_server: &str,
+		id_access_token: &str,
+		sid: &SessionId,
+		client_secret: &ClientSecret,
+	) -> Result {
+		let url = Url::parse(&format!("https://{id_server}/_matrix/identity/v2/3pid/bind"))
+			.map_err(|e| err!(Request(InvalidParam("Invalid identity server: {e}"))))?;
+
+		let body = json!({
+			"sid": sid,
+			"client_secret": client_secret,
+			"mxid": user_id,
+		});
+
+		let response = self
+			.services
+			.client
+			.default
+			.post(url)
+			.bearer_auth(id_access_token)
+			.header(http::header::CONTENT_TYPE, "application/json")
+			.body(serde_json::to_vec(&body)?)
+			.send()
+			.await?;
+
+		let status = response.status();
+		let body = response.bytes().await?;
+		if !status.is_success() {
+			return Err!(Request(ThreepidAuthFailed(debug_warn!(
+				"Identity server {id_server} refused to bind: {status} {}",
+				String::from_utf8_lossy(&body)
+			))));
+		}
+
+		let binding: Binding = serde_json::from_slice(&body)?;
+		let address = binding.address.to_lowercase();
+		let owner = self.find_user(&binding.medium, &address).await.ok();
+		if &*binding.mxid != user_id || owner.as_deref() != Some(user_id) {
+			return Err!(Request(ThreepidAuthFailed(debug_warn!(
+				"Identity server {id_server} bound {} {address} to {}, which is not an address \
+				 validated for {user_id}.",
+				binding.medium,
+				binding.mxid,
+			))));
+		}
+
+		self.db
+			.useridmediumaddress_idserver
+			.put((user_id, binding.medium.as_str(), address.as_str()), id_server);
+
+		Ok(())
+	}
+
+	/// URL clients can submit validation tokens to.
+	#[must_use]
+	pub fn submit_url(&self) -> Option<String> {
+		self.base_url().join(SUBMIT_TOKEN_PATH).ok().map(Into::into)
+	}
+
+	async fn session(&self, sid: &SessionId, client_secret: &ClientSecret) -> Result<Session> {
+		let session: Session = self
+			.db
+			.sessionid_threepidvalidation
+			.get(sid.as_str())
+			.await
+			.deserialized()
+			.map_err(|_| err!(Request(ThreepidAuthFailed("Unknown validation session."))))?;
+
+		session.check(client_secret, now_millis())?;
+
+		Ok(session)
+	}
+
+	/// Removes a session and its index entries.
+	async fn remove_session(&self, sid: &str, session: &Session) {
+		self.db.sessionid_threepidvalidation.remove(sid);
+		self.db.expiresat_sessionid.del((session.expires_at, sid));
+
+		// The client secret and address may have moved on to a newer session.
+		let key = (session.client_secret.as_str(), session.address.as_str());
+		let current: Result<String> = self
+			.db
+			.clientsecretaddress_sessionid
+			.qry(&key)
+			.await
+			.deserialized();
+
+		if current.is_ok_and(|current| current == sid) {
+			self.db.clientsecretaddress_sessionid.del(key);
+		}
+	}
+
+	/// Removes the sessions which have expired, found in expiry order.
+	async fn prune_sessions(&self) {
+		let now = now_millis();
+		let expired: Vec<String> = self
+			.db
+			.expiresat_sessionid
+			.keys()
+			.ignore_err()
+			.ready_take_while(|&(expires_at, _): &(u64, &str)| expires_at < now)
+			.map(|(_, sid)| sid.to_owned())
+			.collect()
+			.await;
+
+		for sid in expired {
+			if let Ok(session) = self
+				.db
+				.sessionid_threepidvalidation
+				.get(&sid)
+				.await
+				.deserialized::<Session>()
+			{
+				self.remove_session(&sid, &session).await;
+			}
+		}
+	}
+
+	/// Counts a validation email against the limits of the client address and
+	/// of the address it is sent to.
+	fn throttle(&self, client: IpAddr, address: &str) -> Result {
+		let config = &self.services.server.config.email;
+		let window = Duration::from_secs(config.validation_rate_window);
+		let now = Instant::now();
+
+		let limited = {
+			let mut clients = self.sent_for_clients.lock().expect("locked");
+			let mut addresses = self.sent_to_addresses.lock().expect("locked");
+			let limit = config.validation_rate_limit_client;
+			sent_in_window(&mut clients, client, limit, window, now).and_then(|by_client| {
+				let limit = config.validation_rate_limit_address;
+				let by_address =
+					sent_in_window(&mut addresses, address.to_owned(), limit, window, now)?;
+
+				count([by_client, by_address]);
+				Ok(())
+			})
+		};
+
+		let Err(remaining) = limited else {
+			return Ok(());
+		};
+
+		warn!(%client, %address, "Rate limiting validation emails");
+		Err(Error::Request(
+			ErrorKind::LimitExceeded {
+				retry_after: Some(RetryAfter::Delay(remaining)),
+			},
+			"Too many validation emails were requested, try again later.".into(),
+			StatusCode::TOO_MANY_REQUESTS,
+		))
+	}
+
+	fn validation_email(
+		&self,
+		sid: &SessionId,
+		session: &Session,
+		purpose: Purpose,
+	) -> Result<(&'static str, String)> {
+		let mut link = self
+			.base_url()
+			.join(SUBMIT_TOKEN_PATH)
+			.map_err(|e| err!("Failed to build validation link: {e}"))?;
+		link.query_pairs_mut()
+			.append_pair("sid", sid.as_str())
+			.append_pair("client_secret", session.client_secret.as_str())
+			.append_pair("token", &session.token);
+
+		let server_name = self.services.globals.server_name();
+		let (subject, action) = match purpose {
+			| Purpose::AddThreepid =>
+				("Validate your email address", "add this email address to an account"),
+			| Purpose::PasswordReset =>
+				("Reset your password", "reset the password of the account using this address"),
+		};
+
+		let body = format!(
+			"A request was made to {action} on {server_name}.\n\nTo confirm, open the following \
+			 link:\n\n{link}\n\nIf you did not make this request, you can safely ignore this \
+			 email.\n"
+		);
+
+		Ok((subject, body))
+	}
+
+	/// Base URL for links in emails.
+	#[must_use]
+	pub fn base_url(&self) -> Url {
+		let config = &self.services.server.config;
+		config
+			.email
+			.client_base_url
+			.clone()
+			.or_else(|| config.well_known.client.clone())
+			.unwrap_or_else(|| {
+				let server_name = self.services.globals.server_name();
+				Url::parse(&format!("https://{server_name}"))
+					.expect("server name is a valid host")
+			})
+	}
+}
+
+impl Session {
+	/// Rejects a session which has expired or which the client secret does
+	/// not belong to.
+	fn check(&self, client_secret: &ClientSecret, now: u64) -> Result {
+		if self.client_secret != client_secret || self.expires_at < now {
+			return Err!(Request(ThreepidAuthFailed("Unknown validation session.")));
+		}
+
+		Ok(())
+	}
+
+	/// Rejects a session which was not validated or was requested for another
+	/// purpose.
+	fn validated_for(&self, purpose: Purpose) -> Result {
+		if self.purpose != purpose {
+			return Err!(Request(ThreepidAuthFailed(
+				"This validation session was requested for another purpose."
+			)));
+		}
+
+		if self.validated_at.is_none() {
+			return Err!(Request(ThreepidAuthFailed(
+				"This email address has not been validated yet."
+			)));
+		}
+
+		Ok(())
+	}
+}
+
+/// Returns the emails sent for the key in the current window, unless `limit`
+/// emails were already sent in it, in which case the time until the window
+/// ends is returned. A limit of 0 disables limiting.
+fn sent_in_window<K>(
+	map: &mut SentMap<K>,
+	key: K,
+	limit: u32,
+	window: Duration,
+	now: Instant,
+) -> Result<Option<&mut Sent>, Duration>
+where
+	K: Eq + Hash,
+{
+	if limit == 0 {
+		return Ok(None);
+	}
+
+	if map.len() >= PRUNE_THRESHOLD {
+		map.retain(|_, sent| now.saturating_duration_since(sent.since) < window);
+	}
+
+	let sent = map.entry(key).or_insert(Sent { count: 0, since: now });
+	let elapsed = now.saturating_duration_since(sent.since);
+	if elapsed >= window {
+		*sent = Sent { count: 0, since: now };
+	}
+
+	if sent.count >= limit {
+		return Err(window.saturating_sub(elapsed));
+	}
+
+	Ok(Some(sent))
+}
+
+/// Counts one email against each of the limits, once all of them allowed it.
+fn count<const N: usize>(limits: [Option<&mut Sent>; N]) {
+	for sent in limits.into_iter().flatten() {
+		sent.count = sent.count.saturating_add(1);
+	}
+}
+
+/// Compares validation tokens in time independent of where they differ.
+pub(crate) fn tokens_match(expected: &str, token: &str) -> bool {
+	expected.len() == token.len()
+		&& expected
+			.bytes()
+			.zip(token.bytes())
+			.fold(0_u8, |diff, (a, b)| diff | (a ^ b))
+			== 0
+}
+
+/// Email addresses are compared case-insensitively.
+fn canonical_email(email: &str) -> Result<String> {
+	let email = email.trim().to_lowercase();
+	match email.split_once('@') {
+		| Some((local, domain)) if !local.is_empty() && !domain.is_empty() => Ok(email),
+		| _ => Err!(Request(InvalidParam("Invalid email address."))),
+	}
+}
+
+fn millis(millis: u64) -> MilliSecondsSinceUnixEpoch {
+	MilliSecondsSinceUnixEpoch(millis.try_into().unwrap_or_default())
+}
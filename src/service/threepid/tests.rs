@@ -0,0 +1,130 @@
+#![cfg(test)]
+
+use std::time::{Duration, Instant};
+
+use ruma::{thirdparty::Medium, ClientSecret, UInt};
+
+use super::{count, sent_in_window, tokens_match, Purpose, Sent, SentMap, Session};
+
+fn session(purpose: Purpose) -> Session {
+	Session {
+		client_secret: ClientSecret::parse("secret").unwrap(),
+		medium: Medium::Email,
+		address: "alice@example.com".to_owned(),
+		token: "token".to_owned(),
+		purpose,
+		send_attempt: UInt::MIN,
+		expires_at: 2_000,
+		validated_at: None,
+	}
+}
+
+#[test]
+fn session_checks_secret_and_expiry() {
+	let session = session(Purpose::AddThreepid);
+	let secret = ClientSecret::parse("secret").unwrap();
+	let other = ClientSecret::parse("other").unwrap();
+
+	assert!(session.check(&secret, 1_000).is_ok());
+	assert!(session.check(&secret, 2_000).is_ok());
+	assert!(session.check(&secret, 2_001).is_err(), "expired session was accepted");
+	assert!(session.check(&other, 1_000).is_err(), "foreign client secret was accepted");
+}
+
+#[test]
+fn session_requires_validation() {
+	let mut session = session(Purpose::PasswordReset);
+	assert!(session.validated_for(Purpose::PasswordReset).is_err());
+
+	session.validated_at = Some(1_500);
+	assert!(session.validated_for(Purpose::PasswordReset).is_ok());
+}
+
+#[test]
+fn session_requires_purpose() {
+	let mut session = session(Purpose::AddThreepid);
+	session.validated_at = Some(1_500);
+
+	assert!(session.validated_for(Purpose::AddThreepid).is_ok());
+	assert!(
+		session.validated_for(Purpose::PasswordReset).is_err(),
+		"session for adding an address reset a password"
+	);
+}
+
+#[test]
+fn session_purpose_persists() {
+	let session = session(Purpose::PasswordReset);
+	let json = serde_json::to_vec(&session).unwrap();
+	let session: Session = serde_json::from_slice(&json).unwrap();
+
+	assert_eq!(session.purpose, Purpose::PasswordReset);
+}
+
+#[test]
+fn token_comparison() {
+	assert!(tokens_match("abcdef", "abcdef"));
+	assert!(!tokens_match("abcdef", "abcdeg"));
+	assert!(!tokens_match("abcdef", "bbcdef"));
+	assert!(!tokens_match("abcdef", "abcde"));
+	assert!(!tokens_match("abcdef", ""));
+}
+
+/// Counts one email for the key, as the service does for a single limit.
+fn throttle(
+	sent: &mut SentMap<&'static str>,
+	key: &'static str,
+	limit: u32,
+	window: Duration,
+	now: Instant,
+) -> Result<(), Duration> {
+	count([sent_in_window(sent, key, limit, window, now)?]);
+	Ok(())
+}
+
+#[test]
+fn throttle_limits_per_window() {
+	let mut sent = SentMap::new();
+	let window = Duration::from_secs(60);
+	let start = Instant::now();
+
+	assert!(throttle(&mut sent, "a", 2, window, start).is_ok());
+	assert!(throttle(&mut sent, "a", 2, window, start).is_ok());
+
+	let later = start.checked_add(Duration::from_secs(20)).unwrap();
+	assert_eq!(throttle(&mut sent, "a", 2, window, later), Err(Duration::from_secs(40)));
+	assert!(throttle(&mut sent, "b", 2, window, later).is_ok(), "keys share a limit");
+
+	let next = start.checked_add(window).unwrap();
+	assert!(throttle(&mut sent, "a", 2, window, next).is_ok(), "window did not end");
+	assert!(matches!(sent.get("a"), Some(Sent { count: 1, .. })));
+}
+
+#[test]
+fn throttle_disabled() {
+	let mut sent = SentMap::new();
+	let now = Instant::now();
+	for _ in 0..10 {
+		assert!(throttle(&mut sent, "a", 0, Duration::from_secs(60), now).is_ok());
+	}
+}
+
+#[test]
+fn throttle_counts_only_allowed_emails() {
+	let mut clients = SentMap::new();
+	let mut addresses = SentMap::new();
+	let window = Duration::from_secs(60);
+	let now = Instant::now();
+
+	throttle(&mut addresses, "alice@example.com", 1, window, now).unwrap();
+
+	let by_client = sent_in_window(&mut clients, "client", 5, window, now).unwrap();
+	let limited = sent_in_window(&mut addresses, "alice@example.com", 1, window, now);
+	assert!(by_client.is_some(), "client limit is disabled");
+	assert!(limited.is_err(), "address limit was not reached");
+
+	assert!(
+		matches!(clients.get("client"), Some(Sent { count: 0, .. })),
+		"email refused by the address limit counted against the client"
+	);
+}
@@ -17,7 +17,7 @@
 	CanonicalJsonValue, DeviceId, OwnedDeviceId, OwnedUserId, UserId,
 };
 
-use crate::{config, globals, users, Dep};
+use crate::{config, globals, lockout, users, Dep};
 
 pub struct Service {
 	userdevicesessionid_uiaarequest: RwLock<RequestMap>,
@@ -27,6 +27,7 @@ pub struct Service {
 
 struct Services {
 	globals: Dep<globals::Service>,
+	lockout: Dep<lockout::Service>,
 	users: Dep<users::Service>,
 	config: Dep<config::Service>,
 }
@@ -49,6 +50,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			},
 			services: Services {
 				globals: args.depend::<globals::Service>("globals"),
+				lockout: args.depend::<lockout::Service>("lockout"),
 				users: args.depend::<users::Service>("users"),
 				config: args.depend::<config::Service>("config"),
 			},
@@ -158,16 +160,21 @@ pub async fn try_auth(
 			)
 			.map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "User ID is invalid."))?;
 
+			self.services.lockout.check(&user_id, None)?;
+
 			// Check if password is correct
 			if let Ok(hash) = self.services.users.password_hash(&user_id).await {
 				let hash_matches = hash::verify_password(password, &hash).is_ok();
 				if !hash_matches {
+					self.services.lockout.failed(&user_id, None).await;
 					uiaainfo.auth_error = Some(ruma::api::client::error::StandardErrorBody {
 						kind: ErrorKind::forbidden(),
 						message: "Invalid username or password.".to_owned(),
 					});
 					return Ok((false, uiaainfo));
 				}
+
+				self.services.lockout.succeeded(&user_id);
 			}
 
 			// Password was correct! Let's add it to `completed`
@@ -1,12 +1,22 @@
-use std::{collections::BTreeMap, mem, sync::Arc};
+mod password_policy;
+mod tests;
+
+use std::{
+	collections::{BTreeMap, HashSet},
+	mem,
+	sync::{Arc, Mutex},
+};
 
 use conduwuit::{
-	at, debug_warn, err, trace,
+	at, debug_warn, err,
+	result::LogErr,
+	trace,
 	utils::{self, stream::TryIgnore, string::Unquoted, ReadyExt},
 	Err, Error, Result, Server,
 };
 use database::{Deserialized, Ignore, Interfix, Json, Map};
 use futures::{Stream, StreamExt, TryFutureExt};
+use lru_cache::LruCache;
 use ruma::{
 	api::client::{device::Device, error::ErrorKind, filter::FilterDefinition},
 	encryption::{CrossSigningKey, DeviceKeys, OneTimeKey},
@@ -19,18 +29,52 @@
 };
 use serde_json::json;
 
-use crate::{account_data, admin, globals, rooms, Dep};
+use crate::{
+	account_data, admin,
+	appservice::{self, RegistrationInfo},
+	globals, rooms,
+	sending::{self, DeviceChange, DeviceChangeKind, EduBuf},
+	Dep,
+};
 
 pub struct Service {
 	services: Services,
 	db: Data,
+	breached_passwords: HashSet<String>,
+	last_seen: Mutex<LruCache<(OwnedUserId, OwnedDeviceId), LastSeen>>,
 }
 
+/// When, from where and with what a device was last recorded as seen, so
+/// requests within `LAST_SEEN_INTERVAL` need not read the database.
+struct LastSeen {
+	at: u64,
+	ip: Option<String>,
+	user_agent: Option<String>,
+}
+
+impl LastSeen {
+	/// Whether a request at `now_ms` from `ip` with `user_agent` changes
+	/// nothing worth writing. Requests without a user agent keep the last one.
+	fn covers(&self, now_ms: u64, ip: Option<&str>, user_agent: Option<&str>) -> bool {
+		now_ms.saturating_sub(self.at) < LAST_SEEN_INTERVAL
+			&& self.ip.as_deref() == ip
+			&& (user_agent.is_none() || self.user_agent.as_deref() == user_agent)
+	}
+}
+
+/// How often the last seen time of a device is written, in milliseconds.
+const LAST_SEEN_INTERVAL: u64 = 5 * 60 * 1000;
+
+/// Devices remembered as seen before the least recently seen are dropped.
+const LAST_SEEN_CAPACITY: usize = 16 * 1024;
+
 struct Services {
 	server: Arc<Server>,
 	account_data: Dep<account_data::Service>,
 	admin: Dep<admin::Service>,
+	appservice: Dep<appservice::Service>,
 	globals: Dep<globals::Service>,
+	sending: Dep<sending::Service>,
 	state_accessor: Dep<rooms::state_accessor::Service>,
 	state_cache: Dep<rooms::state_cache::Service>,
 }
@@ -45,6 +89,7 @@ struct Data {
 	token_userdeviceid: Arc<Map>,
 	userdeviceid_metadata: Arc<Map>,
 	userdeviceid_token: Arc<Map>,
+	userdeviceid_useragent: Arc<Map>,
 	userfilterid_filter: Arc<Map>,
 	userid_avatarurl: Arc<Map>,
 	userid_blurhash: Arc<Map>,
@@ -54,18 +99,29 @@ struct Data {
 	userid_masterkeyid: Arc<Map>,
 	userid_password: Arc<Map>,
 	userid_selfsigningkeyid: Arc<Map>,
+	userid_shadowbanned: Arc<Map>,
 	userid_usersigningkeyid: Arc<Map>,
 	useridprofilekey_value: Arc<Map>,
 }
 
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		let policy = &args.server.config.password_policy;
+		let breached_passwords = password_policy::load_breached_passwords(
+			policy
+				.breached_passwords_file
+				.as_deref()
+				.filter(|_| policy.enable),
+		)?;
+
 		Ok(Arc::new(Self {
 			services: Services {
 				server: args.server.clone(),
 				account_data: args.depend::<account_data::Service>("account_data"),
 				admin: args.depend::<admin::Service>("admin"),
+				appservice: args.depend::<appservice::Service>("appservice"),
 				globals: args.depend::<globals::Service>("globals"),
+				sending: args.depend::<sending::Service>("sending"),
 				state_accessor: args
 					.depend::<rooms::state_accessor::Service>("rooms::state_accessor"),
 				state_cache: args.depend::<rooms::state_cache::Service>("rooms::state_cache"),
@@ -80,6 +136,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				token_userdeviceid: args.db["token_userdeviceid"].clone(),
 				userdeviceid_metadata: args.db["userdeviceid_metadata"].clone(),
 				userdeviceid_token: args.db["userdeviceid_token"].clone(),
+				userdeviceid_useragent: args.db["userdeviceid_useragent"].clone(),
 				userfilterid_filter: args.db["userfilterid_filter"].clone(),
 				userid_avatarurl: args.db["userid_avatarurl"].clone(),
 				userid_blurhash: args.db["userid_blurhash"].clone(),
@@ -89,9 +146,12 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				userid_masterkeyid: args.db["userid_masterkeyid"].clone(),
 				userid_password: args.db["userid_password"].clone(),
 				userid_selfsigningkeyid: args.db["userid_selfsigningkeyid"].clone(),
+				userid_shadowbanned: args.db["userid_shadowbanned"].clone(),
 				userid_usersigningkeyid: args.db["userid_usersigningkeyid"].clone(),
 				useridprofilekey_value: args.db["useridprofilekey_value"].clone(),
 			},
+			breached_passwords,
+			last_seen: Mutex::new(LruCache::new(LAST_SEEN_CAPACITY)),
 		}))
 	}
 
@@ -170,6 +230,25 @@ pub async fn is_active_local(&self, user_id: &UserId) -> bool {
 		self.services.globals.user_is_local(user_id) && self.is_active(user_id).await
 	}
 
+	/// Shadow-bans a user or lifts their shadow-ban. The events of a
+	/// shadow-banned user look sent to them, but nobody else sees them.
+	pub fn shadow_ban(&self, user_id: &UserId, banned: bool) {
+		if banned {
+			self.db.userid_shadowbanned.insert(user_id, []);
+		} else {
+			self.db.userid_shadowbanned.remove(user_id);
+		}
+	}
+
+	#[inline]
+	pub async fn is_shadow_banned(&self, user_id: &UserId) -> bool {
+		self.db.userid_shadowbanned.get(user_id).await.is_ok()
+	}
+
+	pub fn list_shadow_banned(&self) -> impl Stream<Item = &UserId> + Send + '_ {
+		self.db.userid_shadowbanned.keys().ignore_err()
+	}
+
 	/// Returns the number of users registered on this server.
 	#[inline]
 	pub async fn count(&self) -> usize { self.db.userid_password.count().await }
@@ -267,12 +346,13 @@ pub fn set_blurhash(&self, user_id: &UserId, blurhash: Option<String>) {
 		}
 	}
 
-	/// Adds a new device to a user.
+	/// Adds a new device to a user. Devices created by appservices for their
+	/// users (MSC4190) have no access token.
 	pub async fn create_device(
 		&self,
 		user_id: &UserId,
 		device_id: &DeviceId,
-		token: &str,
+		token: Option<&str>,
 		initial_device_display_name: Option<String>,
 		client_ip: Option<String>,
 	) -> Result<()> {
@@ -294,7 +374,11 @@ pub async fn create_device(
 
 		increment(&self.db.userid_devicelistversion, user_id.as_bytes());
 		self.db.userdeviceid_metadata.put(key, Json(val));
-		self.set_token(user_id, device_id, token).await
+
+		match token {
+			| Some(token) => self.set_token(user_id, device_id, token).await,
+			| None => Ok(()),
+		}
 	}
 
 	/// Removes a device from a user.
@@ -321,6 +405,12 @@ pub async fn remove_device(&self, user_id: &UserId, device_id: &DeviceId) {
 		increment(&self.db.userid_devicelistversion, user_id.as_bytes());
 
 		self.db.userdeviceid_metadata.del(userdeviceid);
+		self.db.userdeviceid_useragent.del(userdeviceid);
+		self.last_seen
+			.lock()
+			.expect("locked")
+			.remove(&(user_id.to_owned(), device_id.to_owned()));
+
 		self.mark_device_key_update(user_id).await;
 	}
 
@@ -410,6 +500,9 @@ pub async fn add_one_time_key(
 		let count = self.services.globals.next_count().unwrap();
 		self.db.userid_lastonetimekeyupdate.raw_put(user_id, count);
 
+		self.notify_appservices(user_id, DeviceChangeKind::OneTimeKeys)
+			.await;
+
 		Ok(())
 	}
 
@@ -466,6 +559,9 @@ pub async fn take_one_time_key(
 			.next()
 			.await;
 
+		self.notify_appservices(user_id, DeviceChangeKind::OneTimeKeys)
+			.await;
+
 		one_time_key.ok_or_else(|| err!(Request(NotFound("No one-time-key found"))))
 	}
 
@@ -698,6 +794,113 @@ pub async fn mark_device_key_update(&self, user_id: &UserId) {
 
 		let key = (user_id, count);
 		self.db.keychangeid_userid.put_raw(key, user_id);
+
+		self.notify_appservices(user_id, DeviceChangeKind::Changed)
+			.await;
+	}
+
+	/// Records a device change for appservices which receive device changes
+	/// (MSC3202) and are interested in the user, either through their
+	/// namespace or, for device list changes, through an encrypted room they
+	/// share with the user.
+	async fn notify_appservices(&self, user_id: &UserId, kind: DeviceChangeKind) {
+		let appservices: Vec<_> = self
+			.services
+			.appservice
+			.read()
+			.await
+			.values()
+			.filter(|appservice| appservice.receives_device_changes())
+			.cloned()
+			.collect();
+
+		for appservice in appservices {
+			let interested = appservice.is_user_match(user_id)
+				|| (kind == DeviceChangeKind::Changed
+					&& self
+						.shares_encrypted_room_with_appservice(user_id, &appservice)
+						.await);
+
+			if interested {
+				let change = DeviceChange { user_id: user_id.to_owned(), kind };
+				self.services
+					.sending
+					.send_device_change_appservice(appservice.registration.id, &change)
+					.log_err()
+					.ok();
+			}
+		}
+	}
+
+	/// Tells appservices which receive device changes (MSC3202) and were in
+	/// the encrypted room the user left that they no longer share an encrypted
+	/// room with the user, unless they still do.
+	pub async fn mark_device_list_left(&self, user_id: &UserId, room_id: &RoomId) {
+		let appservices: Vec<_> = self
+			.services
+			.appservice
+			.read()
+			.await
+			.values()
+			.filter(|appservice| {
+				appservice.receives_device_changes() && !appservice.is_user_match(user_id)
+			})
+			.cloned()
+			.collect();
+
+		if appservices.is_empty()
+			|| !self
+				.services
+				.state_accessor
+				.is_encrypted_room(room_id)
+				.await
+		{
+			return;
+		}
+
+		for appservice in appservices {
+			let in_room = self
+				.services
+				.state_cache
+				.appservice_in_room(room_id, &appservice)
+				.await;
+
+			if !in_room
+				|| self
+					.shares_encrypted_room_with_appservice(user_id, &appservice)
+					.await
+			{
+				continue;
+			}
+
+			let change = DeviceChange {
+				user_id: user_id.to_owned(),
+				kind: DeviceChangeKind::Left,
+			};
+
+			self.services
+				.sending
+				.send_device_change_appservice(appservice.registration.id, &change)
+				.log_err()
+				.ok();
+		}
+	}
+
+	async fn shares_encrypted_room_with_appservice(
+		&self,
+		user_id: &UserId,
+		appservice: &RegistrationInfo,
+	) -> bool {
+		self.services
+			.state_cache
+			.rooms_joined(user_id)
+			.filter(|room_id| self.services.state_accessor.is_encrypted_room(room_id))
+			.any(|room_id| {
+				self.services
+					.state_cache
+					.appservice_in_room(room_id, appservice)
+			})
+			.await
 	}
 
 	pub async fn get_device_keys<'a>(
@@ -773,17 +976,39 @@ pub async fn add_to_device_event(
 		event_type: &str,
 		content: serde_json::Value,
 	) {
-		let count = self.services.globals.next_count().unwrap();
+		let event = json!({
+			"type": event_type,
+			"sender": sender,
+			"content": content,
+		});
+
+		// Appservices receive to-device messages for their users in transactions
+		// (MSC2409), addressed to the target device (MSC4203).
+		let mut handed_over = false;
+		for appservice in self.services.appservice.read().await.values() {
+			let Some(buf) =
+				appservice_to_device_event(appservice, &event, target_user_id, target_device_id)
+			else {
+				continue;
+			};
+
+			handed_over |= self
+				.services
+				.sending
+				.send_edu_appservice(appservice.registration.id.clone(), buf)
+				.log_err()
+				.is_ok();
+		}
 
+		// Events handed to an appservice are delivered with its transactions; stored
+		// they would never be fetched by a sync and removed.
+		if handed_over {
+			return;
+		}
+
+		let count = self.services.globals.next_count().unwrap();
 		let key = (target_user_id, target_device_id, count);
-		self.db.todeviceid_events.put(
-			key,
-			Json(json!({
-				"type": event_type,
-				"sender": sender,
-				"content": content,
-			})),
-		);
+		self.db.todeviceid_events.put(key, Json(event));
 	}
 
 	pub fn get_to_device_events<'a>(
@@ -861,6 +1086,83 @@ pub async fn get_device_metadata(
 			.deserialized()
 	}
 
+	/// Records the time, IP address and user agent a device was last seen
+	/// making a request. Nothing is read or written when nothing changed and
+	/// the device was already recorded within the last few minutes.
+	pub async fn update_device_last_seen(
+		&self,
+		user_id: &UserId,
+		device_id: &DeviceId,
+		client_ip: Option<String>,
+		user_agent: Option<&str>,
+	) {
+		let now = MilliSecondsSinceUnixEpoch::now();
+		let now_ms: u64 = now.get().into();
+		let unchanged = |seen: &LastSeen| seen.covers(now_ms, client_ip.as_deref(), user_agent);
+
+		let key = (user_id.to_owned(), device_id.to_owned());
+		if self
+			.last_seen
+			.lock()
+			.expect("locked")
+			.get_mut(&key)
+			.is_some_and(|seen| unchanged(seen))
+		{
+			return;
+		}
+
+		let Ok(mut device) = self.get_device_metadata(user_id, device_id).await else {
+			return;
+		};
+
+		let db_key = (user_id, device_id);
+		let last_user_agent: Option<String> = self
+			.db
+			.userdeviceid_useragent
+			.qry(&db_key)
+			.await
+			.deserialized()
+			.ok();
+
+		let stored = LastSeen {
+			at: device.last_seen_ts.map_or(0, |ts| ts.get().into()),
+			ip: device.last_seen_ip.clone(),
+			user_agent: last_user_agent,
+		};
+
+		let seen = if unchanged(&stored) {
+			stored
+		} else {
+			device.last_seen_ip.clone_from(&client_ip);
+			device.last_seen_ts = Some(now);
+			self.db.userdeviceid_metadata.put(db_key, Json(device));
+			if let Some(user_agent) = user_agent {
+				self.db.userdeviceid_useragent.put_raw(db_key, user_agent);
+			}
+
+			LastSeen {
+				at: now_ms,
+				ip: client_ip,
+				user_agent: user_agent.map(ToOwned::to_owned).or(stored.user_agent),
+			}
+		};
+
+		self.last_seen.lock().expect("locked").insert(key, seen);
+	}
+
+	/// Get the user agent a device was last seen with.
+	pub async fn get_device_user_agent(
+		&self,
+		user_id: &UserId,
+		device_id: &DeviceId,
+	) -> Result<String> {
+		self.db
+			.userdeviceid_useragent
+			.qry(&(user_id, device_id))
+			.await
+			.deserialized()
+	}
+
 	pub async fn get_devicelist_version(&self, user_id: &UserId) -> Result<u64> {
 		self.db
 			.userid_devicelistversion
@@ -1079,6 +1381,29 @@ pub fn parse_master_key(
 	Ok((master_key_key, master_key))
 }
 
+/// Returns the to-device event as it is pushed to the appservice, addressed to
+/// the target device, if the appservice receives the target user's to-device
+/// messages.
+fn appservice_to_device_event(
+	appservice: &RegistrationInfo,
+	event: &serde_json::Value,
+	target_user_id: &UserId,
+	target_device_id: &DeviceId,
+) -> Option<EduBuf> {
+	if !appservice.receives_to_device() || !appservice.is_user_match(target_user_id) {
+		return None;
+	}
+
+	let mut event = event.clone();
+	event["to_user_id"] = target_user_id.as_str().into();
+	event["to_device_id"] = target_device_id.as_str().into();
+
+	let mut buf = EduBuf::new();
+	serde_json::to_writer(&mut buf, &event).expect("failed to serialize to-device event to JSON");
+
+	Some(buf)
+}
+
 /// Ensure that a user only sees signatures from themselves and the target user
 fn clean_signatures<F>(
 	mut cross_signing_key: serde_json::Value,
@@ -0,0 +1,88 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use conduwuit::{config::PasswordPolicyConfig, err, implement, info, Err, Result};
+use ruma::UserId;
+
+/// Checks a new password for the user against the configured password policy,
+/// returning an `M_WEAK_PASSWORD` error describing the first rule it violates.
+#[implement(super::Service)]
+pub fn check_password_policy(&self, user_id: &UserId, password: &str) -> Result {
+	let policy = &self.services.server.config.password_policy;
+	if !policy.enable {
+		return Ok(());
+	}
+
+	check(policy, &self.breached_passwords, user_id.localpart(), password)
+}
+
+pub(super) fn check(
+	policy: &PasswordPolicyConfig,
+	breached_passwords: &HashSet<String>,
+	localpart: &str,
+	password: &str,
+) -> Result {
+	let minimum_length = policy.minimum_length;
+	if password.chars().count() < minimum_length {
+		return Err!(Request(WeakPassword(
+			"Password must be at least {minimum_length} characters long."
+		)));
+	}
+
+	if policy.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+		return Err!(Request(WeakPassword("Password must contain at least one digit.")));
+	}
+
+	if policy.require_symbol && password.chars().all(char::is_alphanumeric) {
+		return Err!(Request(WeakPassword("Password must contain at least one symbol.")));
+	}
+
+	if policy.require_lowercase && !password.chars().any(char::is_lowercase) {
+		return Err!(Request(WeakPassword(
+			"Password must contain at least one lowercase letter."
+		)));
+	}
+
+	if policy.require_uppercase && !password.chars().any(char::is_uppercase) {
+		return Err!(Request(WeakPassword(
+			"Password must contain at least one uppercase letter."
+		)));
+	}
+
+	if policy.reject_localpart && password.to_lowercase().contains(&localpart.to_lowercase()) {
+		return Err!(Request(WeakPassword("Password must not contain the username.")));
+	}
+
+	if breached_passwords.contains(password) {
+		return Err!(Request(WeakPassword(
+			"Password is known to have been exposed in a data breach."
+		)));
+	}
+
+	Ok(())
+}
+
+/// Loads the breached password list, one password per line. Blank lines are
+/// ignored.
+pub(super) fn load_breached_passwords(path: Option<&Path>) -> Result<HashSet<String>> {
+	let Some(path) = path else {
+		return Ok(HashSet::new());
+	};
+
+	let contents = fs::read_to_string(path).map_err(|e| {
+		err!(Config(
+			"password_policy.breached_passwords_file",
+			"Failed to read {path:?}: {e}"
+		))
+	})?;
+
+	let passwords: HashSet<_> = contents
+		.lines()
+		.map(|line| line.trim_end_matches('\r'))
+		.filter(|line| !line.is_empty())
+		.map(ToOwned::to_owned)
+		.collect();
+
+	info!("Loaded {} breached passwords from {path:?}", passwords.len());
+
+	Ok(passwords)
+}
@@ -0,0 +1,163 @@
+#![cfg(test)]
+
+use std::collections::HashSet;
+
+use conduwuit::config::PasswordPolicyConfig;
+use ruma::{device_id, user_id};
+use serde_json::json;
+
+use super::{appservice_to_device_event, password_policy::check, LastSeen, LAST_SEEN_INTERVAL};
+
+fn seen() -> LastSeen {
+	LastSeen {
+		at: 1_000,
+		ip: Some("192.0.2.1".to_owned()),
+		user_agent: Some("client/1.0".to_owned()),
+	}
+}
+
+#[test]
+fn last_seen_throttles_repeated_requests() {
+	let seen = seen();
+	let soon = 1_000_u64
+		.saturating_add(LAST_SEEN_INTERVAL)
+		.saturating_sub(1);
+
+	assert!(seen.covers(1_000, Some("192.0.2.1"), Some("client/1.0")));
+	assert!(seen.covers(soon, Some("192.0.2.1"), Some("client/1.0")));
+	assert!(seen.covers(soon, Some("192.0.2.1"), None), "missing user agent was written");
+}
+
+#[test]
+fn last_seen_writes_after_interval() {
+	let later = 1_000_u64.saturating_add(LAST_SEEN_INTERVAL);
+
+	assert!(!seen().covers(later, Some("192.0.2.1"), Some("client/1.0")));
+}
+
+#[test]
+fn last_seen_writes_changes() {
+	let seen = seen();
+
+	assert!(
+		!seen.covers(1_500, Some("192.0.2.2"), Some("client/1.0")),
+		"new IP was throttled"
+	);
+	assert!(!seen.covers(1_500, None, Some("client/1.0")), "lost IP was throttled");
+	assert!(
+		!seen.covers(1_500, Some("192.0.2.1"), Some("client/2.0")),
+		"new agent was throttled"
+	);
+}
+
+fn policy() -> PasswordPolicyConfig {
+	PasswordPolicyConfig {
+		enable: true,
+		minimum_length: 8,
+		..Default::default()
+	}
+}
+
+#[test]
+fn password_minimum_length() {
+	let none = HashSet::new();
+
+	assert!(check(&policy(), &none, "alice", "12345678").is_ok());
+	assert!(
+		check(&policy(), &none, "alice", "1234567").is_err(),
+		"short password was accepted"
+	);
+	assert!(check(&policy(), &none, "alice", "ääääääää").is_ok(), "length counted bytes");
+}
+
+#[test]
+fn password_character_classes() {
+	let none = HashSet::new();
+	let policy = PasswordPolicyConfig {
+		require_digit: true,
+		require_symbol: true,
+		require_lowercase: true,
+		require_uppercase: true,
+		..policy()
+	};
+
+	assert!(check(&policy, &none, "alice", "Correct-horse-1").is_ok());
+	assert!(check(&policy, &none, "alice", "Correct-horse-x").is_err(), "missing digit");
+	assert!(check(&policy, &none, "alice", "Correcthorse12").is_err(), "missing symbol");
+	assert!(check(&policy, &none, "alice", "CORRECT-HORSE-1").is_err(), "missing lowercase");
+	assert!(check(&policy, &none, "alice", "correct-horse-1").is_err(), "missing uppercase");
+}
+
+#[test]
+fn password_containing_localpart() {
+	let none = HashSet::new();
+	let policy = PasswordPolicyConfig { reject_localpart: true, ..policy() };
+
+	assert!(check(&policy, &none, "alice", "correct horse").is_ok());
+	assert!(
+		check(&policy, &none, "alice", "ilovealice99").is_err(),
+		"localpart was accepted"
+	);
+	assert!(check(&policy, &none, "alice", "ILoveAlice99").is_err(), "case was not ignored");
+	assert!(
+		check(&policy(), &none, "alice", "ilovealice99").is_ok(),
+		"rule applied while off"
+	);
+}
+
+#[test]
+fn password_breached() {
+	let breached: HashSet<_> = ["password1".to_owned()].into();
+
+	assert!(check(&policy(), &breached, "alice", "password1").is_err(), "breached password");
+	assert!(check(&policy(), &breached, "alice", "password2").is_ok());
+}
+
+fn appservice(receive_ephemeral: bool) -> crate::appservice::RegistrationInfo {
+	let registration: ruma::api::appservice::Registration = serde_json::from_value(json!({
+		"id": "bridge",
+		"url": null,
+		"as_token": "as_token",
+		"hs_token": "hs_token",
+		"sender_localpart": "bridgebot",
+		"namespaces": {
+			"users": [{ "exclusive": true, "regex": "@bridge_.*:example\\.com" }],
+		},
+		"receive_ephemeral": receive_ephemeral,
+	}))
+	.expect("valid registration");
+
+	registration.try_into().expect("valid namespaces")
+}
+
+#[test]
+fn to_device_events_for_appservice_users() {
+	let event = json!({ "type": "m.room_key_request", "sender": "@alice:example.com" });
+	let user_id = user_id!("@bridge_bob:example.com");
+	let device_id = device_id!("DEVICE");
+
+	let buf = appservice_to_device_event(&appservice(true), &event, user_id, device_id)
+		.expect("event handed to the appservice");
+	let pushed: serde_json::Value = serde_json::from_slice(&buf).expect("valid JSON");
+	assert_eq!(pushed["to_user_id"], "@bridge_bob:example.com");
+	assert_eq!(pushed["to_device_id"], "DEVICE");
+	assert_eq!(pushed["type"], "m.room_key_request");
+}
+
+#[test]
+fn to_device_events_kept_for_sync() {
+	let event = json!({ "type": "m.room_key_request", "sender": "@alice:example.com" });
+	let device_id = device_id!("DEVICE");
+
+	let other = user_id!("@carol:example.com");
+	assert!(
+		appservice_to_device_event(&appservice(true), &event, other, device_id).is_none(),
+		"event for a user outside the namespace was handed over"
+	);
+
+	let user_id = user_id!("@bridge_bob:example.com");
+	assert!(
+		appservice_to_device_event(&appservice(false), &event, user_id, device_id).is_none(),
+		"event handed to an appservice without receive_ephemeral"
+	);
+}